@@ -116,6 +116,8 @@ parser.add_argument("--qemu-memory", type=int,
                     help="How much total memory in MiB (will get evenly divided among nodes).", default=1024)
 parser.add_argument("--qemu-pmem", type=int,
                     help="How much total peristent memory in MiB (will get evenly divided among nodes).", required=False, default=0)
+parser.add_argument("--qemu-pmem-path", type=str,
+                    help="Directory for the files backing the persistent memory (keeps the contents across runs).", required=False, default="")
 parser.add_argument("--qemu-affinity", action="store_true", default=False,
                     help="Pin QEMU instance to dedicated host cores.")
 parser.add_argument("--qemu-prealloc", action="store_true", default=False,
@@ -463,7 +465,13 @@ def run_qemu(args):
             if args.qemu_cores > 0 and args.qemu_pmem:
                 pmem_per_node = args.qemu_pmem / args.qemu_nodes
                 default = "/mnt/node{}".format(node)
-                if os.path.isdir(default):
+                if args.qemu_pmem_path:
+                    os.makedirs(args.qemu_pmem_path, exist_ok=True)
+                    path = os.path.join(
+                        args.qemu_pmem_path, "nvdimm{}".format(node))
+                    qemu_default_args += ['-object', 'memory-backend-file,id=pmem{},mem-path={},size={}M,pmem=off,share=on'.format(
+                        node, path, int(pmem_per_node))]
+                elif os.path.isdir(default):
                     qemu_default_args += ['-object', 'memory-backend-file,id=pmem{},mem-path={},size={}M,pmem=on,share=on'.format(
                         node, default, int(pmem_per_node))]
                 else:
@@ -0,0 +1,18 @@
+// Copyright © 2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Puts cores that have nothing to do to sleep.
+
+/// Announces that the current core will go to sleep unless it finds work.
+pub(crate) fn enter() {}
+
+/// The current core found work, it won't go to sleep after all.
+pub(crate) fn exit() {}
+
+/// Sleeps until an interrupt arrives or another core calls [`wake`].
+pub(crate) fn sleep() -> ! {
+    super::halt()
+}
+
+/// Wakes up core `gtid` if it sleeps.
+pub(crate) fn wake(_gtid: atopology::GlobalThreadId) {}
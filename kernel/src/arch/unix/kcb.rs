@@ -14,7 +14,6 @@ use crate::memory::mcache::FrameCacheEarly;
 use crate::memory::per_core::PerCoreMemory;
 use crate::nr::KernelNode;
 use crate::nrproc::NrProcess;
-use crate::process::MAX_PROCESSES;
 
 use super::process::{UnixProcess, UnixThread};
 use super::MAX_NUMA_NODES;
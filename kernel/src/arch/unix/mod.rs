@@ -23,6 +23,7 @@ use crate::{main, ExitReason};
 
 pub mod coreboot;
 pub mod debug;
+pub mod idle;
 pub mod irq;
 pub mod kcb;
 pub mod memory;
@@ -3,7 +3,6 @@
 
 //! A dummy process implementation for the unix platform.
 use alloc::boxed::Box;
-use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bootloader_shared::Module;
 use core::cell::RefCell;
@@ -11,11 +10,9 @@ use core::ops::{Deref, DerefMut};
 use x86::current::paging::PAddr;
 
 use arrayvec::ArrayVec;
-use kpi::process::FrameId;
+use kpi::process::{FrameId, ShmId};
 use lazy_static::lazy_static;
 
-use node_replication::{Dispatch, Log, Replica};
-
 use crate::arch::kcb::get_kcb;
 use crate::error::{KError, KResult};
 use crate::fs::fd::FileDescriptorEntry;
@@ -23,16 +20,14 @@ use crate::fs::MAX_FILES_PER_PROCESS;
 use crate::memory::detmem::DA;
 use crate::memory::vspace::MapAction;
 use crate::memory::vspace::{AddressSpace, TlbFlushHandle};
-use crate::memory::{Frame, VAddr, LARGE_PAGE_SIZE};
-use crate::nrproc::NrProcess;
+use crate::memory::{Frame, MemType, VAddr};
+use crate::nrproc::{NrProcess, ProcessTable};
 use crate::process::{
-    Eid, Executor, FrameManagement, Pid, Process, ResumeHandle, MAX_FRAMES_PER_PROCESS,
-    MAX_PROCESSES,
+    Cmdline, Eid, Executor, FrameManagement, Pid, Process, ResumeHandle, MAX_FRAMES_PER_PROCESS,
 };
 
 use super::debug;
 use super::vspace::VSpace;
-use super::MAX_NUMA_NODES;
 
 /// The process model of the current architecture.
 pub(crate) type ArchProcess = UnixProcess;
@@ -57,47 +52,34 @@ where
 }
 
 #[allow(clippy::boxed_local)]
-pub(crate) fn swap_current_executor(_current_executor: Box<UnixThread>) -> Option<Box<UnixThread>> {
-    None
+pub(crate) fn queue_executor(_executor: Box<UnixThread>) -> KResult<()> {
+    Ok(())
 }
 
-lazy_static! {
-    pub(crate) static ref PROCESS_TABLE: ArrayVec<ArrayVec<Arc<Replica<'static, NrProcess<UnixProcess>>>, MAX_PROCESSES>, MAX_NUMA_NODES> = {
-        // Want at least one replica...
-        let numa_nodes = core::cmp::max(1, atopology::MACHINE_TOPOLOGY.num_nodes());
-
-        let mut numa_cache = ArrayVec::new();
-        for _n in 0..numa_nodes {
-            let process_replicas = ArrayVec::new();
-            debug_assert!(!numa_cache.is_full(), "Ensured by loop range");
-            numa_cache.push(process_replicas)
-        }
-
-        for pid in 0..MAX_PROCESSES {
-                let log = Arc::try_new(Log::<<NrProcess<UnixProcess> as Dispatch>::WriteOperation>::new(
-                    LARGE_PAGE_SIZE,
-                )).expect("Can't initialize processes, out of memory.");
-
-            let da = DA::new().expect("Can't initialize process deterministic memory allocator");
-            for node in 0..numa_nodes {
-                let pcm = super::kcb::per_core_mem();
-                assert!(pcm.set_mem_affinity(node as atopology::NodeId).is_ok());
-
-                debug_assert!(!numa_cache[node].is_full(), "Ensured by loop range");
+pub(crate) fn runs_process(_pid: Pid) -> bool {
+    false
+}
 
+pub(crate) fn has_waiting_executors() -> bool {
+    false
+}
 
-                let p = Box::try_new(UnixProcess::new(pid, da.clone()).expect("Can't create process during init")).expect("Not enough memory to initialize processes");
-                let nrp = NrProcess::new(p, da.clone());
+pub(crate) fn dispatch_next() -> Option<UnixResumeHandle> {
+    None
+}
 
-                numa_cache[node].push(Replica::<NrProcess<UnixProcess>>::with_data(&log, nrp));
+pub(crate) fn populate_reserved(_pid: Pid, _vaddr: VAddr) -> KResult<()> {
+    Err(KError::NotMapped)
+}
 
-                debug_assert_eq!(*crate::environment::NODE_ID, 0, "Expect initialization to happen on node 0.");
-                assert!(pcm.set_mem_affinity(0).is_ok());
-            }
-        }
+pub(crate) fn copy_on_write(_pid: Pid, _vaddr: VAddr) -> KResult<()> {
+    Err(KError::NotSupported)
+}
 
-        numa_cache
-    };
+lazy_static! {
+    pub(crate) static ref PROCESS_TABLE: ProcessTable<UnixProcess> =
+        ProcessTable::new(crate::process::max_processes())
+            .expect("Not enough memory to initialize the process table");
 }
 
 pub(crate) struct ArchProcessManagement;
@@ -105,12 +87,7 @@ pub(crate) struct ArchProcessManagement;
 impl crate::nrproc::ProcessManager for ArchProcessManagement {
     type Process = UnixProcess;
 
-    fn process_table(
-        &self,
-    ) -> &'static ArrayVec<
-        ArrayVec<Arc<Replica<'static, NrProcess<Self::Process>>>, MAX_PROCESSES>,
-        MAX_NUMA_NODES,
-    > {
+    fn process_table(&self) -> &'static ProcessTable<Self::Process> {
         &super::process::PROCESS_TABLE
     }
 }
@@ -121,21 +98,12 @@ pub(crate) struct UnixProcess {
     vspace: VSpace,
     /// File descriptors for the opened file.
     fds: ArrayVec<Option<FileDescriptorEntry>, MAX_FILES_PER_PROCESS>,
-    pinfo: kpi::process::ProcessInfo,
+    pinfo: kpi::process::ProcessInfo<'static>,
+    cmdline: Option<Cmdline>,
     /// Physical frame objects registered to the process.
     pub frames: ArrayVec<Option<Frame>, MAX_FRAMES_PER_PROCESS>,
 }
 
-impl UnixProcess {
-    fn new(pid: Pid, _da: DA) -> Result<Self, KError> {
-        Ok(UnixProcess {
-            pid,
-            vspace: VSpace::new(),
-            ..Default::default()
-        })
-    }
-}
-
 #[derive(Copy, Clone, Debug, Default)]
 pub(crate) struct UnixThread {
     pub eid: Eid,
@@ -189,6 +157,14 @@ impl Process for UnixProcess {
     type E = UnixThread;
     type A = VSpace;
 
+    fn new(pid: Pid, _da: DA) -> Result<Self, KError> {
+        Ok(UnixProcess {
+            pid,
+            vspace: VSpace::new(),
+            ..Default::default()
+        })
+    }
+
     fn load(
         &mut self,
         _pid: Pid,
@@ -257,9 +233,21 @@ impl Process for UnixProcess {
         self.fds[index].as_ref().unwrap()
     }
 
-    fn pinfo(&self) -> &kpi::process::ProcessInfo {
+    fn pinfo(&self) -> &kpi::process::ProcessInfo<'static> {
         &self.pinfo
     }
+
+    fn cmdline(&self) -> Option<&Cmdline> {
+        self.cmdline.as_ref()
+    }
+
+    fn set_cmdline(&mut self, cmdline: Cmdline) {
+        self.cmdline = Some(cmdline);
+    }
+
+    fn teardown(&mut self) -> Result<Vec<(Frame, MemType)>, KError> {
+        Ok(Vec::new())
+    }
 }
 
 impl FrameManagement for UnixProcess {
@@ -267,6 +255,10 @@ impl FrameManagement for UnixProcess {
         Err(KError::InvalidFrameId)
     }
 
+    fn add_shared_frame(&mut self, frame: Frame, shm: ShmId) -> Result<FrameId, KError> {
+        Err(KError::InvalidFrameId)
+    }
+
     fn get_frame(&mut self, frame_id: FrameId) -> Result<(Frame, usize), KError> {
         Err(KError::InvalidFrameId)
     }
@@ -279,9 +271,17 @@ impl FrameManagement for UnixProcess {
         Err(KError::InvalidFrameId)
     }
 
-    fn deallocate_frame(&mut self, fid: FrameId) -> Result<Frame, KError> {
+    fn deallocate_frame(&mut self, fid: FrameId) -> Result<(Frame, Option<ShmId>), KError> {
         Err(KError::InvalidFrameId)
     }
+
+    fn add_cow_mapping(&mut self, frame: Frame) -> Result<(), KError> {
+        Err(KError::InvalidFrame)
+    }
+
+    fn remove_cow_mapping(&mut self, paddr: PAddr) -> Result<Option<Frame>, KError> {
+        Err(KError::InvalidFrame)
+    }
 }
 
 pub(crate) fn spawn(binary: &'static str) -> Result<Pid, KError> {
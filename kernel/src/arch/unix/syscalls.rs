@@ -3,7 +3,10 @@
 
 //! System call stubs
 
+use kpi::{MapFileFlags, MemPolicy, MemProtection};
+
 use crate::error::KResult;
+use crate::fs::fd::FileDescriptor;
 use crate::process::UserSlice;
 use crate::syscalls::{ProcessDispatch, SystemCallDispatch, SystemDispatch, VSpaceDispatch};
 
@@ -17,7 +20,7 @@ impl SystemDispatch<u64> for UnixSystemCalls {
         todo!()
     }
 
-    fn get_stats(&self) -> KResult<(u64, u64)> {
+    fn get_stats(&self, _vbuf_base: u64, _vbuf_len: u64) -> KResult<(u64, u64)> {
         todo!()
     }
 
@@ -47,6 +50,10 @@ impl ProcessDispatch<u64> for UnixSystemCalls {
         todo!()
     }
 
+    fn release_core(&self, _core_id: u64) -> KResult<(u64, u64)> {
+        todo!()
+    }
+
     fn allocate_physical(&self, _page_size: u64, _affinity: u64) -> KResult<(u64, u64)> {
         todo!()
     }
@@ -55,9 +62,29 @@ impl ProcessDispatch<u64> for UnixSystemCalls {
         todo!()
     }
 
+    fn create_shm(&self, _page_size: u64) -> KResult<(u64, u64)> {
+        todo!()
+    }
+
+    fn attach_shm(&self, _shm_id: u64) -> KResult<(u64, u64)> {
+        todo!()
+    }
+
     fn exit(&self, _code: u64) -> KResult<(u64, u64)> {
         todo!()
     }
+
+    fn spawn(&self, _args: UserSlice) -> KResult<(u64, u64)> {
+        todo!()
+    }
+
+    fn wait(&self, _pid: u64) -> KResult<(u64, u64)> {
+        todo!()
+    }
+
+    fn yield_core(&self) -> KResult<(u64, u64)> {
+        todo!()
+    }
 }
 
 impl VSpaceDispatch<u64> for UnixSystemCalls {
@@ -88,6 +115,45 @@ impl VSpaceDispatch<u64> for UnixSystemCalls {
     fn identify(&self, _addr: u64) -> KResult<(u64, u64)> {
         todo!()
     }
+
+    fn reserve_mem(&self, _base: u64, _size: u64) -> KResult<(u64, u64)> {
+        todo!()
+    }
+
+    fn protect(&self, _base: u64, _size: u64, _rights: MemProtection) -> KResult<(u64, u64)> {
+        todo!()
+    }
+
+    fn snapshot(&self, _src: u64, _dst: u64, _size: u64) -> KResult<(u64, u64)> {
+        todo!()
+    }
+
+    fn map_mem_policy(&self, _base: u64, _size: u64, _policy: MemPolicy) -> KResult<(u64, u64)> {
+        todo!()
+    }
+
+    fn set_mem_policy(&self, _policy: MemPolicy) -> KResult<(u64, u64)> {
+        todo!()
+    }
+
+    fn map_file(
+        &self,
+        _base: u64,
+        _size: u64,
+        _fd: FileDescriptor,
+        _offset: u64,
+        _flags: MapFileFlags,
+    ) -> KResult<(u64, u64)> {
+        todo!()
+    }
+
+    fn unmap_file(&self, _base: u64, _size: u64) -> KResult<(u64, u64)> {
+        todo!()
+    }
+
+    fn sync_file(&self, _base: u64, _size: u64) -> KResult<(u64, u64)> {
+        todo!()
+    }
 }
 
 /// Dispatch logic for global system calls.
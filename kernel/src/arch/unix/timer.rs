@@ -8,3 +8,6 @@ pub(crate) const DEFAULT_TIMER_DEADLINE: u64 = 2_000_000_000;
 
 /// Register a periodic timer to advance replica.
 pub(crate) fn set(_deadline: u64) {}
+
+/// Cancel the timer (if it's armed).
+pub(crate) fn disarm() {}
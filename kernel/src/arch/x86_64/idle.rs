@@ -0,0 +1,106 @@
+// Copyright © 2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Puts cores that have nothing to do to sleep.
+//!
+//! A core announces that it wants to sleep with [`enter`], checks one last
+//! time for work and then goes to [`sleep`]. It sleeps with MWAIT (on its wake
+//! word) if the CPU supports it, otherwise with HLT. Interrupts (IPIs, the
+//! timer) always wake it, [`wake`] brings it back from another core.
+//!
+//! If [`wake`] comes after [`enter`], [`sleep`] returns to the scheduler
+//! immediately, so no wake-up gets lost in-between.
+
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use fallible_collections::FallibleVecGlobal;
+use lazy_static::lazy_static;
+use log::trace;
+use x86::cpuid::CpuId;
+
+/// The core runs (or is about to run) something.
+const AWAKE: u8 = 0;
+/// The core sleeps (or is about to).
+const SLEEPING: u8 = 1;
+
+/// What a core monitors while it sleeps, it sits on its own cache-line so
+/// writes for other cores don't wake it.
+#[repr(align(64))]
+struct WakeWord(AtomicU8);
+
+lazy_static! {
+    static ref WAKE_WORDS: Vec<WakeWord> = {
+        let num_threads = atopology::MACHINE_TOPOLOGY.num_threads();
+        let mut words =
+            Vec::try_with_capacity(num_threads).expect("Not enough memory to initialize system");
+        for _i in 0..num_threads {
+            words.push(WakeWord(AtomicU8::new(AWAKE)));
+        }
+
+        words
+    };
+    static ref HAS_MWAIT: bool = CpuId::new()
+        .get_feature_info()
+        .map_or(false, |f| f.has_monitor_mwait());
+}
+
+/// Announces that the current core will go to sleep unless it finds work.
+pub(crate) fn enter() {
+    WAKE_WORDS[*crate::environment::CORE_ID]
+        .0
+        .store(SLEEPING, Ordering::SeqCst);
+}
+
+/// The current core found work, it won't go to sleep after all.
+pub(crate) fn exit() {
+    WAKE_WORDS[*crate::environment::CORE_ID]
+        .0
+        .store(AWAKE, Ordering::SeqCst);
+}
+
+/// Sleeps until an interrupt arrives or another core calls [`wake`], then
+/// goes back to the scheduler.
+///
+/// Has to be called with interrupts disabled, after [`enter`].
+pub(crate) fn sleep() -> ! {
+    let word = &WAKE_WORDS[*crate::environment::CORE_ID].0;
+
+    if *HAS_MWAIT {
+        unsafe {
+            asm!("monitor", in("rax") word as *const AtomicU8 as u64, in("ecx") 0u32, in("edx") 0u32);
+            // Somebody might have woken us before we started monitoring
+            if word.load(Ordering::SeqCst) == SLEEPING {
+                // `sti` only takes effect after `mwait` started, so we don't
+                // miss interrupts that are already pending
+                asm!("sti", "mwait", in("eax") 0u32, in("ecx") 0u32);
+            }
+        }
+        // An interrupt takes a different path back to the scheduler, we
+        // only come here if the wake word changed (or spuriously)
+        super::irq::disable();
+    } else if word.load(Ordering::SeqCst) == SLEEPING {
+        // The interrupt (the IPI from `wake` or the timer) takes us back to
+        // the scheduler
+        super::halt()
+    }
+
+    exit();
+    crate::scheduler::schedule()
+}
+
+/// Wakes up core `gtid` if it sleeps (or is about to).
+pub(crate) fn wake(gtid: atopology::GlobalThreadId) {
+    let was_sleeping = WAKE_WORDS[gtid]
+        .0
+        .compare_exchange(SLEEPING, AWAKE, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok();
+
+    // With MWAIT the write above is enough
+    if was_sleeping && !*HAS_MWAIT {
+        trace!("Send wake-up IPI to {}", gtid);
+        let apic_id = atopology::MACHINE_TOPOLOGY.threads[gtid].apic_id();
+        super::tlb::send_work_pending_ipi(apic_id);
+    }
+}
@@ -48,17 +48,19 @@ use x86::segmentation::{
 };
 use x86::{dtables, Ring};
 
-use crate::memory::vspace::MapAction;
-use crate::memory::Frame;
+use crate::error::KError;
+use crate::memory::backends::PhysicalPageProvider;
+use crate::memory::vspace::{MapAction, MappingType};
+use crate::memory::{Frame, MemType};
 use crate::panic::{backtrace, backtrace_from};
-use crate::process::{Executor, ResumeHandle};
+use crate::process::{Executor, Pid, ResumeHandle};
 use crate::{nr, nrproc, ExitReason};
 
 use super::gdt::GdtTable;
 use super::kcb::{get_kcb, per_core_mem, Arch86Kcb};
-use super::memory::{PAddr, VAddr, BASE_PAGE_SIZE, KERNEL_BASE};
+use super::memory::{PAddr, VAddr, BASE_PAGE_SIZE, KERNEL_BASE, LARGE_PAGE_SIZE};
 use super::process::{Ring0Resumer, Ring3Process, Ring3Resumer};
-use super::{debug, gdb, timer};
+use super::{debug, gdb};
 
 // TODO(hunhoffe): probably not the right place for this but transport/shmem isn't always included.
 pub(crate) const SHMEM_VECTOR: u8 = 249;
@@ -330,6 +332,94 @@ unsafe fn unhandled_irq(a: &ExceptionArguments) {
     debug::shutdown(ExitReason::UnhandledInterrupt);
 }
 
+/// Backs the page at `vaddr` with a zeroed frame if it is part of a region the
+/// process reserved with `VSpaceOperation::ReserveMem`.
+pub(super) fn populate_reserved(pid: Pid, vaddr: VAddr) -> Result<(), KError> {
+    if vaddr.as_u64() >= kpi::KERNEL_BASE {
+        return Err(KError::NotMapped);
+    }
+    let (_base, reservation) = nrproc::NrProcess::<Ring3Process>::reservation(pid, vaddr)?;
+
+    crate::memory::KernelAllocator::try_refill_tcache(1, 0, reservation.mem_type)?;
+    let mut frame = {
+        let pcm = per_core_mem();
+        let mut pmanager = match reservation.mem_type {
+            MemType::Mem => pcm.mem_manager(),
+            MemType::PMem => pcm.pmem_manager(),
+        };
+        pmanager.allocate_base_page()?
+    };
+    unsafe { frame.zero() };
+
+    match nrproc::NrProcess::<Ring3Process>::populate(pid, vaddr, frame) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            crate::memory::KernelAllocator::release_frame(frame, reservation.mem_type)?;
+            match e {
+                // Another core faulted on the same page first
+                KError::AlreadyMapped { .. } => Ok(()),
+                e => Err(e),
+            }
+        }
+    }
+}
+
+/// Replaces the copy-on-write frame mapped at `vaddr` with a copy.
+pub(super) fn copy_on_write(pid: Pid, vaddr: VAddr) -> Result<(), KError> {
+    let (base, mapping) = nrproc::NrProcess::<Ring3Process>::mapping(pid, vaddr)?;
+    if !mapping.rights.is_cow() {
+        // Another core copied it first
+        return Ok(());
+    }
+    let mem_type = match mapping.typ {
+        MappingType::Heap(mem_type) => mem_type,
+        _ => return Err(KError::NotSupported),
+    };
+
+    let large = mapping.frame.size == LARGE_PAGE_SIZE;
+    let (base_pages, large_pages) = if large { (0, 1) } else { (1, 0) };
+    crate::memory::KernelAllocator::try_refill_tcache(base_pages, large_pages, mem_type)?;
+    let mut frame = {
+        let pcm = per_core_mem();
+        let mut pmanager = match mem_type {
+            MemType::Mem => pcm.mem_manager(),
+            MemType::PMem => pcm.pmem_manager(),
+        };
+        if large {
+            pmanager.allocate_large_page()?
+        } else {
+            pmanager.allocate_base_page()?
+        }
+    };
+    unsafe { frame.copy_from(&mapping.frame) };
+
+    match nrproc::NrProcess::<Ring3Process>::copy_on_write(pid, base, mapping.frame.base, frame) {
+        Ok((handle, released)) => {
+            // Other cores must not keep using the old frame
+            super::tlb::shootdown(handle);
+            if let Some(released) = released {
+                crate::memory::KernelAllocator::release_frame(released, mem_type)?;
+            }
+            Ok(())
+        }
+        Err(e) => {
+            crate::memory::KernelAllocator::release_frame(frame, mem_type)?;
+            match e {
+                KError::AlreadyMapped { .. } => Ok(()),
+                e => Err(e),
+            }
+        }
+    }
+}
+
+/// Returns true if the access that caused the user-space page-fault `err` is
+/// allowed by `rights`.
+fn is_permitted(err: PageFaultError, rights: MapAction) -> bool {
+    rights.is_userspace()
+        && (!err.contains(PageFaultError::WR) || rights.is_writable())
+        && (!err.contains(PageFaultError::ID) || rights.is_executable())
+}
+
 /// Handler for unexpected page-faults.
 ///
 /// TODO: Right now we terminate kernel.
@@ -347,7 +437,7 @@ unsafe fn pf_handler(a: &ExceptionArguments) {
             .expect("A pid must be set in this if branch (US bit set in page-fault error)");
 
         match nrproc::NrProcess::<Ring3Process>::resolve(pid, faulting_address_va) {
-            Ok((paddr, rights)) => {
+            Ok((paddr, rights)) if is_permitted(err, rights) => {
                 // TODO(harden): We probably want to warn/abort if we get many
                 // "spurious" pfaults for the same addr in quick succession: one
                 // bug I encountered is when I accidentially made executor
@@ -356,14 +446,45 @@ unsafe fn pf_handler(a: &ExceptionArguments) {
                 // here until the other replica (by chance) advances and this
                 // code doesn't really do anything...
                 trace!(
-                    "Spurious page-fault, after resolve page-table is up to date {} {} -> {:#x} {:#b} on {}",
+                    "Spurious page-fault, after resolve page-table is up to date {} {} -> {:#x} {} on {}",
                     pid, faulting_address_va, paddr, rights, *crate::environment::CORE_ID
                 );
                 let r = kcb_iret_handle(kcb);
                 r.resume()
             }
+            Ok((_paddr, rights))
+                if rights.is_cow() && rights.is_userspace() && err.contains(PageFaultError::WR) =>
+            {
+                match copy_on_write(pid, faulting_address_va) {
+                    Ok(()) => {
+                        let r = kcb_iret_handle(kcb);
+                        r.resume()
+                    }
+                    Err(e) => {
+                        warn!("Failed to copy page {}: {:?}", faulting_address_va, e);
+                    }
+                }
+            }
+            Ok((_paddr, rights)) => {
+                warn!(
+                    "Access to {} violates rights of the mapping {:?}",
+                    faulting_address_va, rights
+                );
+            }
             Err(_) => {
-                // unresolved page-fault, proceed with abort below
+                // Not mapped yet, maybe the page just needs to be backed
+                match populate_reserved(pid, faulting_address_va) {
+                    Ok(()) => {
+                        let r = kcb_iret_handle(kcb);
+                        r.resume()
+                    }
+                    Err(KError::NotMapped) => {
+                        // unresolved page-fault, proceed with abort below
+                    }
+                    Err(e) => {
+                        warn!("Failed to back page {}: {:?}", faulting_address_va, e);
+                    }
+                }
             }
         }
     }
@@ -499,9 +620,10 @@ unsafe fn bkp_handler(a: &ExceptionArguments) {
 
 /// Handler for the timer exception.
 ///
-/// We currently use it to periodically make sure that a replica
-/// makes forward progress to avoid liveness issues.
-unsafe fn timer_handler(_a: &ExceptionArguments) {
+/// We use it to periodically make sure that a replica makes forward progress
+/// to avoid liveness issues, and to preempt executors once their time slice
+/// is up.
+unsafe fn timer_handler(a: &ExceptionArguments) {
     #[cfg(feature = "test-timer")]
     {
         // Don't change this print stmt. without changing
@@ -513,9 +635,11 @@ unsafe fn timer_handler(_a: &ExceptionArguments) {
     // Periodically advance replica state, then resume immediately
     nr::KernelNode::synchronize().expect("Synchronized failed?");
     let kcb = get_kcb();
-    for pid in 0..crate::process::MAX_PROCESSES {
-        nrproc::NrProcess::<Ring3Process>::synchronize(pid);
-    }
+    nrproc::advance_all();
+
+    // Give back cached memory if another core ran out of it
+    crate::memory::reclaim::poll();
+    crate::memory::stats::publish();
 
     // If this is a rackscale client, check for work from the controller
     #[cfg(feature = "rackscale")]
@@ -528,25 +652,18 @@ unsafe fn timer_handler(_a: &ExceptionArguments) {
     }
 
     if super::process::has_executor() {
-        // TODO(process-mgmt): Ensures that we still periodically
-        // check and advance replicas even on cores that have a core.
-        // Only a single idle core per replica should probably do that,
-        // so if cores go properly back to idling when finished execution,
-        // this is no longer necessary...
-        let is_replica_main_thread = {
-            let thread = atopology::MACHINE_TOPOLOGY.current_thread();
-            thread.node().is_none()
-                || thread
-                    .node()
-                    .unwrap()
-                    .threads()
-                    .next()
-                    .map(|t| t.id == thread.id)
-                    .unwrap_or(false)
-        };
-        if is_replica_main_thread {
-            timer::set(timer::DEFAULT_TIMER_DEADLINE);
+        crate::scheduler::admit_processes();
+
+        // Only user-space state can be saved for later, the kernel code we
+        // interrupted has to finish first
+        let from_user = a.cs & 0x3 == 0x3;
+        if from_user {
+            if let Some(r) = super::process::preempt() {
+                crate::scheduler::arm_timer();
+                r.resume()
+            }
         }
+        crate::scheduler::arm_timer();
 
         // Return immediately
         let r = kcb_iret_handle(kcb);
@@ -787,15 +904,9 @@ pub extern "C" fn handle_generic_exception(a: ExceptionArguments) -> ! {
             if super::process::has_executor() {
                 kcb_iret_handle(kcb).resume()
             } else {
-                loop {
-                    super::tlb::eager_advance_fs_replica();
-
-                    // Reset a timer and sleep for some time
-                    timer::set(timer::DEFAULT_TIMER_DEADLINE);
-                    for _i in 0..1200 {
-                        core::hint::spin_loop();
-                    }
-                }
+                // Catch up with the log that poked us, then go back to sleep
+                super::tlb::eager_advance_fs_replica();
+                crate::scheduler::schedule()
             }
         } else if a.vector == apic::TSC_TIMER_VECTOR.into() {
             timer_handler(&a);
@@ -28,7 +28,7 @@ pub use bootloader_shared::*;
 use cnr::Replica as MlnrReplica;
 use fallible_collections::TryClone;
 use klogger::sprint;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use node_replication::{Log, Replica};
 use x86::{controlregs, cpuid};
 
@@ -48,6 +48,7 @@ pub mod coreboot;
 pub mod debug;
 mod gdb;
 pub mod gdt;
+pub mod idle;
 pub mod irq;
 mod isr;
 pub mod kcb;
@@ -349,7 +350,20 @@ fn _start(argc: isize, _argv: *const *const u8) -> isize {
     dyn_mem.set_global_mem(&global_memory_static);
 
     // Initializes persistent memory
-    let annotated_regions = memory::init_persistent_memory();
+    let mut annotated_regions = memory::init_persistent_memory();
+
+    // The file-system keeps its data at the start of the first PMem region (if
+    // asked to)
+    if crate::CMDLINE
+        .get()
+        .map_or(false, |c| c.fs_storage == crate::cmdline::FsStorage::PMem)
+    {
+        if annotated_regions.is_empty() {
+            warn!("No PMem available, file-system won't be persistent");
+        } else {
+            annotated_regions[0] = crate::fs::pmem::reserve(annotated_regions[0]);
+        }
+    }
     let global_memory = if annotated_regions.len() > 0 {
         unsafe {
             // Safety:
@@ -379,8 +393,9 @@ fn _start(argc: isize, _argv: *const *const u8) -> isize {
 
     // Create the global operation log and first replica and store it (needs
     // TLS)
-    let log: Arc<Log<Op>> = Arc::try_new(Log::<Op>::new(LARGE_PAGE_SIZE))
-        .expect("Not enough memory to initialize system");
+    let mut log = Log::<Op>::new(LARGE_PAGE_SIZE);
+    log.update_closure(|replicas, _idx| crate::nr::wake_lagging_replicas(replicas));
+    let log: Arc<Log<Op>> = Arc::try_new(log).expect("Not enough memory to initialize system");
     let bsp_replica = Replica::<KernelNode>::new(&log);
     let local_ridx = bsp_replica.register().unwrap();
     crate::nr::NR_REPLICA.call_once(|| (bsp_replica.clone(), local_ridx));
@@ -3,7 +3,6 @@
 
 use alloc::boxed::Box;
 use alloc::collections::TryReserveError;
-use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::asm;
 use core::cell::RefCell;
@@ -15,33 +14,32 @@ use arrayvec::ArrayVec;
 use fallible_collections::try_vec;
 use fallible_collections::FallibleVec;
 use kpi::arch::SaveArea;
-use kpi::process::{FrameId, ELF_OFFSET, EXECUTOR_OFFSET};
+use kpi::process::{FrameId, ShmId, ELF_OFFSET, EXECUTOR_OFFSET};
 use lazy_static::lazy_static;
 use log::{debug, info, trace, warn};
-use node_replication::{Dispatch, Log, Replica};
 use x86::bits64::paging::*;
 use x86::bits64::rflags;
 use x86::{controlregs, Ring};
 
-use crate::arch::kcb::per_core_mem;
 use crate::error::{KError, KResult};
 use crate::fs::{fd::FileDescriptorEntry, MAX_FILES_PER_PROCESS};
 use crate::memory::detmem::DA;
-use crate::memory::vspace::{AddressSpace, MapAction};
+use crate::memory::vspace::{AddressSpace, MapAction, MappingType};
 use crate::memory::{paddr_to_kernel_vaddr, Frame, KernelAllocator, MemType, PAddr, VAddr};
-use crate::nrproc::NrProcess;
+use crate::nrproc::{NrProcess, ProcessTable};
 use crate::process::{
-    Eid, Executor, FrameManagement, Pid, Process, ProcessFrames, ResumeHandle, MAX_PROCESSES,
-    MAX_WRITEABLE_SECTIONS_PER_PROCESS,
+    Cmdline, Eid, Executor, FrameManagement, Pid, Process, ProcessFrames, ResumeHandle,
+    MAX_FRAMES_PER_PROCESS, MAX_WRITEABLE_SECTIONS_PER_PROCESS,
 };
 use crate::round_up;
+use crate::scheduler::runqueue::{RunQueue, Runnable};
 
 use super::gdt::GdtTable;
 use super::vspace::*;
 use super::Module;
 use super::MAX_NUMA_NODES;
 
-const INVALID_EXECUTOR_START: VAddr = VAddr(0xdeadffff);
+pub(crate) const INVALID_EXECUTOR_START: VAddr = VAddr(0xdeadffff);
 
 /// The process model of the current architecture.
 pub(crate) type ArchProcess = Ring3Process;
@@ -50,11 +48,164 @@ pub(crate) type ArchProcess = Ring3Process;
 #[thread_local]
 pub(crate) static CURRENT_EXECUTOR: RefCell<Option<Box<Ring3Executor>>> = RefCell::new(None);
 
-/// Swaps out current process with a new process. Returns the old process.
-pub(crate) fn swap_current_executor(
-    new_executor: Box<Ring3Executor>,
-) -> Option<Box<Ring3Executor>> {
-    CURRENT_EXECUTOR.borrow_mut().replace(new_executor)
+/// Executors that wait for their turn on the core (the current one is not
+/// part of it).
+#[thread_local]
+pub(crate) static RUN_QUEUE: RefCell<RunQueue<Box<Ring3Executor>>> = RefCell::new(RunQueue::new());
+
+/// Adds an executor that didn't run yet to the run-queue of the core.
+pub(crate) fn queue_executor(executor: Box<Ring3Executor>) -> Result<(), KError> {
+    let pid = executor.pid;
+    RUN_QUEUE.borrow_mut().push(pid, executor, false)
+}
+
+/// Returns true if an executor of `pid` runs on (or waits for) the core.
+pub(crate) fn runs_process(pid: Pid) -> bool {
+    CURRENT_EXECUTOR
+        .borrow()
+        .as_ref()
+        .map_or(false, |e| e.pid == pid)
+        || RUN_QUEUE.borrow().contains(pid)
+}
+
+/// Returns true if other executors wait for their turn on the core.
+pub(crate) fn has_waiting_executors() -> bool {
+    !RUN_QUEUE.borrow().is_empty()
+}
+
+/// Makes the next executor in the run-queue the current one (unless there
+/// is a current one already).
+///
+/// Returns the handle to run it.
+pub(crate) fn dispatch_next() -> Option<Ring3Resumer> {
+    let mut current = CURRENT_EXECUTOR.borrow_mut();
+    if current.is_some() {
+        return None;
+    }
+
+    let next = RUN_QUEUE.borrow_mut().pop()?;
+    let resumer = runnable_resumer(&next);
+    *current = Some(next.executor);
+    Some(resumer)
+}
+
+/// Puts the current executor at the end of the run-queue and makes the next
+/// one current.
+///
+/// Has to be called from an interrupt that came from user-space, the state
+/// of the preempted executor is taken from the save area of the core.
+/// Returns `None` if nobody else waits for the core.
+pub(crate) fn preempt() -> Option<Ring3Resumer> {
+    let mut queue = RUN_QUEUE.borrow_mut();
+    if queue.is_empty() {
+        return None;
+    }
+    let mut current = CURRENT_EXECUTOR.borrow_mut();
+    let mut executor = current.take()?;
+    if let Some(save_area) = super::kcb::get_kcb().save_area.as_ref() {
+        executor.save_area = **save_area;
+    }
+
+    let preempted = Runnable {
+        pid: executor.pid,
+        executor,
+        started: true,
+    };
+    let next = queue.rotate(preempted);
+    let resumer = runnable_resumer(&next);
+    *current = Some(next.executor);
+    Some(resumer)
+}
+
+/// Puts the current executor at the end of the run-queue so it repeats the
+/// system call it's in once it's its turn again.
+///
+/// Has to be called from a system call, the state of the executor is taken
+/// from the save area of the core. Afterwards, the core runs no executor:
+/// call `scheduler::schedule` (or sleep) next. Returns true if nobody else
+/// waits for the core.
+pub(crate) fn retry_syscall_later() -> Result<bool, KError> {
+    requeue_current(|save_area| {
+        // Go back to the `syscall` instruction (two bytes)
+        save_area.rip -= 2;
+    })
+}
+
+/// Puts the current executor at the end of the run-queue, the system call
+/// it's in returns (successfully, without results) once it's its turn again.
+///
+/// Same requirements as `retry_syscall_later`.
+pub(crate) fn return_from_syscall_later() -> Result<bool, KError> {
+    requeue_current(|save_area| save_area.rax = 0)
+}
+
+/// Moves the current executor (with its state from the save area, adjusted
+/// by `resume_with`) to the end of the run-queue.
+fn requeue_current<F: FnOnce(&mut SaveArea)>(resume_with: F) -> Result<bool, KError> {
+    let mut queue = RUN_QUEUE.borrow_mut();
+    // Make room first, so pushing can't fail once we took the executor
+    queue.reserve()?;
+    let mut executor = CURRENT_EXECUTOR
+        .borrow_mut()
+        .take()
+        .ok_or(KError::ProcessNotSet)?;
+    if let Some(save_area) = super::kcb::get_kcb().save_area.as_ref() {
+        executor.save_area = **save_area;
+    }
+    // We resume with `iret` which takes the flags from the save area rather
+    // than %r11
+    executor.save_area.rflags = executor.save_area.r11;
+    resume_with(&mut executor.save_area);
+
+    let alone = queue.is_empty();
+    queue.push(executor.pid, executor, true)?;
+
+    // The process may get torn down before it's its turn again
+    let kernel_pml4 = super::vspace::INITIAL_VSPACE.lock().pml4_address();
+    unsafe { controlregs::cr3_write(kernel_pml4.into()) };
+    Ok(alone)
+}
+
+/// Backs the page at `vaddr` in a reserved region of `pid` (like a page-fault
+/// of the process would).
+pub(crate) fn populate_reserved(pid: Pid, vaddr: VAddr) -> KResult<()> {
+    super::irq::populate_reserved(pid, vaddr)
+}
+
+/// Replaces the copy-on-write frame at `vaddr` of `pid` with a copy (like a
+/// write page-fault of the process would).
+pub(crate) fn copy_on_write(pid: Pid, vaddr: VAddr) -> KResult<()> {
+    super::irq::copy_on_write(pid, vaddr)
+}
+
+/// Starts or resumes (if it got preempted before) an executor.
+fn runnable_resumer(runnable: &Runnable<Box<Ring3Executor>>) -> Ring3Resumer {
+    if runnable.started {
+        // The executor got interrupted rather than doing a syscall, so we
+        // need `iret` to restore all of its registers (`sysret` clobbers
+        // rcx and r11).
+        runnable.executor.maybe_switch_vspace();
+        Ring3Resumer::new_iret(&runnable.executor.save_area as *const kpi::arch::SaveArea)
+    } else {
+        runnable.executor.start()
+    }
+}
+
+/// Removes the executors of process `pid` from the current core (the current
+/// one and the ones in the run-queue) and switches back to the kernel address
+/// space if the process was running.
+///
+/// This is necessary before the process' page-tables can be torn down.
+pub(crate) fn release_current_executor(pid: Pid) -> Option<Box<Ring3Executor>> {
+    RUN_QUEUE.borrow_mut().remove(pid);
+    let mut current = CURRENT_EXECUTOR.borrow_mut();
+    if current.as_ref().map_or(false, |e| e.pid == pid) {
+        let kernel_pml4 = super::vspace::INITIAL_VSPACE.lock().pml4_address();
+        unsafe { controlregs::cr3_write(kernel_pml4.into()) };
+        current.take()
+    } else {
+        None
+    }
 }
 
 pub(crate) fn has_executor() -> bool {
@@ -70,40 +221,9 @@ pub(crate) fn current_pid() -> KResult<Pid> {
 }
 
 lazy_static! {
-    pub(crate) static ref PROCESS_TABLE: ArrayVec<ArrayVec<Arc<Replica<'static, NrProcess<Ring3Process>>>, MAX_PROCESSES>, MAX_NUMA_NODES> = {
-        // Want at least one replica...
-        let numa_nodes = core::cmp::max(1, atopology::MACHINE_TOPOLOGY.num_nodes());
-
-        let mut numa_cache = ArrayVec::new();
-        for _n in 0..numa_nodes {
-            let process_replicas = ArrayVec::new();
-            debug_assert!(!numa_cache.is_full());
-            numa_cache.push(process_replicas)
-        }
-
-        for pid in 0..MAX_PROCESSES {
-                let log = Arc::try_new(Log::<<NrProcess<Ring3Process> as Dispatch>::WriteOperation>::new(
-                    LARGE_PAGE_SIZE,
-                )).expect("Can't initialize processes, out of memory.");
-
-            let da = DA::new().expect("Can't initialize process deterministic memory allocator");
-            for node in 0..numa_nodes {
-                let pcm = per_core_mem();
-                pcm.set_mem_affinity(node as atopology::NodeId).expect("Can't change affinity");
-                debug_assert!(!numa_cache[node].is_full());
-
-                let p = Box::try_new(Ring3Process::new(pid, da.clone()).expect("Can't create process during init")).expect("Not enough memory to initialize processes");
-                let nrp = NrProcess::new(p, da.clone());
-
-                numa_cache[node].push(Replica::<NrProcess<Ring3Process>>::with_data(&log, nrp));
-
-                debug_assert_eq!(*crate::environment::NODE_ID, 0, "Expect initialization to happen on node 0.");
-                pcm.set_mem_affinity(0 as atopology::NodeId).expect("Can't change affinity");
-            }
-        }
-
-        numa_cache
-    };
+    pub(crate) static ref PROCESS_TABLE: ProcessTable<Ring3Process> =
+        ProcessTable::new(crate::process::max_processes())
+            .expect("Not enough memory to initialize the process table");
 }
 
 pub(crate) struct ArchProcessManagement;
@@ -111,12 +231,7 @@ pub(crate) struct ArchProcessManagement;
 impl crate::nrproc::ProcessManager for ArchProcessManagement {
     type Process = Ring3Process;
 
-    fn process_table(
-        &self,
-    ) -> &'static ArrayVec<
-        ArrayVec<Arc<Replica<'static, NrProcess<Self::Process>>>, MAX_PROCESSES>,
-        MAX_NUMA_NODES,
-    > {
+    fn process_table(&self) -> &'static ProcessTable<Self::Process> {
         &*super::process::PROCESS_TABLE
     }
 }
@@ -849,7 +964,9 @@ pub(crate) struct Ring3Process {
     /// Offset where ELF is located.
     pub offset: VAddr,
     /// Process info struct (can be retrieved by user-space)
-    pub pinfo: kpi::process::ProcessInfo,
+    pub pinfo: kpi::process::ProcessInfo<'static>,
+    /// Arguments the process was spawned with.
+    pub cmdline: Option<Cmdline>,
     /// The entry point of the ELF file (set during elfloading).
     pub entry_point: VAddr,
     /// Executor cache (holds a per-region cache of executors)
@@ -869,35 +986,6 @@ pub(crate) struct Ring3Process {
     pub read_only_offset: VAddr,
 }
 
-impl Ring3Process {
-    fn new(pid: Pid, da: DA) -> Result<Self, KError> {
-        const NONE_EXECUTOR: Option<Vec<Box<Ring3Executor>>> = None;
-        let executor_cache: ArrayVec<Option<Vec<Box<Ring3Executor>>>, MAX_NUMA_NODES> =
-            ArrayVec::from([NONE_EXECUTOR; MAX_NUMA_NODES]);
-
-        const NONE_FD: Option<FileDescriptorEntry> = None;
-        let fds: ArrayVec<Option<FileDescriptorEntry>, MAX_FILES_PER_PROCESS> =
-            ArrayVec::from([NONE_FD; MAX_FILES_PER_PROCESS]);
-
-        let pfm = ProcessFrames::default();
-
-        Ok(Ring3Process {
-            pid: pid,
-            current_eid: 0,
-            offset: VAddr::from(ELF_OFFSET),
-            vspace: VSpace::new(da)?,
-            entry_point: VAddr::from(0usize),
-            executor_cache,
-            executor_offset: VAddr::from(EXECUTOR_OFFSET),
-            fds,
-            pinfo: Default::default(),
-            pfm,
-            writeable_sections: ArrayVec::new(),
-            read_only_offset: VAddr::zero(),
-        })
-    }
-}
-
 impl fmt::Debug for Ring3Process {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Ring3Process {}", self.pid)
@@ -997,11 +1085,17 @@ impl elfloader::ElfLoader for Ring3Process {
                     self.offset + page_base + i * LARGE_PAGE_SIZE,
                     frame
                 );
+                let typ = if flags.is_write() {
+                    MappingType::ElfData
+                } else {
+                    MappingType::ElfText
+                };
                 self.vspace
-                    .map_frame(
+                    .map_frame_as(
                         self.offset + page_base + i * LARGE_PAGE_SIZE,
                         frame,
                         map_action,
+                        typ,
                     )
                     .expect("Can't map ELF region");
             }
@@ -1151,6 +1245,34 @@ impl Process for Ring3Process {
     type E = Ring3Executor;
     type A = VSpace;
 
+    fn new(pid: Pid, da: DA) -> Result<Self, KError> {
+        const NONE_EXECUTOR: Option<Vec<Box<Ring3Executor>>> = None;
+        let executor_cache: ArrayVec<Option<Vec<Box<Ring3Executor>>>, MAX_NUMA_NODES> =
+            ArrayVec::from([NONE_EXECUTOR; MAX_NUMA_NODES]);
+
+        const NONE_FD: Option<FileDescriptorEntry> = None;
+        let fds: ArrayVec<Option<FileDescriptorEntry>, MAX_FILES_PER_PROCESS> =
+            ArrayVec::from([NONE_FD; MAX_FILES_PER_PROCESS]);
+
+        let pfm = ProcessFrames::default();
+
+        Ok(Ring3Process {
+            pid: pid,
+            current_eid: 0,
+            offset: VAddr::from(ELF_OFFSET),
+            vspace: VSpace::new(da)?,
+            entry_point: VAddr::from(0usize),
+            executor_cache,
+            executor_offset: VAddr::from(EXECUTOR_OFFSET),
+            fds,
+            pinfo: Default::default(),
+            cmdline: None,
+            pfm,
+            writeable_sections: ArrayVec::new(),
+            read_only_offset: VAddr::zero(),
+        })
+    }
+
     /// Return the process ID.
     fn pid(&self) -> Pid {
         self.pid
@@ -1235,10 +1357,11 @@ impl Process for Ring3Process {
 
         KernelAllocator::try_refill_tcache(20, 0, MemType::Mem).expect("Refill didn't work");
         self.vspace
-            .map_frame(
+            .map_frame_as(
                 self.executor_offset,
                 memory,
                 MapAction::user() | MapAction::write(),
+                MappingType::Executor,
             )
             .expect("Can't map user-space executor memory.");
         info!(
@@ -1329,9 +1452,75 @@ impl Process for Ring3Process {
         self.fds[index].as_ref().unwrap()
     }
 
-    fn pinfo(&self) -> &kpi::process::ProcessInfo {
+    fn pinfo(&self) -> &kpi::process::ProcessInfo<'static> {
         &self.pinfo
     }
+
+    fn cmdline(&self) -> Option<&Cmdline> {
+        self.cmdline.as_ref()
+    }
+
+    fn set_cmdline(&mut self, cmdline: Cmdline) {
+        self.cmdline = Some(cmdline);
+    }
+
+    fn teardown(&mut self) -> Result<Vec<(Frame, MemType)>, KError> {
+        // This runs on every replica: allocate everything we need before we
+        // destroy anything, so we either fail or succeed everywhere
+        let mut shared =
+            Vec::try_with_capacity(self.vspace.mappings.len() + MAX_FRAMES_PER_PROCESS)?;
+        let da = self
+            .vspace
+            .page_table
+            .da
+            .clone()
+            .expect("Process page-tables are created with a DA");
+        let fresh = Ring3Process::new(self.pid, da)?;
+
+        for (base, mapping) in self.vspace.mappings.iter() {
+            match mapping.typ {
+                // Every replica has its own copy of the read-only ELF sections
+                MappingType::ElfText => {
+                    if let Err(e) = KernelAllocator::release_frame(mapping.frame, MemType::Mem) {
+                        warn!(
+                            "Leaking ELF frame {:?} at {:#x}: {:?}",
+                            mapping.frame, base, e
+                        );
+                    }
+                }
+                MappingType::ElfData | MappingType::Executor => {
+                    shared.push((mapping.frame, MemType::Mem));
+                }
+                // Shared frames go with the last mapping that refers to them
+                MappingType::Heap(mem_type) if mapping.rights.is_cow() => {
+                    match self.pfm.remove_cow_mapping(mapping.frame.base) {
+                        Ok(Some(frame)) => shared.push((frame, mem_type)),
+                        Ok(None) => {}
+                        Err(e) => warn!(
+                            "Leaking COW frame {:?} at {:#x}: {:?}",
+                            mapping.frame, base, e
+                        ),
+                    }
+                }
+                // Aliased mappings refer to frames in `pfm` (released below)
+                MappingType::Heap(mem_type) if !mapping.rights.is_aliasable() => {
+                    shared.push((mapping.frame, mem_type));
+                }
+                // The file-system unpins file pages when the process is removed
+                MappingType::Heap(_) | MappingType::Device | MappingType::File { .. } => {}
+            }
+        }
+
+        for frame in self.pfm.release_all() {
+            shared.push((frame, MemType::Mem));
+        }
+
+        // Dropping the old vspace frees the page-tables, the executors and
+        // file-descriptors go away with the rest of the struct
+        *self = fresh;
+
+        Ok(shared)
+    }
 }
 
 impl FrameManagement for Ring3Process {
@@ -1339,6 +1528,10 @@ impl FrameManagement for Ring3Process {
         self.pfm.add_frame(frame)
     }
 
+    fn add_shared_frame(&mut self, frame: Frame, shm: ShmId) -> Result<FrameId, KError> {
+        self.pfm.add_shared_frame(frame, shm)
+    }
+
     fn get_frame(&mut self, frame_id: FrameId) -> Result<(Frame, usize), KError> {
         self.pfm.get_frame(frame_id)
     }
@@ -1351,9 +1544,17 @@ impl FrameManagement for Ring3Process {
         self.pfm.remove_frame_mapping(paddr, _vaddr)
     }
 
-    fn deallocate_frame(&mut self, fid: FrameId) -> Result<Frame, KError> {
+    fn deallocate_frame(&mut self, fid: FrameId) -> Result<(Frame, Option<ShmId>), KError> {
         self.pfm.deallocate_frame(fid)
     }
+
+    fn add_cow_mapping(&mut self, frame: Frame) -> Result<(), KError> {
+        self.pfm.add_cow_mapping(frame)
+    }
+
+    fn remove_cow_mapping(&mut self, paddr: PAddr) -> Result<Option<Frame>, KError> {
+        self.pfm.remove_cow_mapping(paddr)
+    }
 }
 
 /// Spawns a new process
@@ -87,6 +87,18 @@ lazy_static! {
     };
 }
 
+// Keep track of which (local) pid an allocated hwthread belongs to. Index corresponds to gtid of hwthread
+lazy_static! {
+    pub(crate) static ref HWTHREADS_OWNER: Arc<Mutex<Vec<Option<Pid>>>> = {
+        let mut hwthreads_owner = Vec::try_with_capacity(get_num_clients() as usize * 30)
+            .expect("Failed to create vector for rack cpu thread owners");
+        for i in 0..(get_num_clients() as usize * 30) {
+            hwthreads_owner.push(None);
+        }
+        Arc::new(Mutex::new(hwthreads_owner))
+    };
+}
+
 // Keep track of unfulfilled core assignments
 lazy_static! {
     pub(crate) static ref UNFULFILLED_CORE_ASSIGNMENTS: Arc<Mutex<Vec<Box<VecDeque<RequestCoreReq>>>>> = {
@@ -200,12 +212,30 @@ fn register_rpcs(server: &mut Box<dyn RPCServer>) {
     server
         .register(KernelRpc::Open as RPCType, &OPEN_HANDLER)
         .unwrap();
+    server
+        .register(KernelRpc::ReadDir as RPCType, &READDIR_HANDLER)
+        .unwrap();
     server
         .register(KernelRpc::FileRename as RPCType, &RENAME_HANDLER)
         .unwrap();
     server
         .register(KernelRpc::Write as RPCType, &WRITE_HANDLER)
         .unwrap();
+    server
+        .register(KernelRpc::Truncate as RPCType, &TRUNCATE_HANDLER)
+        .unwrap();
+    server
+        .register(KernelRpc::Seek as RPCType, &SEEK_HANDLER)
+        .unwrap();
+    server
+        .register(KernelRpc::FStat as RPCType, &FSTAT_HANDLER)
+        .unwrap();
+    server
+        .register(KernelRpc::Pipe as RPCType, &PIPE_HANDLER)
+        .unwrap();
+    server
+        .register(KernelRpc::InheritFds as RPCType, &INHERIT_FDS_HANDLER)
+        .unwrap();
     server
         .register(KernelRpc::WriteAt as RPCType, &WRITE_HANDLER)
         .unwrap();
@@ -233,6 +263,9 @@ fn register_rpcs(server: &mut Box<dyn RPCServer>) {
     server
         .register(KernelRpc::RequestCore as RPCType, &REQUEST_CORE_HANDLER)
         .unwrap();
+    server
+        .register(KernelRpc::ReleaseCore as RPCType, &RELEASE_CORE_HANDLER)
+        .unwrap();
 
     server
         .register(
@@ -275,6 +308,10 @@ pub(crate) fn register_pid(client_id: ClientId, remote_pid: usize) -> Result<usi
         .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
             let response = replica.execute_mut(nr::Op::AllocatePid, *token)?;
             if let nr::NodeResult::PidAllocated(local_pid) = response {
+                if let Err(e) = crate::arch::process::PROCESS_TABLE.create(local_pid) {
+                    nr::KernelNode::release_pid(local_pid)?;
+                    return Err(e);
+                }
                 // TODO: some way to unwind if fails??
                 match cnrfs::MlnrKernelNode::add_process(local_pid) {
                     Ok(_) => {
@@ -18,7 +18,11 @@ impl From<KError> for RPCError {
             KError::PermissionError => RPCError::PermissionError,
             KError::AlreadyPresent => RPCError::AlreadyPresent,
             KError::DirectoryError => RPCError::DirectoryError,
+            KError::NotADirectory => RPCError::NotADirectory,
+            KError::DirectoryNotEmpty => RPCError::DirectoryNotEmpty,
             KError::OpenFileLimit => RPCError::OpenFileLimit,
+            KError::WouldBlock => RPCError::WouldBlock,
+            KError::BrokenPipe => RPCError::BrokenPipe,
             KError::FileDescForPidAlreadyAdded => RPCError::FileDescForPidAlreadyAdded,
             KError::NoFileDescForPid => RPCError::NoFileDescForPid,
 
@@ -47,7 +51,11 @@ impl From<RPCError> for KError {
             RPCError::PermissionError => KError::PermissionError,
             RPCError::AlreadyPresent => KError::AlreadyPresent,
             RPCError::DirectoryError => KError::DirectoryError,
+            RPCError::NotADirectory => KError::NotADirectory,
+            RPCError::DirectoryNotEmpty => KError::DirectoryNotEmpty,
             RPCError::OpenFileLimit => KError::OpenFileLimit,
+            RPCError::WouldBlock => KError::WouldBlock,
+            RPCError::BrokenPipe => KError::BrokenPipe,
             RPCError::FileDescForPidAlreadyAdded => KError::FileDescForPidAlreadyAdded,
             RPCError::NoFileDescForPid => KError::NoFileDescForPid,
 
@@ -6,11 +6,16 @@ use rpc::rpc::RPCType;
 
 pub mod close;
 pub mod delete;
+pub mod fstat;
 pub mod getinfo;
 pub mod mkdir;
 pub mod open;
+pub mod pipe;
+pub mod readdir;
 pub mod rename;
 pub mod rw;
+pub mod seek;
+pub mod truncate;
 
 #[derive(Debug, Eq, PartialEq, PartialOrd, Clone, Copy)]
 #[repr(u8)]
@@ -39,8 +44,18 @@ pub(crate) enum FileIO {
     FileRename = 10,
     /// Create a directory.
     MkDir = 11,
+    /// List the entries of a directory.
+    ReadDir = 12,
+    /// Shrink or grow a file.
+    Truncate = 13,
+    /// Move the offset of a file descriptor.
+    Seek = 14,
+    /// Get the information related to the file given by a file descriptor.
+    FStat = 15,
+    /// Create a pipe.
+    Pipe = 16,
 
-    Unknown = 12,
+    Unknown = 17,
 }
 
 impl From<RPCType> for FileIO {
@@ -59,6 +74,11 @@ impl From<RPCType> for FileIO {
             9 => FileIO::WriteDirect,
             10 => FileIO::FileRename,
             11 => FileIO::MkDir,
+            12 => FileIO::ReadDir,
+            13 => FileIO::Truncate,
+            14 => FileIO::Seek,
+            15 => FileIO::FStat,
+            16 => FileIO::Pipe,
             _ => FileIO::Unknown,
         }
     }
@@ -0,0 +1,133 @@
+// Copyright © 2021 University of Colorado. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use abomonation::{decode, encode, unsafe_abomonate, Abomonation};
+use core2::io::Result as IOResult;
+use core2::io::Write;
+use log::{debug, warn};
+
+use rpc::rpc::*;
+use rpc::RPCClient;
+
+use crate::fs::cnrfs;
+
+use super::super::kernelrpc::*;
+use crate::arch::rackscale::controller::get_local_pid;
+
+#[derive(Debug)]
+pub(crate) struct InheritFdsReq {
+    pub child: u64,
+}
+unsafe_abomonate!(InheritFdsReq: child);
+
+pub(crate) fn rpc_pipe(rpc_client: &mut dyn RPCClient, pid: usize) -> Result<(u64, u64), RPCError> {
+    // Setup result
+    let mut res_data = [0u8; core::mem::size_of::<KernelRpcRes>()];
+
+    // Call Pipe() RPC
+    rpc_client
+        .call(
+            pid,
+            KernelRpc::Pipe as RPCType,
+            &[&[]],
+            &mut [&mut res_data],
+        )
+        .unwrap();
+
+    // Decode and return result
+    if let Some((res, remaining)) = unsafe { decode::<KernelRpcRes>(&mut res_data) } {
+        if remaining.len() > 0 {
+            return Err(RPCError::ExtraData);
+        }
+        debug!("Pipe() {:?}", res);
+        return res.ret;
+    } else {
+        return Err(RPCError::MalformedResponse);
+    }
+}
+
+// RPC Handler function for pipe() RPCs in the controller
+pub(crate) fn handle_pipe(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    // Lookup local pid
+    let local_pid = { get_local_pid(hdr.client_id, hdr.pid) };
+    if local_pid.is_err() {
+        return construct_error_ret(hdr, payload, RPCError::NoFileDescForPid);
+    }
+    let local_pid = local_pid.unwrap();
+
+    // Call pipe and return the two file descriptors
+    let res = KernelRpcRes {
+        ret: convert_return(cnrfs::MlnrKernelNode::pipe(local_pid)),
+    };
+    construct_ret(hdr, payload, res)
+}
+
+/// Copies the file descriptors of `pid` to the (freshly spawned) process
+/// `child`, so the child can use the pipes of its parent.
+pub(crate) fn rpc_inherit_fds(
+    rpc_client: &mut dyn RPCClient,
+    pid: usize,
+    child: usize,
+) -> Result<(u64, u64), RPCError> {
+    // Setup request data
+    let req = InheritFdsReq {
+        child: child as u64,
+    };
+    let mut req_data = [0u8; core::mem::size_of::<InheritFdsReq>()];
+    unsafe { encode(&req, &mut (&mut req_data).as_mut()) }.unwrap();
+
+    // Setup result
+    let mut res_data = [0u8; core::mem::size_of::<KernelRpcRes>()];
+
+    // Call InheritFds() RPC
+    rpc_client
+        .call(
+            pid,
+            KernelRpc::InheritFds as RPCType,
+            &[&req_data],
+            &mut [&mut res_data],
+        )
+        .unwrap();
+
+    // Decode and return result
+    if let Some((res, remaining)) = unsafe { decode::<KernelRpcRes>(&mut res_data) } {
+        if remaining.len() > 0 {
+            return Err(RPCError::ExtraData);
+        }
+        debug!("InheritFds() {:?}", res);
+        return res.ret;
+    } else {
+        return Err(RPCError::MalformedResponse);
+    }
+}
+
+// RPC Handler function for inherit_fds() RPCs in the controller
+pub(crate) fn handle_inherit_fds(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    // Lookup local pid
+    let local_pid = { get_local_pid(hdr.client_id, hdr.pid) };
+    if local_pid.is_err() {
+        return construct_error_ret(hdr, payload, RPCError::NoFileDescForPid);
+    }
+    let local_pid = local_pid.unwrap();
+
+    // Decode request
+    if let Some((req, _)) = unsafe { decode::<InheritFdsReq>(payload) } {
+        debug!(
+            "InheritFds(child={:?}), local_pid={:?}",
+            req.child, local_pid
+        );
+
+        // The child didn't talk to us yet, so this registers it
+        let ret = get_local_pid(hdr.client_id, req.child as usize)
+            .and_then(|local_child| cnrfs::MlnrKernelNode::inherit_fds(local_pid, local_child));
+        let res = KernelRpcRes {
+            ret: convert_return(ret),
+        };
+        construct_ret(hdr, payload, res)
+
+    // Report error if failed to decode request
+    } else {
+        warn!("Invalid payload for request: {:?}", hdr);
+        construct_error_ret(hdr, payload, RPCError::MalformedRequest)
+    }
+}
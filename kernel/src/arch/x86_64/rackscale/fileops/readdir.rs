@@ -0,0 +1,110 @@
+// Copyright © 2021 University of Colorado. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use alloc::string::String;
+use core::fmt::Debug;
+
+use abomonation::{decode, encode, unsafe_abomonate, Abomonation};
+use core2::io::Result as IOResult;
+use core2::io::Write;
+use log::{debug, warn};
+
+use rpc::rpc::*;
+use rpc::RPCClient;
+
+use super::super::kernelrpc::*;
+use super::FileIO;
+use crate::arch::rackscale::controller::get_local_pid;
+use crate::fallible_string::TryString;
+use crate::fs::cnrfs;
+
+#[derive(Debug)]
+pub(crate) struct ReadDirReq {
+    pub len: u64,
+}
+unsafe_abomonate!(ReadDirReq: len);
+
+pub(crate) fn rpc_readdir<P: AsRef<[u8]> + Debug>(
+    rpc_client: &mut dyn RPCClient,
+    pid: usize,
+    pathname: P,
+    buff_ptr: &mut [u8],
+) -> Result<(u64, u64), RPCError> {
+    debug!("ReadDir({:?}, {:?})", pathname, buff_ptr.len());
+
+    // Construct request data
+    let req = ReadDirReq {
+        len: buff_ptr.len() as u64,
+    };
+    let mut req_data = [0u8; core::mem::size_of::<ReadDirReq>()];
+    unsafe { encode(&req, &mut (&mut req_data).as_mut()) }.unwrap();
+
+    // Create result buffer
+    let mut res_data = [0u8; core::mem::size_of::<KernelRpcRes>()];
+
+    // Call ReadDir() RPC, the serialized entries are written directly into
+    // `buff_ptr` (if they fit)
+    rpc_client
+        .call(
+            pid,
+            KernelRpc::ReadDir as RPCType,
+            &[&req_data, pathname.as_ref()],
+            &mut [&mut res_data, buff_ptr],
+        )
+        .unwrap();
+
+    // Decode result, if successful, return result
+    if let Some((res, remaining)) = unsafe { decode::<KernelRpcRes>(&mut res_data) } {
+        if remaining.len() > 0 {
+            return Err(RPCError::ExtraData);
+        }
+        debug!("ReadDir() {:?}", res);
+        return res.ret;
+    } else {
+        return Err(RPCError::MalformedResponse);
+    }
+}
+
+// RPC Handler function for readdir() RPCs in the controller
+pub(crate) fn handle_readdir(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    // Lookup local pid
+    let local_pid = { get_local_pid(hdr.client_id, hdr.pid) };
+    if local_pid.is_err() {
+        return construct_error_ret(hdr, payload, RPCError::NoFileDescForPid);
+    }
+    let local_pid = local_pid.unwrap();
+
+    // Parse request
+    let len = match unsafe { decode::<ReadDirReq>(payload) } {
+        Some((req, _)) => req.len as usize,
+        None => {
+            warn!("Invalid payload for request: {:?}", hdr);
+            return construct_error_ret(hdr, payload, RPCError::MalformedRequest);
+        }
+    };
+
+    let path =
+        core::str::from_utf8(&payload[core::mem::size_of::<ReadDirReq>()..hdr.msg_len as usize])?;
+    let path_string: String = TryString::try_from(path)?.into();
+
+    // Call readdir function, serialize the entries into the payload buffer
+    // after the result field if they fit into the client's buffer
+    let mut additional_data = 0;
+    let ret = cnrfs::MlnrKernelNode::readdir(local_pid, path_string).and_then(|entries| {
+        let serialized =
+            serde_cbor::to_vec(&entries).map_err(|_e| crate::error::KError::OutOfMemory)?;
+        if serialized.len() <= len {
+            let start = KernelRpcRes_SIZE as usize;
+            let end = start + serialized.len();
+            payload[start..end].copy_from_slice(&serialized);
+            additional_data = serialized.len();
+        }
+        Ok((serialized.len() as u64, entries.len() as u64))
+    });
+
+    // Construct return
+    let res = KernelRpcRes {
+        ret: convert_return(ret),
+    };
+    construct_ret_extra_data(hdr, payload, res, additional_data as u64)
+}
@@ -0,0 +1,107 @@
+// Copyright © 2021 University of Colorado. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use abomonation::{decode, encode, unsafe_abomonate, Abomonation};
+use core2::io::Result as IOResult;
+use core2::io::Write;
+use kpi::io::SeekWhence;
+use log::{debug, warn};
+
+use rpc::rpc::*;
+use rpc::RPCClient;
+
+use crate::fs::cnrfs;
+use crate::fs::fd::FileDescriptor;
+
+use super::super::kernelrpc::*;
+use super::FileIO;
+use crate::arch::rackscale::controller::get_local_pid;
+
+#[derive(Debug)]
+pub(crate) struct SeekReq {
+    pub fd: FileDescriptor,
+    pub offset: i64,
+    pub whence: u64,
+}
+unsafe_abomonate!(SeekReq: fd, offset, whence);
+
+pub(crate) fn rpc_seek(
+    rpc_client: &mut dyn RPCClient,
+    pid: usize,
+    fd: FileDescriptor,
+    offset: i64,
+    whence: SeekWhence,
+) -> Result<(u64, u64), RPCError> {
+    // Setup request data
+    let req = SeekReq {
+        fd: fd,
+        offset: offset,
+        whence: whence.into(),
+    };
+    let mut req_data = [0u8; core::mem::size_of::<SeekReq>()];
+    unsafe { encode(&req, &mut (&mut req_data).as_mut()) }.unwrap();
+
+    // Setup result
+    let mut res_data = [0u8; core::mem::size_of::<KernelRpcRes>()];
+
+    // Call Seek() RPC
+    rpc_client
+        .call(
+            pid,
+            KernelRpc::Seek as RPCType,
+            &[&req_data],
+            &mut [&mut res_data],
+        )
+        .unwrap();
+
+    // Decode and return result
+    if let Some((res, remaining)) = unsafe { decode::<KernelRpcRes>(&mut res_data) } {
+        // Check for extra data
+        if remaining.len() > 0 {
+            return Err(RPCError::ExtraData);
+        }
+
+        debug!("Seek() {:?}", res);
+        return res.ret;
+
+    // Report malformed data if failed to decode result
+    } else {
+        return Err(RPCError::MalformedResponse);
+    }
+}
+
+// RPC Handler function for seek() RPCs in the controller
+pub(crate) fn handle_seek(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    // Lookup local pid
+    let local_pid = { get_local_pid(hdr.client_id, hdr.pid) };
+    if local_pid.is_err() {
+        return construct_error_ret(hdr, payload, RPCError::NoFileDescForPid);
+    }
+    let local_pid = local_pid.unwrap();
+
+    // Decode request
+    if let Some((req, _)) = unsafe { decode::<SeekReq>(payload) } {
+        debug!(
+            "Seek(fd={:?}, offset={:?}, whence={:?}), local_pid={:?}",
+            req.fd, req.offset, req.whence, local_pid
+        );
+
+        let whence = match SeekWhence::new(req.whence) {
+            Some(whence) => whence,
+            None => return construct_error_ret(hdr, payload, RPCError::MalformedRequest),
+        };
+
+        // Call seek and return result
+        let res = KernelRpcRes {
+            ret: convert_return(cnrfs::MlnrKernelNode::file_seek(
+                local_pid, req.fd, req.offset, whence,
+            )),
+        };
+        construct_ret(hdr, payload, res)
+
+    // Report error if failed to decode request
+    } else {
+        warn!("Invalid payload for request: {:?}", hdr);
+        construct_error_ret(hdr, payload, RPCError::MalformedRequest)
+    }
+}
@@ -59,6 +59,27 @@ pub(crate) enum KernelRpc {
 
     /// Get the hardware threads for the rack
     GetHardwareThreads = 17,
+
+    /// List the entries of a directory.
+    ReadDir = 18,
+
+    /// Shrink or grow a file.
+    Truncate = 19,
+
+    /// Move the offset of a file descriptor.
+    Seek = 20,
+
+    /// Get the information related to the file given by a file descriptor.
+    FStat = 21,
+
+    /// Create a pipe.
+    Pipe = 22,
+
+    /// Copy the file descriptors of a process to a spawned child.
+    InheritFds = 23,
+
+    /// Give a core of a process back
+    ReleaseCore = 24,
 }
 
 impl TryFrom<RPCType> for KernelRpc {
@@ -85,6 +106,13 @@ impl TryFrom<RPCType> for KernelRpc {
             15 => Ok(KernelRpc::RequestCore),
             16 => Ok(KernelRpc::RequestWork),
             17 => Ok(KernelRpc::GetHardwareThreads),
+            18 => Ok(KernelRpc::ReadDir),
+            19 => Ok(KernelRpc::Truncate),
+            20 => Ok(KernelRpc::Seek),
+            21 => Ok(KernelRpc::FStat),
+            22 => Ok(KernelRpc::Pipe),
+            23 => Ok(KernelRpc::InheritFds),
+            24 => Ok(KernelRpc::ReleaseCore),
             _ => Err(KError::InvalidRpcType),
         }
     }
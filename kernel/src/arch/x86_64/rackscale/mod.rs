@@ -26,15 +26,22 @@ pub(crate) const CLIENT_REGISTRAR: RegistrationHandler = register_client;
 // Re-export handlers: file operations
 pub(crate) const CLOSE_HANDLER: RPCHandler = fileops::close::handle_close;
 pub(crate) const DELETE_HANDLER: RPCHandler = fileops::delete::handle_delete;
+pub(crate) const FSTAT_HANDLER: RPCHandler = fileops::fstat::handle_fstat;
 pub(crate) const GETINFO_HANDLER: RPCHandler = fileops::getinfo::handle_getinfo;
+pub(crate) const INHERIT_FDS_HANDLER: RPCHandler = fileops::pipe::handle_inherit_fds;
 pub(crate) const MKDIR_HANDLER: RPCHandler = fileops::mkdir::handle_mkdir;
 pub(crate) const OPEN_HANDLER: RPCHandler = fileops::open::handle_open;
+pub(crate) const PIPE_HANDLER: RPCHandler = fileops::pipe::handle_pipe;
+pub(crate) const READDIR_HANDLER: RPCHandler = fileops::readdir::handle_readdir;
 pub(crate) const RENAME_HANDLER: RPCHandler = fileops::rename::handle_rename;
 pub(crate) const READ_HANDLER: RPCHandler = fileops::rw::handle_read;
 pub(crate) const WRITE_HANDLER: RPCHandler = fileops::rw::handle_write;
+pub(crate) const SEEK_HANDLER: RPCHandler = fileops::seek::handle_seek;
+pub(crate) const TRUNCATE_HANDLER: RPCHandler = fileops::truncate::handle_truncate;
 
 // Re-export handdlers: process operations
 pub(crate) const REQUEST_CORE_HANDLER: RPCHandler = processops::request_core::handle_request_core;
+pub(crate) const RELEASE_CORE_HANDLER: RPCHandler = processops::release_core::handle_release_core;
 pub(crate) const ALLOCATE_PHYSICAL_HANDLER: RPCHandler =
     processops::allocate_physical::handle_allocate_physical;
 pub(crate) const RELEASE_PHYSICAL_HANDLER: RPCHandler =
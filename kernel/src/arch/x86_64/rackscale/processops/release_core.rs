@@ -0,0 +1,119 @@
+// Copyright © 2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use abomonation::{decode, encode, unsafe_abomonate, Abomonation};
+use core2::io::Result as IOResult;
+use core2::io::Write;
+use log::{debug, error, info, warn};
+use rpc::rpc::*;
+use rpc::RPCClient;
+
+use crate::error::KError;
+
+use super::super::controller::{get_local_pid, HWTHREADS_BUSY, HWTHREADS_OWNER};
+use super::super::dcm::resource_release::dcm_resource_release;
+use super::super::kernelrpc::*;
+use super::super::systemops::gtid_to_client_id;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ReleaseCoreReq {
+    pub core_id: u64,
+}
+unsafe_abomonate!(ReleaseCoreReq: core_id);
+
+/// RPC to tell the controller that the process no longer uses `core_id`.
+pub(crate) fn rpc_release_core(
+    rpc_client: &mut dyn RPCClient,
+    pid: usize,
+    core_id: u64,
+) -> Result<(u64, u64), RPCError> {
+    info!("ReleaseCore({:?})", core_id);
+
+    // Construct request data
+    let req = ReleaseCoreReq { core_id };
+    let mut req_data = [0u8; core::mem::size_of::<ReleaseCoreReq>()];
+    unsafe { encode(&req, &mut (&mut req_data).as_mut()) }.unwrap();
+
+    // Construct result buffer and call RPC
+    let mut res_data = [0u8; core::mem::size_of::<KernelRpcRes>()];
+    rpc_client
+        .call(
+            pid,
+            KernelRpc::ReleaseCore as RPCType,
+            &[&req_data],
+            &mut [&mut res_data],
+        )
+        .unwrap();
+
+    // Decode and return the result
+    if let Some((res, remaining)) = unsafe { decode::<KernelRpcRes>(&mut res_data) } {
+        if remaining.len() > 0 {
+            return Err(RPCError::ExtraData);
+        }
+        debug!("ReleaseCore() {:?}", res);
+        return res.ret;
+    } else {
+        return Err(RPCError::MalformedResponse);
+    }
+}
+
+/// RPC handler for releasing a core on the controller.
+pub(crate) fn handle_release_core(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    // Lookup local pid
+    let local_pid = { get_local_pid(hdr.client_id, hdr.pid) };
+    if local_pid.is_err() {
+        return construct_error_ret(hdr, payload, RPCError::NoFileDescForPid);
+    }
+    let local_pid = local_pid.unwrap();
+
+    // Parse request
+    let core_req = match unsafe { decode::<ReleaseCoreReq>(payload) } {
+        Some((req, _)) => *req,
+        None => {
+            warn!("Invalid payload for request: {:?}", hdr);
+            return construct_error_ret(hdr, payload, RPCError::MalformedRequest);
+        }
+    };
+
+    // The core is free for other assignments again (if the process has it)
+    {
+        let mut rack_hwthreads_busy = HWTHREADS_BUSY.lock();
+        let mut rack_hwthreads_owner = HWTHREADS_OWNER.lock();
+        let owner = rack_hwthreads_owner.get_mut(core_req.core_id as usize);
+        let busy = rack_hwthreads_busy.get_mut(core_req.core_id as usize);
+        match (owner, busy) {
+            (Some(owner), Some(busy @ Some(true))) if *owner == Some(local_pid) => {
+                *owner = None;
+                *busy = Some(false);
+            }
+            _ => {
+                warn!(
+                    "Release of a core that process {} doesn't have: {:?}",
+                    local_pid, core_req
+                );
+                return construct_ret(
+                    hdr,
+                    payload,
+                    KernelRpcRes {
+                        ret: convert_return(Err(KError::CoreNotAllocated)),
+                    },
+                );
+            }
+        }
+    }
+
+    // Tell DCM the core is no longer being used by the client owning it
+    let client_id = gtid_to_client_id(core_req.core_id as usize);
+    let res = if dcm_resource_release(client_id, local_pid, true) == 0 {
+        debug!("DCM release resource was successful");
+        KernelRpcRes {
+            ret: convert_return(Ok((0, 0))),
+        }
+    } else {
+        error!("DCM release resource failed");
+        KernelRpcRes {
+            ret: convert_return(Err(KError::DCMError)),
+        }
+    };
+    construct_ret(hdr, payload, res)
+}
@@ -42,7 +42,8 @@ pub(crate) fn rpc_release_physical(
 
     // Construct request data
     let node_id = get_frame_as(frame_id)?;
-    let frame = NrProcess::<Ring3Process>::release_frame_from_process(pid, frame_id as usize)?;
+    let (frame, _shm) =
+        NrProcess::<Ring3Process>::release_frame_from_process(pid, frame_id as usize)?;
 
     let mut frame_map = FRAME_MAP.write();
     frame_map
@@ -18,7 +18,7 @@ use crate::nr::KernelNode;
 
 use super::super::client::{get_local_client_id, get_num_clients};
 use super::super::controller::{
-    get_local_pid, HWTHREADS, HWTHREADS_BUSY, UNFULFILLED_CORE_ASSIGNMENTS,
+    get_local_pid, HWTHREADS_BUSY, HWTHREADS_OWNER, UNFULFILLED_CORE_ASSIGNMENTS,
 };
 use super::super::dcm::resource_alloc::dcm_resource_alloc;
 use super::super::kernelrpc::*;
@@ -101,15 +101,16 @@ pub(crate) fn handle_request_core(hdr: &mut RPCHeader, payload: &mut [u8]) -> Re
     let num_clients = get_num_clients();
     let mut rack_hwthreads_busy = HWTHREADS_BUSY.lock();
     let mut index = 0;
-    loop {
+    let gtid = loop {
         //
-        match rack_hwthreads_busy[local_to_gtid(index, client_id)] {
+        let gtid = local_to_gtid(index, client_id);
+        match rack_hwthreads_busy[gtid] {
             // thread is busy, keep looking
             Some(true) => index += 1,
             // found an empty thread! set to busy and break
             Some(false) => {
-                rack_hwthreads_busy[index] = Some(true);
-                break;
+                rack_hwthreads_busy[gtid] = Some(true);
+                break gtid;
             }
             // Ran out of threads for client; DCM should not have allowed this to happen
             None => panic!(
@@ -117,12 +118,15 @@ pub(crate) fn handle_request_core(hdr: &mut RPCHeader, payload: &mut [u8]) -> Re
                 client_id
             ),
         }
-    }
-
-    let rack_hwthreads = HWTHREADS.lock();
-    let gtid = rack_hwthreads[index].id;
+    };
     log::info!("Chose thread id {:?} for request", gtid);
 
+    // Only the process that got the core may release it again (always lock
+    // HWTHREADS_BUSY before HWTHREADS_OWNER)
+    if let Some(owner) = HWTHREADS_OWNER.lock().get_mut(gtid) {
+        *owner = Some(local_pid);
+    }
+
     // Construct and return result
     let res = KernelRpcRes {
         ret: convert_return(Ok((gtid as u64, 0))),
@@ -1,28 +1,36 @@
 use alloc::boxed::Box;
 use alloc::string::String;
 
-use kpi::io::{FileFlags, FileModes};
+use kpi::io::{FileFlags, FileModes, SeekWhence};
 use rpc::rpc::ClientId;
 
 use crate::arch::process::{current_pid, Ring3Process};
-use crate::error::KResult;
+use crate::error::{KError, KResult};
 use crate::fs::fd::FileDescriptor;
 use crate::memory::Frame;
 use crate::nrproc;
-use crate::process::{KernArcBuffer, UserSlice};
+use crate::process::{KernArcBuffer, UVAddr, UserSlice};
 use crate::syscalls::{FsDispatch, ProcessDispatch, SystemCallDispatch, SystemDispatch};
 
-use super::super::syscall::{Arch86SystemCall, Arch86SystemDispatch, Arch86VSpaceDispatch};
-use super::client::{get_local_client_id, RPC_CLIENT};
+use super::super::syscall::{
+    spawn_process, Arch86SystemCall, Arch86SystemDispatch, Arch86VSpaceDispatch,
+};
+use super::client::{get_frame_as, get_local_client_id, RPC_CLIENT};
 use super::fileops::close::rpc_close;
 use super::fileops::delete::rpc_delete;
+use super::fileops::fstat::rpc_fstat;
 use super::fileops::getinfo::rpc_getinfo;
 use super::fileops::mkdir::rpc_mkdir;
 use super::fileops::open::rpc_open;
+use super::fileops::pipe::{rpc_inherit_fds, rpc_pipe};
+use super::fileops::readdir::rpc_readdir;
 use super::fileops::rename::rpc_rename;
 use super::fileops::rw::{rpc_read, rpc_readat, rpc_write, rpc_writeat};
+use super::fileops::seek::rpc_seek;
+use super::fileops::truncate::rpc_truncate;
 use super::processops::allocate_physical::rpc_allocate_physical;
 use super::processops::print::rpc_log;
+use super::processops::release_core::rpc_release_core;
 use super::processops::release_physical::rpc_release_physical;
 use super::processops::request_core::rpc_request_core;
 use super::systemops::get_hardware_threads::rpc_get_hardware_threads;
@@ -43,8 +51,8 @@ impl SystemDispatch<u64> for Arch86LwkSystemCall {
         rpc_get_hardware_threads(&mut **client, pid, vaddr_buf, vaddr_buf_len).map_err(|e| e.into())
     }
 
-    fn get_stats(&self) -> KResult<(u64, u64)> {
-        self.local.get_stats()
+    fn get_stats(&self, vbuf_base: u64, vbuf_len: u64) -> KResult<(u64, u64)> {
+        self.local.get_stats(vbuf_base, vbuf_len)
     }
 
     fn get_core_id(&self) -> KResult<(u64, u64)> {
@@ -142,6 +150,53 @@ impl FsDispatch<u64> for Arch86LwkSystemCall {
         let mut client = RPC_CLIENT.lock();
         rpc_mkdir(&mut **client, pid, pathstring, modes).map_err(|e| e.into())
     }
+
+    fn readdir(&self, path: UserSlice, uslice: UserSlice) -> KResult<(u64, u64)> {
+        let pid = path.pid;
+        let pathstring: String = path.try_into()?;
+
+        nrproc::NrProcess::<Ring3Process>::userspace_exec_slice_mut(
+            uslice,
+            Box::try_new(move |ubuf: &mut [u8]| {
+                let mut client = RPC_CLIENT.lock();
+                rpc_readdir(&mut **client, pid, &pathstring, ubuf).map_err(|e| e.into())
+            })?,
+        )
+    }
+
+    fn truncate(&self, fd: FileDescriptor, len: u64) -> KResult<(u64, u64)> {
+        let pid = crate::arch::process::current_pid()?;
+        let mut client = RPC_CLIENT.lock();
+        rpc_truncate(&mut **client, pid, fd, len).map_err(|e| e.into())
+    }
+
+    fn seek(&self, fd: FileDescriptor, offset: i64, whence: SeekWhence) -> KResult<(u64, u64)> {
+        let pid = crate::arch::process::current_pid()?;
+        let mut client = RPC_CLIENT.lock();
+        rpc_seek(&mut **client, pid, fd, offset, whence).map_err(|e| e.into())
+    }
+
+    fn fstat(&self, fd: FileDescriptor) -> KResult<(u64, u64)> {
+        let pid = crate::arch::process::current_pid()?;
+        let mut client = RPC_CLIENT.lock();
+        rpc_fstat(&mut **client, pid, fd).map_err(|e| e.into())
+    }
+
+    fn pipe(&self) -> KResult<(u64, u64)> {
+        let pid = crate::arch::process::current_pid()?;
+        let mut client = RPC_CLIENT.lock();
+        rpc_pipe(&mut **client, pid).map_err(|e| e.into())
+    }
+
+    fn ring_register(&self, ring: UVAddr) -> KResult<(u64, u64)> {
+        let pid = current_pid()?;
+        crate::fs::ring::register(pid, ring)
+    }
+
+    fn ring_enter(&self) -> KResult<(u64, u64)> {
+        let pid = current_pid()?;
+        crate::fs::ring::enter(self, pid)
+    }
 }
 
 impl ProcessDispatch<u64> for Arch86LwkSystemCall {
@@ -183,6 +238,34 @@ impl ProcessDispatch<u64> for Arch86LwkSystemCall {
         }
     }
 
+    fn release_core(&self, core_id: u64) -> KResult<(u64, u64)> {
+        // TODO: cores of other clients would need to be released by them
+        let client_id = get_local_client_id();
+        if !is_gtid_local(core_id as usize, client_id) {
+            return Err(KError::InvalidGlobalThreadId);
+        }
+
+        // Don't let the controller hand out a core that isn't ours
+        let pid = current_pid()?;
+        let local_gtid = gtid_to_local(core_id as usize, client_id);
+        let owned = match crate::nr::KernelNode::core_processes(local_gtid) {
+            Ok(processes) => processes.iter().any(|ci| ci.pid == pid),
+            Err(KError::NoExecutorForCore) => false,
+            Err(e) => return Err(e),
+        };
+        if !owned {
+            return Err(KError::CoreNotAllocated);
+        }
+
+        {
+            let mut client = RPC_CLIENT.lock();
+            rpc_release_core(&mut **client, pid, core_id).map_err(KError::from)?;
+        }
+
+        // Doesn't return if we release the core we're running on
+        self.local.release_core(local_gtid as u64)
+    }
+
     fn allocate_physical(&self, page_size: u64, affinity: u64) -> KResult<(u64, u64)> {
         let mut client = RPC_CLIENT.lock();
         let pid = crate::arch::process::current_pid()?;
@@ -190,12 +273,44 @@ impl ProcessDispatch<u64> for Arch86LwkSystemCall {
     }
 
     fn release_physical(&self, frame_id: u64) -> KResult<(u64, u64)> {
+        // Shared memory objects are backed by local memory
+        if get_frame_as(frame_id).is_err() {
+            return self.local.release_physical(frame_id);
+        }
+
         let mut client = RPC_CLIENT.lock();
         let pid = crate::arch::process::current_pid()?;
         rpc_release_physical(&mut **client, pid, frame_id).map_err(|e| e.into())
     }
 
+    fn create_shm(&self, page_size: u64) -> KResult<(u64, u64)> {
+        self.local.create_shm(page_size)
+    }
+
+    fn attach_shm(&self, shm_id: u64) -> KResult<(u64, u64)> {
+        self.local.attach_shm(shm_id)
+    }
+
     fn exit(&self, code: u64) -> KResult<(u64, u64)> {
         self.local.exit(code)
     }
+
+    fn spawn(&self, args: UserSlice) -> KResult<(u64, u64)> {
+        // The controller learns about the new pid on its first RPC (or when
+        // it inherits the file descriptors)
+        spawn_process(args, |parent, pid| {
+            let mut client = RPC_CLIENT.lock();
+            rpc_inherit_fds(&mut **client, parent, pid)
+                .map(|_| ())
+                .map_err(|e| e.into())
+        })
+    }
+
+    fn wait(&self, pid: u64) -> KResult<(u64, u64)> {
+        self.local.wait(pid)
+    }
+
+    fn yield_core(&self) -> KResult<(u64, u64)> {
+        self.local.yield_core()
+    }
 }
@@ -21,6 +21,10 @@ pub(crate) fn is_gtid_local(gtid: GlobalThreadId, client_id: ClientId) -> bool {
     gtid % get_num_clients() as GlobalThreadId == client_id as GlobalThreadId
 }
 
+pub(crate) fn gtid_to_client_id(gtid: GlobalThreadId) -> ClientId {
+    (gtid % get_num_clients() as GlobalThreadId) as ClientId
+}
+
 // Helper functions for CpuThread NodeId
 pub(crate) fn local_to_node_id(node_id: NodeId, client_id: ClientId) -> NodeId {
     get_num_clients() as NodeId * node_id + client_id as NodeId
@@ -7,27 +7,30 @@ use core::convert::TryInto;
 
 use abomonation::encode;
 use fallible_collections::{FallibleVec, FallibleVecGlobal};
-use log::{debug, info, trace, warn};
+use log::{debug, error, info, trace, warn};
 use x86::bits64::paging::{PAddr, VAddr, BASE_PAGE_SIZE, LARGE_PAGE_SIZE};
 use x86::bits64::rflags;
 use x86::msr::{rdmsr, wrmsr, IA32_EFER, IA32_FMASK, IA32_LSTAR, IA32_STAR};
 
-use kpi::process::FrameId;
-use kpi::{MemType, SystemCallError};
+use kpi::process::{CoreMask, FrameId, ProcessInfo, ShmId, SpawnArgs, WAIT_ANY};
+use kpi::{MapFileFlags, MemPolicy, MemProtection, MemType, SystemCallError};
 
 use crate::arch::process::current_pid;
 use crate::cmdline::CommandLineArguments;
 use crate::error::KError;
+use crate::fs::cnrfs::MlnrKernelNode;
+use crate::fs::fd::FileDescriptor;
+use crate::fs::MnodeNum;
 use crate::memory::backends::PhysicalPageProvider;
-use crate::memory::vspace::MapAction;
+use crate::memory::vspace::{MapAction, MappingType, Reservation};
 use crate::memory::Frame;
 use crate::nr;
 use crate::nrproc::NrProcess;
-use crate::process::{ResumeHandle, SliceAccess, UVAddr, UserSlice};
+use crate::process::{Cmdline, KernArcBuffer, Pid, ResumeHandle, SliceAccess, UVAddr, UserSlice};
 use crate::syscalls::{ProcessDispatch, SystemCallDispatch, SystemDispatch, VSpaceDispatch};
 
 use super::gdt::GdtTable;
-use super::process::Ring3Process;
+use super::process::{Ring3Process, INVALID_EXECUTOR_START};
 use super::serial::SerialControl;
 
 extern "C" {
@@ -101,9 +104,21 @@ impl<T: Arch86SystemDispatch> SystemDispatch<u64> for T {
         Ok((serialized.len() as u64, 0))
     }
 
-    fn get_stats(&self) -> Result<(u64, u64), KError> {
+    fn get_stats(&self, vaddr_buf: u64, vaddr_buf_len: u64) -> Result<(u64, u64), KError> {
         info!("IRQ handler time: {} cycles", super::irq::TLB_TIME.get());
-        Ok((0, 0))
+
+        let stats = crate::memory::stats::collect()?;
+        let serialized = serde_cbor::to_vec(&stats).map_err(|_e| KError::OutOfMemory)?;
+        if serialized.len() <= vaddr_buf_len as usize {
+            let mut user_slice = UserSlice::new(
+                current_pid()?,
+                UVAddr::try_from(vaddr_buf)?,
+                serialized.len(),
+            )?;
+            NrProcess::<Ring3Process>::write_to_userspace(&mut user_slice, &serialized)?;
+        }
+
+        Ok((serialized.len() as u64, 0))
     }
 
     fn get_core_id(&self) -> Result<(u64, u64), KError> {
@@ -152,11 +167,16 @@ impl<T: Arch86ProcessDispatch> ProcessDispatch<u64> for T {
         // vaddr_buf = buf.as_mut_ptr() as u64
         // vaddr_buf_len = buf.len() as u64
         let pid = current_pid()?;
-        let mut pinfo = NrProcess::<Ring3Process>::pinfo(pid)?;
+        let mut pinfo: ProcessInfo = NrProcess::<Ring3Process>::pinfo(pid)?;
+        let cmdline = NrProcess::<Ring3Process>::cmdline(pid)?;
         pinfo.cmdline = crate::CMDLINE
             .get()
             .unwrap_or(&CommandLineArguments::default())
             .init_args;
+        if let Some(cmdline) = cmdline.as_ref() {
+            // Spawned processes get their own arguments
+            pinfo.cmdline = cmdline.as_str();
+        }
         pinfo.app_cmdline = crate::CMDLINE
             .get()
             .unwrap_or(&CommandLineArguments::default())
@@ -196,39 +216,45 @@ impl<T: Arch86ProcessDispatch> ProcessDispatch<u64> for T {
         Ok((core_id, 0))
     }
 
-    fn allocate_physical(&self, page_size: u64, _affinity: u64) -> Result<(u64, u64), KError> {
-        let page_size: usize = page_size.try_into().unwrap_or(0);
-        //let affinity: usize = arg3.try_into().unwrap_or(0);
-        // Validate input
-        if page_size != BASE_PAGE_SIZE && page_size != LARGE_PAGE_SIZE {
-            return Err(KError::InvalidSyscallArgument1 {
-                a: page_size as u64,
-            });
+    fn release_core(&self, core_id: u64) -> Result<(u64, u64), KError> {
+        let gtid: usize = core_id.try_into()?;
+        if gtid >= atopology::MACHINE_TOPOLOGY.num_threads() {
+            return Err(KError::InvalidGlobalThreadId);
         }
+        let pid = current_pid()?;
+        let terminate = super::tlb::Terminate::try_new(pid)?;
 
-        let pcm = super::kcb::per_core_mem();
-        // Figure out what memory to allocate
-        let (bp, lp) = if page_size == BASE_PAGE_SIZE {
-            (1, 0)
-        } else {
-            (0, 1)
-        };
-        crate::memory::KernelAllocator::try_refill_tcache(bp, lp, MemType::Mem)?;
+        // Other processes can have the core from now on
+        nr::KernelNode::release_core_from_process(pid, gtid)?;
 
-        // Allocate the page (need to make sure we drop pmanager again
-        // before we go to NR):
-        let frame = {
-            let mut pmanager = pcm.mem_manager();
-            if page_size == BASE_PAGE_SIZE {
-                pmanager.allocate_base_page()?
-            } else {
-                pmanager.allocate_large_page()?
+        if gtid == *crate::environment::CORE_ID {
+            // We gave up the core we're running on, there is nothing to
+            // return to
+            let _executor = super::process::release_current_executor(pid);
+            if let Err(e) = NrProcess::<Ring3Process>::release_executor(pid, gtid) {
+                error!("Failed to release executor of process {}: {:?}", pid, e);
             }
-        };
+            crate::scheduler::schedule()
+        }
+
+        super::tlb::terminate(terminate, &[gtid]);
+        NrProcess::<Ring3Process>::release_executor(pid, gtid)?;
+        Ok((0, 0))
+    }
+
+    fn allocate_physical(&self, page_size: u64, _affinity: u64) -> Result<(u64, u64), KError> {
+        //let affinity: usize = arg3.try_into().unwrap_or(0);
+        let frame = allocate_page(page_size)?;
 
         // Associate memory with the process
         let pid = current_pid()?;
-        let fid = NrProcess::<Ring3Process>::allocate_frame_to_process(pid, frame)?;
+        let fid = match NrProcess::<Ring3Process>::allocate_frame_to_process(pid, frame) {
+            Ok(fid) => fid,
+            Err(e) => {
+                crate::memory::KernelAllocator::release_frame(frame, MemType::Mem)?;
+                return Err(e);
+            }
+        };
 
         Ok((fid as u64, frame.base.as_u64()))
     }
@@ -236,133 +262,635 @@ impl<T: Arch86ProcessDispatch> ProcessDispatch<u64> for T {
     fn release_physical(&self, fid: u64) -> Result<(u64, u64), KError> {
         // Fetch the frame and release from the process
         let pid = current_pid()?;
-        let frame = NrProcess::<Ring3Process>::release_frame_from_process(pid, fid as FrameId)?;
-
-        // Release the frame (need to make sure we drop pmanager again before we
-        // go to NR):
-        let pcm = super::kcb::per_core_mem();
-        let mut pmanager = pcm.mem_manager();
-
-        // This entire logic should probably go into [`GlobalMemory`]:
-        if frame.size == BASE_PAGE_SIZE {
-            match pmanager.release_base_page(frame) {
-                Ok(_) => {}
-                Err(KError::CacheFull) => {
-                    pcm.gmanager.map(|g| {
-                        let mut gmanager = g.node_caches[frame.affinity].lock();
-                        gmanager.release_base_page(frame).expect("Can't fail");
-                    });
-                }
-                Err(e) => return Err(e),
+        let (frame, shm) =
+            NrProcess::<Ring3Process>::release_frame_from_process(pid, fid as FrameId)?;
+
+        // A shared frame is only freed once nobody holds it anymore
+        let frame = match shm {
+            Some(shm) => nr::KernelNode::shm_detach(pid, shm)?,
+            None => Some(frame),
+        };
+        if let Some(frame) = frame {
+            crate::memory::KernelAllocator::release_frame(frame, MemType::Mem)?;
+        }
+
+        Ok((0, 0))
+    }
+
+    fn create_shm(&self, page_size: u64) -> Result<(u64, u64), KError> {
+        let mut frame = allocate_page(page_size)?;
+        // Other processes will see the content
+        unsafe { frame.zero() };
+
+        let pid = current_pid()?;
+        let shm = match nr::KernelNode::shm_create(pid, frame) {
+            Ok(shm) => shm,
+            Err(e) => {
+                crate::memory::KernelAllocator::release_frame(frame, MemType::Mem)?;
+                return Err(e);
             }
-        } else {
-            assert_eq!(frame.size, LARGE_PAGE_SIZE);
-            match pmanager.release_large_page(frame) {
-                Ok(_) => {}
-                Err(KError::CacheFull) => {
-                    pcm.gmanager.map(|g| {
-                        let mut gmanager = g.node_caches[frame.affinity].lock();
-                        gmanager.release_large_page(frame).expect("Can't fail");
-                    });
+        };
+
+        let fid = attach_shared_frame(pid, frame, shm)?;
+        Ok((shm, fid as u64))
+    }
+
+    fn attach_shm(&self, shm: u64) -> Result<(u64, u64), KError> {
+        let pid = current_pid()?;
+        let frame = nr::KernelNode::shm_attach(pid, shm)?;
+
+        let fid = attach_shared_frame(pid, frame, shm)?;
+        Ok((fid as u64, frame.base.as_u64()))
+    }
+
+    fn exit(&self, code: u64) -> Result<(u64, u64), KError> {
+        let pid = current_pid()?;
+        debug!("Process {} exited with code {}", pid, code);
+        let terminate = super::tlb::Terminate::try_new(pid)?;
+
+        // Stop running the process everywhere before we touch its state
+        let cores = nr::KernelNode::release_cores(pid)?;
+        if !cores.contains(&*crate::environment::CORE_ID) {
+            // Somebody else is already tearing down the process, wait until
+            // they tell us to go away:
+            while super::process::has_executor() {
+                super::tlb::dequeue(*crate::environment::CORE_ID);
+                core::hint::spin_loop();
+            }
+            crate::scheduler::schedule()
+        }
+        super::tlb::terminate(terminate, &cores);
+        let _executor = super::process::release_current_executor(pid);
+
+        // We can't return to the process anymore, so just complain if
+        // something goes wrong from here on:
+        let limits = mem_limits(pid);
+        if let Err(e) = reclaim_process(pid) {
+            error!("Failed to reclaim resources of process {}: {:?}", pid, e);
+        }
+        match nr::KernelNode::process_exited(pid, code) {
+            // The parent might sleep in `wait`
+            Ok(Some(parent)) => {
+                if let Err(e) = limits.and_then(|limits| return_mem_limits(parent, limits)) {
+                    error!("Failed to return memory limits of {}: {:?}", pid, e);
+                }
+                for gtid in nr::KernelNode::process_cores(parent).unwrap_or_default() {
+                    super::idle::wake(gtid);
                 }
-                Err(e) => return Err(e),
             }
+            Ok(None) => {}
+            Err(e) => error!("Failed to record exit of process {}: {:?}", pid, e),
         }
 
-        Ok((0, 0))
+        if nr::KernelNode::num_processes().unwrap_or(0) == 0 {
+            // Nothing left to run: our integration tests rely on the exit code
+            // of the last process
+            if code != 0 {
+                super::debug::shutdown(crate::ExitReason::UserSpaceError);
+            } else {
+                super::debug::shutdown(crate::ExitReason::Ok);
+            }
+        }
+
+        crate::scheduler::schedule()
     }
 
-    fn exit(&self, code: u64) -> Result<(u64, u64), KError> {
-        debug!("Process got exit, we are done for now...");
-        // TODO: For now just a dummy version that exits Qemu
-        if code != 0 {
-            // When testing we want to indicate to our integration
-            // test that our user-space test failed with a non-zero exit
-            super::debug::shutdown(crate::ExitReason::UserSpaceError);
+    fn spawn(&self, args: UserSlice) -> Result<(u64, u64), KError> {
+        spawn_process(args, |parent, pid| {
+            crate::fs::cnrfs::MlnrKernelNode::inherit_fds(parent, pid).map(|_| ())
+        })
+    }
+
+    fn wait(&self, pid: u64) -> Result<(u64, u64), KError> {
+        let parent = current_pid()?;
+        let child = if pid == WAIT_ANY {
+            None
         } else {
-            super::debug::shutdown(crate::ExitReason::Ok);
+            Some(pid.try_into()?)
+        };
+
+        if let Some((pid, code)) = nr::KernelNode::wait(parent, child)? {
+            return Ok((pid as u64, code));
+        }
+
+        // Let the core run something else, we check again when it's our turn
+        if super::process::retry_syscall_later()? {
+            // Nobody else needs the core: sleep until the child exits (it
+            // wakes us) or the time slice is over
+            super::idle::enter();
+            super::timer::set(crate::scheduler::TIME_SLICE);
+            super::idle::sleep()
         }
+        crate::scheduler::schedule()
     }
-}
 
-/// Dispatch logic for vspace system calls.
-pub(crate) trait Arch86VSpaceDispatch {
-    fn map_generic(&self, mem_type: MemType, base: u64, size: u64) -> Result<(u64, u64), KError> {
-        let base = VAddr::from(base);
+    fn yield_core(&self) -> Result<(u64, u64), KError> {
+        if !super::process::has_waiting_executors() {
+            return Ok((0, 0));
+        }
 
-        let pcm = super::kcb::per_core_mem();
+        super::process::return_from_syscall_later()?;
+        crate::scheduler::schedule()
+    }
+}
 
-        let (bp, lp) = crate::memory::utils::size_to_pages(size as usize);
-        let mut frames = Vec::try_with_capacity(bp + lp)?;
-        crate::memory::KernelAllocator::try_refill_tcache(20 + bp, lp, mem_type)?;
+/// Loads and starts the process described by the (serialized) `SpawnArgs` in
+/// `args`.
+///
+/// `inherit_fds` copies the file descriptors of the parent to the new process
+/// (if requested), before the new process starts running.
+pub(crate) fn spawn_process<F: FnOnce(Pid, Pid) -> Result<(), KError>>(
+    args: UserSlice,
+    inherit_fds: F,
+) -> Result<(u64, u64), KError> {
+    let buffer: KernArcBuffer = args.try_into()?;
+    let args: SpawnArgs =
+        serde_cbor::from_slice(&buffer.buffer).map_err(|_e| KError::InvalidSpawnArguments)?;
+    let binary = crate::process::find_binary(args.binary).ok_or(KError::UnknownBinary)?;
+    let cmdline = Cmdline::from(args.cmdline).map_err(|_e| KError::InvalidSpawnArguments)?;
+
+    let parent = current_pid()?;
+    let pid = crate::process::make_process::<Ring3Process>(binary)?;
+    let started = if args.inherit_fds {
+        inherit_fds(parent, pid)
+    } else {
+        Ok(())
+    }
+    .and_then(|_| set_mem_limits(parent, pid, &args))
+    .and_then(|_| {
+        start_process(parent, pid, cmdline, args.affinity).map_err(|e| {
+            // The limits go back to the parent, as if the process exited
+            if let Err(return_err) =
+                mem_limits(pid).and_then(|limits| return_mem_limits(parent, limits))
+            {
+                error!(
+                    "Failed to return memory limits of {}: {:?}",
+                    pid, return_err
+                );
+            }
+            e
+        })
+    });
 
-        // TODO(apihell): This `paddr` is bogus, it will return the PAddr of the
-        // first frame mapped but if you map multiple Frames, no chance getting that
-        // Better would be a function to request physically consecutive DMA memory
-        // or use IO-MMU translation (see also rumpuser_pci_dmalloc)
-        // also better to just return what NR replies with...
-        let mut paddr = None;
-        let mut total_len = 0;
+    if let Err(e) = started {
+        if let Err(reclaim_err) =
+            reclaim_process(pid).and_then(|_| nr::KernelNode::release_pid(pid))
         {
+            error!("Failed to reclaim process {}: {:?}", pid, reclaim_err);
+        }
+        return Err(e);
+    }
+    debug!("Spawned {} ({}) as process {}", binary, cmdline, pid);
+
+    Ok((pid as u64, 0))
+}
+
+/// Limits the memory of the new process `pid` to what was requested in `args`.
+///
+/// The limits are taken from what its `parent` has left (see
+/// `SpawnArgs::mem_limit`), the parent gets them back with
+/// `return_mem_limits` once `pid` exits.
+fn set_mem_limits(parent: Pid, pid: Pid, args: &SpawnArgs) -> Result<(), KError> {
+    let mut granted = [(MemType::Mem, 0), (MemType::PMem, 0)];
+    for (i, requested) in [args.mem_limit, args.pmem_limit].into_iter().enumerate() {
+        let mem_type = granted[i].0;
+        let r = NrProcess::<Ring3Process>::grant_mem_limit(parent, mem_type, requested).and_then(
+            |limit| {
+                granted[i].1 = limit;
+                NrProcess::<Ring3Process>::set_mem_limit(pid, mem_type, limit)
+            },
+        );
+        if let Err(e) = r {
+            return_mem_limits(parent, granted)?;
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// The memory limits of `pid` (for `return_mem_limits`).
+fn mem_limits(pid: Pid) -> Result<[(MemType, u64); 2], KError> {
+    let pinfo = NrProcess::<Ring3Process>::pinfo(pid)?;
+    Ok([
+        (MemType::Mem, pinfo.mem_usage.limit),
+        (MemType::PMem, pinfo.pmem_usage.limit),
+    ])
+}
+
+/// Gives the memory `limits` of an exited child back to its `parent`.
+fn return_mem_limits(parent: Pid, limits: [(MemType, u64); 2]) -> Result<(), KError> {
+    for (mem_type, limit) in limits {
+        NrProcess::<Ring3Process>::return_mem_limit(parent, mem_type, limit)?;
+    }
+    Ok(())
+}
+
+/// Hands a freshly loaded process its parent and arguments and allocates the
+/// least busy core in `affinity` (any core if the mask is empty) to it.
+fn start_process(
+    parent: Pid,
+    pid: Pid,
+    cmdline: Cmdline,
+    affinity: CoreMask,
+) -> Result<(), KError> {
+    nr::KernelNode::set_parent(parent, pid)?;
+    NrProcess::<Ring3Process>::set_cmdline(pid, cmdline)?;
+
+    // Cores are shared (round-robin) if the process can't have one to itself
+    let mut least_busy = None;
+    for thread in atopology::MACHINE_TOPOLOGY.threads() {
+        if !affinity.is_empty() && !affinity.contains(thread.id) {
+            continue;
+        }
+        let load = nr::KernelNode::core_load(thread.id)?;
+        if least_busy.map_or(true, |(_thread, min)| load < min) {
+            least_busy = Some((thread, load));
+        }
+    }
+
+    let (thread, _load) = least_busy.ok_or(KError::NoCoreAvailable)?;
+    nr::KernelNode::allocate_core_to_process(
+        pid,
+        INVALID_EXECUTOR_START, // Irrelevant, the ELF entry point is used
+        Some(thread.node_id.unwrap_or(0)),
+        Some(thread.id),
+    )?;
+    Ok(())
+}
+
+/// Releases the memory and file-descriptors of an exited process.
+///
+/// The process must no longer run on any core. The pid stays allocated, it's
+/// released with `KernelNode::process_exited` or `KernelNode::release_pid`.
+fn reclaim_process(pid: Pid) -> Result<(), KError> {
+    // The file-system unpins the pages of the mappings below
+    if let Err(e) = write_back_file_mappings(pid) {
+        error!("Lost changes of process {} to mapped files: {:?}", pid, e);
+    }
+    for (frame, mem_type) in NrProcess::<Ring3Process>::exit(pid)? {
+        crate::memory::KernelAllocator::release_frame(frame, mem_type)?;
+    }
+    for frame in nr::KernelNode::shm_detach_process(pid)? {
+        crate::memory::KernelAllocator::release_frame(frame, MemType::Mem)?;
+    }
+    crate::fs::cnrfs::MlnrKernelNode::remove_process(pid)?;
+    Ok(())
+}
+
+/// Allocates a page of `page_size` bytes (either a base or a large page).
+fn allocate_page(page_size: u64) -> Result<Frame, KError> {
+    let page_size: usize = page_size.try_into().unwrap_or(0);
+    // Validate input
+    if page_size != BASE_PAGE_SIZE && page_size != LARGE_PAGE_SIZE {
+        return Err(KError::InvalidSyscallArgument1 {
+            a: page_size as u64,
+        });
+    }
+
+    let pcm = super::kcb::per_core_mem();
+    // Figure out what memory to allocate
+    let (bp, lp) = if page_size == BASE_PAGE_SIZE {
+        (1, 0)
+    } else {
+        (0, 1)
+    };
+    crate::memory::KernelAllocator::try_refill_tcache(bp, lp, MemType::Mem)?;
+
+    // Allocate the page (need to make sure we drop pmanager again
+    // before we go to NR):
+    let mut pmanager = pcm.mem_manager();
+    if page_size == BASE_PAGE_SIZE {
+        pmanager.allocate_base_page()
+    } else {
+        pmanager.allocate_large_page()
+    }
+}
+
+/// Registers the frame of the shared memory object `shm` with `pid`.
+///
+/// `pid` must already hold a reference to `shm`, it is dropped again if the
+/// frame can't be registered.
+fn attach_shared_frame(pid: Pid, frame: Frame, shm: ShmId) -> Result<FrameId, KError> {
+    match NrProcess::<Ring3Process>::allocate_shared_frame_to_process(pid, frame, shm) {
+        Ok(fid) => Ok(fid),
+        Err(e) => {
+            if let Some(frame) = nr::KernelNode::shm_detach(pid, shm)? {
+                crate::memory::KernelAllocator::release_frame(frame, MemType::Mem)?;
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Makes sure the nodes `policy` refers to exist.
+fn check_mem_policy(policy: MemPolicy) -> Result<(), KError> {
+    let nodes = core::cmp::max(1, atopology::MACHINE_TOPOLOGY.num_nodes()) as u64;
+    match policy {
+        MemPolicy::Bind(node) | MemPolicy::Preferred(node) if node >= nodes => {
+            Err(KError::InvalidAffinityId)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Makes sure `base..base+size` is a page aligned region of user-space.
+fn check_user_region(base: u64, size: u64) -> Result<(), KError> {
+    if base % BASE_PAGE_SIZE as u64 != 0 {
+        return Err(KError::InvalidBase);
+    }
+    if size == 0 || size % BASE_PAGE_SIZE as u64 != 0 {
+        return Err(KError::InvalidLength);
+    }
+    let end = base
+        .checked_add(size)
+        .ok_or(KError::BaseOverflow { base })?;
+    if end > kpi::KERNEL_BASE {
+        return Err(KError::InvalidLength);
+    }
+    Ok(())
+}
+
+/// Consecutive pages of a process that map consecutive bytes of a file.
+struct FileRun {
+    base: VAddr,
+    len: usize,
+    mnode: MnodeNum,
+    offset: usize,
+    writable: bool,
+}
+
+/// Returns the file mappings that make up `base..base+size`.
+///
+/// Fails if part of the region isn't a file mapping or a mapping only partly
+/// overlaps with the region.
+fn file_runs(pid: Pid, base: u64, size: u64) -> Result<Vec<FileRun>, KError> {
+    check_user_region(base, size)?;
+    let end = VAddr::from(base + size);
+
+    let mut runs: Vec<FileRun> = Vec::new();
+    let mut vaddr = VAddr::from(base);
+    while vaddr < end {
+        let (mbase, mapping) = NrProcess::<Ring3Process>::mapping(pid, vaddr)?;
+        let (mnode, offset, writable) = match mapping.typ {
+            MappingType::File {
+                mnode,
+                offset,
+                writable,
+            } => (mnode, offset, writable),
+            _ => return Err(KError::NotSupported),
+        };
+        if mbase != vaddr {
+            return Err(KError::InvalidBase);
+        }
+        if vaddr + mapping.frame.size > end {
+            return Err(KError::InvalidLength);
+        }
+
+        match runs.last_mut() {
+            Some(run)
+                if run.mnode == mnode
+                    && run.base + run.len == vaddr
+                    && run.offset + run.len == offset
+                    && run.writable == writable =>
+            {
+                run.len += mapping.frame.size
+            }
+            _ => runs.try_push(FileRun {
+                base: vaddr,
+                len: mapping.frame.size,
+                mnode,
+                offset,
+                writable,
+            })?,
+        }
+        vaddr = vaddr + mapping.frame.size;
+    }
+
+    Ok(runs)
+}
+
+/// Writes the pages of `run` back to the file.
+fn write_back(pid: Pid, run: &FileRun) -> Result<(), KError> {
+    let pages = UserSlice::new(pid, UVAddr::try_from(run.base.as_u64())?, run.len)?;
+    MlnrKernelNode::file_write_back(pid, run.mnode, pages.try_into()?, run.offset)?;
+    Ok(())
+}
+
+/// Writes the shared file mappings of the exited process `pid` back to their
+/// files (replicas other than the mapped one don't see the changes otherwise).
+///
+/// The process doesn't run anymore, so this copies from the mapped frames
+/// rather than from its address space.
+fn write_back_file_mappings(pid: Pid) -> Result<(), KError> {
+    let write_back = |mnode, offset, frame: Frame| {
+        let data = unsafe {
+            // Safety: The file-system keeps the pages around until the
+            // process is removed from it
+            core::slice::from_raw_parts(frame.kernel_vaddr().as_ptr::<u8>(), frame.size)
+        };
+        MlnrKernelNode::file_write_back(pid, mnode, data.try_into()?, offset).map(|_| ())
+    };
+
+    // Pages that are consecutive in the file and in memory are written back
+    // together
+    let mut run: Option<(MnodeNum, usize, Frame)> = None;
+    for (_base, mapping) in NrProcess::<Ring3Process>::shared_file_mappings(pid)? {
+        let (mnode, offset) = match mapping.typ {
+            MappingType::File { mnode, offset, .. } => (mnode, offset),
+            _ => continue,
+        };
+        match run.as_mut() {
+            Some((rmnode, roffset, frame))
+                if *rmnode == mnode
+                    && *roffset + frame.size == offset
+                    && frame.end() == mapping.frame.base =>
+            {
+                frame.size += mapping.frame.size;
+            }
+            _ => {
+                if let Some((mnode, offset, frame)) = run.replace((mnode, offset, mapping.frame)) {
+                    write_back(mnode, offset, frame)?;
+                }
+            }
+        }
+    }
+    if let Some((mnode, offset, frame)) = run {
+        write_back(mnode, offset, frame)?;
+    }
+    Ok(())
+}
+
+/// Removes the mappings of `base..base+len` page by page.
+fn unmap_pages(pid: Pid, base: VAddr, len: usize) -> Result<(), KError> {
+    let mut vaddr = base;
+    while vaddr < base + len {
+        let (handle, _frames) = NrProcess::<Ring3Process>::unmap(pid, vaddr)?;
+        vaddr = handle.vaddr + handle.size;
+        super::tlb::shootdown(handle);
+    }
+    Ok(())
+}
+
+/// Allocates a (zeroed) frame of `size` for anonymous user memory from the
+/// nodes given by `policy`.
+///
+/// `nth` is the index of the frame in the mapping (to interleave the frames
+/// of a mapping across the nodes).
+fn allocate_user_frame(
+    size: usize,
+    mem_type: MemType,
+    policy: MemPolicy,
+    nth: usize,
+) -> Result<Frame, KError> {
+    use crate::memory::KernelAllocator;
+    let local = *crate::environment::NODE_ID;
+
+    let mut frame = match policy {
+        MemPolicy::Local => {
+            let pcm = super::kcb::per_core_mem();
             let mut pmanager = match mem_type {
                 MemType::Mem => pcm.mem_manager(),
                 MemType::PMem => pcm.pmem_manager(),
             };
-
-            for _i in 0..lp {
-                let mut frame = pmanager
-                    .allocate_large_page()
-                    .expect("We refilled so allocation should work.");
-                total_len += frame.size;
-                unsafe { frame.zero() };
-                frames
-                    .try_push(frame)
-                    .expect("Can't fail see `try_with_capacity`");
-                if paddr.is_none() {
-                    paddr = Some(frame.base);
-                }
+            if size == BASE_PAGE_SIZE {
+                pmanager.allocate_base_page()?
+            } else {
+                pmanager.allocate_large_page()?
             }
-            for _i in 0..bp {
-                let mut frame = pmanager
-                    .allocate_base_page()
-                    .expect("We refilled so allocation should work.");
-                total_len += frame.size;
-                unsafe { frame.zero() };
+        }
+        MemPolicy::Bind(node) => {
+            KernelAllocator::allocate_frame_on(node as atopology::NodeId, size, mem_type)?
+        }
+        MemPolicy::Preferred(node) => {
+            match KernelAllocator::allocate_frame_on(node as atopology::NodeId, size, mem_type) {
+                Err(KError::InvalidAffinityId) => return Err(KError::InvalidAffinityId),
+                Err(_e) => KernelAllocator::allocate_frame_on(local, size, mem_type)?,
+                Ok(frame) => frame,
+            }
+        }
+        MemPolicy::Interleave => {
+            // Skip the nodes that ran out of memory
+            let nodes = core::cmp::max(1, atopology::MACHINE_TOPOLOGY.num_nodes());
+            (0..nodes)
+                .map(|i| (nth + i) % nodes)
+                .find_map(|node| KernelAllocator::allocate_frame_on(node, size, mem_type).ok())
+                .ok_or(KError::OutOfMemory)?
+        }
+    };
+
+    unsafe { frame.zero() };
+    Ok(frame)
+}
+
+/// Dispatch logic for vspace system calls.
+pub(crate) trait Arch86VSpaceDispatch {
+    fn map_generic(
+        &self,
+        mem_type: MemType,
+        base: u64,
+        size: u64,
+        policy: MemPolicy,
+    ) -> Result<(u64, u64), KError> {
+        let base = VAddr::from(base);
+
+        let pid = current_pid()?;
+        let (bp, lp) = crate::memory::utils::size_to_pages(size as usize);
+        let mut frames = Vec::try_with_capacity(bp + lp)?;
+        if policy == MemPolicy::Local {
+            crate::memory::KernelAllocator::try_refill_tcache(20 + bp, lp, mem_type)?;
+        } else {
+            crate::memory::KernelAllocator::try_refill_tcache(20, 0, mem_type)?;
+        }
+
+        let sizes = core::iter::repeat(LARGE_PAGE_SIZE)
+            .take(lp)
+            .chain(core::iter::repeat(BASE_PAGE_SIZE).take(bp));
+        let allocated = sizes
+            .enumerate()
+            .try_for_each(|(nth, size)| {
+                let frame = allocate_user_frame(size, mem_type, policy, nth)?;
                 frames
                     .try_push(frame)
                     .expect("Can't fail see `try_with_capacity`");
-                if paddr.is_none() {
-                    paddr = Some(frame.base);
-                }
+                Ok(())
+            })
+            .and_then(|_| {
+                NrProcess::<Ring3Process>::charge(
+                    pid,
+                    mem_type,
+                    bp * BASE_PAGE_SIZE + lp * LARGE_PAGE_SIZE,
+                )
+            });
+        if let Err(e) = allocated {
+            for frame in frames {
+                crate::memory::KernelAllocator::release_frame(frame, mem_type)?;
             }
+            return Err(e);
         }
 
-        NrProcess::<Ring3Process>::map_frames(current_pid()?, base, frames, MapAction::write())
-            .expect("Can't map memory");
+        // TODO(apihell): This `paddr` is bogus, it will return the PAddr of the
+        // first frame mapped but if you map multiple Frames, no chance getting that
+        // Better would be a function to request physically consecutive DMA memory
+        // or use IO-MMU translation (see also rumpuser_pci_dmalloc)
+        // also better to just return what NR replies with...
+        let paddr = frames.first().map(|frame| frame.base);
+        let total_len: usize = frames.iter().map(|frame| frame.size).sum();
+
+        NrProcess::<Ring3Process>::map_frames(
+            pid,
+            base,
+            frames,
+            MapAction::write(),
+            MappingType::Heap(mem_type),
+        )
+        .expect("Can't map memory");
 
         Ok((paddr.unwrap().as_u64(), total_len as u64))
     }
 
-    fn unmap_generic(&self, _mem_type: MemType, base: u64) -> Result<(u64, u64), KError> {
+    fn unmap_generic(&self, base: u64) -> Result<(u64, u64), KError> {
         let base = VAddr::from(base);
         let pid = current_pid()?;
 
-        let handle = NrProcess::<Ring3Process>::unmap(pid, base)?;
+        let (handle, frames) = NrProcess::<Ring3Process>::unmap(pid, base)?;
         let va: u64 = handle.vaddr.as_u64();
         let sz: u64 = handle.size as u64;
         super::tlb::shootdown(handle);
 
+        // Pages of a reserved region can go once no TLB refers to them anymore
+        for (frame, mem_type) in frames {
+            crate::memory::KernelAllocator::release_frame(frame, mem_type)?;
+        }
+
         Ok((va, sz))
     }
 }
 
 impl<T: Arch86VSpaceDispatch> VSpaceDispatch<u64> for T {
     fn map_mem(&self, base: u64, size: u64) -> Result<(u64, u64), KError> {
-        self.map_generic(MemType::Mem, base, size)
+        let policy = NrProcess::<Ring3Process>::mem_policy(current_pid()?)?;
+        self.map_generic(MemType::Mem, base, size, policy)
+    }
+
+    fn map_mem_policy(
+        &self,
+        base: u64,
+        size: u64,
+        policy: MemPolicy,
+    ) -> Result<(u64, u64), KError> {
+        check_mem_policy(policy)?;
+        self.map_generic(MemType::Mem, base, size, policy)
     }
 
     fn map_pmem(&self, base: u64, size: u64) -> Result<(u64, u64), KError> {
-        self.map_generic(MemType::PMem, base, size)
+        let policy = NrProcess::<Ring3Process>::mem_policy(current_pid()?)?;
+        self.map_generic(MemType::PMem, base, size, policy)
+    }
+
+    fn set_mem_policy(&self, policy: MemPolicy) -> Result<(u64, u64), KError> {
+        check_mem_policy(policy)?;
+        NrProcess::<Ring3Process>::set_mem_policy(current_pid()?, policy)?;
+        Ok((0, 0))
     }
 
     fn map_device(&self, base: u64, size: u64) -> Result<(u64, u64), KError> {
@@ -393,18 +921,181 @@ impl<T: Arch86VSpaceDispatch> VSpaceDispatch<u64> for T {
     }
 
     fn unmap_mem(&self, base: u64) -> Result<(u64, u64), KError> {
-        self.unmap_generic(MemType::Mem, base)
+        self.unmap_generic(base)
     }
 
     fn unmap_pmem(&self, base: u64) -> Result<(u64, u64), KError> {
-        self.unmap_generic(MemType::PMem, base)
+        self.unmap_generic(base)
     }
 
     fn identify(&self, addr: u64) -> Result<(u64, u64), KError> {
         let pid = current_pid()?;
         let base = VAddr::from(addr);
         trace!("Identify address: {:#x}.", addr);
-        NrProcess::<Ring3Process>::resolve(pid, base)
+        NrProcess::<Ring3Process>::resolve(pid, base).map(|(paddr, _rights)| (paddr.as_u64(), 0x0))
+    }
+
+    fn reserve_mem(&self, base: u64, size: u64) -> Result<(u64, u64), KError> {
+        let pid = current_pid()?;
+        check_user_region(base, size)?;
+        let reservation = Reservation {
+            size: size.try_into().map_err(|_e| KError::InvalidLength)?,
+            rights: MapAction::write(),
+            mem_type: MemType::Mem,
+        };
+
+        NrProcess::<Ring3Process>::reserve(pid, VAddr::from(base), reservation)?;
+        Ok((0, size))
+    }
+
+    fn protect(&self, base: u64, size: u64, rights: MemProtection) -> Result<(u64, u64), KError> {
+        let pid = current_pid()?;
+        check_user_region(base, size)?;
+
+        // A kernel-only mapping is the closest we get to "no access" while
+        // keeping the mapping around
+        let mut action = if rights.is_empty() {
+            MapAction::kernel()
+        } else if rights.contains(MemProtection::WRITE) {
+            MapAction::write()
+        } else {
+            MapAction::user()
+        };
+        if rights.contains(MemProtection::EXEC) {
+            action |= MapAction::execute();
+        }
+
+        let handle = NrProcess::<Ring3Process>::protect(
+            pid,
+            VAddr::from(base),
+            size.try_into().map_err(|_e| KError::InvalidLength)?,
+            action,
+        )?;
+        super::tlb::shootdown(handle);
+
+        Ok((0, size))
+    }
+
+    fn snapshot(&self, src: u64, dst: u64, size: u64) -> Result<(u64, u64), KError> {
+        let pid = current_pid()?;
+        for base in [src, dst] {
+            let end = base
+                .checked_add(size)
+                .ok_or(KError::BaseOverflow { base })?;
+            if end > kpi::KERNEL_BASE {
+                return Err(KError::InvalidLength);
+            }
+        }
+
+        // The source loses write access until its pages are copied
+        let handle = NrProcess::<Ring3Process>::share_cow(
+            pid,
+            VAddr::from(src),
+            VAddr::from(dst),
+            size.try_into().map_err(|_e| KError::InvalidLength)?,
+        )?;
+        super::tlb::shootdown(handle);
+
+        Ok((dst, size))
+    }
+
+    fn map_file(
+        &self,
+        base: u64,
+        size: u64,
+        fd: FileDescriptor,
+        offset: u64,
+        flags: MapFileFlags,
+    ) -> Result<(u64, u64), KError> {
+        let pid = current_pid()?;
+        check_user_region(base, size)?;
+        let len: usize = size.try_into().map_err(|_e| KError::InvalidLength)?;
+        let offset: usize = offset.try_into().map_err(|_e| KError::InvalidOffset)?;
+        let writable = flags.contains(MapFileFlags::WRITE);
+
+        if !flags.contains(MapFileFlags::SHARED) {
+            // A private mapping is a copy of the file that is never written back
+            MlnrKernelNode::fd_to_mnode(pid, fd)?;
+            self.map_mem(base, size)?;
+            let mut pages = UserSlice::new(pid, UVAddr::try_from(base)?, len)?;
+            let copied =
+                MlnrKernelNode::file_read(pid, fd, &mut pages, offset as i64).and_then(|r| {
+                    if writable {
+                        Ok(r)
+                    } else {
+                        self.protect(base, size, MemProtection::READ)
+                    }
+                });
+            if let Err(e) = copied {
+                unmap_pages(pid, VAddr::from(base), len)?;
+                return Err(e);
+            }
+            return Ok((base, size));
+        }
+
+        let (mnode, frames) = MlnrKernelNode::file_map(pid, fd, offset, len, writable)?;
+        let action = if writable {
+            MapAction::write()
+        } else {
+            MapAction::user()
+        };
+
+        let mut mapped = 0;
+        let r: Result<(), KError> = frames.into_iter().try_for_each(|mut frame| {
+            while frame.size > 0 {
+                let vaddr = VAddr::from(base) + mapped;
+                let page = if frame.size == LARGE_PAGE_SIZE && vaddr % LARGE_PAGE_SIZE == 0 {
+                    LARGE_PAGE_SIZE
+                } else {
+                    BASE_PAGE_SIZE
+                };
+                let (low, high) = frame.split_at(page);
+                let typ = MappingType::File {
+                    mnode,
+                    offset: offset + mapped,
+                    writable,
+                };
+                NrProcess::<Ring3Process>::map_frame(pid, vaddr, low, action, typ)?;
+                mapped += low.size;
+                frame = high;
+            }
+            Ok(())
+        });
+        if let Err(e) = r {
+            // Don't leave the region half-mapped
+            unmap_pages(pid, VAddr::from(base), mapped)?;
+            MlnrKernelNode::file_unmap(pid, mnode, len / BASE_PAGE_SIZE)?;
+            return Err(e);
+        }
+
+        Ok((base, size))
+    }
+
+    fn unmap_file(&self, base: u64, size: u64) -> Result<(u64, u64), KError> {
+        let pid = current_pid()?;
+        let runs = file_runs(pid, base, size)?;
+
+        for run in runs.iter().filter(|run| run.writable) {
+            write_back(pid, run)?;
+        }
+        for run in runs {
+            unmap_pages(pid, run.base, run.len)?;
+            MlnrKernelNode::file_unmap(pid, run.mnode, run.len / BASE_PAGE_SIZE)?;
+        }
+
+        Ok((base, size))
+    }
+
+    fn sync_file(&self, base: u64, size: u64) -> Result<(u64, u64), KError> {
+        let pid = current_pid()?;
+        for run in file_runs(pid, base, size)?
+            .iter()
+            .filter(|run| run.writable)
+        {
+            write_back(pid, run)?;
+        }
+
+        Ok((base, size))
     }
 }
 
@@ -18,3 +18,10 @@ pub(crate) fn set(deadline: u64) {
     apic.tsc_enable();
     unsafe { apic.tsc_set(x86::time::rdtsc() + deadline) };
 }
+
+/// Cancel the timer (if it's armed).
+pub(crate) fn disarm() {
+    let apic = super::irq::LOCAL_APIC.borrow();
+    // A deadline of zero disarms the TSC-deadline timer
+    apic.tsc_set(0);
+}
@@ -4,7 +4,7 @@
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::ops::Range;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use apic::ApicDriver;
 use bit_field::BitField;
@@ -18,8 +18,10 @@ use x86::apic::{
 };
 
 use super::memory::BASE_PAGE_SIZE;
+use crate::error::KError;
 use crate::fs::cnrfs;
 use crate::memory::vspace::TlbFlushHandle;
+use crate::process::Pid;
 use crate::{is_page_aligned, nr};
 
 // In the xAPIC mode, the Destination Format Register (DFR) through the MMIO
@@ -58,6 +60,41 @@ lazy_static! {
 pub(crate) enum WorkItem {
     Shootdown(Arc<Shootdown>),
     AdvanceReplica(usize),
+    Terminate(Arc<Terminate>),
+}
+
+/// Request to stop running the executor of a process on a core (because the
+/// process exited or gave up the core).
+#[derive(Debug)]
+pub(crate) struct Terminate {
+    pid: Pid,
+    /// How many receivers still run the process.
+    pending: AtomicUsize,
+}
+
+impl Terminate {
+    /// Create a new terminate request for process `pid`.
+    ///
+    /// It's allocated up-front so [`terminate`] can't fail half-way.
+    pub(crate) fn try_new(pid: Pid) -> Result<Arc<Self>, KError> {
+        Ok(Arc::try_new(Terminate {
+            pid,
+            pending: AtomicUsize::new(0),
+        })?)
+    }
+
+    /// Check if all receivers have stopped running the process.
+    pub(crate) fn is_acknowledged(&self) -> bool {
+        self.pending.load(Ordering::Acquire) == 0
+    }
+
+    /// Drop the executor (if it still belongs to the process).
+    fn process(&self) {
+        // Unlike for shootdowns, we can only acknowledge once we no longer
+        // use the page-tables of the process:
+        let _executor = super::process::release_current_executor(self.pid);
+        self.pending.fetch_sub(1, Ordering::Release);
+    }
 }
 
 #[derive(Debug)]
@@ -120,6 +157,10 @@ pub(crate) fn dequeue(gtid: atopology::GlobalThreadId) {
                 s.process();
             }
             WorkItem::AdvanceReplica(log_id) => advance_log(log_id),
+            WorkItem::Terminate(t) => {
+                trace!("Terminate msg {:?}", t);
+                t.process();
+            }
         },
         None => { /*IPI request was handled by eager_advance_fs_replica()*/ }
     }
@@ -146,8 +187,9 @@ pub(crate) fn eager_advance_fs_replica() {
     match IPI_WORKQUEUE[core_id].pop() {
         Some(msg) => {
             match &msg {
-                WorkItem::Shootdown(_s) => {
-                    // If its for TLB shootdown, insert it back into the queue.
+                WorkItem::Shootdown(_) | WorkItem::Terminate(_) => {
+                    // If its for TLB shootdown or termination, insert it back
+                    // into the queue.
                     enqueue(core_id, msg)
                 }
                 WorkItem::AdvanceReplica(log_id) => advance_log(*log_id),
@@ -186,6 +228,23 @@ pub(crate) fn send_ipi_to_apic(apic_id: ApicId) {
     unsafe { apic.send_ipi(icr) }
 }
 
+pub(crate) fn send_work_pending_ipi(apic_id: ApicId) {
+    let mut apic = super::irq::LOCAL_APIC.borrow_mut();
+
+    let icr = Icr::for_x2apic(
+        super::irq::TLB_WORK_PENDING,
+        apic_id,
+        DestinationShorthand::NoShorthand,
+        DeliveryMode::Fixed,
+        DestinationMode::Physical,
+        DeliveryStatus::Idle,
+        Level::Assert,
+        TriggerMode::Edge,
+    );
+
+    unsafe { apic.send_ipi(icr) }
+}
+
 fn send_ipi_multicast(ldr: u32) {
     let mut apic = super::irq::LOCAL_APIC.borrow_mut();
 
@@ -285,6 +344,35 @@ pub(crate) fn shootdown(handle: TlbFlushHandle) {
     trace!("done with all shootdowns");
 }
 
+/// Stops the process of `terminate` on all `cores` (except the current one).
+///
+/// Waits until all cores have switched away from the address space of the
+/// process before it returns.
+pub(crate) fn terminate(terminate: Arc<Terminate>, cores: &[atopology::GlobalThreadId]) {
+    let my_gtid = *crate::environment::CORE_ID;
+    let receivers = cores.iter().filter(|&&gtid| gtid != my_gtid).count();
+
+    // All receivers share the request
+    terminate.pending.store(receivers, Ordering::Release);
+    for &gtid in cores.iter().filter(|&&gtid| gtid != my_gtid) {
+        enqueue(gtid, WorkItem::Terminate(terminate.clone()));
+
+        let apic_id = atopology::MACHINE_TOPOLOGY.threads[gtid].apic_id();
+        send_work_pending_ipi(apic_id);
+    }
+
+    // Wait synchronously on cores to complete, keep handling our own work in
+    // case another core waits for us in the meantime
+    while !terminate.is_acknowledged() {
+        if !IPI_WORKQUEUE[my_gtid].is_empty() {
+            dequeue(my_gtid);
+        }
+        core::hint::spin_loop();
+    }
+
+    trace!("done with all terminates");
+}
+
 pub(crate) fn advance_replica(gtid: atopology::GlobalThreadId, log_id: usize) {
     trace!("Send AdvanceReplica IPI for {} to {}", log_id, gtid);
     let apic_id = atopology::MACHINE_TOPOLOGY.threads[gtid as usize].apic_id();
@@ -2,9 +2,12 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::ops::Bound::*;
+use core::ops::Range;
 
 use fallible_collections::btree::BTreeMap;
+use fallible_collections::{FallibleVec, FallibleVecGlobal};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use x86::current::paging::{PDFlags, PDPTFlags, PML4Entry, PML4Flags, PTFlags};
@@ -16,7 +19,7 @@ mod test;
 
 use crate::error::KError;
 use crate::memory::{detmem::DA, vspace::*};
-use crate::memory::{Frame, PAddr, VAddr};
+use crate::memory::{Frame, MemType, PAddr, VAddr, BASE_PAGE_SIZE, LARGE_PAGE_SIZE};
 
 use page_table::{PageTable, PT_LAYOUT};
 
@@ -106,11 +109,292 @@ pub(crate) unsafe fn init_large_objects_pml4() {
 
 pub(crate) struct VSpace {
     pub mappings: BTreeMap<VAddr, MappingInfo>,
+    /// Regions that get backed with memory on first access.
+    pub reservations: BTreeMap<VAddr, Reservation>,
     pub page_table: PageTable,
 }
 
 impl AddressSpace for VSpace {
     fn map_frame(&mut self, base: VAddr, frame: Frame, action: MapAction) -> Result<(), KError> {
+        self.map_frame_as(base, frame, action, MappingType::Heap(MemType::Mem))
+    }
+
+    fn map_frame_as(
+        &mut self,
+        base: VAddr,
+        frame: Frame,
+        action: MapAction,
+        typ: MappingType,
+    ) -> Result<(), KError> {
+        // Reserved regions are only backed through `populate`
+        let end = base.as_usize().saturating_add(frame.size);
+        if let Some((existing_base, _reservation)) = self.reserved_region(base.as_usize()..end) {
+            return Err(KError::AlreadyMapped {
+                base: existing_base,
+            });
+        }
+
+        self.insert_mapping(base, frame, action, typ)
+    }
+
+    fn map_memory_requirements(_base: VAddr, _frames: &[Frame]) -> usize {
+        // Implementation specific, the model does not require additional
+        // memory for page-tables
+        0
+    }
+
+    fn resolve(&self, addr: VAddr) -> Result<(PAddr, MapAction), KError> {
+        self.page_table.resolve(addr)
+    }
+
+    fn unmap(&mut self, base: VAddr) -> Result<TlbFlushHandle, KError> {
+        for (&existing_base, existing_mapping) in
+            self.mappings.range((Unbounded, Included(base))).rev()
+        {
+            let existing_map_range = existing_mapping.vrange(existing_base);
+            if existing_map_range.contains(&base.as_usize()) {
+                break;
+            } else {
+                return Err(KError::NotMapped);
+            }
+        }
+
+        let r = self.page_table.unmap(base)?;
+        let rbt = self.mappings.remove(&r.vaddr);
+        debug_assert!(rbt.is_some());
+        Ok(r)
+    }
+
+    fn adjust(&mut self, base: VAddr, new_rights: MapAction) -> Result<(VAddr, usize), KError> {
+        let r = self.page_table.adjust(base, new_rights)?;
+        // The adjusted page can be in the middle of a bigger mapping
+        let (&existing_base, mapping) = self
+            .mappings
+            .range_mut((Unbounded, Included(r.0)))
+            .next_back()
+            .ok_or(KError::NotMapped)?;
+        if !mapping.vrange(existing_base).contains(&r.0.as_usize()) {
+            return Err(KError::NotMapped);
+        }
+        mapping.rights = new_rights;
+        Ok(r)
+    }
+
+    fn reserve(&mut self, base: VAddr, reservation: Reservation) -> Result<(), KError> {
+        if reservation.size == 0 || reservation.size % BASE_PAGE_SIZE != 0 {
+            return Err(KError::InvalidLength);
+        }
+        if base % BASE_PAGE_SIZE != 0 {
+            return Err(KError::InvalidBase);
+        }
+        let end = base
+            .as_usize()
+            .checked_add(reservation.size)
+            .ok_or(KError::BaseOverflow {
+                base: base.as_u64(),
+            })?;
+        // Reservations are backed on page-faults of the process, they can't
+        // reach into the kernel half of the address space
+        if end > kpi::KERNEL_BASE as usize {
+            return Err(KError::InvalidLength);
+        }
+
+        if let Some((existing_base, _reservation)) = self.reserved_region(base.as_usize()..end) {
+            return Err(KError::AlreadyMapped {
+                base: existing_base,
+            });
+        }
+        if let Some((&existing_base, existing_mapping)) = self
+            .mappings
+            .range((Unbounded, Excluded(VAddr::from(end))))
+            .next_back()
+        {
+            if existing_mapping.vrange(existing_base).end > base.as_usize() {
+                return Err(KError::AlreadyMapped {
+                    base: existing_base,
+                });
+            }
+        }
+
+        self.reservations.try_insert(base, reservation)?;
+        Ok(())
+    }
+
+    fn reservation(&self, vaddr: VAddr) -> Option<(VAddr, Reservation)> {
+        self.reserved_region(vaddr.as_usize()..vaddr.as_usize() + 1)
+    }
+
+    fn populate(&mut self, vaddr: VAddr, frame: Frame) -> Result<(), KError> {
+        let (_base, reservation) = self.reservation(vaddr).ok_or(KError::NotMapped)?;
+        if frame.size() != BASE_PAGE_SIZE {
+            return Err(KError::InvalidFrame);
+        }
+
+        self.insert_mapping(
+            vaddr.align_down_to_base_page(),
+            frame,
+            reservation.rights,
+            MappingType::Heap(reservation.mem_type),
+        )
+    }
+
+    fn unreserve(&mut self, vaddr: VAddr) -> Result<(TlbFlushHandle, Vec<Frame>), KError> {
+        let (base, reservation) = self.reservation(vaddr).ok_or(KError::NotMapped)?;
+        let end = VAddr::from(base.as_usize() + reservation.size);
+
+        let mut populated = Vec::new();
+        for (&page, _mapping) in self.mappings.range((Included(base), Excluded(end))) {
+            populated.try_push(page)?;
+        }
+        let mut frames = Vec::try_with_capacity(populated.len())?;
+        for page in populated {
+            self.page_table.unmap(page)?;
+            let mapping = self.mappings.remove(&page).expect("Found it above");
+            frames.push(mapping.frame);
+        }
+        self.reservations.remove(&base);
+
+        let handle = TlbFlushHandle::new(base, PAddr::zero(), reservation.size, reservation.rights);
+        Ok((handle, frames))
+    }
+
+    fn mapping(&self, vaddr: VAddr) -> Option<(VAddr, MappingInfo)> {
+        self.mappings
+            .range((Unbounded, Included(vaddr)))
+            .next_back()
+            .filter(|(base, mapping)| mapping.vrange(**base).contains(&vaddr.as_usize()))
+            .map(|(&base, mapping)| (base, *mapping))
+    }
+
+    fn next_mapping(&self, vaddr: VAddr) -> Option<(VAddr, MappingInfo)> {
+        self.mappings
+            .range((Included(vaddr), Unbounded))
+            .next()
+            .map(|(&base, mapping)| (base, *mapping))
+    }
+
+    fn share_cow(
+        &mut self,
+        src: VAddr,
+        dst: VAddr,
+        len: usize,
+    ) -> Result<(TlbFlushHandle, Vec<Frame>), KError> {
+        if len == 0 || len % BASE_PAGE_SIZE != 0 {
+            return Err(KError::InvalidLength);
+        }
+        if src % BASE_PAGE_SIZE != 0 || dst % BASE_PAGE_SIZE != 0 {
+            return Err(KError::InvalidBase);
+        }
+        let src_end = src
+            .as_usize()
+            .checked_add(len)
+            .ok_or(KError::BaseOverflow { base: src.as_u64() })?;
+        let dst_end = dst
+            .as_usize()
+            .checked_add(len)
+            .ok_or(KError::BaseOverflow { base: dst.as_u64() })?;
+
+        // Pages of reserved regions are owned by the reservation
+        if self.reserved_region(src.as_usize()..src_end).is_some() {
+            return Err(KError::NotSupported);
+        }
+        if let Some((existing_base, _reservation)) = self.reserved_region(dst.as_usize()..dst_end) {
+            return Err(KError::AlreadyMapped {
+                base: existing_base,
+            });
+        }
+
+        // Check everything first so we don't leave the region half-shared
+        let mut shared: Vec<(VAddr, MappingInfo)> = Vec::new();
+        let mut cursor = src;
+        while cursor < VAddr::from(src_end) {
+            let mapping = self.mappings.get(&cursor).ok_or(KError::NotMapped)?;
+            let offset = cursor - src;
+            let shareable = matches!(mapping.typ, MappingType::Heap(_))
+                && !mapping.rights.is_aliasable()
+                && (mapping.rights.is_writable() || mapping.rights.is_cow())
+                && (mapping.frame.size == BASE_PAGE_SIZE || mapping.frame.size == LARGE_PAGE_SIZE);
+            if !shareable {
+                return Err(KError::NotSupported);
+            }
+            if cursor + mapping.frame.size > VAddr::from(src_end) {
+                return Err(KError::InvalidLength);
+            }
+            if (dst + offset) % mapping.frame.size != 0 {
+                return Err(KError::InvalidBase);
+            }
+            shared.try_push((cursor, *mapping))?;
+            cursor = cursor + mapping.frame.size;
+        }
+        if let Some((&existing_base, existing_mapping)) = self
+            .mappings
+            .range((Unbounded, Excluded(VAddr::from(dst_end))))
+            .next_back()
+        {
+            if existing_mapping.vrange(existing_base).end > dst.as_usize() {
+                return Err(KError::AlreadyMapped {
+                    base: existing_base,
+                });
+            }
+        }
+
+        let mut frames = Vec::try_with_capacity(shared.len())?;
+        for (base, mapping) in shared {
+            let rights = mapping.rights.copy_on_write();
+            self.insert_mapping(dst + (base - src), mapping.frame, rights, mapping.typ)?;
+            if !mapping.rights.is_cow() {
+                self.adjust(base, rights)?;
+            }
+            frames.push(mapping.frame);
+        }
+
+        let handle = TlbFlushHandle::new(src, PAddr::zero(), len, MapAction::cow());
+        Ok((handle, frames))
+    }
+
+    fn copy_on_write(
+        &mut self,
+        base: VAddr,
+        frame: Frame,
+    ) -> Result<(TlbFlushHandle, Frame), KError> {
+        let mapping = self.mappings.get(&base).copied().ok_or(KError::NotMapped)?;
+        if !mapping.rights.is_cow() {
+            return Err(KError::AlreadyMapped { base });
+        }
+        if frame.size != mapping.frame.size || frame.base % frame.size != 0 {
+            return Err(KError::InvalidFrame);
+        }
+
+        let rights = mapping.rights.copied();
+        let handle = self.page_table.unmap(base)?;
+        self.page_table.map_frame(base, frame, rights)?;
+        let entry = self.mappings.get_mut(&base).expect("Found it above");
+        entry.frame = frame;
+        entry.rights = rights;
+
+        Ok((handle, mapping.frame))
+    }
+}
+
+impl VSpace {
+    /// Returns the reserved region that overlaps with `range` (if any).
+    fn reserved_region(&self, range: Range<usize>) -> Option<(VAddr, Reservation)> {
+        self.reservations
+            .range((Unbounded, Excluded(VAddr::from(range.end))))
+            .next_back()
+            .filter(|(base, reservation)| reservation.vrange(**base).end > range.start)
+            .map(|(&base, reservation)| (base, *reservation))
+    }
+
+    /// Adds a mapping for `frame` at `base` (unless it overlaps with an
+    /// existing mapping).
+    fn insert_mapping(
+        &mut self,
+        base: VAddr,
+        frame: Frame,
+        action: MapAction,
+        typ: MappingType,
+    ) -> Result<(), KError> {
         if frame.size() == 0 {
             return Err(KError::InvalidFrame);
         }
@@ -152,45 +436,10 @@ impl AddressSpace for VSpace {
         }
 
         self.mappings
-            .try_insert(base, MappingInfo::new(frame, action))?;
+            .try_insert(base, MappingInfo::with_type(frame, action, typ))?;
         let r = self.page_table.map_frame(base, frame, action);
         r
     }
-
-    fn map_memory_requirements(_base: VAddr, _frames: &[Frame]) -> usize {
-        // Implementation specific, the model does not require additional
-        // memory for page-tables
-        0
-    }
-
-    fn resolve(&self, addr: VAddr) -> Result<(PAddr, MapAction), KError> {
-        self.page_table.resolve(addr)
-    }
-
-    fn unmap(&mut self, base: VAddr) -> Result<TlbFlushHandle, KError> {
-        for (&existing_base, existing_mapping) in
-            self.mappings.range((Unbounded, Included(base))).rev()
-        {
-            let existing_map_range = existing_mapping.vrange(existing_base);
-            if existing_map_range.contains(&base.as_usize()) {
-                break;
-            } else {
-                return Err(KError::NotMapped);
-            }
-        }
-
-        let r = self.page_table.unmap(base)?;
-        let rbt = self.mappings.remove(&r.vaddr);
-        debug_assert!(rbt.is_some());
-        Ok(r)
-    }
-
-    fn adjust(&mut self, base: VAddr, new_rights: MapAction) -> Result<(VAddr, usize), KError> {
-        let r = self.page_table.adjust(base, new_rights)?;
-        let mapping = self.mappings.get_mut(&r.0).ok_or(KError::NotMapped)?;
-        mapping.rights = new_rights;
-        Ok(r)
-    }
 }
 
 impl Drop for VSpace {
@@ -203,6 +452,7 @@ impl VSpace {
     pub(crate) fn new(da: DA) -> Result<Self, KError> {
         Ok(VSpace {
             mappings: BTreeMap::new(),
+            reservations: BTreeMap::new(),
             page_table: PageTable::new(da)?,
         })
     }
@@ -233,6 +483,9 @@ impl MapAction {
         if self.is_aliasable() {
             flags |= PDPTFlags::USER_11;
         }
+        if self.is_cow() {
+            flags |= PDPTFlags::USER_10;
+        }
 
         flags
     }
@@ -257,6 +510,9 @@ impl MapAction {
         if self.is_aliasable() {
             flags |= PDFlags::USER_11;
         }
+        if self.is_cow() {
+            flags |= PDFlags::USER_10;
+        }
 
         flags
     }
@@ -281,6 +537,9 @@ impl MapAction {
         if self.is_aliasable() {
             flags |= PTFlags::USER_11;
         }
+        if self.is_cow() {
+            flags |= PTFlags::USER_10;
+        }
 
         flags
     }
@@ -317,6 +576,9 @@ impl From<PTFlags> for MapAction {
         if cleaned.contains(PTFlags::USER_11) {
             ma |= MapAction::aliased()
         }
+        if cleaned.contains(PTFlags::USER_10) {
+            ma |= MapAction::cow()
+        }
 
         ma
     }
@@ -353,6 +615,9 @@ impl From<PDFlags> for MapAction {
         if cleaned.contains(PDFlags::USER_11) {
             ma |= MapAction::aliased()
         }
+        if cleaned.contains(PDFlags::USER_10) {
+            ma |= MapAction::cow()
+        }
 
         ma
     }
@@ -393,6 +658,9 @@ impl From<PDPTFlags> for MapAction {
         if cleaned.contains(PDPTFlags::USER_11) {
             ma |= MapAction::aliased()
         }
+        if cleaned.contains(PDPTFlags::USER_10) {
+            ma |= MapAction::cow()
+        }
 
         ma
     }
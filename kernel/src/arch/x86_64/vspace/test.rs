@@ -143,3 +143,120 @@ fn from_ptflags() {
     let ma: MapAction = rk.into();
     assert_eq!(ma, MapAction::kernel());
 }
+
+/// Reserved regions are only backed once they are populated.
+#[test]
+fn reserve_and_populate() {
+    use crate::memory::detmem::DA;
+
+    let mut vspace =
+        VSpace::new(DA::new().expect("Unable to create DA")).expect("Can't create vspace");
+    KernelAllocator::try_refill_tcache(14, 14, MemType::Mem).expect("Can't refill FrameCacheSmall");
+
+    let base = VAddr::from(0x1000_0000u64);
+    let reservation = Reservation {
+        size: 4 * BASE_PAGE_SIZE,
+        rights: MapAction::write(),
+        mem_type: MemType::Mem,
+    };
+    assert_eq!(vspace.reserve(base, reservation), Ok(()));
+    assert_eq!(
+        vspace.reserve(base + BASE_PAGE_SIZE, reservation),
+        Err(KError::AlreadyMapped { base })
+    );
+    assert_eq!(
+        vspace.reservation(base + 0x1234usize),
+        Some((base, reservation))
+    );
+    assert_eq!(vspace.resolve(base + 0x1234usize), Err(KError::NotMapped));
+
+    let frame = Frame::new(PAddr::from(0x2000_0000u64), BASE_PAGE_SIZE, 0);
+    assert_eq!(vspace.populate(base + 0x1234usize, frame), Ok(()));
+    assert_eq!(
+        vspace
+            .resolve(base + 0x1234usize)
+            .map(|(paddr, _rights)| paddr),
+        Ok(frame.base + 0x234usize)
+    );
+    assert!(vspace.populate(base + 0x1000usize, frame).is_err());
+    assert!(vspace
+        .map_frame(base + 0x2000usize, frame, MapAction::write())
+        .is_err());
+
+    let (handle, frames) = vspace
+        .unreserve(base + 0x3000usize)
+        .expect("Can't unreserve");
+    assert_eq!(handle.vaddr, base);
+    assert_eq!(handle.size, 4 * BASE_PAGE_SIZE);
+    assert_eq!(frames, vec![frame]);
+    assert_eq!(vspace.reservation(base), None);
+    assert_eq!(vspace.resolve(base + 0x1234usize), Err(KError::NotMapped));
+
+    // Nothing of the kernel half can be reserved
+    let kernel_base = VAddr::from(kpi::KERNEL_BASE - BASE_PAGE_SIZE as u64);
+    assert_eq!(
+        vspace.reserve(kernel_base, reservation),
+        Err(KError::InvalidLength)
+    );
+}
+
+/// Shared frames are read-only until they are replaced with a copy.
+#[test]
+fn share_and_copy_on_write() {
+    use crate::memory::detmem::DA;
+
+    let mut vspace =
+        VSpace::new(DA::new().expect("Unable to create DA")).expect("Can't create vspace");
+    KernelAllocator::try_refill_tcache(14, 14, MemType::Mem).expect("Can't refill FrameCacheSmall");
+
+    let src = VAddr::from(0x1000_0000u64);
+    let dst = VAddr::from(0x2000_0000u64);
+    let frames = [
+        Frame::new(PAddr::from(0x4000_0000u64), BASE_PAGE_SIZE, 0),
+        Frame::new(PAddr::from(0x4000_1000u64), BASE_PAGE_SIZE, 0),
+    ];
+    for (i, frame) in frames.iter().enumerate() {
+        vspace
+            .map_frame(src + i * BASE_PAGE_SIZE, *frame, MapAction::write())
+            .expect("Can't map frame");
+    }
+
+    // Only whole mappings can be shared and the target has to be free
+    assert_eq!(
+        vspace.share_cow(src + BASE_PAGE_SIZE, dst, 2 * BASE_PAGE_SIZE),
+        Err(KError::NotMapped)
+    );
+    assert_eq!(
+        vspace.share_cow(src, src + BASE_PAGE_SIZE, BASE_PAGE_SIZE),
+        Err(KError::AlreadyMapped {
+            base: src + BASE_PAGE_SIZE
+        })
+    );
+
+    let (handle, shared) = vspace
+        .share_cow(src, dst, 2 * BASE_PAGE_SIZE)
+        .expect("Can't share region");
+    assert_eq!(handle.vaddr, src);
+    assert_eq!(handle.size, 2 * BASE_PAGE_SIZE);
+    assert_eq!(shared, frames.to_vec());
+
+    let cow = MapAction::write().copy_on_write();
+    assert!(!cow.is_writable());
+    for vaddr in [src, dst] {
+        assert_eq!(vspace.resolve(vaddr), Ok((frames[0].base, cow)));
+    }
+
+    // Writing to the copy gives it a frame of its own
+    let copy = Frame::new(PAddr::from(0x5000_0000u64), BASE_PAGE_SIZE, 0);
+    let (handle, replaced) = vspace
+        .copy_on_write(dst, copy)
+        .expect("Can't replace frame");
+    assert_eq!(handle.vaddr, dst);
+    assert_eq!(replaced, frames[0]);
+    assert_eq!(vspace.resolve(dst), Ok((copy.base, MapAction::write())));
+    assert_eq!(vspace.resolve(src), Ok((frames[0].base, cow)));
+    assert_eq!(
+        vspace.copy_on_write(dst, copy),
+        Err(KError::AlreadyMapped { base: dst })
+    );
+}
@@ -40,6 +40,14 @@ enum CmdToken {
     #[token("workers")]
     Workers,
 
+    /// Maximum number of processes the kernel supports.
+    #[token("maxprocs")]
+    MaxProcesses,
+
+    /// Where the file-system stores its data.
+    #[token("fs")]
+    FsStorage,
+
     /// Init binary (which is loaded by default)
     #[token("init")]
     InitBinary,
@@ -109,6 +117,15 @@ impl From<&str> for Transport {
     }
 }
 
+/// Where the file-system stores its data.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum FsStorage {
+    /// In DRAM only (lost on reboot).
+    Memory,
+    /// Logged to a PMem region and remounted on boot.
+    PMem,
+}
+
 /// Arguments parsed from command line string passed from the bootloader to the
 /// kernel.
 #[derive(Copy, Clone, Debug)]
@@ -122,6 +139,8 @@ pub(crate) struct CommandLineArguments {
     pub transport: Transport,
     pub machine_id: u8,
     pub workers: u8,
+    pub max_processes: usize,
+    pub fs_storage: FsStorage,
 }
 // If you move or rename `CommandLineArguments`, you may also need to update the `s02_gdb` test.
 static_assertions::assert_type_eq_all!(CommandLineArguments, crate::cmdline::CommandLineArguments);
@@ -138,6 +157,8 @@ impl Default for CommandLineArguments {
             transport: Transport::Shmem,
             machine_id: 0,
             workers: 1,
+            max_processes: crate::process::DEFAULT_MAX_PROCESSES,
+            fs_storage: FsStorage::Memory,
         }
     }
 }
@@ -174,7 +195,9 @@ impl CommandLineArguments {
                 | CmdToken::InitArgs
                 | CmdToken::AppArgs
                 | CmdToken::MachineId
-                | CmdToken::Workers => {
+                | CmdToken::Workers
+                | CmdToken::MaxProcesses
+                | CmdToken::FsStorage => {
                     prev = token;
                 }
                 CmdToken::Ident => match prev {
@@ -206,10 +229,18 @@ impl CommandLineArguments {
                         parsed_args.workers = slice.parse::<u8>().unwrap_or(0x1);
                         prev = CmdToken::Error;
                     }
+                    CmdToken::MaxProcesses => {
+                        parsed_args.max_processes = parse_max_processes(slice);
+                        prev = CmdToken::Error;
+                    }
                     CmdToken::AppArgs => {
                         parsed_args.app_args = slice;
                         prev = CmdToken::Error;
                     }
+                    CmdToken::FsStorage => {
+                        parsed_args.fs_storage = parse_fs_storage(slice);
+                        prev = CmdToken::Error;
+                    }
                     CmdToken::Test => {
                         parsed_args.test = Some(slice);
                         prev = CmdToken::Error;
@@ -227,6 +258,8 @@ impl CommandLineArguments {
                         && prev != CmdToken::Test
                         && prev != CmdToken::MachineId
                         && prev != CmdToken::Workers
+                        && prev != CmdToken::MaxProcesses
+                        && prev != CmdToken::FsStorage
                     {
                         error!("Malformed args (unexpected equal sign) in {}", args);
                         continue;
@@ -264,6 +297,14 @@ impl CommandLineArguments {
                             parsed_args.workers = slice_no_quote.parse::<u8>().unwrap_or(0x1);
                             prev = CmdToken::Error;
                         }
+                        CmdToken::MaxProcesses => {
+                            parsed_args.max_processes = parse_max_processes(slice_no_quote);
+                            prev = CmdToken::Error;
+                        }
+                        CmdToken::FsStorage => {
+                            parsed_args.fs_storage = parse_fs_storage(slice_no_quote);
+                            prev = CmdToken::Error;
+                        }
                         _ => {
                             error!("Invalid cmd arguments: {} (skipped {})", args, slice);
                             continue;
@@ -297,9 +338,35 @@ impl CommandLineArguments {
     }
 }
 
+/// Parses the value of `maxprocs`, we need room for at least one process.
+fn parse_max_processes(value: &str) -> usize {
+    match value.parse::<usize>() {
+        Ok(max_processes) if max_processes > 0 => max_processes,
+        _ => {
+            error!("Invalid maxprocs={}, using the default", value);
+            crate::process::DEFAULT_MAX_PROCESSES
+        }
+    }
+}
+
+/// Parses the value of `fs`, unknown values keep the data in memory.
+fn parse_fs_storage(value: &str) -> FsStorage {
+    match value {
+        "memory" => FsStorage::Memory,
+        "pmem" => FsStorage::PMem,
+        _ => {
+            error!(
+                "Invalid fs={} (expected memory or pmem), using memory",
+                value
+            );
+            FsStorage::Memory
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::CommandLineArguments;
+    use super::{CommandLineArguments, FsStorage};
 
     #[test]
     fn parse_args_empty() {
@@ -349,6 +416,19 @@ mod test {
         assert_eq!(ba.init_args, "0");
     }
 
+    #[test]
+    fn parse_args_maxprocs() {
+        let ba = CommandLineArguments::from_str("./kernel maxprocs=512 log=trace");
+        assert_eq!(ba.log_filter, "trace");
+        assert_eq!(ba.max_processes, 512);
+
+        let ba = CommandLineArguments::from_str("./kernel");
+        assert_eq!(ba.max_processes, crate::process::DEFAULT_MAX_PROCESSES);
+
+        let ba = CommandLineArguments::from_str("./kernel maxprocs=0");
+        assert_eq!(ba.max_processes, crate::process::DEFAULT_MAX_PROCESSES);
+    }
+
     #[test]
     fn parse_args_leveldb() {
         let args = "./kernel log=warn init=dbbench.bin initargs=3 appcmd='--threads=1 --benchmarks=fillseq,readrandom --reads=100000 --num=50000 --value_size=65535'";
@@ -452,4 +532,20 @@ mod test {
         let ba = CommandLineArguments::from_str(args);
         assert_eq!(ba.workers, 1);
     }
+
+    #[test]
+    fn parse_fs_storage() {
+        let ba = CommandLineArguments::from_str("./kernel fs=pmem log=debug");
+        assert_eq!(ba.fs_storage, FsStorage::PMem);
+        assert_eq!(ba.log_filter, "debug");
+
+        let ba = CommandLineArguments::from_str("./kernel fs='pmem'");
+        assert_eq!(ba.fs_storage, FsStorage::PMem);
+
+        let ba = CommandLineArguments::from_str("./kernel");
+        assert_eq!(ba.fs_storage, FsStorage::Memory);
+
+        let ba = CommandLineArguments::from_str("./kernel fs=pmme");
+        assert_eq!(ba.fs_storage, FsStorage::Memory);
+    }
 }
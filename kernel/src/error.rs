@@ -97,10 +97,24 @@ pub enum KError {
     InvalidFileDescriptor,
     /// Can't spawn binary {binary}: Not found
     BinaryNotFound { binary: &'static str },
+    /// Can't spawn binary: No module with the requested name
+    UnknownBinary,
+    /// Arguments for spawn are malformed or the command line is too long
+    InvalidSpawnArguments,
+    /// None of the requested cores is available
+    NoCoreAvailable,
+    /// The core is not allocated to the process
+    CoreNotAllocated,
+    /// The process has no (matching) child process to wait for
+    NoChildProcess,
     /// Supplied frame was invalid
     InvalidFrame,
     /// The frame could not be detached from the process -- still mapped in its VSpace.
     FrameStillMapped,
+    /// The shared memory object does not exist
+    InvalidShmId,
+    /// The process would exceed its memory limit
+    MemoryLimitExceeded,
     /// Address space operation covers existing mapping {base:?}
     AlreadyMapped { base: VAddr },
     /// Provided virtual base {base:?} is invalid (led to overflow on mappings).
@@ -123,8 +137,20 @@ pub enum KError {
     AlreadyPresent,
     /// Can't read or write to a directory
     DirectoryError,
+    /// Supplied path is not a directory
+    NotADirectory,
+    /// Can't remove or replace a directory that isn't empty
+    DirectoryNotEmpty,
     /// Can't open more files for the process
     OpenFileLimit,
+    /// The persistent storage of the file-system is full
+    FileSystemFull,
+    /// The operation can't make progress right now (e.g., the pipe is empty)
+    WouldBlock,
+    /// Write to a pipe that has no readers left
+    BrokenPipe,
+    /// The process didn't register a ring for its file operations
+    NoIoRing,
     /// PID is already stored in scheduler state.
     FileDescForPidAlreadyAdded,
     /// No file-descriptors found for PID.
@@ -253,6 +279,15 @@ impl From<KError> for SystemCallError {
             KError::InvalidVSpaceOperation { .. } => SystemCallError::NotSupported,
             KError::InvalidProcessOperation { .. } => SystemCallError::NotSupported,
             KError::BadAddress { .. } => SystemCallError::BadAddress,
+            KError::WouldBlock => SystemCallError::WouldBlock,
+            KError::BrokenPipe => SystemCallError::BrokenPipe,
+            KError::NoChildProcess => SystemCallError::NoChildProcess,
+            KError::UnknownBinary => SystemCallError::UnknownBinary,
+            KError::InvalidSpawnArguments => SystemCallError::InvalidArguments,
+            KError::NotADirectory => SystemCallError::NotADirectory,
+            KError::DirectoryNotEmpty => SystemCallError::DirectoryNotEmpty,
+            KError::MemoryLimitExceeded => SystemCallError::OutOfMemory,
+            KError::FileSystemFull => SystemCallError::OutOfMemory,
             _ => SystemCallError::InternalError,
         }
     }
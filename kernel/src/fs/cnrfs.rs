@@ -3,20 +3,23 @@
 
 use alloc::sync::Arc;
 use core::cell::RefCell;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 use cnr::{Dispatch, Log, LogMapper, Replica as MlnrReplica, ReplicaToken as MlnrReplicaToken};
-use fallible_collections::FallibleVecGlobal;
+use fallible_collections::{FallibleVec, FallibleVecGlobal};
 use hashbrown::HashMap;
 use kpi::io::*;
 use log::trace;
 
 use crate::error::KError;
-use crate::memory::LARGE_PAGE_SIZE;
+use crate::memory::{Frame, BASE_PAGE_SIZE, LARGE_PAGE_SIZE};
 use crate::prelude::*;
 use crate::process::SliceAccess;
 use crate::process::{KernArcBuffer, Pid};
 
-use super::fd::{FileDescriptor, FileDescriptorTable};
+use super::fd::{FileDescriptor, FileDescriptorEntry, FileDescriptorTable};
+use super::pipe::{Pipe, PipeNum};
+use super::ring::RingOp;
 use super::{FileSystem, MlnrFS, MnodeNum, NrLock, MNODE_OFFSET};
 
 /// A handle to the node-local CNR based kernel replica.
@@ -25,6 +28,9 @@ pub(crate) static CNRFS: RefCell<
     Option<(Arc<MlnrReplica<'static, MlnrKernelNode>>, MlnrReplicaToken)>,
 > = RefCell::new(None);
 
+/// How many logs the file-system has (set by [`allocate_logs`]).
+static NUM_LOGS: AtomicUsize = AtomicUsize::new(1);
+
 /// Initializes the CNRFS thread local variable.
 ///
 /// Function should only be called during initialization and must be called on
@@ -69,6 +75,7 @@ pub(crate) fn allocate_logs() -> Vec<Arc<Log<'static, Modify>>> {
 
     let mut fs_logs: Vec<Arc<Log<Modify>>> =
         Vec::try_with_capacity(cores_per_node).expect("Not enough memory to initialize system");
+    NUM_LOGS.store(cores_per_node, Ordering::Relaxed);
     for i in 0..cores_per_node {
         // Log idx in range [1, cores_per_node+1]
         let mut log = Log::<Modify>::new(LARGE_PAGE_SIZE, i + 1);
@@ -90,18 +97,43 @@ pub(crate) struct MlnrKernelNode {
     process_map: NrLock<HashMap<Pid, FileDescriptorTable>>,
     /// MLNR kernel node primarily replicates the in-memory filesystem.
     fs: MlnrFS,
+    /// Pipes that are still referred to by a file descriptor.
+    pipes: NrLock<HashMap<PipeNum, Pipe>>,
+    /// The number handed out to the next pipe.
+    next_pipe: AtomicU64,
+    /// How many pages of which files every process has mapped.
+    mapped: NrLock<HashMap<Pid, Vec<(MnodeNum, usize)>>>,
 }
 
 #[derive(Hash, Clone, Debug, PartialEq)]
 pub(crate) enum Modify {
     ProcessAdd(Pid),
-    //ProcessRemove(Pid),
+    ProcessRemove(Pid),
+    ProcessInheritFds(Pid, Pid),
     FileOpen(Pid, String, FileFlags, FileModes),
     FileWrite(Pid, FileDescriptor, MnodeNum, Arc<[u8]>, i64),
+    /// Modifications of files that go through the same log (in order).
+    FileBatch(Pid, Vec<(FileDescriptor, MnodeNum, FileModify)>),
+    FileTruncate(Pid, FileDescriptor, MnodeNum, u64),
+    FileSeek(Pid, FileDescriptor, MnodeNum, i64, SeekWhence),
     FileClose(Pid, FileDescriptor),
     FileDelete(Pid, String),
     FileRename(Pid, String, String),
     MkDir(Pid, String, FileModes),
+    PipeCreate(Pid),
+    PipeRead(Pid, FileDescriptor, PipeNum, usize),
+    PipeWrite(Pid, FileDescriptor, PipeNum, Arc<[u8]>),
+    FileMap(Pid, FileDescriptor, MnodeNum, usize, usize, bool),
+    FileUnmap(Pid, MnodeNum, usize),
+    FileWriteBack(Pid, MnodeNum, Arc<[u8]>, usize),
+}
+
+/// A modification of a file in a [`Modify::FileBatch`].
+#[derive(Hash, Clone, Debug, PartialEq)]
+pub(crate) enum FileModify {
+    Write(Arc<[u8]>, i64),
+    Truncate(u64),
+    Seek(i64, SeekWhence),
 }
 
 // TODO: Stateless op to log mapping. Maintain some state for correct redirection.
@@ -111,15 +143,39 @@ impl LogMapper for Modify {
         logs.clear();
         match self {
             Modify::ProcessAdd(_pid) => push_to_all(nlogs, logs),
-            //Modify::ProcessRemove(_pid) => push_to_all(nlogs, logs),
+            Modify::ProcessRemove(_pid) => push_to_all(nlogs, logs),
+            Modify::ProcessInheritFds(_parent, _child) => push_to_all(nlogs, logs),
             Modify::FileOpen(_pid, _filename, _flags, _modes) => push_to_all(nlogs, logs),
             Modify::FileWrite(_pid, _fd, mnode, _kernslice, _offset) => {
                 logs.push((*mnode as usize - MNODE_OFFSET) % nlogs)
             }
+            Modify::FileBatch(_pid, ops) => {
+                push_batch_logs(nlogs, logs, ops.iter().map(|(_fd, mnode, _op)| *mnode))
+            }
+            Modify::FileTruncate(_pid, _fd, mnode, _len) => {
+                logs.push((*mnode as usize - MNODE_OFFSET) % nlogs)
+            }
+            // The fd offset is also updated by reads and writes, so seek has to
+            // go through the same log as those.
+            Modify::FileSeek(_pid, _fd, mnode, _offset, _whence) => {
+                logs.push((*mnode as usize - MNODE_OFFSET) % nlogs)
+            }
             Modify::FileClose(_pid, _fd) => push_to_all(nlogs, logs),
             Modify::FileDelete(_pid, _filename) => push_to_all(nlogs, logs),
             Modify::FileRename(_pid, _oldname, _newname) => push_to_all(nlogs, logs),
             Modify::MkDir(_pid, _name, _modes) => push_to_all(nlogs, logs),
+            Modify::PipeCreate(_pid) => push_to_all(nlogs, logs),
+            Modify::PipeRead(_pid, _fd, pipe, _len) => logs.push(*pipe as usize % nlogs),
+            Modify::PipeWrite(_pid, _fd, pipe, _kernslice) => logs.push(*pipe as usize % nlogs),
+            Modify::FileMap(_pid, _fd, mnode, _offset, _len, _writable) => {
+                logs.push((*mnode as usize - MNODE_OFFSET) % nlogs)
+            }
+            Modify::FileUnmap(_pid, mnode, _pages) => {
+                logs.push((*mnode as usize - MNODE_OFFSET) % nlogs)
+            }
+            Modify::FileWriteBack(_pid, mnode, _kernslice, _offset) => {
+                logs.push((*mnode as usize - MNODE_OFFSET) % nlogs)
+            }
         }
 
         fn push_to_all(nlogs: usize, logs: &mut Vec<usize>) {
@@ -130,6 +186,16 @@ impl LogMapper for Modify {
     }
 }
 
+/// Adds the logs of all `mnodes` to `logs` (once each).
+fn push_batch_logs(nlogs: usize, logs: &mut Vec<usize>, mnodes: impl Iterator<Item = MnodeNum>) {
+    for mnode in mnodes {
+        let log = (mnode as usize - MNODE_OFFSET) % nlogs;
+        if !logs.contains(&log) {
+            logs.push(log);
+        }
+    }
+}
+
 pub(crate) enum Access<'buf> {
     FileRead(
         Pid,
@@ -138,9 +204,18 @@ pub(crate) enum Access<'buf> {
         &'buf mut dyn SliceAccess,
         i64,
     ),
+    /// Reads of files that go through the same log.
+    FileReadBatch(
+        Pid,
+        Vec<(FileDescriptor, MnodeNum, &'buf mut dyn SliceAccess, i64)>,
+    ),
     FileInfo(Pid, String, MnodeNum),
+    FileStat(Pid, FileDescriptor, MnodeNum),
     FdToMnode(Pid, FileDescriptor),
     FileNameToMnode(Pid, String),
+    ReadDir(Pid, String),
+    /// Would reading from (or writing to) the pipe end block?
+    PipeReady(Pid, FileDescriptor, PipeNum),
     Synchronize(usize),
 }
 
@@ -153,12 +228,22 @@ impl<'buf> LogMapper for Access<'buf> {
             Access::FileRead(_pid, _fd, mnode, _buffer, _offset) => {
                 logs.push((*mnode as usize - MNODE_OFFSET) % nlogs)
             }
+            Access::FileReadBatch(_pid, reads) => push_batch_logs(
+                nlogs,
+                logs,
+                reads.iter().map(|(_fd, mnode, _buffer, _offset)| *mnode),
+            ),
             Access::FileInfo(_pid, _filename, mnode) => {
                 logs.push((*mnode as usize - MNODE_OFFSET) % nlogs)
             }
+            Access::FileStat(_pid, _fd, mnode) => {
+                logs.push((*mnode as usize - MNODE_OFFSET) % nlogs)
+            }
             // TODO: Assume that all metadata modifying operations go through log 0.
             Access::FdToMnode(_pid, _fd) => logs.push(0),
             Access::FileNameToMnode(_pid, _filename) => logs.push(0),
+            Access::ReadDir(_pid, _pathname) => logs.push(0),
+            Access::PipeReady(_pid, _fd, pipe) => logs.push(*pipe as usize % nlogs),
             // Log number start with 1 in CNR, however, replica uses mod
             // operation which starts with 0; hence `log_id - 1`.
             Access::Synchronize(log_id) => logs.push((*log_id - 1) % nlogs),
@@ -169,16 +254,84 @@ impl<'buf> LogMapper for Access<'buf> {
 #[derive(Clone, Debug)]
 pub(crate) enum MlnrNodeResult {
     ProcessAdded(Pid),
-    //ProcessRemoved(Pid),
+    ProcessRemoved(Pid),
+    FdsInherited,
     FileOpened(FileDescriptor),
     FileAccessed(u64),
+    FileBatchAccessed(Vec<Result<(u64, u64), KError>>),
+    FileTruncated,
+    FileSeeked(u64),
     FileClosed(FileDescriptor),
     FileDeleted,
     FileInfo(FileInfo),
     FileRenamed,
     DirCreated,
     MappedFileToMnode(u64),
+    MappedFdToPipe(u64),
+    PipeCreated(FileDescriptor, FileDescriptor),
+    PipeData(Vec<u8>),
+    PipeReady(bool),
+    DirEntries(Vec<DirEntry>),
     Synchronized,
+    FileMapped(Vec<Frame>),
+    FileUnmapped,
+}
+
+/// What a file descriptor refers to.
+#[derive(Clone, Copy)]
+enum FdTarget {
+    Mnode(MnodeNum),
+    Pipe(PipeNum),
+}
+
+/// The operations of a batch that go through the same log, and where each
+/// of them is in the whole batch.
+struct LogBatch<T> {
+    log: usize,
+    indices: Vec<usize>,
+    ops: Vec<T>,
+}
+
+impl<T> LogBatch<T> {
+    /// Adds `op` (at position `index`) to the batch for `log`.
+    fn add(batches: &mut Vec<LogBatch<T>>, log: usize, index: usize, op: T) -> Result<(), KError> {
+        if let Some(batch) = batches.iter_mut().find(|batch| batch.log == log) {
+            batch.indices.try_push(index)?;
+            batch.ops.try_push(op)?;
+            return Ok(());
+        }
+
+        let mut batch = LogBatch {
+            log,
+            indices: Vec::new(),
+            ops: Vec::new(),
+        };
+        batch.indices.try_push(index)?;
+        batch.ops.try_push(op)?;
+        batches.try_push(batch)?;
+        Ok(())
+    }
+}
+
+/// Stores the result of every operation of a [`LogBatch`] at its position.
+fn store_batch_results(
+    indices: &[usize],
+    response: Result<MlnrNodeResult, KError>,
+    results: &mut [Option<Result<(u64, u64), KError>>],
+) {
+    match response {
+        Ok(MlnrNodeResult::FileBatchAccessed(batch_results)) => {
+            for (index, result) in indices.iter().zip(batch_results.into_iter()) {
+                results[*index] = Some(result);
+            }
+        }
+        Err(e) => {
+            for index in indices {
+                results[*index] = Some(Err(e.clone()));
+            }
+        }
+        Ok(_) => unreachable!("Got unexpected response"),
+    }
 }
 
 /// TODO: Most of the functions looks same as in nr.rs. Merge the
@@ -198,6 +351,36 @@ impl MlnrKernelNode {
             })
     }
 
+    pub(crate) fn remove_process(pid: usize) -> Result<(u64, u64), KError> {
+        let cnrfs = CNRFS.borrow();
+        cnrfs
+            .as_ref()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut_scan(Modify::ProcessRemove(pid), *token);
+                match response {
+                    Ok(MlnrNodeResult::ProcessRemoved(pid)) => Ok((pid as u64, 0)),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Give `child` a copy of the file descriptors of `parent`.
+    pub(crate) fn inherit_fds(parent: Pid, child: Pid) -> Result<(u64, u64), KError> {
+        let cnrfs = CNRFS.borrow();
+        cnrfs
+            .as_ref()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response =
+                    replica.execute_mut_scan(Modify::ProcessInheritFds(parent, child), *token);
+                match response {
+                    Ok(MlnrNodeResult::FdsInherited) => Ok((0, 0)),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
     pub(crate) fn map_fd(
         pid: Pid,
         path: String,
@@ -225,8 +408,11 @@ impl MlnrKernelNode {
         kernslice: KernArcBuffer,
         offset: i64,
     ) -> Result<(u64, u64), KError> {
-        let mnode = match MlnrKernelNode::fd_to_mnode(pid, fd) {
-            Ok((mnode, _)) => mnode,
+        let mnode = match MlnrKernelNode::fd_target(pid, fd) {
+            Ok(FdTarget::Mnode(mnode)) => mnode,
+            Ok(FdTarget::Pipe(pipe)) => {
+                return MlnrKernelNode::pipe_write(pid, fd, pipe, kernslice, offset)
+            }
             Err(_) => return Err(KError::InvalidFileDescriptor),
         };
         let cnrfs = CNRFS.borrow();
@@ -245,17 +431,216 @@ impl MlnrKernelNode {
             })
     }
 
-    pub(crate) fn file_read(
+    /// Executes `ops` in order, returns the result of every operation.
+    ///
+    /// Runs of modifications (writes, truncates and seeks) and runs of reads
+    /// of files take one operation per log. Closes and operations on pipes
+    /// are executed on their own.
+    pub(crate) fn file_batch(
+        pid: Pid,
+        mut ops: Vec<RingOp>,
+    ) -> Result<Vec<Result<(u64, u64), KError>>, KError> {
+        let nlogs = NUM_LOGS.load(Ordering::Relaxed);
+        let mut results: Vec<Option<Result<(u64, u64), KError>>> =
+            Vec::try_with_capacity(ops.len())?;
+        for _i in 0..ops.len() {
+            results.push(None);
+        }
+
+        let mut targets: Vec<(FileDescriptor, FdTarget)> = Vec::new();
+        let mut modifications: Vec<LogBatch<(FileDescriptor, MnodeNum, FileModify)>> = Vec::new();
+        let mut reads: Vec<LogBatch<(FileDescriptor, MnodeNum, &mut dyn SliceAccess, i64)>> =
+            Vec::new();
+        for (index, op) in ops.iter_mut().enumerate() {
+            let fd = match op {
+                RingOp::Read(fd, _, _)
+                | RingOp::Write(fd, _, _)
+                | RingOp::Truncate(fd, _)
+                | RingOp::Seek(fd, _, _)
+                | RingOp::Close(fd) => *fd,
+            };
+            let target = match targets.iter().find(|(known, _target)| *known == fd) {
+                Some((_fd, target)) => *target,
+                None => match MlnrKernelNode::fd_target(pid, fd) {
+                    Ok(target) => {
+                        targets.try_push((fd, target))?;
+                        target
+                    }
+                    Err(_) => {
+                        results[index] = Some(Err(KError::InvalidFileDescriptor));
+                        continue;
+                    }
+                },
+            };
+            let log = |mnode: MnodeNum| (mnode as usize - MNODE_OFFSET) % nlogs;
+
+            match (op, target) {
+                (RingOp::Read(fd, buffer, offset), FdTarget::Mnode(mnode)) => {
+                    MlnrKernelNode::modify_batches(pid, &mut modifications, &mut results)?;
+                    let read = (*fd, mnode, buffer as &mut dyn SliceAccess, *offset);
+                    LogBatch::add(&mut reads, log(mnode), index, read)?;
+                }
+                (RingOp::Write(fd, buffer, offset), FdTarget::Mnode(mnode)) => {
+                    MlnrKernelNode::read_batches(pid, &mut reads, &mut results)?;
+                    match KernArcBuffer::try_from(*buffer) {
+                        Ok(kernslice) => {
+                            let write = FileModify::Write(kernslice.buffer, *offset);
+                            LogBatch::add(
+                                &mut modifications,
+                                log(mnode),
+                                index,
+                                (*fd, mnode, write),
+                            )?;
+                        }
+                        Err(e) => results[index] = Some(Err(e)),
+                    }
+                }
+                (RingOp::Truncate(fd, len), FdTarget::Mnode(mnode)) => {
+                    MlnrKernelNode::read_batches(pid, &mut reads, &mut results)?;
+                    let truncate = FileModify::Truncate(*len);
+                    LogBatch::add(
+                        &mut modifications,
+                        log(mnode),
+                        index,
+                        (*fd, mnode, truncate),
+                    )?;
+                }
+                (RingOp::Seek(fd, offset, whence), FdTarget::Mnode(mnode)) => {
+                    MlnrKernelNode::read_batches(pid, &mut reads, &mut results)?;
+                    let seek = FileModify::Seek(*offset, *whence);
+                    LogBatch::add(&mut modifications, log(mnode), index, (*fd, mnode, seek))?;
+                }
+                (op, target) => {
+                    // Pipes may block, so they don't go in a batch
+                    MlnrKernelNode::read_batches(pid, &mut reads, &mut results)?;
+                    MlnrKernelNode::modify_batches(pid, &mut modifications, &mut results)?;
+                    results[index] = Some(match (op, target) {
+                        (RingOp::Read(fd, buffer, offset), FdTarget::Pipe(pipe)) => {
+                            MlnrKernelNode::pipe_read(pid, *fd, pipe, buffer, *offset)
+                        }
+                        (RingOp::Write(fd, buffer, offset), FdTarget::Pipe(pipe)) => {
+                            KernArcBuffer::try_from(*buffer).and_then(|kernslice| {
+                                MlnrKernelNode::pipe_write(pid, *fd, pipe, kernslice, *offset)
+                            })
+                        }
+                        (RingOp::Close(fd), _) => {
+                            targets.retain(|(known, _target)| known != fd);
+                            MlnrKernelNode::unmap_fd(pid, *fd)
+                        }
+                        // Truncate and seek fail for pipes
+                        (RingOp::Truncate(fd, len), _) => {
+                            MlnrKernelNode::file_truncate(pid, *fd, *len)
+                        }
+                        (RingOp::Seek(fd, offset, whence), _) => {
+                            MlnrKernelNode::file_seek(pid, *fd, *offset, *whence)
+                        }
+                        _ => unreachable!("Files are batched"),
+                    });
+                }
+            }
+        }
+        MlnrKernelNode::read_batches(pid, &mut reads, &mut results)?;
+        MlnrKernelNode::modify_batches(pid, &mut modifications, &mut results)?;
+
+        Ok(results
+            .into_iter()
+            .map(|result| result.unwrap_or(Err(KError::NotSupported)))
+            .collect())
+    }
+
+    /// Appends the modifications of `batches` to their logs (one operation per
+    /// log) and stores their results.
+    fn modify_batches(
+        pid: Pid,
+        batches: &mut Vec<LogBatch<(FileDescriptor, MnodeNum, FileModify)>>,
+        results: &mut [Option<Result<(u64, u64), KError>>],
+    ) -> Result<(), KError> {
+        let cnrfs = CNRFS.borrow();
+        let (replica, token) = cnrfs.as_ref().ok_or(KError::ReplicaNotSet)?;
+        for batch in batches.drain(..) {
+            let response = replica.execute_mut(Modify::FileBatch(pid, batch.ops), *token);
+            store_batch_results(&batch.indices, response, results);
+        }
+        Ok(())
+    }
+
+    /// Executes the reads of `batches` (one operation per log) and stores
+    /// their results.
+    fn read_batches(
+        pid: Pid,
+        batches: &mut Vec<LogBatch<(FileDescriptor, MnodeNum, &mut dyn SliceAccess, i64)>>,
+        results: &mut [Option<Result<(u64, u64), KError>>],
+    ) -> Result<(), KError> {
+        let cnrfs = CNRFS.borrow();
+        let (replica, token) = cnrfs.as_ref().ok_or(KError::ReplicaNotSet)?;
+        for batch in batches.drain(..) {
+            let response = replica.execute(Access::FileReadBatch(pid, batch.ops), *token);
+            store_batch_results(&batch.indices, response, results);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn file_truncate(
+        pid: Pid,
+        fd: FileDescriptor,
+        len: u64,
+    ) -> Result<(u64, u64), KError> {
+        let mnode = match MlnrKernelNode::fd_to_mnode(pid, fd) {
+            Ok((mnode, _)) => mnode,
+            Err(_) => return Err(KError::InvalidFileDescriptor),
+        };
+        let cnrfs = CNRFS.borrow();
+        cnrfs
+            .as_ref()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response =
+                    replica.execute_mut(Modify::FileTruncate(pid, fd, mnode, len), *token);
+                match response {
+                    Ok(MlnrNodeResult::FileTruncated) => Ok((0, 0)),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    pub(crate) fn file_seek(
         pid: Pid,
         fd: FileDescriptor,
-        buffer: &mut dyn SliceAccess,
         offset: i64,
+        whence: SeekWhence,
     ) -> Result<(u64, u64), KError> {
         let mnode = match MlnrKernelNode::fd_to_mnode(pid, fd) {
             Ok((mnode, _)) => mnode,
             Err(_) => return Err(KError::InvalidFileDescriptor),
         };
         let cnrfs = CNRFS.borrow();
+        cnrfs
+            .as_ref()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response =
+                    replica.execute_mut(Modify::FileSeek(pid, fd, mnode, offset, whence), *token);
+                match response {
+                    Ok(MlnrNodeResult::FileSeeked(new_offset)) => Ok((new_offset, 0)),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    pub(crate) fn file_read(
+        pid: Pid,
+        fd: FileDescriptor,
+        buffer: &mut dyn SliceAccess,
+        offset: i64,
+    ) -> Result<(u64, u64), KError> {
+        let mnode = match MlnrKernelNode::fd_target(pid, fd) {
+            Ok(FdTarget::Mnode(mnode)) => mnode,
+            Ok(FdTarget::Pipe(pipe)) => {
+                return MlnrKernelNode::pipe_read(pid, fd, pipe, buffer, offset)
+            }
+            Err(_) => return Err(KError::InvalidFileDescriptor),
+        };
+        let cnrfs = CNRFS.borrow();
         cnrfs
             .as_ref()
             .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
@@ -270,6 +655,103 @@ impl MlnrKernelNode {
             })
     }
 
+    /// Create a pipe, returns the file descriptors of the read and the write
+    /// end.
+    pub(crate) fn pipe(pid: Pid) -> Result<(u64, u64), KError> {
+        let cnrfs = CNRFS.borrow();
+        cnrfs
+            .as_ref()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut_scan(Modify::PipeCreate(pid), *token);
+                match response {
+                    Ok(MlnrNodeResult::PipeCreated(read_fd, write_fd)) => {
+                        Ok((read_fd.into(), write_fd.into()))
+                    }
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Checks (without going through the log) whether reading from (or
+    /// writing to) the pipe behind `fd` would block.
+    fn pipe_ready(pid: Pid, fd: FileDescriptor, pipe: PipeNum) -> Result<bool, KError> {
+        let cnrfs = CNRFS.borrow();
+        cnrfs
+            .as_ref()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute(Access::PipeReady(pid, fd, pipe), *token);
+                match response {
+                    Ok(MlnrNodeResult::PipeReady(ready)) => Ok(ready),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Reading from a pipe takes the data out of it, so unlike file reads
+    /// this has to go through the log (unless the read would block).
+    fn pipe_read(
+        pid: Pid,
+        fd: FileDescriptor,
+        pipe: PipeNum,
+        buffer: &mut dyn SliceAccess,
+        offset: i64,
+    ) -> Result<(u64, u64), KError> {
+        if offset != -1 {
+            return Err(KError::InvalidOffset);
+        }
+        // Callers retry until there is data, don't fill the log meanwhile
+        if !MlnrKernelNode::pipe_ready(pid, fd, pipe)? {
+            return Err(KError::WouldBlock);
+        }
+
+        let cnrfs = CNRFS.borrow();
+        cnrfs
+            .as_ref()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response =
+                    replica.execute_mut(Modify::PipeRead(pid, fd, pipe, buffer.len()), *token);
+                match response {
+                    Ok(MlnrNodeResult::PipeData(data)) => {
+                        buffer.write_subslice(&data, 0)?;
+                        Ok((data.len() as u64, 0))
+                    }
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    fn pipe_write(
+        pid: Pid,
+        fd: FileDescriptor,
+        pipe: PipeNum,
+        kernslice: KernArcBuffer,
+        offset: i64,
+    ) -> Result<(u64, u64), KError> {
+        if offset != -1 {
+            return Err(KError::InvalidOffset);
+        }
+        // Callers retry until there is space, don't fill the log meanwhile
+        if !kernslice.buffer.is_empty() && !MlnrKernelNode::pipe_ready(pid, fd, pipe)? {
+            return Err(KError::WouldBlock);
+        }
+
+        let cnrfs = CNRFS.borrow();
+        cnrfs
+            .as_ref()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response =
+                    replica.execute_mut(Modify::PipeWrite(pid, fd, pipe, kernslice.buffer), *token);
+                match response {
+                    Ok(MlnrNodeResult::FileAccessed(len)) => Ok((len, 0)),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
     pub(crate) fn unmap_fd(pid: Pid, fd: FileDescriptor) -> Result<(u64, u64), KError> {
         let cnrfs = CNRFS.borrow();
         cnrfs
@@ -317,6 +799,25 @@ impl MlnrKernelNode {
             })
     }
 
+    pub(crate) fn file_stat(pid: Pid, fd: FileDescriptor) -> Result<(u64, u64), KError> {
+        let mnode = match MlnrKernelNode::fd_to_mnode(pid, fd) {
+            Ok((mnode, _)) => mnode,
+            Err(_) => return Err(KError::InvalidFileDescriptor),
+        };
+        let cnrfs = CNRFS.borrow();
+        cnrfs
+            .as_ref()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute(Access::FileStat(pid, fd, mnode), *token);
+
+                match response {
+                    Ok(MlnrNodeResult::FileInfo(f_info)) => Ok((f_info.ftype, f_info.fsize)),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
     pub(crate) fn file_rename(
         pid: Pid,
         oldname: String,
@@ -352,8 +853,30 @@ impl MlnrKernelNode {
             })
     }
 
+    pub(crate) fn readdir(pid: Pid, path: String) -> Result<Vec<DirEntry>, KError> {
+        let cnrfs = CNRFS.borrow();
+        cnrfs
+            .as_ref()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute(Access::ReadDir(pid, path), *token);
+
+                match response {
+                    Ok(MlnrNodeResult::DirEntries(entries)) => Ok(entries),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
     #[inline(always)]
     pub(crate) fn fd_to_mnode(pid: Pid, fd: FileDescriptor) -> Result<(u64, u64), KError> {
+        match MlnrKernelNode::fd_target(pid, fd)? {
+            FdTarget::Mnode(mnode) => Ok((mnode, 0)),
+            FdTarget::Pipe(_pipe) => Err(KError::InvalidFileDescriptor),
+        }
+    }
+
+    fn fd_target(pid: Pid, fd: FileDescriptor) -> Result<FdTarget, KError> {
         let cnrfs = CNRFS.borrow();
         cnrfs
             .as_ref()
@@ -361,7 +884,8 @@ impl MlnrKernelNode {
                 let response = replica.execute(Access::FdToMnode(pid, fd), *token);
 
                 match response {
-                    Ok(MlnrNodeResult::MappedFileToMnode(mnode)) => Ok((mnode, 0)),
+                    Ok(MlnrNodeResult::MappedFileToMnode(mnode)) => Ok(FdTarget::Mnode(mnode)),
+                    Ok(MlnrNodeResult::MappedFdToPipe(pipe)) => Ok(FdTarget::Pipe(pipe)),
                     Err(e) => Err(e),
                     Ok(_) => unreachable!("Got unexpected response"),
                 }
@@ -384,6 +908,72 @@ impl MlnrKernelNode {
             })
     }
 
+    /// Returns the mnode of `fd` along with the (local) frames that hold
+    /// `offset..offset+len` of the file.
+    ///
+    /// The pages stay allocated until they're released with `file_unmap`.
+    pub(crate) fn file_map(
+        pid: Pid,
+        fd: FileDescriptor,
+        offset: usize,
+        len: usize,
+        writable: bool,
+    ) -> Result<(MnodeNum, Vec<Frame>), KError> {
+        let (mnode, _) = MlnrKernelNode::fd_to_mnode(pid, fd)?;
+        let cnrfs = CNRFS.borrow();
+        cnrfs
+            .as_ref()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut(
+                    Modify::FileMap(pid, fd, mnode, offset, len, writable),
+                    *token,
+                );
+                match response {
+                    Ok(MlnrNodeResult::FileMapped(frames)) => Ok((mnode, frames)),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Releases `pages` pages of `mnode` that were mapped with `file_map`.
+    pub(crate) fn file_unmap(pid: Pid, mnode: MnodeNum, pages: usize) -> Result<(), KError> {
+        let cnrfs = CNRFS.borrow();
+        cnrfs
+            .as_ref()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut(Modify::FileUnmap(pid, mnode, pages), *token);
+                match response {
+                    Ok(MlnrNodeResult::FileUnmapped) => Ok(()),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Writes back the content of a shared mapping of `mnode` at `offset`.
+    pub(crate) fn file_write_back(
+        pid: Pid,
+        mnode: MnodeNum,
+        kernslice: KernArcBuffer,
+        offset: usize,
+    ) -> Result<(u64, u64), KError> {
+        let cnrfs = CNRFS.borrow();
+        cnrfs
+            .as_ref()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut(
+                    Modify::FileWriteBack(pid, mnode, kernslice.buffer, offset),
+                    *token,
+                );
+                match response {
+                    Ok(MlnrNodeResult::FileAccessed(len)) => Ok((len, 0)),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
     pub(crate) fn synchronize_log(log_id: usize) -> Result<(u64, u64), KError> {
         let cnrfs = CNRFS.borrow();
         cnrfs
@@ -399,6 +989,165 @@ impl MlnrKernelNode {
     }
 }
 
+impl MlnrKernelNode {
+    /// Drops the reference `fd` holds on a pipe (if it refers to one), the
+    /// pipe goes away once no file descriptor refers to it anymore.
+    fn release_pipe_end(&self, fd: &FileDescriptorEntry) {
+        if !fd.is_pipe() {
+            return;
+        }
+
+        let mut pipes = self.pipes.write();
+        if let Some(pipe) = pipes.get_mut(&fd.mnode()) {
+            pipe.close_end(fd.flags().is_write());
+            if pipe.is_closed() {
+                pipes.remove(&fd.mnode());
+            }
+        }
+    }
+
+    /// Reads from the file behind `fd` at `offset` into `userslice` (-1 uses
+    /// the offset of `fd` and moves it), returns how many bytes were read.
+    fn read_fd(
+        &self,
+        pid: Pid,
+        fd: FileDescriptor,
+        userslice: &mut dyn SliceAccess,
+        offset: i64,
+    ) -> Result<usize, KError> {
+        let process_lookup = self.process_map.read();
+        let p = process_lookup
+            .get(&pid)
+            .ok_or(KError::NoProcessFoundForPid)?;
+
+        let fd = p.get_fd(fd).ok_or(KError::PermissionError)?;
+        // The fd may have been reused for a pipe since the op was logged
+        if fd.is_pipe() {
+            return Err(KError::InvalidFileDescriptor);
+        }
+
+        let mnode_num = fd.mnode();
+        let flags = fd.flags();
+
+        // Check if the file has read-only or read-write permissions before reading it.
+        if !flags.is_read() {
+            return Err(KError::PermissionError);
+        }
+
+        // If the arguments doesn't provide an offset,
+        // then use the offset associated with the FD.
+        let mut curr_offset: usize = offset as usize;
+        if offset == -1 {
+            curr_offset = fd.offset();
+        }
+
+        let len = self.fs.read(mnode_num, userslice, curr_offset)?;
+        // Update the FD associated offset only when the
+        // offset wasn't given in the arguments.
+        if offset == -1 {
+            fd.update_offset(curr_offset + len);
+        }
+        Ok(len)
+    }
+
+    /// Resizes the file behind `fd` to `len` bytes.
+    fn truncate_fd(&self, pid: Pid, fd: FileDescriptor, len: u64) -> Result<(), KError> {
+        let process_lookup = self.process_map.read();
+        let p = process_lookup
+            .get(&pid)
+            .ok_or(KError::NoProcessFoundForPid)?;
+        let fd = p.get_fd(fd).ok_or(KError::PermissionError)?;
+        if fd.is_pipe() {
+            return Err(KError::InvalidFileDescriptor);
+        }
+
+        // Check if the file has write-only or read-write permissions before resizing it.
+        if !fd.flags().is_write() {
+            return Err(KError::PermissionError);
+        }
+
+        self.fs.resize(fd.mnode(), len as usize)
+    }
+
+    /// Moves the offset of `fd`, returns the new offset.
+    fn seek_fd(
+        &self,
+        pid: Pid,
+        fd: FileDescriptor,
+        offset: i64,
+        whence: SeekWhence,
+    ) -> Result<u64, KError> {
+        let process_lookup = self.process_map.read();
+        let p = process_lookup
+            .get(&pid)
+            .ok_or(KError::NoProcessFoundForPid)?;
+        let fd = p.get_fd(fd).ok_or(KError::PermissionError)?;
+        if fd.is_pipe() {
+            return Err(KError::InvalidFileDescriptor);
+        }
+
+        let base = match whence {
+            SeekWhence::Set => 0,
+            SeekWhence::Current => fd.offset(),
+            SeekWhence::End => self.fs.file_info(fd.mnode()).fsize as usize,
+        };
+
+        // Seeking past the end is fine, the gap gets filled on write.
+        let new_offset = (base as i64)
+            .checked_add(offset)
+            .filter(|new_offset| *new_offset >= 0)
+            .ok_or(KError::InvalidOffset)?;
+        fd.update_offset(new_offset as usize);
+        Ok(new_offset as u64)
+    }
+
+    /// Writes `kernslice` to the file behind `fd` at `offset` (-1 uses the
+    /// offset of `fd` and moves it), returns how many bytes were written.
+    fn write_fd(
+        &self,
+        pid: Pid,
+        fd: FileDescriptor,
+        kernslice: &[u8],
+        offset: i64,
+    ) -> Result<usize, KError> {
+        let process_lookup = self.process_map.read();
+        let p = process_lookup
+            .get(&pid)
+            .expect("TODO: FileWrite process lookup failed");
+        let fd = p.get_fd(fd).ok_or(KError::PermissionError)?;
+        if fd.is_pipe() {
+            return Err(KError::InvalidFileDescriptor);
+        }
+
+        let mnode_num = fd.mnode();
+        let flags = fd.flags();
+
+        // Check if the file has write-only or read-write permissions before reading it.
+        if !flags.is_write() {
+            return Err(KError::PermissionError);
+        }
+
+        let mut curr_offset: usize = offset as usize;
+        if offset == -1 {
+            if flags.is_append() {
+                // If offset value is not provided and file is opened with O_APPEND flag.
+                let finfo = self.fs.file_info(mnode_num);
+                curr_offset = finfo.fsize as usize;
+            } else {
+                // If offset value is not provided and file is doesn't have O_APPEND flag.
+                curr_offset = fd.offset();
+            }
+        }
+
+        let len = self.fs.write(mnode_num, kernslice, curr_offset)?;
+        if offset == -1 {
+            // Update offset when FileWrite doesn't give an explicit offset value.
+            fd.update_offset(curr_offset + len);
+        }
+        Ok(len)
+    }
+}
+
 impl Dispatch for MlnrKernelNode {
     type ReadOperation<'rop> = Access<'rop>;
     type WriteOperation = Modify;
@@ -407,39 +1156,19 @@ impl Dispatch for MlnrKernelNode {
     fn dispatch<'rop>(&self, op: Self::ReadOperation<'_>) -> Self::Response {
         match op {
             Access::FileRead(pid, fd, _mnode, userslice, offset) => {
-                let process_lookup = self.process_map.read();
-                let p = process_lookup
-                    .get(&pid)
-                    .ok_or(KError::NoProcessFoundForPid)?;
-
-                let fd = p.get_fd(fd).ok_or(KError::PermissionError)?;
-
-                let mnode_num = fd.mnode();
-                let flags = fd.flags();
-
-                // Check if the file has read-only or read-write permissions before reading it.
-                if !flags.is_read() {
-                    return Err(KError::PermissionError);
-                }
-
-                // If the arguments doesn't provide an offset,
-                // then use the offset associated with the FD.
-                let mut curr_offset: usize = offset as usize;
-                if offset == -1 {
-                    curr_offset = fd.offset();
-                }
+                let len = self.read_fd(pid, fd, userslice, offset)?;
+                Ok(MlnrNodeResult::FileAccessed(len as u64))
+            }
 
-                match self.fs.read(mnode_num, userslice, curr_offset) {
-                    Ok(len) => {
-                        // Update the FD associated offset only when the
-                        // offset wasn't given in the arguments.
-                        if offset == -1 {
-                            fd.update_offset(curr_offset + len);
-                        }
-                        Ok(MlnrNodeResult::FileAccessed(len as u64))
-                    }
-                    Err(e) => Err(e),
+            Access::FileReadBatch(pid, reads) => {
+                let mut results = Vec::try_with_capacity(reads.len())?;
+                for (fd, _mnode, userslice, offset) in reads {
+                    results.push(
+                        self.read_fd(pid, fd, userslice, offset)
+                            .map(|len| (len as u64, 0)),
+                    );
                 }
+                Ok(MlnrNodeResult::FileBatchAccessed(results))
             }
 
             Access::FileInfo(pid, name, _mnode) => {
@@ -449,7 +1178,20 @@ impl Dispatch for MlnrKernelNode {
                     .get(&pid)
                     .ok_or(KError::NoProcessFoundForPid)?;
                 let mnode = self.fs.lookup(&name).ok_or(KError::InvalidFile)?;
-                let f_info = self.fs.file_info(*mnode);
+                let f_info = self.fs.file_info(mnode);
+                Ok(MlnrNodeResult::FileInfo(f_info))
+            }
+
+            Access::FileStat(pid, fd, _mnode) => {
+                let process_lookup = self.process_map.read();
+                let p = process_lookup
+                    .get(&pid)
+                    .ok_or(KError::NoProcessFoundForPid)?;
+                let fd = p.get_fd(fd).ok_or(KError::PermissionError)?;
+                if fd.is_pipe() {
+                    return Err(KError::InvalidFileDescriptor);
+                }
+                let f_info = self.fs.file_info(fd.mnode());
                 Ok(MlnrNodeResult::FileInfo(f_info))
             }
 
@@ -460,6 +1202,9 @@ impl Dispatch for MlnrKernelNode {
                     .ok_or(KError::NoProcessFoundForPid)?;
 
                 let fd = p.get_fd(fd).ok_or(KError::PermissionError)?;
+                if fd.is_pipe() {
+                    return Ok(MlnrNodeResult::MappedFdToPipe(fd.mnode()));
+                }
                 let mnode_num = fd.mnode();
                 Ok(MlnrNodeResult::MappedFileToMnode(mnode_num))
             }
@@ -473,11 +1218,40 @@ impl Dispatch for MlnrKernelNode {
 
                 match self.fs.lookup(&filename) {
                     // match on (file_exists, mnode_number)
-                    Some(mnode) => Ok(MlnrNodeResult::MappedFileToMnode(*mnode)),
+                    Some(mnode) => Ok(MlnrNodeResult::MappedFileToMnode(mnode)),
                     None => Err(KError::InvalidFile),
                 }
             }
 
+            Access::ReadDir(pid, pathname) => {
+                let _p = self
+                    .process_map
+                    .read()
+                    .get(&pid)
+                    .ok_or(KError::NoProcessFoundForPid)?;
+                let entries = self.fs.readdir(&pathname)?;
+                Ok(MlnrNodeResult::DirEntries(entries))
+            }
+
+            Access::PipeReady(pid, fd, _pipe) => {
+                let process_lookup = self.process_map.read();
+                let p = process_lookup
+                    .get(&pid)
+                    .ok_or(KError::NoProcessFoundForPid)?;
+                let fd = p.get_fd(fd).ok_or(KError::PermissionError)?;
+                if !fd.is_pipe() {
+                    return Err(KError::PermissionError);
+                }
+
+                let pipes = self.pipes.read();
+                let pipe = pipes
+                    .get(&fd.mnode())
+                    .ok_or(KError::InvalidFileDescriptor)?;
+                Ok(MlnrNodeResult::PipeReady(
+                    pipe.is_ready(fd.flags().is_write()),
+                ))
+            }
+
             Access::Synchronize(_log_id) => {
                 // A NOP that just makes sure we've advanced the replica
                 Ok(MlnrNodeResult::Synchronized)
@@ -495,11 +1269,47 @@ impl Dispatch for MlnrKernelNode {
                 Ok(MlnrNodeResult::ProcessAdded(pid))
             }
 
-            //            Modify::ProcessRemove(pid) => {
-            //                let mut pmap = self.process_map.write();
-            //                let _file_desc = pmap.remove(&pid).ok_or(KError::NoFileDescForPid)?;
-            //                Ok(MlnrNodeResult::ProcessRemoved(pid))
-            //            }
+            Modify::ProcessRemove(pid) => {
+                let mut pmap = self.process_map.write();
+                let file_desc = pmap.remove(&pid).ok_or(KError::NoFileDescForPid)?;
+                for (_fd, entry) in file_desc.iter() {
+                    self.release_pipe_end(entry);
+                }
+                // Whatever the process still had mapped isn't anymore
+                if let Some(mapped) = self.mapped.write().remove(&pid) {
+                    for (mnode, pages) in mapped {
+                        self.fs.unmap(mnode, pages);
+                    }
+                }
+                Ok(MlnrNodeResult::ProcessRemoved(pid))
+            }
+
+            Modify::ProcessInheritFds(parent, child) => {
+                let mut pmap = self.process_map.write();
+                let parent_fds = pmap.get(&parent).ok_or(KError::NoFileDescForPid)?;
+                let mut inherited = Vec::try_with_capacity(parent_fds.iter().count())?;
+                for (fd, entry) in parent_fds.iter() {
+                    inherited.push((fd, entry.clone()));
+                }
+
+                let child_fds = pmap.get_mut(&child).ok_or(KError::NoFileDescForPid)?;
+                // Only a fresh process can inherit, it would lose its own fds otherwise
+                if child_fds.iter().next().is_some() {
+                    return Err(KError::FileDescForPidAlreadyAdded);
+                }
+
+                let mut pipes = self.pipes.write();
+                for (fd, entry) in inherited {
+                    if entry.is_pipe() {
+                        if let Some(pipe) = pipes.get_mut(&entry.mnode()) {
+                            pipe.open_end(entry.flags().is_write());
+                        }
+                    }
+                    child_fds.set_fd(fd, entry);
+                }
+                Ok(MlnrNodeResult::FdsInherited)
+            }
+
             Modify::FileOpen(pid, filename, flags, modes) => {
                 let mnode = self.fs.lookup(&filename);
                 if mnode.is_none() && !flags.is_create() {
@@ -521,7 +1331,7 @@ impl Dispatch for MlnrKernelNode {
                             return Err(e);
                         }
                     }
-                    mnode_num = *mnode;
+                    mnode_num = mnode;
                 } else {
                     match self.fs.create(filename, modes) {
                         Ok(m_num) => mnode_num = m_num,
@@ -537,42 +1347,36 @@ impl Dispatch for MlnrKernelNode {
             }
 
             Modify::FileWrite(pid, fd, _mnode, kernslice, offset) => {
-                let process_lookup = self.process_map.read();
-                let p = process_lookup
-                    .get(&pid)
-                    .expect("TODO: FileWrite process lookup failed");
-                let fd = p.get_fd(fd).ok_or(KError::PermissionError)?;
-
-                let mnode_num = fd.mnode();
-                let flags = fd.flags();
+                let len = self.write_fd(pid, fd, &kernslice, offset)?;
+                Ok(MlnrNodeResult::FileAccessed(len as u64))
+            }
 
-                // Check if the file has write-only or read-write permissions before reading it.
-                if !flags.is_write() {
-                    return Err(KError::PermissionError);
+            Modify::FileBatch(pid, ops) => {
+                let mut results = Vec::try_with_capacity(ops.len())?;
+                for (fd, _mnode, op) in ops {
+                    results.push(match op {
+                        FileModify::Write(kernslice, offset) => self
+                            .write_fd(pid, fd, &kernslice, offset)
+                            .map(|len| (len as u64, 0)),
+                        FileModify::Truncate(len) => {
+                            self.truncate_fd(pid, fd, len).map(|()| (0, 0))
+                        }
+                        FileModify::Seek(offset, whence) => self
+                            .seek_fd(pid, fd, offset, whence)
+                            .map(|new_offset| (new_offset, 0)),
+                    });
                 }
+                Ok(MlnrNodeResult::FileBatchAccessed(results))
+            }
 
-                let mut curr_offset: usize = offset as usize;
-                if offset == -1 {
-                    if flags.is_append() {
-                        // If offset value is not provided and file is opened with O_APPEND flag.
-                        let finfo = self.fs.file_info(mnode_num);
-                        curr_offset = finfo.fsize as usize;
-                    } else {
-                        // If offset value is not provided and file is doesn't have O_APPEND flag.
-                        curr_offset = fd.offset();
-                    }
-                }
+            Modify::FileTruncate(pid, fd, _mnode, len) => {
+                self.truncate_fd(pid, fd, len)?;
+                Ok(MlnrNodeResult::FileTruncated)
+            }
 
-                match self.fs.write(mnode_num, &kernslice, curr_offset) {
-                    Ok(len) => {
-                        if offset == -1 {
-                            // Update offset when FileWrite doesn't give an explicit offset value.
-                            fd.update_offset(curr_offset + len);
-                        }
-                        Ok(MlnrNodeResult::FileAccessed(len as u64))
-                    }
-                    Err(e) => Err(e),
-                }
+            Modify::FileSeek(pid, fd, _mnode, offset, whence) => {
+                let new_offset = self.seek_fd(pid, fd, offset, whence)?;
+                Ok(MlnrNodeResult::FileSeeked(new_offset))
             }
 
             Modify::FileClose(pid, fd) => {
@@ -580,6 +1384,9 @@ impl Dispatch for MlnrKernelNode {
                 let p = process_lookup
                     .get_mut(&pid)
                     .expect("TODO: FileClose process lookup failed");
+                if let Some(entry) = p.get_fd(fd) {
+                    self.release_pipe_end(entry);
+                }
                 p.deallocate_fd(fd)?;
                 Ok(MlnrNodeResult::FileClosed(fd))
             }
@@ -613,6 +1420,126 @@ impl Dispatch for MlnrKernelNode {
                 self.fs.mkdir(filename, modes)?;
                 Ok(MlnrNodeResult::DirCreated)
             }
+
+            Modify::PipeCreate(pid) => {
+                let mut pmap = self.process_map.write();
+                let p = pmap.get_mut(&pid).ok_or(KError::NoProcessFoundForPid)?;
+                let mut pipes = self.pipes.write();
+                pipes.try_reserve(1)?;
+                let pipe = Pipe::new()?;
+                // Only used up once we have the file descriptors
+                let pipe_num = self.next_pipe.load(Ordering::Relaxed);
+
+                let (read_fd, read_end) = p.allocate_fd().ok_or(KError::OpenFileLimit)?;
+                read_end.update_pipe(pipe_num, FileFlags::O_RDONLY);
+                let (write_fd, write_end) = match p.allocate_fd() {
+                    Some(fd) => fd,
+                    None => {
+                        p.deallocate_fd(read_fd)?;
+                        return Err(KError::OpenFileLimit);
+                    }
+                };
+                write_end.update_pipe(pipe_num, FileFlags::O_WRONLY);
+
+                self.next_pipe.store(pipe_num + 1, Ordering::Relaxed);
+                pipes.insert(pipe_num, pipe);
+                Ok(MlnrNodeResult::PipeCreated(read_fd, write_fd))
+            }
+
+            Modify::PipeRead(pid, fd, _pipe, len) => {
+                let process_lookup = self.process_map.read();
+                let p = process_lookup
+                    .get(&pid)
+                    .ok_or(KError::NoProcessFoundForPid)?;
+                let fd = p.get_fd(fd).ok_or(KError::PermissionError)?;
+                if !fd.is_pipe() || !fd.flags().is_read() {
+                    return Err(KError::PermissionError);
+                }
+
+                let mut pipes = self.pipes.write();
+                let pipe = pipes
+                    .get_mut(&fd.mnode())
+                    .ok_or(KError::InvalidFileDescriptor)?;
+                Ok(MlnrNodeResult::PipeData(pipe.read(len)?))
+            }
+
+            Modify::PipeWrite(pid, fd, _pipe, kernslice) => {
+                let process_lookup = self.process_map.read();
+                let p = process_lookup
+                    .get(&pid)
+                    .ok_or(KError::NoProcessFoundForPid)?;
+                let fd = p.get_fd(fd).ok_or(KError::PermissionError)?;
+                if !fd.is_pipe() || !fd.flags().is_write() {
+                    return Err(KError::PermissionError);
+                }
+
+                let mut pipes = self.pipes.write();
+                let pipe = pipes
+                    .get_mut(&fd.mnode())
+                    .ok_or(KError::InvalidFileDescriptor)?;
+                let len = pipe.write(&kernslice)?;
+                Ok(MlnrNodeResult::FileAccessed(len as u64))
+            }
+
+            Modify::FileMap(pid, fd, _mnode, offset, len, writable) => {
+                let process_lookup = self.process_map.read();
+                let p = process_lookup
+                    .get(&pid)
+                    .ok_or(KError::NoProcessFoundForPid)?;
+                let fd = p.get_fd(fd).ok_or(KError::PermissionError)?;
+                if fd.is_pipe() {
+                    return Err(KError::InvalidFileDescriptor);
+                }
+                // A writable mapping needs a file that was opened for writing
+                if !fd.flags().is_read() || (writable && !fd.flags().is_write()) {
+                    return Err(KError::PermissionError);
+                }
+
+                let mut mapped = self.mapped.write();
+                mapped.try_reserve(1)?;
+                let pins = mapped.entry(pid).or_insert_with(Vec::new);
+                pins.try_reserve(1)?;
+
+                let frames = self.fs.map(fd.mnode(), offset, len, writable)?;
+                let pages = len / BASE_PAGE_SIZE;
+                match pins.iter_mut().find(|(mnode, _)| *mnode == fd.mnode()) {
+                    Some((_mnode, count)) => *count += pages,
+                    None => pins.push((fd.mnode(), pages)),
+                }
+                Ok(MlnrNodeResult::FileMapped(frames))
+            }
+
+            Modify::FileUnmap(pid, mnode, pages) => {
+                let mut mapped = self.mapped.write();
+                let pins = mapped.get_mut(&pid).ok_or(KError::NoProcessFoundForPid)?;
+                let idx = pins
+                    .iter()
+                    .position(|(m, count)| *m == mnode && *count >= pages)
+                    .ok_or(KError::InvalidFile)?;
+
+                pins[idx].1 -= pages;
+                if pins[idx].1 == 0 {
+                    pins.swap_remove(idx);
+                }
+                self.fs.unmap(mnode, pages);
+                Ok(MlnrNodeResult::FileUnmapped)
+            }
+
+            Modify::FileWriteBack(pid, mnode, kernslice, offset) => {
+                let pinned = self
+                    .mapped
+                    .read()
+                    .get(&pid)
+                    .map_or(false, |pins| pins.iter().any(|(m, _)| *m == mnode));
+                if !pinned {
+                    return Err(KError::PermissionError);
+                }
+
+                // Every replica copies the data (also the one the mapping
+                // refers to), so they all apply the same writes in log order
+                let len = self.fs.write_back(mnode, &kernslice, offset)?;
+                Ok(MlnrNodeResult::FileAccessed(len as u64))
+            }
         }
     }
 }
@@ -7,6 +7,7 @@ use core::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::error::KError;
 
+use super::pipe::PipeNum;
 use super::{FileFlags, MnodeNum, MAX_FILES_PER_PROCESS};
 
 /// A user-space file descriptor.
@@ -95,14 +96,41 @@ impl FileDescriptorTable {
         let idx: usize = fd.into();
         self.table[idx].as_ref()
     }
+
+    /// Install `entry` as `fd`, replacing whatever `fd` referred to before.
+    pub(crate) fn set_fd(&mut self, fd: FileDescriptor, entry: FileDescriptorEntry) {
+        let idx: usize = fd.into();
+        self.table[idx] = Some(entry);
+    }
+
+    /// Iterate over all open file descriptors.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (FileDescriptor, &FileDescriptorEntry)> {
+        self.table
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, fd)| fd.as_ref().map(|fd| (FileDescriptor::new(idx), fd)))
+    }
 }
 
 /// A file descriptor representaion.
 #[derive(Debug, Default)]
 pub(crate) struct FileDescriptorEntry {
+    /// The mnode of the file, or the pipe number if `pipe` is set.
     mnode: MnodeNum,
     flags: FileFlags,
     offset: AtomicUsize,
+    pipe: bool,
+}
+
+impl Clone for FileDescriptorEntry {
+    fn clone(&self) -> Self {
+        FileDescriptorEntry {
+            mnode: self.mnode,
+            flags: self.flags,
+            offset: AtomicUsize::new(self.offset()),
+            pipe: self.pipe,
+        }
+    }
 }
 
 impl FileDescriptorEntry {
@@ -111,6 +139,16 @@ impl FileDescriptorEntry {
         self.flags = flags;
     }
 
+    pub(super) fn update_pipe(&mut self, pipe: PipeNum, flags: FileFlags) {
+        self.mnode = pipe;
+        self.flags = flags;
+        self.pipe = true;
+    }
+
+    pub(super) fn is_pipe(&self) -> bool {
+        self.pipe
+    }
+
     pub(super) fn mnode(&self) -> MnodeNum {
         self.mnode
     }
@@ -2,72 +2,151 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 use alloc::vec::Vec;
-use core::mem::size_of;
-
-use fallible_collections::{FallibleVec, FallibleVecGlobal, TryReserveError};
+use core::alloc::Layout;
+use core::cmp::{max, min};
+use core::fmt;
+use core::ops::Range;
+use core::ptr::NonNull;
+use core::slice;
+
+use fallible_collections::FallibleVecGlobal;
 use kpi::io::*;
 
+use crate::arch::kcb::try_per_core_mem;
 use crate::error::KError;
-use crate::memory::BASE_PAGE_SIZE;
+use crate::memory::{kernel_vaddr_to_paddr, Frame, VAddr, BASE_PAGE_SIZE, LARGE_PAGE_SIZE};
 use crate::process::SliceAccess;
+use crate::round_up;
+
+/// Number of extents we reserve space for when a file is created.
+const INITIAL_EXTENTS: usize = 64;
+
+/// Data below this file offset is stored in `BASE_PAGE_SIZE` extents,
+/// everything above in `LARGE_PAGE_SIZE` extents.
+///
+/// This keeps small files small while big files are backed by 2 MiB frames
+/// (the kernel allocator serves a `LARGE_PAGE_SIZE` allocation with a single
+/// large frame).
+const LARGE_EXTENT_OFFSET: usize = LARGE_PAGE_SIZE;
+
+/// Used to fill holes in a file on read.
+static ZEROES: [u8; BASE_PAGE_SIZE] = [0; BASE_PAGE_SIZE];
+
+/// A piece of file data. Extents are aligned to their size in the file and
+/// are either BASE_PAGE_SIZE or LARGE_PAGE_SIZE long (see
+/// [`extent_bounds`]).
+///
+/// The memory of an extent is aligned to its size as well, so it can be
+/// mapped into a process as a frame (see [`File::map`]).
+struct Extent {
+    data: NonNull<u8>,
+    size: usize,
+    /// The NUMA node the memory was allocated on.
+    node: atopology::NodeId,
+}
 
-#[derive(Debug, Eq, PartialEq)]
-/// The buffer is used by the file. Each buffer is BASE_PAGE_SIZE
-/// long and a file consists of many such buffers.
-struct Buffer {
-    data: Vec<u8>,
+// Safety: The extent owns its memory.
+unsafe impl Send for Extent {}
+unsafe impl Sync for Extent {}
+
+impl Extent {
+    /// This function tries to allocate a zeroed extent of `size` bytes and
+    /// returns it in case of the success; error otherwise.
+    fn try_alloc(size: usize) -> Result<Extent, KError> {
+        let layout = Extent::layout(size);
+        // The kernel allocator uses the memory of the node the core allocates
+        // from (which isn't necessarily the node of the core)
+        let node = try_per_core_mem()
+            .and_then(|pcm| pcm.physical_memory.try_borrow().ok().map(|pm| pm.affinity))
+            .unwrap_or(*crate::environment::NODE_ID);
+        // Safety: `size` is never zero.
+        let data = unsafe { alloc::alloc::alloc_zeroed(layout) };
+        NonNull::new(data)
+            .map(|data| Extent { data, size, node })
+            .ok_or(KError::OutOfMemory)
+    }
+
+    fn layout(size: usize) -> Layout {
+        debug_assert!(size == BASE_PAGE_SIZE || size == LARGE_PAGE_SIZE);
+        Layout::from_size_align(size, size).expect("Extent sizes are powers of two")
+    }
+
+    fn data(&self) -> &[u8] {
+        // Safety: We own `size` initialized bytes at `data`.
+        unsafe { slice::from_raw_parts(self.data.as_ptr(), self.size) }
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        // Safety: We own `size` initialized bytes at `data`.
+        unsafe { slice::from_raw_parts_mut(self.data.as_ptr(), self.size) }
+    }
+
+    /// The physical memory of `range` within the extent.
+    fn frame(&self, range: Range<usize>) -> Frame {
+        let vaddr = VAddr::from(self.data.as_ptr() as u64 + range.start as u64);
+        Frame::new(kernel_vaddr_to_paddr(vaddr), range.len(), self.node)
+    }
+}
+
+impl Drop for Extent {
+    fn drop(&mut self) {
+        // Safety: Allocated with the same layout in `try_alloc`.
+        unsafe { alloc::alloc::dealloc(self.data.as_ptr(), Extent::layout(self.size)) };
+    }
 }
 
-impl Buffer {
-    /// This function tries to allocate a vector of BASE_PAGE_SIZE long
-    /// and returns a buffer in case of the success; error otherwise.
-    pub(crate) fn try_alloc_buffer() -> Result<Buffer, TryReserveError> {
-        Vec::try_with_capacity(BASE_PAGE_SIZE).map(|data| Buffer { data })
+impl PartialEq for Extent {
+    fn eq(&self, other: &Extent) -> bool {
+        self.data() == other.data()
+    }
+}
+
+impl Eq for Extent {}
+
+impl fmt::Debug for Extent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extent")
+            .field("data", &self.data)
+            .field("size", &self.size)
+            .field("node", &self.node)
+            .finish()
     }
 }
 
 #[derive(Debug, Eq, PartialEq)]
-/// File type has a list of buffers and modes to access the file
+/// File type has a list of extents (sorted by their offset in the file) and
+/// modes to access the file.
+///
+/// Extents are only allocated once something is written to them, the ranges
+/// of the file that are not covered by an extent are holes and read as zeros.
 pub(crate) struct File {
-    mcache: Vec<Buffer>,
+    extents: Vec<(usize, Extent)>,
+    size: usize,
     modes: FileModes,
+    /// How many pages of the file are mapped into processes, the extents of
+    /// a mapped file are never freed.
+    mappings: usize,
     // TODO: Add more file related attributes
 }
 
 impl File {
-    /// Initialize a file. Pre-intialize the buffer list with 64 size.
+    /// Initialize a file. Pre-intialize the extent list with `INITIAL_EXTENTS`
+    /// entries.
     pub(crate) fn new(modes: FileModes) -> Result<File, KError> {
-        let mcache = Vec::try_with_capacity(64 * size_of::<Buffer>())?;
-        Ok(File { mcache, modes })
+        let extents = Vec::try_with_capacity(INITIAL_EXTENTS)?;
+        Ok(File {
+            extents,
+            size: 0,
+            modes,
+            mappings: 0,
+        })
     }
 
-    /// This method returns the current-size of the file. This method follows
-    /// the same convention as a vector length. So, size of the file is equal
-    /// to the data in it and not the max-allocated buffer-size.
+    /// This method returns the current-size of the file. So, size of the file
+    /// is equal to the data in it (including holes) and not the allocated
+    /// extent-size.
     pub(crate) fn get_size(&self) -> usize {
-        let buffer_num = self.mcache.len();
-        match buffer_num {
-            0 => 0,
-            1 => self.mcache[buffer_num - 1].data.len(),
-            _ => {
-                match self.mcache[buffer_num - 1].data.len() {
-                    // If resize_file()/write() added some empty buffers to be filled
-                    // later, then scan all the buffers to get the file-size.
-                    0 => {
-                        let mut len = 0;
-                        for buf in &self.mcache {
-                            match buf.data.len() {
-                                0 => break,
-                                curr_buff_len => len += curr_buff_len,
-                            }
-                        }
-                        len
-                    }
-                    // If file is filled till last buffer
-                    last_buffer_len => (buffer_num - 1) * BASE_PAGE_SIZE + last_buffer_len,
-                }
-            }
-        }
+        self.size
     }
 
     /// This method returns the mode in which file is created.
@@ -75,69 +154,54 @@ impl File {
         self.modes
     }
 
-    /// This method is internally used by write_file() method. The additional
-    /// length is initialzed to zero.
-    pub(crate) fn increase_file_size(
-        &mut self,
-        curr_file_len: usize,
-        new_len: usize,
-    ) -> Result<(), KError> {
-        if new_len == 0 {
-            return Ok(());
-        }
-
-        let free_in_last_buffer = match self.mcache.last() {
-            Some(buffer) => BASE_PAGE_SIZE - buffer.data.len(),
-            None => 0,
-        };
-
-        let add_new = new_len - curr_file_len;
-        if add_new <= free_in_last_buffer {
-            // Don't need to add new buffer
-            let offset = self.mcache.last().unwrap().data.len();
-            self.mcache
-                .last_mut()
-                .unwrap()
-                .data
-                .try_resize(offset + add_new, 0)
-                .map_err(|e| e.into())
-        } else {
-            // Add new buffer
-            if !self.mcache.is_empty() {
-                self.mcache
-                    .last_mut()
-                    .unwrap()
-                    .data
-                    .try_resize(BASE_PAGE_SIZE, 0)?;
-            }
-
-            let remaining = add_new - free_in_last_buffer;
-            let new_buffers = ceil(remaining, BASE_PAGE_SIZE);
-            let mut vec = Vec::try_with_capacity(new_buffers)?;
+    /// Returns true if some of the file is mapped into a process.
+    pub(crate) fn is_mapped(&self) -> bool {
+        self.mappings > 0
+    }
 
-            for _i in 0..new_buffers {
-                let mut buffer = Buffer::try_alloc_buffer()?;
-                // TODO(error-handling): On failure, might want to
-                // shrink previous buffers again?
-                buffer.data.try_resize(BASE_PAGE_SIZE, 0)?;
+    /// Find the extent that starts at `start`.
+    fn extent(&self, start: usize) -> Option<&Extent> {
+        self.extents
+            .binary_search_by_key(&start, |(offset, _)| *offset)
+            .ok()
+            .map(|idx| &self.extents[idx].1)
+    }
 
-                debug_assert!(vec.len() < vec.capacity(), "ensured by try_with_capacity");
-                vec.push(buffer);
+    /// Find the extent that starts at `start`, allocate it if it doesn't
+    /// exist yet.
+    fn extent_mut(&mut self, start: usize, size: usize) -> Result<&mut Extent, KError> {
+        let idx = match self
+            .extents
+            .binary_search_by_key(&start, |(offset, _)| *offset)
+        {
+            Ok(idx) => idx,
+            Err(idx) => {
+                let extent = Extent::try_alloc(size)?;
+                self.extents.try_reserve(1)?;
+                self.extents.insert(idx, (start, extent));
+                idx
             }
+        };
 
-            // Filled all the buffers with zeros, resize the last buffer:
-            if new_len % BASE_PAGE_SIZE != 0 {
-                let sure_bytes_to_write = (new_buffers - 1) * BASE_PAGE_SIZE;
-                let bytes_in_last_buffer = new_len - (self.get_size() + sure_bytes_to_write);
+        Ok(&mut self.extents[idx].1)
+    }
 
-                // TODO(error-handling): shrink others again on error?
-                vec.last_mut()
-                    .unwrap()
-                    .data
-                    .try_resize(bytes_in_last_buffer, 0)?;
+    /// Zero the data in `from..to`.
+    ///
+    /// The data past the end of the file isn't necessarily zero (extents
+    /// are kept when a mapped file shrinks and a mapping can write past the
+    /// end in its last page), so this has to be called when the file grows.
+    fn clear(&mut self, from: usize, to: usize) {
+        let first = self
+            .extents
+            .partition_point(|(start, extent)| start + extent.size <= from);
+        for (start, extent) in self.extents[first..].iter_mut() {
+            if *start >= to {
+                break;
             }
-
-            self.mcache.try_append(&mut vec).map_err(|e| e.into())
+            let begin = from.saturating_sub(*start);
+            let end = min(extent.size, to - *start);
+            extent.data_mut()[begin..end].fill(0);
         }
     }
 
@@ -150,153 +214,198 @@ impl File {
         start_offset: usize,
         end_offset: usize,
     ) -> Result<usize, KError> {
-        let mut buffer_num = offset_to_buffernum(start_offset, BASE_PAGE_SIZE);
-        let mut offset_in_buffer = start_offset - (buffer_num * BASE_PAGE_SIZE);
-        let mut copied = 0;
-        let mut dst_start = 0;
-        let mut dst_end;
-
-        let len = end_offset - start_offset;
-        while copied < len {
-            let useful_data_curr_buffer = self.mcache[buffer_num].data.len() - offset_in_buffer;
-            let remaining = len - copied;
-
-            let src_start = offset_in_buffer;
-            let src_end;
-            if remaining >= useful_data_curr_buffer {
-                dst_end = dst_start + useful_data_curr_buffer;
-                src_end = src_start + useful_data_curr_buffer;
-                copied += useful_data_curr_buffer;
-            } else {
-                dst_end = dst_start + remaining;
-                src_end = src_start + remaining;
-                copied += remaining;
+        let mut offset = start_offset;
+        while offset < end_offset {
+            let (start, size) = extent_bounds(offset);
+            let len = min(end_offset, start + size) - offset;
+            let dst_start = offset - start_offset;
+
+            match self.extent(start) {
+                Some(extent) => {
+                    let src_start = offset - start;
+                    user_slice
+                        .write_subslice(&extent.data()[src_start..src_start + len], dst_start)?;
+                }
+                None => {
+                    // A hole, fill with zeros
+                    let mut zeroed = 0;
+                    while zeroed < len {
+                        let chunk = min(len - zeroed, ZEROES.len());
+                        user_slice.write_subslice(&ZEROES[..chunk], dst_start + zeroed)?;
+                        zeroed += chunk;
+                    }
+                }
             }
-            debug_assert_eq!(
-                dst_end - dst_start,
-                self.mcache[buffer_num].data[src_start..src_end].len()
-            );
-            user_slice
-                .write_subslice(&self.mcache[buffer_num].data[src_start..src_end], dst_start)?;
-            buffer_num += 1;
-            dst_start = dst_end;
-            offset_in_buffer = 0;
+
+            offset += len;
         }
 
-        Ok(copied)
+        Ok(end_offset - start_offset)
     }
 
     /// This method is internally called on a write() system-call. The user provided the
-    /// data in a user-slice and the method copies that data into the file buffers. Beside
+    /// data in a user-slice and the method copies that data into the file extents. Beside
     /// the slice the user also provides the length of the data and it can also specify an
     /// arbitrary offset in the file to write the data.
+    ///
+    /// If the offset is past the end of the file, the range in between becomes a hole.
     pub(crate) fn write_file(
         &mut self,
         user_slice: &[u8],
         len: usize,
         start_offset: usize,
     ) -> Result<usize, KError> {
-        // If offset is specified, then resize the file to the offset + len.
-        // If offset is more than file size then fill the file with zeros till the offset.
-        let curr_file_len = self.get_size();
-        let new_len = start_offset + len;
-        if new_len > 0
-            && new_len > curr_file_len
-            && self.increase_file_size(curr_file_len, new_len).is_err()
-        {
-            return Err(KError::OutOfMemory);
+        let end_offset = start_offset + len;
+
+        // Allocate all extents first, so we don't change the file if we run
+        // out of memory.
+        let mut offset = start_offset;
+        while offset < end_offset {
+            let (start, size) = extent_bounds(offset);
+            self.extent_mut(start, size)
+                .map_err(|_e| KError::OutOfMemory)?;
+            offset = start + size;
         }
 
-        let mut buffer_num = offset_to_buffernum(start_offset, BASE_PAGE_SIZE);
-        let mut offset_in_buffer = start_offset - (buffer_num * BASE_PAGE_SIZE);
-        let mut copied = 0;
-        let mut dst_start = 0;
-        let mut dst_end;
-
-        while copied < len {
-            let useful_data_curr_buffer = BASE_PAGE_SIZE - offset_in_buffer;
-            let remaining = len - copied;
-
-            let src_start = offset_in_buffer;
-            let src_end;
-            if remaining >= useful_data_curr_buffer {
-                dst_end = dst_start + useful_data_curr_buffer;
-                src_end = src_start + useful_data_curr_buffer;
-                copied += useful_data_curr_buffer;
-            } else {
-                dst_end = dst_start + remaining;
-                src_end = src_start + remaining;
-                copied += remaining;
-            }
+        if start_offset > self.size {
+            self.clear(self.size, start_offset);
+        }
 
-            self.mcache[buffer_num].data[src_start..src_end]
-                .copy_from_slice(&user_slice[dst_start..dst_end]);
-            buffer_num += 1;
-            dst_start = dst_end;
-            offset_in_buffer = 0;
+        let mut offset = start_offset;
+        while offset < end_offset {
+            let (start, size) = extent_bounds(offset);
+            let len = min(end_offset, start + size) - offset;
+            let src_start = offset - start_offset;
+            let dst_start = offset - start;
+
+            let extent = self.extent_mut(start, size)?;
+            extent.data_mut()[dst_start..dst_start + len]
+                .copy_from_slice(&user_slice[src_start..src_start + len]);
+            offset += len;
         }
 
+        self.size = max(self.size, end_offset);
         Ok(len)
     }
 
     /// Truncate the file in reasponse of O_TRUNC flag.
     pub(crate) fn file_truncate(&mut self) {
-        self.mcache.clear();
+        if !self.is_mapped() {
+            self.extents.clear();
+        }
+        self.size = 0;
+    }
+
+    /// Change the size of the file to `new_len` bytes.
+    ///
+    /// Growing the file adds a hole at the end (which reads as zeros),
+    /// shrinking it frees all extents that are past the new end of the file
+    /// (unless the file is mapped).
+    pub(crate) fn resize(&mut self, new_len: usize) -> Result<(), KError> {
+        if new_len > self.size {
+            self.clear(self.size, new_len);
+        } else if !self.is_mapped() {
+            let keep = self
+                .extents
+                .partition_point(|(offset, _)| *offset < new_len);
+            self.extents.truncate(keep);
+        }
+
+        self.size = new_len;
+        Ok(())
+    }
+
+    /// Returns the frames that hold `offset..offset+len` of the file, so they
+    /// can be mapped into a process (holes in the range get allocated).
+    ///
+    /// The range has to be page aligned and within the file (rounded up to a
+    /// page). Every page of the range counts as a mapping of the file until
+    /// it is released again with [`File::unmap`].
+    pub(crate) fn map(&mut self, offset: usize, len: usize) -> Result<Vec<Frame>, KError> {
+        if offset % BASE_PAGE_SIZE != 0 {
+            return Err(KError::InvalidOffset);
+        }
+        if len == 0 || len % BASE_PAGE_SIZE != 0 {
+            return Err(KError::InvalidLength);
+        }
+        let end = offset.checked_add(len).ok_or(KError::InvalidLength)?;
+        if end > round_up!(self.size, BASE_PAGE_SIZE) {
+            return Err(KError::InvalidOffset);
+        }
+
+        let mut frames = Vec::new();
+        let mut offset = offset;
+        while offset < end {
+            let (start, size) = extent_bounds(offset);
+            let stop = min(end, start + size);
+            frames.try_reserve(1)?;
+            let extent = self.extent_mut(start, size)?;
+            frames.push(extent.frame(offset - start..stop - start));
+            offset = stop;
+        }
+
+        self.mappings += len / BASE_PAGE_SIZE;
+        Ok(frames)
     }
-}
 
-/// This is used to determine, how many buffers to add dependeing on the number
-/// of bytes and buffer-size.
-fn ceil(bytes: usize, buffer_size: usize) -> usize {
-    let mut val = bytes / buffer_size;
-    if bytes > val * buffer_size {
-        val += 1;
+    /// Releases `pages` mapped pages handed out by [`File::map`].
+    pub(crate) fn unmap(&mut self, pages: usize) {
+        debug_assert!(self.mappings >= pages, "Released more than we mapped");
+        self.mappings = self.mappings.saturating_sub(pages);
     }
-    val
 }
 
-/// This method converts the file offset to buffer number with-in a file.
-/// The assumption is that the buffer-size is equal for all the buffers
-/// in a file.
-fn offset_to_buffernum(offset: usize, buffer_size: usize) -> usize {
-    offset / buffer_size
+/// Returns the start offset and the size of the extent that holds the byte
+/// at `offset` in a file.
+fn extent_bounds(offset: usize) -> (usize, usize) {
+    let size = if offset < LARGE_EXTENT_OFFSET {
+        BASE_PAGE_SIZE
+    } else {
+        LARGE_PAGE_SIZE
+    };
+    (offset & !(size - 1), size)
 }
 
 #[cfg(test)]
 pub mod test {
     use super::*;
 
-    #[test]
-    /// This method test the offset to buffer number conversion for a file.
-    /// It uses BASE_PAGE_SIZE as buffer size.
-    fn test_offset_to_buffernum() {
-        let mut buffer_num: i64 = -1;
-        for i in 0..10000 {
-            if (i % BASE_PAGE_SIZE) == 0 {
-                buffer_num += 1;
-            }
-            assert_eq!(offset_to_buffernum(i, BASE_PAGE_SIZE), buffer_num as usize);
+    impl File {
+        /// Returns the extent that holds `offset` (if it's allocated).
+        fn extent_at(&self, offset: usize) -> Option<&Extent> {
+            self.extent(extent_bounds(offset).0)
         }
     }
 
     #[test]
-    /// This method tests the ceil method.
-    fn test_ceil() {
-        let mut cval = 0;
-        for i in 0..10000 {
-            assert_eq!(ceil(i, BASE_PAGE_SIZE), cval as usize);
-            if (i % BASE_PAGE_SIZE) == 0 {
-                cval += 1;
-            }
-        }
+    /// This method test the offset to extent conversion for a file.
+    fn test_extent_bounds() {
+        assert_eq!(extent_bounds(0), (0, BASE_PAGE_SIZE));
+        assert_eq!(extent_bounds(BASE_PAGE_SIZE - 1), (0, BASE_PAGE_SIZE));
+        assert_eq!(
+            extent_bounds(BASE_PAGE_SIZE + 1),
+            (BASE_PAGE_SIZE, BASE_PAGE_SIZE)
+        );
+        assert_eq!(
+            extent_bounds(LARGE_PAGE_SIZE - 1),
+            (LARGE_PAGE_SIZE - BASE_PAGE_SIZE, BASE_PAGE_SIZE)
+        );
+        assert_eq!(
+            extent_bounds(LARGE_PAGE_SIZE),
+            (LARGE_PAGE_SIZE, LARGE_PAGE_SIZE)
+        );
+        assert_eq!(
+            extent_bounds(3 * LARGE_PAGE_SIZE - 1),
+            (2 * LARGE_PAGE_SIZE, LARGE_PAGE_SIZE)
+        );
     }
 
     #[test]
-    /// This method test the size of the allocated buffer.
-    fn test_buffer_alloc() {
-        let buffer = Buffer::try_alloc_buffer().unwrap();
-        assert_eq!(buffer.data.len(), 0);
-        assert_eq!(buffer.data.capacity(), BASE_PAGE_SIZE);
+    /// This method test the size of the allocated extent.
+    fn test_extent_alloc() {
+        let extent = Extent::try_alloc(BASE_PAGE_SIZE).unwrap();
+        assert_eq!(extent.data().len(), BASE_PAGE_SIZE);
+        assert!(extent.data().iter().all(|b| *b == 0));
     }
 
     #[test]
@@ -305,25 +414,21 @@ pub mod test {
         let file = File::new(FileModes::S_IRWXU.into()).unwrap();
         assert_eq!(file.get_mode(), FileModes::S_IRWXU);
         assert_eq!(file.get_size(), 0);
-        assert_eq!(file.mcache.len(), 0);
-        assert_eq!(file.mcache.capacity(), 64 * size_of::<Buffer>());
+        assert_eq!(file.extents.len(), 0);
+        assert_eq!(file.extents.capacity(), INITIAL_EXTENTS);
     }
 
     #[test]
-    /// This tests the resize file method.
+    /// This tests the resize file method, growing a file doesn't allocate
+    /// any extents.
     fn test_resize_file() {
         let mut file = File::new(FileModes::S_IRWXU.into()).unwrap();
-        assert_eq!(file.get_mode(), FileModes::S_IRWXU);
-        assert_eq!(file.mcache.len(), 0);
-        assert_eq!(file.mcache.capacity(), 64 * size_of::<Buffer>());
-
         assert_eq!(file.get_size(), 0);
 
         for i in 0..10000 {
-            assert!(file.increase_file_size(file.get_size(), i).is_ok());
+            assert!(file.resize(i).is_ok());
             assert_eq!(file.get_size(), i);
-            let buffer_num = ceil(i, BASE_PAGE_SIZE);
-            assert_eq!(file.mcache.len(), buffer_num);
+            assert_eq!(file.extents.len(), 0);
         }
     }
 
@@ -332,18 +437,18 @@ pub mod test {
     fn test_write_file() {
         let mut file = File::new(FileModes::S_IRWXU.into()).unwrap();
         assert_eq!(file.get_mode(), FileModes::S_IRWXU);
-        assert_eq!(file.mcache.len(), 0);
-        assert_eq!(file.mcache.capacity(), 64 * size_of::<Buffer>());
+        assert_eq!(file.extents.len(), 0);
 
         let buffer: &mut [u8] = &mut [0xb; 10000];
         for i in 0..10000 {
             file.write_file(buffer, i, 0).unwrap();
             assert_eq!(file.get_size(), i);
         }
+        assert_eq!(file.extents.len(), 3);
 
-        // verify the content for first buffer
+        // verify the content for first extent
         for i in 0..4096 {
-            assert_eq!(file.mcache[0].data[i], 0xb);
+            assert_eq!(file.extent_at(0).unwrap().data()[i], 0xb);
         }
     }
 
@@ -352,8 +457,6 @@ pub mod test {
     fn test_read_file() {
         let mut file = File::new(FileModes::S_IRWXU.into()).unwrap();
         assert_eq!(file.get_mode(), FileModes::S_IRWXU);
-        assert_eq!(file.mcache.len(), 0);
-        assert_eq!(file.mcache.capacity(), 64 * size_of::<Buffer>());
 
         let wbuffer = [0xb; 10_000];
         let mut rbuffer: [u8; 10_000] = [0; 10_000];
@@ -368,6 +471,62 @@ pub mod test {
         }
     }
 
+    #[test]
+    /// Writing past the end of the file leaves a hole that reads as zeros
+    /// and doesn't allocate memory.
+    fn test_sparse_file() {
+        let mut file = File::new(FileModes::S_IRWXU.into()).unwrap();
+        let wbuffer = [0xb; 8];
+        let offset = 4 * LARGE_PAGE_SIZE + 5;
+
+        assert_eq!(file.write_file(&wbuffer, 8, offset), Ok(8));
+        assert_eq!(file.get_size(), offset + 8);
+        assert_eq!(file.extents.len(), 1);
+        assert_eq!(
+            file.extent_at(offset).unwrap().data().len(),
+            LARGE_PAGE_SIZE
+        );
+
+        let mut rbuffer: [u8; 3 * BASE_PAGE_SIZE] = [0xff; 3 * BASE_PAGE_SIZE];
+        let start = offset + 8 - rbuffer.len();
+        let mut subs = &mut rbuffer[..];
+        assert_eq!(
+            file.read_file(&mut subs, start, offset + 8),
+            Ok(3 * BASE_PAGE_SIZE)
+        );
+        assert!(rbuffer[..3 * BASE_PAGE_SIZE - 8].iter().all(|b| *b == 0));
+        assert!(rbuffer[3 * BASE_PAGE_SIZE - 8..].iter().all(|b| *b == 0xb));
+    }
+
+    #[test]
+    /// Large writes are stored in large extents.
+    fn test_large_extents() {
+        let mut file = File::new(FileModes::S_IRWXU.into()).unwrap();
+        let mut wbuffer = Vec::new();
+        wbuffer.resize(3 * LARGE_PAGE_SIZE, 0xa);
+
+        assert_eq!(
+            file.write_file(&wbuffer, wbuffer.len(), 0),
+            Ok(3 * LARGE_PAGE_SIZE)
+        );
+        assert_eq!(file.get_size(), 3 * LARGE_PAGE_SIZE);
+        assert_eq!(file.extents.len(), LARGE_PAGE_SIZE / BASE_PAGE_SIZE + 2);
+        assert_eq!(
+            file.extent_at(LARGE_PAGE_SIZE).unwrap().data().len(),
+            LARGE_PAGE_SIZE
+        );
+
+        let mut rbuffer = Vec::new();
+        rbuffer.resize(2 * BASE_PAGE_SIZE, 0);
+        let offset = LARGE_PAGE_SIZE - BASE_PAGE_SIZE;
+        let mut subs = &mut rbuffer[..];
+        assert_eq!(
+            file.read_file(&mut subs, offset, offset + 2 * BASE_PAGE_SIZE),
+            Ok(2 * BASE_PAGE_SIZE)
+        );
+        assert!(rbuffer.iter().all(|b| *b == 0xa));
+    }
+
     #[test]
     /// This test checks if the file truncation works as expected.
     fn test_file_truncate() {
@@ -379,7 +538,95 @@ pub mod test {
 
         file.file_truncate();
         assert_eq!(file.get_size(), 0);
-        assert_eq!(file.mcache.len(), 0);
+        assert_eq!(file.extents.len(), 0);
+    }
+
+    #[test]
+    /// This test checks that growing and shrinking a file works as expected.
+    fn test_file_grow_and_shrink() {
+        let mut file = File::new(FileModes::S_IRWXU.into()).unwrap();
+        let wbuffer: &mut [u8] = &mut [0xb; 10000];
+        assert_eq!(file.write_file(wbuffer, 10000, 0), Ok(10000));
+
+        // Shrink frees the extents at the end
+        assert_eq!(file.resize(5000), Ok(()));
+        assert_eq!(file.get_size(), 5000);
+        assert_eq!(file.extents.len(), 2);
+        assert_eq!(file.resize(BASE_PAGE_SIZE), Ok(()));
+        assert_eq!(file.get_size(), BASE_PAGE_SIZE);
+        assert_eq!(file.extents.len(), 1);
+
+        // Grow adds a hole
+        assert_eq!(file.resize(3 * BASE_PAGE_SIZE + 1), Ok(()));
+        assert_eq!(file.get_size(), 3 * BASE_PAGE_SIZE + 1);
+        assert_eq!(file.extents.len(), 1);
+
+        let mut rbuffer: [u8; 2] = [0xff; 2];
+        let mut subs = &mut rbuffer[..];
+        let offset = BASE_PAGE_SIZE - 1;
+        assert_eq!(file.read_file(&mut subs, offset, offset + 2), Ok(2));
+        assert_eq!(rbuffer, [0xb, 0x0]);
+
+        // Data past the end is gone after shrinking and growing again
+        assert_eq!(file.resize(10), Ok(()));
+        assert_eq!(file.resize(20), Ok(()));
+        let mut rbuffer: [u8; 2] = [0xff; 2];
+        let mut subs = &mut rbuffer[..];
+        assert_eq!(file.read_file(&mut subs, 9, 11), Ok(2));
+        assert_eq!(rbuffer, [0xb, 0x0]);
+
+        assert_eq!(file.resize(0), Ok(()));
+        assert_eq!(file.get_size(), 0);
+        assert_eq!(file.extents.len(), 0);
+    }
+
+    #[test]
+    /// Mapping a file hands out its extents as frames and keeps them around
+    /// until they're unmapped.
+    fn test_map_file() {
+        let mut file = File::new(FileModes::S_IRWXU.into()).unwrap();
+        let wbuffer: &mut [u8] = &mut [0xb; 10000];
+        assert_eq!(
+            file.write_file(wbuffer, 10000, LARGE_PAGE_SIZE - BASE_PAGE_SIZE),
+            Ok(10000)
+        );
+
+        assert_eq!(file.map(1, BASE_PAGE_SIZE), Err(KError::InvalidOffset));
+        assert_eq!(file.map(0, 100), Err(KError::InvalidLength));
+        assert_eq!(
+            file.map(LARGE_PAGE_SIZE, 2 * LARGE_PAGE_SIZE),
+            Err(KError::InvalidOffset)
+        );
+
+        // A hole, a small extent and the start of a large extent
+        let offset = LARGE_PAGE_SIZE - 2 * BASE_PAGE_SIZE;
+        let frames = file.map(offset, 4 * BASE_PAGE_SIZE).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].size, BASE_PAGE_SIZE);
+        assert_eq!(frames[1].size, BASE_PAGE_SIZE);
+        assert_eq!(frames[2].size, 2 * BASE_PAGE_SIZE);
+        assert!(frames
+            .iter()
+            .all(|f| f.base.as_u64() % BASE_PAGE_SIZE as u64 == 0));
+        assert!(file.is_mapped());
+
+        // Shrinking a mapped file doesn't free the extents
+        let extents = file.extents.len();
+        assert_eq!(file.resize(0), Ok(()));
+        assert_eq!(file.extents.len(), extents);
+
+        // Growing it again reads zeros
+        assert_eq!(file.resize(LARGE_PAGE_SIZE), Ok(()));
+        let mut rbuffer: [u8; 2] = [0xff; 2];
+        let mut subs = &mut rbuffer[..];
+        let offset = LARGE_PAGE_SIZE - 2;
+        assert_eq!(file.read_file(&mut subs, offset, offset + 2), Ok(2));
+        assert_eq!(rbuffer, [0x0, 0x0]);
+
+        file.unmap(4);
+        assert!(!file.is_mapped());
+        assert_eq!(file.resize(0), Ok(()));
+        assert_eq!(file.extents.len(), 0);
     }
 
     #[test]
@@ -387,8 +634,6 @@ pub mod test {
     fn test_overwrite_file() {
         let mut file = File::new(FileModes::S_IRWXU.into()).unwrap();
         assert_eq!(file.get_mode(), FileModes::S_IRWXU);
-        assert_eq!(file.mcache.len(), 0);
-        assert_eq!(file.mcache.capacity(), 64 * size_of::<Buffer>());
 
         let buffer: &mut [u8] = &mut [0xb; 10000];
         for i in 0..10000 {
@@ -402,13 +647,13 @@ pub mod test {
             assert_eq!(file.get_size(), 9999);
         }
 
-        // verify the content for first buffer
+        // verify the content for first extent
         for i in 0..4095 {
-            assert_eq!(file.mcache[0].data[i], 0xa);
+            assert_eq!(file.extent_at(0).unwrap().data()[i], 0xa);
         }
-        // verify the content for second buffer
+        // verify the content for second extent
         for i in 0..4096 {
-            assert_eq!(file.mcache[1].data[i], 0xb);
+            assert_eq!(file.extent_at(BASE_PAGE_SIZE).unwrap().data()[i], 0xb);
         }
     }
 }
@@ -2,12 +2,15 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 use alloc::string::String;
+use alloc::vec::Vec;
 use core::convert::TryFrom;
 
+use hashbrown::HashMap;
 use kpi::io::{FileModes, FileType};
 
 use crate::error::KError;
 use crate::fallible_string::TryString;
+use crate::memory::Frame;
 use crate::process::SliceAccess;
 
 use super::file::*;
@@ -20,6 +23,8 @@ pub(crate) struct MemNode {
     name: String,
     node_type: FileType,
     file: Option<File>,
+    /// The entries of a directory (always empty for files).
+    entries: HashMap<String, MnodeNum>,
 }
 
 /// Required for the testing
@@ -29,6 +34,7 @@ impl PartialEq for MemNode {
             && (self.name == other.name)
             && (self.node_type == other.node_type)
             && (self.file == other.file)
+            && (self.entries == other.entries)
     }
 }
 
@@ -40,6 +46,7 @@ impl Default for MemNode {
             name: String::new(),
             node_type: FileType::File,
             file: None,
+            entries: HashMap::new(),
         }
     }
 }
@@ -65,9 +72,57 @@ impl MemNode {
             name: TryString::try_from(pathname)?.into(),
             node_type,
             file,
+            entries: HashMap::new(),
         })
     }
 
+    /// Rename the mnode (the name is the last component of the path).
+    pub(crate) fn set_name(&mut self, name: &str) -> Result<(), KError> {
+        self.name = TryString::try_from(name)?.into();
+        Ok(())
+    }
+
+    /// Look up the entry `name` in a directory.
+    pub(crate) fn get_entry(&self, name: &str) -> Result<MnodeNum, KError> {
+        if self.node_type != FileType::Directory {
+            return Err(KError::NotADirectory);
+        }
+        self.entries.get(name).copied().ok_or(KError::InvalidFile)
+    }
+
+    /// Add the entry `name` to a directory.
+    pub(crate) fn add_entry(&mut self, name: &str, mnode_num: MnodeNum) -> Result<(), KError> {
+        if self.node_type != FileType::Directory {
+            return Err(KError::NotADirectory);
+        }
+        if self.entries.contains_key(name) {
+            return Err(KError::AlreadyPresent);
+        }
+
+        let name = TryString::try_from(name)?.into();
+        self.entries.try_reserve(1)?;
+        self.entries.insert(name, mnode_num);
+        Ok(())
+    }
+
+    /// Remove the entry `name` from a directory.
+    pub(crate) fn remove_entry(&mut self, name: &str) -> Result<MnodeNum, KError> {
+        if self.node_type != FileType::Directory {
+            return Err(KError::NotADirectory);
+        }
+        self.entries.remove(name).ok_or(KError::InvalidFile)
+    }
+
+    /// Iterate over the entries of a directory.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&String, &MnodeNum)> {
+        self.entries.iter()
+    }
+
+    /// Returns true if this is a directory with at least one entry.
+    pub(crate) fn has_entries(&self) -> bool {
+        !self.entries.is_empty()
+    }
+
     /// Write to an in-memory file.
     pub(crate) fn write(&mut self, buffer: &[u8], offset: usize) -> Result<usize, KError> {
         // Return if the user doesn't have write permissions for the file.
@@ -117,11 +172,32 @@ impl MemNode {
             .read_file(buffer, offset, new_offset)
     }
 
+    /// Read the file contents at `offset` into `buffer`, regardless of the
+    /// file modes.
+    ///
+    /// Returns how many bytes were read (0 at the end of the file).
+    pub(crate) fn read_contents(&self, buffer: &mut [u8], offset: usize) -> Result<usize, KError> {
+        let file = self.file.as_ref().ok_or(KError::DirectoryError)?;
+        let file_size = file.get_size();
+        if offset >= file_size {
+            return Ok(0);
+        }
+
+        let end = core::cmp::min(file_size, offset + buffer.len());
+        let mut buffer = &mut buffer[..end - offset];
+        file.read_file(&mut buffer, offset, end)
+    }
+
     /// Get the file size
     pub(crate) fn get_file_size(&self) -> usize {
         self.file.as_ref().unwrap().get_size()
     }
 
+    /// Get the modes the file was created with.
+    pub(crate) fn get_file_mode(&self) -> FileModes {
+        self.file.as_ref().unwrap().get_mode()
+    }
+
     /// Get the type of mnode; Directory or file.
     pub(crate) fn get_mnode_type(&self) -> FileType {
         self.node_type
@@ -138,6 +214,44 @@ impl MemNode {
         self.file.as_mut().unwrap().file_truncate();
         Ok(())
     }
+
+    /// Shrink or grow the file to `len` bytes.
+    pub(crate) fn file_resize(&mut self, len: usize) -> Result<(), KError> {
+        if self.node_type != FileType::File || !self.file.as_ref().unwrap().get_mode().is_writable()
+        {
+            return Err(KError::PermissionError);
+        }
+
+        self.file.as_mut().unwrap().resize(len)
+    }
+
+    /// Returns the frames that hold `offset..offset+len` of the file so they
+    /// can be mapped into a process.
+    pub(crate) fn map(
+        &mut self,
+        offset: usize,
+        len: usize,
+        writable: bool,
+    ) -> Result<Vec<Frame>, KError> {
+        let file = self.file.as_mut().ok_or(KError::DirectoryError)?;
+        if !file.get_mode().is_readable() || (writable && !file.get_mode().is_writable()) {
+            return Err(KError::PermissionError);
+        }
+
+        file.map(offset, len)
+    }
+
+    /// Releases `pages` pages that were mapped with `map`.
+    pub(crate) fn unmap(&mut self, pages: usize) {
+        if let Some(file) = self.file.as_mut() {
+            file.unmap(pages);
+        }
+    }
+
+    /// Returns true if some of the file is mapped into a process.
+    pub(crate) fn is_mapped(&self) -> bool {
+        self.file.as_ref().map_or(false, |file| file.is_mapped())
+    }
 }
 
 #[cfg(test)]
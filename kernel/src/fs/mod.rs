@@ -2,30 +2,35 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 use alloc::string::String;
-use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::convert::TryFrom;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
+use fallible_collections::{FallibleVec, FallibleVecGlobal};
 use hashbrown::HashMap;
 use kpi::io::*;
-use spin::RwLock;
 
 use crate::error::KError;
 use crate::fallible_string::TryString;
+use crate::memory::Frame;
 use crate::process::SliceAccess;
 
 pub(crate) use rwlock::RwLock as NrLock;
 
 pub mod cnrfs;
 pub mod fd;
+pub mod pmem;
+pub mod ring;
 
 mod file;
 mod mnode;
+mod pipe;
 mod rwlock;
 #[cfg(test)]
 mod test;
 
 use mnode::MemNode;
+use pmem::{Checkpoint, Journal, PmemStore, Record};
 
 /// The maximum number of open files for a process.
 pub(crate) const MAX_FILES_PER_PROCESS: usize = 4096;
@@ -43,102 +48,471 @@ pub(crate) trait FileSystem {
         buffer: &mut dyn SliceAccess,
         offset: usize,
     ) -> Result<usize, KError>;
-    fn lookup(&self, pathname: &str) -> Option<Arc<MnodeNum>>;
+    fn lookup(&self, pathname: &str) -> Option<MnodeNum>;
     fn file_info(&self, mnode: MnodeNum) -> FileInfo;
     fn delete(&self, pathname: &str) -> Result<(), KError>;
     fn truncate(&self, pathname: &str) -> Result<(), KError>;
+    fn resize(&self, mnode_num: MnodeNum, len: usize) -> Result<(), KError>;
     fn rename(&self, oldname: &str, newname: String) -> Result<(), KError>;
     fn mkdir(&self, pathname: String, modes: FileModes) -> Result<(), KError>;
+    fn readdir(&self, pathname: &str) -> Result<Vec<DirEntry>, KError>;
+    fn map(
+        &self,
+        mnode_num: MnodeNum,
+        offset: usize,
+        len: usize,
+        writable: bool,
+    ) -> Result<Vec<Frame>, KError>;
+    fn unmap(&self, mnode_num: MnodeNum, pages: usize);
+    fn write_back(
+        &self,
+        mnode_num: MnodeNum,
+        buffer: &[u8],
+        offset: usize,
+    ) -> Result<usize, KError>;
 }
 
 /// The mnode number assigned to the first file.
 pub(crate) const MNODE_OFFSET: usize = 2;
 
+/// The mnode number of the root directory.
+pub(crate) const ROOT_MNODE: MnodeNum = 1;
+
+/// All mnodes of the file-system, indexed by their mnode number.
+type MnodeMap = HashMap<MnodeNum, NrLock<MemNode>>;
+
+/// Returns the components of `pathname`.
+///
+/// Paths are always resolved starting from the root directory, empty
+/// components (leading, trailing or repeated `/`) are ignored.
+pub(crate) fn path_components(pathname: &str) -> impl Iterator<Item = &str> {
+    pathname.split('/').filter(|c| !c.is_empty())
+}
+
+/// Splits `pathname` into the path of the parent directory and the last
+/// component.
+///
+/// Returns `None` if `pathname` refers to the root directory.
+pub(crate) fn split_path(pathname: &str) -> Option<(&str, &str)> {
+    let pathname = pathname.trim_end_matches('/');
+    let (parent, name) = pathname.rsplit_once('/').unwrap_or(("", pathname));
+    if name.is_empty() {
+        None
+    } else {
+        Some((parent, name))
+    }
+}
+
 /// The in-memory file-system representation.
+///
+/// Files and directories form a tree: every directory mnode holds the names
+/// and mnode numbers of its entries, and paths are resolved one component at
+/// a time starting from the root directory.
 #[derive(Debug)]
 pub(crate) struct MlnrFS {
-    /// Only create file will lock the hashmap in write mode,
-    /// every other operation is locked in read mode.
-    mnodes: NrLock<HashMap<MnodeNum, NrLock<MemNode>>>,
-    files: RwLock<HashMap<String, Arc<MnodeNum>>>,
-    _root: (String, MnodeNum),
+    /// Only operations that create or remove mnodes lock the hashmap in
+    /// write mode, every other operation is locked in read mode.
+    mnodes: NrLock<MnodeMap>,
+    root: MnodeNum,
     nextmemnode: AtomicUsize,
+    /// The persistent log every update is appended to (only one replica of
+    /// the file-system writes it).
+    store: Option<&'static PmemStore>,
+    /// Files that were deleted while they were still mapped, they are
+    /// removed once the last mapping goes away.
+    unlinked: NrLock<Vec<MnodeNum>>,
 }
 
 unsafe impl Sync for MlnrFS {}
 
 impl Default for MlnrFS {
-    /// Initialize the file system from the root directory.
+    /// Initialize the file system, from the PMem storage if there is one.
     fn default() -> MlnrFS {
+        MlnrFS::new(pmem::PMEM_FS.get())
+    }
+}
+
+impl MlnrFS {
+    /// Initialize the file system from the root directory and replay the
+    /// updates in `store` (if any).
+    fn new(store: Option<&'static PmemStore>) -> MlnrFS {
         let rootdir = "/";
-        let rootmnode = 1;
 
-        let mnodes = NrLock::<HashMap<MnodeNum, NrLock<MemNode>>>::default();
+        let mnodes = NrLock::<MnodeMap>::default();
         mnodes.write().insert(
-            rootmnode,
+            ROOT_MNODE,
             NrLock::new(
-                MemNode::new(rootmnode, rootdir, FileModes::S_IRWXU, FileType::Directory).unwrap(),
+                MemNode::new(ROOT_MNODE, rootdir, FileModes::S_IRWXU, FileType::Directory)
+                    .expect("Not enough memory to initialize system"),
             ),
         );
-        let files = RwLock::new(HashMap::new());
-        files.write().insert(
-            TryString::try_from(rootdir)
-                .expect("Not enough memory to initialize system")
-                .into(),
-            Arc::try_new(1).expect("Not enough memory to initialize system"),
-        );
-        let root = (
-            TryString::try_from(rootdir)
-                .expect("Not enough memory to initialize system")
-                .into(),
-            1,
-        );
 
-        MlnrFS {
+        let mut fs = MlnrFS {
             mnodes,
-            files,
-            _root: root,
+            root: ROOT_MNODE,
             nextmemnode: AtomicUsize::new(MNODE_OFFSET),
+            store: None,
+            unlinked: NrLock::default(),
+        };
+
+        if let Some(store) = store {
+            store.replay(|record| fs.replay(record));
+            if store.claim() {
+                fs.store = Some(store);
+            }
         }
+        fs
     }
-}
 
-impl MlnrFS {
     /// Get the next available memnode number.
     fn get_next_mno(&self) -> usize {
         self.nextmemnode.fetch_add(1, Ordering::Relaxed)
     }
-}
 
-impl FileSystem for MlnrFS {
-    fn create(&self, pathname: String, modes: FileModes) -> Result<u64, KError> {
-        // Check if the file with the same name already exists.
-        if self.files.read().get(&pathname).is_some() {
+    /// Removes an mnode that no directory refers to anymore (it stays around
+    /// until it's unmapped if a process still maps it).
+    fn release_mnode(&self, mnodes: &mut MnodeMap, mnode_num: MnodeNum) -> Result<(), KError> {
+        let mapped = mnodes
+            .get(&mnode_num)
+            .map_or(false, |mnode| mnode.read().is_mapped());
+        if mapped {
+            let mut unlinked = self.unlinked.write();
+            unlinked.try_reserve(1)?;
+            unlinked.push(mnode_num);
+        } else {
+            let r = mnodes.remove(&mnode_num);
+            assert!(r.is_some(), "Didn't remove the mnode?");
+        }
+        Ok(())
+    }
+
+    /// Starts an update of the file-system which appends (at most) `len`
+    /// bytes of records to the persistent log.
+    ///
+    /// Returns `None` if the file-system isn't persisted.
+    fn journal(&self, len: usize) -> Result<Option<Journal<'static>>, KError> {
+        match self.store {
+            Some(store) => store
+                .begin(len, |checkpoint| self.checkpoint(checkpoint))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Runs `update` and appends `record` to the persistent log if it
+    /// succeeds.
+    fn persisted<T, F>(&self, record: Record, update: F) -> Result<T, KError>
+    where
+        F: FnOnce() -> Result<T, KError>,
+    {
+        let journal = self.journal(record.size())?;
+        let r = update()?;
+        if let Some(journal) = journal {
+            journal.commit(&record);
+        }
+        Ok(r)
+    }
+
+    /// Applies an update from the persistent log.
+    fn replay(&self, record: Record) -> Result<(), KError> {
+        match record {
+            Record::Create { mnode, path, modes } => self
+                .insert_mnode(path, modes, FileType::File, Some(mnode))
+                .map(|_mnode_num| ()),
+            Record::MkDir { mnode, path } => self
+                .insert_mnode(path, FileModes::S_IRWXU, FileType::Directory, Some(mnode))
+                .map(|_mnode_num| ()),
+            Record::Write {
+                mnode,
+                offset,
+                data,
+            } => self.write(mnode, data, offset).map(|_len| ()),
+            Record::Resize { mnode, len } => self.resize(mnode, len),
+            Record::Delete { path } => self.delete(path),
+            Record::Rename { oldname, newname } => self.rename_entry(oldname, newname),
+        }
+    }
+
+    /// Writes records that recreate the current state of the file-system to
+    /// `checkpoint`.
+    fn checkpoint(&self, checkpoint: &mut Checkpoint) -> Result<(), KError> {
+        let mnodes = self.mnodes.read();
+        let mut path = String::new();
+        let mut buffer = Vec::try_with_capacity(pmem::CHECKPOINT_CHUNK)?;
+        buffer.try_resize(pmem::CHECKPOINT_CHUNK, 0)?;
+        self.checkpoint_dir(&mnodes, self.root, &mut path, &mut buffer, checkpoint)
+    }
+
+    /// Writes the records for everything below directory `dir` (at `path`).
+    fn checkpoint_dir(
+        &self,
+        mnodes: &MnodeMap,
+        dir: MnodeNum,
+        path: &mut String,
+        buffer: &mut [u8],
+        checkpoint: &mut Checkpoint,
+    ) -> Result<(), KError> {
+        let dir = mnodes.get(&dir).ok_or(KError::InvalidFile)?.read();
+        for (name, mnode_num) in dir.entries() {
+            let parent_len = path.len();
+            path.try_reserve(name.len() + 1)?;
+            path.push('/');
+            path.push_str(name);
+
+            let mnode = mnodes.get(mnode_num).ok_or(KError::InvalidFile)?.read();
+            match mnode.get_mnode_type() {
+                FileType::Directory => {
+                    checkpoint.append(&Record::MkDir {
+                        mnode: *mnode_num,
+                        path: path.as_str(),
+                    })?;
+                    drop(mnode);
+                    self.checkpoint_dir(mnodes, *mnode_num, path, buffer, checkpoint)?;
+                }
+                FileType::File => {
+                    checkpoint.append(&Record::Create {
+                        mnode: *mnode_num,
+                        path: path.as_str(),
+                        modes: mnode.get_file_mode(),
+                    })?;
+
+                    // Holes (and zeroed chunks) are skipped, a resize
+                    // restores the size of the file if they are at the end.
+                    let mut offset = 0;
+                    let mut written = 0;
+                    while offset < mnode.get_file_size() {
+                        let len = mnode.read_contents(buffer, offset)?;
+                        if buffer[..len].iter().any(|b| *b != 0) {
+                            checkpoint.append(&Record::Write {
+                                mnode: *mnode_num,
+                                offset,
+                                data: &buffer[..len],
+                            })?;
+                            written = offset + len;
+                        }
+                        offset += len;
+                    }
+                    if written < mnode.get_file_size() {
+                        checkpoint.append(&Record::Resize {
+                            mnode: *mnode_num,
+                            len: mnode.get_file_size(),
+                        })?;
+                    }
+                }
+            }
+
+            path.truncate(parent_len);
+        }
+
+        Ok(())
+    }
+
+    /// Resolve `pathname` to an mnode number.
+    ///
+    /// Returns `InvalidFile` if a component doesn't exist and `NotADirectory`
+    /// if a component (other than the last one) is a file.
+    fn resolve(&self, mnodes: &MnodeMap, pathname: &str) -> Result<MnodeNum, KError> {
+        path_components(pathname).try_fold(self.root, |dir, name| {
+            mnodes
+                .get(&dir)
+                .ok_or(KError::InvalidFile)?
+                .read()
+                .get_entry(name)
+        })
+    }
+
+    /// Resolve the parent directory of `pathname` and look up the last
+    /// component in it.
+    ///
+    /// Returns the parent, the last component and its mnode number (if it
+    /// exists). Fails with `on_root` if `pathname` is the root directory.
+    fn resolve_entry<'a>(
+        &self,
+        mnodes: &MnodeMap,
+        pathname: &'a str,
+        on_root: KError,
+    ) -> Result<(MnodeNum, &'a str, Option<MnodeNum>), KError> {
+        let (parent, name) = split_path(pathname).ok_or(on_root)?;
+        let parent = self.resolve(mnodes, parent)?;
+        let entry = match mnodes
+            .get(&parent)
+            .ok_or(KError::InvalidFile)?
+            .read()
+            .get_entry(name)
+        {
+            Ok(mnode_num) => Some(mnode_num),
+            Err(KError::InvalidFile) => None,
+            Err(e) => return Err(e),
+        };
+
+        Ok((parent, name, entry))
+    }
+
+    /// Returns true if `mnode_num` is one of the directories on the path
+    /// from the root to `pathname` (including `pathname` itself).
+    fn on_path(&self, mnodes: &MnodeMap, pathname: &str, mnode_num: MnodeNum) -> bool {
+        let mut cur = self.root;
+        for name in path_components(pathname) {
+            if cur == mnode_num {
+                return true;
+            }
+            cur = match mnodes.get(&cur).map(|m| m.read().get_entry(name)) {
+                Some(Ok(next)) => next,
+                _ => return false,
+            };
+        }
+        cur == mnode_num
+    }
+
+    /// Returns the type of `mnode_num` and whether it's a directory with
+    /// entries.
+    fn mnode_kind(
+        &self,
+        mnodes: &MnodeMap,
+        mnode_num: MnodeNum,
+    ) -> Result<(FileType, bool), KError> {
+        let mnode = mnodes.get(&mnode_num).ok_or(KError::InvalidFile)?.read();
+        Ok((mnode.get_mnode_type(), mnode.has_entries()))
+    }
+
+    /// Create a new file or directory at `pathname`.
+    fn create_mnode(
+        &self,
+        pathname: &str,
+        modes: FileModes,
+        node_type: FileType,
+    ) -> Result<MnodeNum, KError> {
+        let record = |mnode| match node_type {
+            FileType::File => Record::Create {
+                mnode,
+                path: pathname,
+                modes,
+            },
+            FileType::Directory => Record::MkDir {
+                mnode,
+                path: pathname,
+            },
+        };
+        let journal = self.journal(record(0).size())?;
+
+        let mnode_num = self.insert_mnode(pathname, modes, node_type, None)?;
+        if let Some(journal) = journal {
+            journal.commit(&record(mnode_num));
+        }
+        Ok(mnode_num)
+    }
+
+    /// Inserts a new file or directory at `pathname`, it gets the mnode
+    /// number `mnode_num` (or the next free one if that's `None`).
+    fn insert_mnode(
+        &self,
+        pathname: &str,
+        modes: FileModes,
+        node_type: FileType,
+        mnode_num: Option<MnodeNum>,
+    ) -> Result<MnodeNum, KError> {
+        let mut mnodes = self.mnodes.write();
+        let (parent, name, entry) =
+            self.resolve_entry(&mnodes, pathname, KError::AlreadyPresent)?;
+        if entry.is_some() {
             return Err(KError::AlreadyPresent);
         }
 
-        let mnode_num = self.get_next_mno() as u64;
+        mnodes.try_reserve(1)?;
+        let mnode_num = match mnode_num {
+            Some(mnode_num) => {
+                if mnodes.contains_key(&mnode_num) {
+                    return Err(KError::AlreadyPresent);
+                }
+                // Don't hand out the number again
+                self.nextmemnode
+                    .fetch_max(mnode_num as usize + 1, Ordering::Relaxed);
+                mnode_num
+            }
+            None => self.get_next_mno() as u64,
+        };
         // TODO(error-handling): can we ignore or should we decrease mnode_num
         // on error?
-        let arc_mnode_num = Arc::try_new(mnode_num)?;
+        let memnode = MemNode::new(mnode_num, name, modes, node_type)?;
+        mnodes
+            .get(&parent)
+            .ok_or(KError::InvalidFile)?
+            .write()
+            .add_entry(name, mnode_num)?;
+        mnodes.insert(mnode_num, NrLock::new(memnode));
+
+        Ok(mnode_num)
+    }
+
+    /// Moves the entry at `oldname` to `newname` (see [`FileSystem::rename`]).
+    fn rename_entry(&self, oldname: &str, newname: &str) -> Result<(), KError> {
         let mut mnodes = self.mnodes.write();
-        mnodes.try_reserve(1)?;
+        let (old_parent, old_name, old_entry) =
+            self.resolve_entry(&mnodes, oldname, KError::PermissionError)?;
+        let old_mnode = old_entry.ok_or(KError::InvalidFile)?;
+        let (new_parent, new_name, new_entry) =
+            self.resolve_entry(&mnodes, newname, KError::PermissionError)?;
+
+        if new_entry == Some(old_mnode) {
+            // Renaming to itself is a no-op.
+            return Ok(());
+        }
 
-        // TODO: For now all newly created mnode are for file. How to differentiate
-        // between a file and a directory. Take input from the user?
-        let memnode = MemNode::new(mnode_num, &pathname, modes, FileType::File)?;
+        let (old_type, _) = self.mnode_kind(&mnodes, old_mnode)?;
+        if old_type == FileType::Directory && self.on_path(&mnodes, newname, old_mnode) {
+            // Can't move a directory into its own subtree.
+            return Err(KError::PermissionError);
+        }
 
-        self.files.write().insert(pathname, arc_mnode_num);
-        mnodes.insert(mnode_num, NrLock::new(memnode));
+        if let Some(replaced) = new_entry {
+            match (old_type, self.mnode_kind(&mnodes, replaced)?) {
+                (FileType::File, (FileType::Directory, _)) => return Err(KError::DirectoryError),
+                (FileType::Directory, (FileType::File, _)) => return Err(KError::NotADirectory),
+                (_, (_, true)) => return Err(KError::DirectoryNotEmpty),
+                (_, (_, false)) => {}
+            }
 
-        Ok(mnode_num)
+            mnodes
+                .get(&new_parent)
+                .ok_or(KError::InvalidFile)?
+                .write()
+                .remove_entry(new_name)?;
+            self.release_mnode(&mut mnodes, replaced)?;
+        }
+
+        mnodes
+            .get(&old_parent)
+            .ok_or(KError::InvalidFile)?
+            .write()
+            .remove_entry(old_name)?;
+        mnodes
+            .get(&new_parent)
+            .ok_or(KError::InvalidFile)?
+            .write()
+            .add_entry(new_name, old_mnode)?;
+        mnodes
+            .get(&old_mnode)
+            .ok_or(KError::InvalidFile)?
+            .write()
+            .set_name(new_name)
+    }
+}
+
+impl FileSystem for MlnrFS {
+    fn create(&self, pathname: String, modes: FileModes) -> Result<u64, KError> {
+        self.create_mnode(&pathname, modes, FileType::File)
     }
 
     fn write(&self, mnode_num: MnodeNum, buffer: &[u8], offset: usize) -> Result<usize, KError> {
-        match self.mnodes.read().get(&mnode_num) {
+        let record = Record::Write {
+            mnode: mnode_num,
+            offset,
+            data: buffer,
+        };
+        self.persisted(record, || match self.mnodes.read().get(&mnode_num) {
             Some(mnode) => mnode.write().write(buffer, offset),
             None => Err(KError::InvalidFile),
-        }
+        })
     }
 
     fn read(
@@ -153,8 +527,8 @@ impl FileSystem for MlnrFS {
         }
     }
 
-    fn lookup(&self, pathname: &str) -> Option<Arc<MnodeNum>> {
-        self.files.read().get(pathname).cloned()
+    fn lookup(&self, pathname: &str) -> Option<MnodeNum> {
+        self.resolve(&self.mnodes.read(), pathname).ok()
     }
 
     fn file_info(&self, mnode: MnodeNum) -> FileInfo {
@@ -173,72 +547,173 @@ impl FileSystem for MlnrFS {
         }
     }
 
+    /// Delete a file or an empty directory.
     fn delete(&self, pathname: &str) -> Result<(), KError> {
-        let mut files = self.files.write();
-        if let Some(mnode) = files.get(pathname) {
-            if Arc::strong_count(mnode) == 1 {
-                self.mnodes.write().remove(mnode);
-            } else {
-                return Err(KError::PermissionError);
+        self.persisted(Record::Delete { path: pathname }, || {
+            let mut mnodes = self.mnodes.write();
+            let (parent, name, entry) =
+                self.resolve_entry(&mnodes, pathname, KError::PermissionError)?;
+            let mnode_num = entry.ok_or(KError::InvalidFile)?;
+
+            let (_ftype, has_entries) = self.mnode_kind(&mnodes, mnode_num)?;
+            if has_entries {
+                return Err(KError::DirectoryNotEmpty);
             }
-        } else {
-            return Err(KError::InvalidFile);
-        }
 
-        let r = files.remove(pathname);
-        assert!(r.is_some(), "Didn't remove the mnode?");
-        Ok(())
+            mnodes
+                .get(&parent)
+                .ok_or(KError::InvalidFile)?
+                .write()
+                .remove_entry(name)?;
+            self.release_mnode(&mut mnodes, mnode_num)
+        })
     }
 
     fn truncate(&self, pathname: &str) -> Result<(), KError> {
-        match self.files.read().get(pathname) {
-            Some(mnode) => match self.mnodes.read().get(mnode) {
-                Some(memnode) => memnode.write().file_truncate(),
-                None => Err(KError::InvalidFile),
-            },
+        let mnode_num = self.resolve(&self.mnodes.read(), pathname)?;
+        let record = Record::Resize {
+            mnode: mnode_num,
+            len: 0,
+        };
+        self.persisted(record, || match self.mnodes.read().get(&mnode_num) {
+            Some(memnode) => memnode.write().file_truncate(),
             None => Err(KError::InvalidFile),
-        }
+        })
     }
 
+    fn resize(&self, mnode_num: MnodeNum, len: usize) -> Result<(), KError> {
+        let record = Record::Resize {
+            mnode: mnode_num,
+            len,
+        };
+        self.persisted(record, || match self.mnodes.read().get(&mnode_num) {
+            Some(mnode) => mnode.write().file_resize(len),
+            None => Err(KError::InvalidFile),
+        })
+    }
+
+    /// Rename a file or a directory (along with everything below it).
+    ///
+    /// If `newname` exists it is replaced, as long as it's a file that
+    /// replaces a file or an empty directory that replaces a directory.
     fn rename(&self, oldname: &str, newname: String) -> Result<(), KError> {
-        if self.files.read().get(oldname).is_none() {
-            return Err(KError::InvalidFile);
+        let record = Record::Rename {
+            oldname,
+            newname: &newname,
+        };
+        self.persisted(record, || self.rename_entry(oldname, &newname))
+    }
+
+    /// Create a directory.
+    fn mkdir(&self, pathname: String, modes: FileModes) -> Result<(), KError> {
+        self.create_mnode(&pathname, modes, FileType::Directory)
+            .map(|_mnode_num| ())
+    }
+
+    /// List the entries of a directory.
+    fn readdir(&self, pathname: &str) -> Result<Vec<DirEntry>, KError> {
+        let mnodes = self.mnodes.read();
+        let dir_mnode = self.resolve(&mnodes, pathname)?;
+        let dir = mnodes.get(&dir_mnode).ok_or(KError::InvalidFile)?.read();
+        if dir.get_mnode_type() != FileType::Directory {
+            return Err(KError::NotADirectory);
         }
 
-        // If the newfile exists then overwrite it with the oldfile.
-        if self.files.read().get(&newname).is_some() {
-            self.delete(&newname).unwrap();
+        let mut entries = Vec::new();
+        for (name, mnode_num) in dir.entries() {
+            let (ftype, _) = self.mnode_kind(&mnodes, *mnode_num)?;
+            entries.try_reserve(1)?;
+            entries.push(DirEntry {
+                name: TryString::try_from(name.as_str())?.into(),
+                mnode: *mnode_num,
+                ftype,
+            });
         }
 
-        // TODO: Can we optimize it somehow?
-        let mut lock_at_root = self.files.write();
-        match lock_at_root.remove_entry(oldname) {
-            Some((_key, oldnmode)) => match lock_at_root.insert(newname, oldnmode) {
-                None => Ok(()),
-                Some(_) => Err(KError::PermissionError),
-            },
+        // The hashmap iteration order isn't stable, sort the entries so every
+        // replica returns them in the same order
+        entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    /// Returns the frames of this replica that hold `offset..offset+len` of
+    /// the file.
+    ///
+    /// Every replica keeps track of the mapping (so the file's memory stays
+    /// around on all of them), but only the frames of the local replica end
+    /// up being mapped. Writes to them only reach the other replicas with
+    /// `write_back`.
+    fn map(
+        &self,
+        mnode_num: MnodeNum,
+        offset: usize,
+        len: usize,
+        writable: bool,
+    ) -> Result<Vec<Frame>, KError> {
+        match self.mnodes.read().get(&mnode_num) {
+            Some(mnode) => mnode.write().map(offset, len, writable),
             None => Err(KError::InvalidFile),
         }
     }
 
-    /// Create a directory. The implementation is quite simplistic for now, and only used
-    /// by leveldb benchmark.
-    fn mkdir(&self, pathname: String, modes: FileModes) -> Result<(), KError> {
-        // Check if the file with the same name already exists.
-        if self.files.read().get(&pathname).is_some() {
-            Err(KError::AlreadyPresent)
-        } else {
-            let mnode_num = self.get_next_mno() as u64;
-            // TODO(error-handling): Should we decrease mnode-num or ignore?
-            let arc_mnode_num = Arc::try_new(mnode_num)?;
-            let mut mnodes = self.mnodes.write();
-            mnodes.try_reserve(1)?;
-            let memnode = MemNode::new(mnode_num, &pathname, modes, FileType::Directory)?;
+    /// Releases `pages` pages of a file that were mapped with `map`.
+    fn unmap(&self, mnode_num: MnodeNum, pages: usize) {
+        let mut mnodes = self.mnodes.write();
+        let mapped = match mnodes.get(&mnode_num) {
+            Some(mnode) => {
+                let mut mnode = mnode.write();
+                mnode.unmap(pages);
+                mnode.is_mapped()
+            }
+            None => return,
+        };
+
+        // A deleted file goes away with its last mapping
+        let mut unlinked = self.unlinked.write();
+        if let Some(idx) = unlinked.iter().position(|m| *m == mnode_num) {
+            if !mapped {
+                unlinked.swap_remove(idx);
+                mnodes.remove(&mnode_num);
+            }
+        }
+    }
+
+    /// Writes back `buffer` from a shared mapping of the file at `offset`.
+    ///
+    /// Nothing past the end of the file is written (a mapping can't grow the
+    /// file).
+    fn write_back(
+        &self,
+        mnode_num: MnodeNum,
+        buffer: &[u8],
+        offset: usize,
+    ) -> Result<usize, KError> {
+        let size = match self.mnodes.read().get(&mnode_num) {
+            Some(mnode) if mnode.read().get_mnode_type() == FileType::File => {
+                mnode.read().get_file_size()
+            }
+            Some(_mnode) => return Err(KError::DirectoryError),
+            None => return Err(KError::InvalidFile),
+        };
+        if offset >= size {
+            return Ok(0);
+        }
 
-            self.files.write().insert(pathname, arc_mnode_num);
-            mnodes.insert(mnode_num, NrLock::new(memnode));
+        let buffer = &buffer[..core::cmp::min(buffer.len(), size - offset)];
+        let update = || match self.mnodes.read().get(&mnode_num) {
+            Some(mnode) => mnode.write().write(buffer, offset),
+            None => Err(KError::InvalidFile),
+        };
 
-            Ok(())
+        // A deleted file is gone from the persistent log already
+        if self.unlinked.read().contains(&mnode_num) {
+            return update();
         }
+        let record = Record::Write {
+            mnode: mnode_num,
+            offset,
+            data: buffer,
+        };
+        self.persisted(record, update)
     }
 }
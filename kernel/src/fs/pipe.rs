@@ -0,0 +1,164 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! A bounded in-kernel buffer that connects the two ends of a pipe.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use fallible_collections::FallibleVecGlobal;
+
+use crate::error::KError;
+
+/// Pipe number, stored in the file descriptors that refer to a pipe.
+pub(crate) type PipeNum = u64;
+
+/// The number of bytes a pipe can hold before writers have to wait.
+pub(crate) const PIPE_CAPACITY: usize = 16 * 1024;
+
+/// A pipe and the number of file descriptors referring to each of its ends.
+#[derive(Debug)]
+pub(crate) struct Pipe {
+    buffer: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+impl Pipe {
+    /// Create a new, empty pipe with one read and one write end.
+    pub(crate) fn new() -> Result<Pipe, KError> {
+        let mut buffer = VecDeque::new();
+        buffer.try_reserve_exact(PIPE_CAPACITY)?;
+        Ok(Pipe {
+            buffer,
+            readers: 1,
+            writers: 1,
+        })
+    }
+
+    /// Take up to `len` bytes out of the pipe.
+    ///
+    /// Returns `WouldBlock` if the pipe is empty but still has writers, and no
+    /// data (end of file) if it is empty and all writers are gone.
+    pub(crate) fn read(&mut self, len: usize) -> Result<Vec<u8>, KError> {
+        if self.buffer.is_empty() {
+            return if self.writers == 0 {
+                Ok(Vec::new())
+            } else {
+                Err(KError::WouldBlock)
+            };
+        }
+
+        let len = core::cmp::min(len, self.buffer.len());
+        let mut data = Vec::try_with_capacity(len)?;
+        data.extend(self.buffer.drain(..len));
+        Ok(data)
+    }
+
+    /// Append as much of `data` to the pipe as fits.
+    ///
+    /// Returns `WouldBlock` if the pipe is full, and `BrokenPipe` if all
+    /// readers are gone.
+    pub(crate) fn write(&mut self, data: &[u8]) -> Result<usize, KError> {
+        if self.readers == 0 {
+            return Err(KError::BrokenPipe);
+        }
+
+        let len = core::cmp::min(data.len(), PIPE_CAPACITY - self.buffer.len());
+        if len == 0 && !data.is_empty() {
+            return Err(KError::WouldBlock);
+        }
+
+        self.buffer.extend(&data[..len]);
+        Ok(len)
+    }
+
+    /// Returns true if a read (or write) wouldn't block right now.
+    pub(crate) fn is_ready(&self, write: bool) -> bool {
+        if write {
+            self.readers == 0 || self.buffer.len() < PIPE_CAPACITY
+        } else {
+            self.writers == 0 || !self.buffer.is_empty()
+        }
+    }
+
+    /// Another file descriptor refers to the read (or write) end.
+    pub(crate) fn open_end(&mut self, write: bool) {
+        if write {
+            self.writers += 1;
+        } else {
+            self.readers += 1;
+        }
+    }
+
+    /// A file descriptor referring to the read (or write) end got closed.
+    pub(crate) fn close_end(&mut self, write: bool) {
+        if write {
+            debug_assert!(self.writers > 0, "Closed more write ends than opened");
+            self.writers -= 1;
+        } else {
+            debug_assert!(self.readers > 0, "Closed more read ends than opened");
+            self.readers -= 1;
+        }
+    }
+
+    /// Returns true if no file descriptor refers to the pipe anymore.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.readers == 0 && self.writers == 0
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    /// Data comes out of the pipe in the order it was written.
+    fn test_pipe_read_write() {
+        let mut pipe = Pipe::new().unwrap();
+        assert_eq!(pipe.read(10), Err(KError::WouldBlock));
+
+        assert_eq!(pipe.write(&[1, 2, 3]), Ok(3));
+        assert_eq!(pipe.write(&[4]), Ok(1));
+        assert_eq!(pipe.read(2), Ok(vec![1, 2]));
+        assert_eq!(pipe.read(10), Ok(vec![3, 4]));
+        assert_eq!(pipe.read(10), Err(KError::WouldBlock));
+    }
+
+    #[test]
+    /// Writes only fill the pipe up to its capacity.
+    fn test_pipe_full() {
+        let mut pipe = Pipe::new().unwrap();
+        let data = [0xa; PIPE_CAPACITY + 1];
+
+        assert!(!pipe.is_ready(false));
+        assert_eq!(pipe.write(&data), Ok(PIPE_CAPACITY));
+        assert!(pipe.is_ready(false));
+        assert!(!pipe.is_ready(true));
+        assert_eq!(pipe.write(&data), Err(KError::WouldBlock));
+        assert_eq!(pipe.read(1), Ok(vec![0xa]));
+        assert!(pipe.is_ready(true));
+        assert_eq!(pipe.write(&data), Ok(1));
+    }
+
+    #[test]
+    /// Closing one end is visible at the other end.
+    fn test_pipe_close_ends() {
+        let mut pipe = Pipe::new().unwrap();
+        pipe.open_end(true);
+        assert_eq!(pipe.write(&[1]), Ok(1));
+
+        pipe.close_end(true);
+        assert_eq!(pipe.read(10), Ok(vec![1]));
+        assert_eq!(pipe.read(10), Err(KError::WouldBlock));
+        pipe.close_end(true);
+        assert!(pipe.is_ready(false));
+        assert_eq!(pipe.read(10), Ok(vec![]));
+        assert!(!pipe.is_closed());
+
+        pipe.close_end(false);
+        assert_eq!(pipe.write(&[1]), Err(KError::BrokenPipe));
+        assert!(pipe.is_closed());
+    }
+}
@@ -0,0 +1,707 @@
+// Copyright © 2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Persistent memory backed storage for [`MlnrFS`](super::MlnrFS).
+//!
+//! The file-system still serves all requests from DRAM, but (if the kernel is
+//! started with `fs=pmem`) every update is also appended as a redo record to
+//! a log in a PMem region that is reserved at boot. When the kernel boots
+//! again, the file-system is remounted by replaying the records.
+//!
+//! The region starts with a [`Superblock`] and is split into two log areas,
+//! only one of them is active at a time. Once the active area runs full, the
+//! current state of the file-system is written as a fresh set of records to
+//! the other area, which then becomes the active one by bumping the
+//! generation in the superblock (a single 8-byte store).
+//!
+//! Every record carries the generation of its area and a checksum. A record
+//! that was only partially written before a crash (or that is left over from
+//! an older generation) ends the log, so a crash never leaves us with more
+//! than a torn last update.
+
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use kpi::io::FileModes;
+use log::{info, warn};
+use spin::{Mutex, MutexGuard, Once};
+
+use crate::error::KError;
+use crate::memory::{Frame, BASE_PAGE_SIZE, LARGE_PAGE_SIZE};
+use crate::round_up;
+
+use super::MnodeNum;
+
+/// How much PMem we reserve for the file-system (at most).
+pub(crate) const PMEM_FS_SIZE: usize = 256 * 1024 * 1024;
+
+/// Identifies a formatted region ("NRKPMFS").
+const MAGIC: u64 = u64::from_le_bytes(*b"NRKPMFS\0");
+
+/// Layout version of the region.
+const VERSION: u64 = 1;
+
+/// The superblock occupies the first page of the region.
+const SUPERBLOCK_SIZE: usize = BASE_PAGE_SIZE;
+
+/// Size of a [`RecordHeader`].
+const HEADER_SIZE: usize = size_of::<RecordHeader>();
+
+/// Records are aligned to this.
+const RECORD_ALIGN: usize = 8;
+
+/// Granularity of cache flushes.
+const CACHE_LINE_SIZE: usize = 64;
+
+/// The file contents are split into write records of at most this size when
+/// we write a checkpoint.
+pub(crate) const CHECKPOINT_CHUNK: usize = 16 * BASE_PAGE_SIZE;
+
+/// The PMem storage of the file-system (if the kernel was asked to use one).
+pub(crate) static PMEM_FS: Once<PmemStore> = Once::new();
+
+/// Mounts (or formats) the file-system storage in `region`.
+///
+/// Must be called before the first file-system replica is created.
+pub(crate) fn init(region: Frame) {
+    PMEM_FS.call_once(|| unsafe {
+        // Safety:
+        // - The region is mapped in the kernel address space and reserved for
+        //   the file-system (it's not handed to any allocator)
+        PmemStore::mount(region.kernel_vaddr().as_mut_ptr(), region.size())
+    });
+}
+
+/// Takes the first `PMEM_FS_SIZE` bytes (or half, if the region is small) of
+/// `region` for the file-system, returns what's left of it.
+pub(crate) fn reserve(region: Frame) -> Frame {
+    let size = core::cmp::min(PMEM_FS_SIZE, region.size() / 2) & !(LARGE_PAGE_SIZE - 1);
+    if size == 0 {
+        warn!("{:?} is too small to hold the file-system", region);
+        return region;
+    }
+
+    let (fs_region, rest) = region.split_at(size);
+    init(fs_region);
+    rest
+}
+
+/// The first page of the PMem region.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct Superblock {
+    magic: u64,
+    version: u64,
+    /// Size (in bytes) of each of the two log areas.
+    area_size: u64,
+    /// The generation of the active area (`generation % 2` is its index).
+    generation: u64,
+}
+
+/// Precedes every record in a log area.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct RecordHeader {
+    /// Covers all other fields and the payload.
+    checksum: u64,
+    /// The generation of the area the record was written for.
+    generation: u64,
+    kind: u32,
+    /// Length of the payload (in bytes).
+    len: u32,
+    mnode: u64,
+    arg: u64,
+}
+
+/// An update of the file-system as it's stored in the log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Record<'a> {
+    /// A file `mnode` was created at `path`.
+    Create {
+        mnode: MnodeNum,
+        path: &'a str,
+        modes: FileModes,
+    },
+    /// A directory `mnode` was created at `path`.
+    MkDir { mnode: MnodeNum, path: &'a str },
+    /// `data` was written to `mnode` at `offset`.
+    Write {
+        mnode: MnodeNum,
+        offset: usize,
+        data: &'a [u8],
+    },
+    /// File `mnode` was resized to `len` bytes.
+    Resize { mnode: MnodeNum, len: usize },
+    /// `path` was removed.
+    Delete { path: &'a str },
+    /// `oldname` was renamed to `newname`.
+    Rename { oldname: &'a str, newname: &'a str },
+}
+
+impl<'a> Record<'a> {
+    const CREATE: u32 = 1;
+    const MKDIR: u32 = 2;
+    const WRITE: u32 = 3;
+    const RESIZE: u32 = 4;
+    const DELETE: u32 = 5;
+    const RENAME: u32 = 6;
+
+    /// Returns the kind, mnode number, argument and the payload (in two
+    /// parts) of the record.
+    fn encode(&self) -> (u32, u64, u64, [&'a [u8]; 2]) {
+        match *self {
+            Record::Create { mnode, path, modes } => {
+                (Self::CREATE, mnode, modes.into(), [path.as_bytes(), &[]])
+            }
+            Record::MkDir { mnode, path } => (Self::MKDIR, mnode, 0, [path.as_bytes(), &[]]),
+            Record::Write {
+                mnode,
+                offset,
+                data,
+            } => (Self::WRITE, mnode, offset as u64, [data, &[]]),
+            Record::Resize { mnode, len } => (Self::RESIZE, mnode, len as u64, [&[], &[]]),
+            Record::Delete { path } => (Self::DELETE, 0, 0, [path.as_bytes(), &[]]),
+            Record::Rename { oldname, newname } => (
+                Self::RENAME,
+                0,
+                oldname.len() as u64,
+                [oldname.as_bytes(), newname.as_bytes()],
+            ),
+        }
+    }
+
+    /// Parses the record described by `header` and `payload`.
+    fn decode(header: &RecordHeader, payload: &'a [u8]) -> Option<Record<'a>> {
+        let str_payload = || core::str::from_utf8(payload).ok();
+        match header.kind {
+            Self::CREATE => Some(Record::Create {
+                mnode: header.mnode,
+                path: str_payload()?,
+                modes: FileModes::from(header.arg),
+            }),
+            Self::MKDIR => Some(Record::MkDir {
+                mnode: header.mnode,
+                path: str_payload()?,
+            }),
+            Self::WRITE => Some(Record::Write {
+                mnode: header.mnode,
+                offset: header.arg as usize,
+                data: payload,
+            }),
+            Self::RESIZE => Some(Record::Resize {
+                mnode: header.mnode,
+                len: header.arg as usize,
+            }),
+            Self::DELETE => Some(Record::Delete {
+                path: str_payload()?,
+            }),
+            Self::RENAME => {
+                let names = str_payload()?;
+                let split = header.arg as usize;
+                if !names.is_char_boundary(split) {
+                    return None;
+                }
+                let (oldname, newname) = names.split_at(split);
+                Some(Record::Rename { oldname, newname })
+            }
+            _ => None,
+        }
+    }
+
+    /// How many bytes the record occupies in the log.
+    pub(crate) fn size(&self) -> usize {
+        let (_kind, _mnode, _arg, payload) = self.encode();
+        round_up!(
+            HEADER_SIZE + payload[0].len() + payload[1].len(),
+            RECORD_ALIGN
+        )
+    }
+}
+
+/// FNV-1a hash of the header fields (except the checksum) and the payload.
+fn checksum(header: &RecordHeader, payload: [&[u8]; 2]) -> u64 {
+    const PRIME: u64 = 0x100_0000_01b3;
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    let fields = [
+        header.generation,
+        header.kind as u64,
+        header.len as u64,
+        header.mnode,
+        header.arg,
+    ];
+    let bytes = fields
+        .iter()
+        .flat_map(|f| f.to_le_bytes())
+        .chain(payload[0].iter().copied())
+        .chain(payload[1].iter().copied());
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(PRIME);
+    }
+    hash
+}
+
+/// Writes back the cache-lines covering `len` bytes at `addr` to memory.
+fn persist(addr: *const u8, len: usize) {
+    let start = addr as usize & !(CACHE_LINE_SIZE - 1);
+    let end = addr as usize + len;
+    for line in (start..end).step_by(CACHE_LINE_SIZE) {
+        unsafe { core::arch::x86_64::_mm_clflush(line as *const u8) };
+    }
+    unsafe { core::arch::x86_64::_mm_sfence() };
+}
+
+/// Where the next record goes.
+#[derive(Debug)]
+struct LogState {
+    /// Generation of the active area.
+    generation: u64,
+    /// Offset of the end of the log in the active area.
+    tail: usize,
+}
+
+/// The log of file-system updates in a PMem region.
+#[derive(Debug)]
+pub(crate) struct PmemStore {
+    /// Start of the region (in the kernel address space).
+    base: *mut u8,
+    /// Size of each log area.
+    area_size: usize,
+    /// The generation and length of the log when the store was mounted (this
+    /// is what the file-system replicas replay).
+    mounted: (u64, usize),
+    log: Mutex<LogState>,
+    /// Set once a file-system replica appends its updates to the store.
+    writer: AtomicBool,
+}
+
+// Safety: The region is only accessed through the `log` lock (or read-only).
+unsafe impl Send for PmemStore {}
+unsafe impl Sync for PmemStore {}
+
+impl PmemStore {
+    /// Mounts the log in the `size` bytes at `base`, formats the region if it
+    /// doesn't hold a log yet.
+    ///
+    /// # Safety
+    /// `base` has to point to `size` bytes of memory that only the returned
+    /// store uses.
+    pub(crate) unsafe fn mount(base: *mut u8, size: usize) -> PmemStore {
+        assert!(
+            size > SUPERBLOCK_SIZE + 2 * HEADER_SIZE,
+            "PMem region too small"
+        );
+        assert_eq!(base as usize % RECORD_ALIGN, 0);
+        let area_size = ((size - SUPERBLOCK_SIZE) / 2) & !(RECORD_ALIGN - 1);
+
+        let mut store = PmemStore {
+            base,
+            area_size,
+            mounted: (0, 0),
+            log: Mutex::new(LogState {
+                generation: 0,
+                tail: 0,
+            }),
+            writer: AtomicBool::new(false),
+        };
+
+        let superblock = ptr::read_volatile(base as *const Superblock);
+        let generation = if superblock.magic == MAGIC
+            && superblock.version == VERSION
+            && superblock.area_size == area_size as u64
+        {
+            superblock.generation
+        } else {
+            info!("Formatting PMem file-system ({} bytes)", size);
+            store.format()
+        };
+
+        let mut tail = 0;
+        while let Some((_record, len)) = store.read_record(generation, tail) {
+            tail += len;
+        }
+        info!(
+            "Mounted PMem file-system (generation {}, {} bytes of records)",
+            generation, tail
+        );
+
+        store.mounted = (generation, tail);
+        *store.log.lock() = LogState { generation, tail };
+        store
+    }
+
+    /// Initializes an empty log, returns its generation.
+    unsafe fn format(&self) -> u64 {
+        let superblock = self.base as *mut Superblock;
+        // Invalidate the region first, a crash in between formats again
+        ptr::write_volatile(ptr::addr_of_mut!((*superblock).magic), 0);
+        persist(self.base, size_of::<Superblock>());
+
+        let generation = 1;
+        self.terminate(generation, 0);
+        self.terminate(generation + 1, 0);
+
+        ptr::write_volatile(
+            superblock,
+            Superblock {
+                magic: 0,
+                version: VERSION,
+                area_size: self.area_size as u64,
+                generation,
+            },
+        );
+        persist(self.base, size_of::<Superblock>());
+        ptr::write_volatile(ptr::addr_of_mut!((*superblock).magic), MAGIC);
+        persist(self.base, size_of::<Superblock>());
+
+        generation
+    }
+
+    /// Start of the log area of `generation`.
+    fn area(&self, generation: u64) -> *mut u8 {
+        let idx = (generation % 2) as usize;
+        unsafe { self.base.add(SUPERBLOCK_SIZE + idx * self.area_size) }
+    }
+
+    /// Makes the area of `generation` the active one.
+    fn activate(&self, generation: u64) {
+        let superblock = self.base as *mut Superblock;
+        unsafe { ptr::write_volatile(ptr::addr_of_mut!((*superblock).generation), generation) };
+        persist(self.base, size_of::<Superblock>());
+    }
+
+    /// Ends the log of `generation` at `offset` (if there is room for another
+    /// header).
+    fn terminate(&self, generation: u64, offset: usize) {
+        if offset + HEADER_SIZE <= self.area_size {
+            let header = unsafe { self.area(generation).add(offset) };
+            unsafe { ptr::write_volatile(header as *mut RecordHeader, RecordHeader::default()) };
+            persist(header, HEADER_SIZE);
+        }
+    }
+
+    /// Returns the record at `offset` of the area of `generation` and its
+    /// length, or `None` if the log ends there.
+    fn read_record(&self, generation: u64, offset: usize) -> Option<(Record<'_>, usize)> {
+        if offset + HEADER_SIZE > self.area_size {
+            return None;
+        }
+
+        let area = self.area(generation);
+        let header = unsafe { ptr::read_volatile(area.add(offset) as *const RecordHeader) };
+        let len = round_up!(HEADER_SIZE + header.len as usize, RECORD_ALIGN);
+        if header.generation != generation || len > self.area_size - offset {
+            return None;
+        }
+
+        // Safety: Within the area (checked above), nobody writes to records
+        // that are part of the log.
+        let payload = unsafe {
+            core::slice::from_raw_parts(area.add(offset + HEADER_SIZE), header.len as usize)
+        };
+        if checksum(&header, [payload, &[]]) != header.checksum {
+            return None;
+        }
+
+        Record::decode(&header, payload).map(|record| (record, len))
+    }
+
+    /// Writes `record` at `offset` of the area of `generation`, returns the
+    /// offset after it.
+    ///
+    /// The caller has to make sure the record fits.
+    fn write_record(&self, generation: u64, offset: usize, record: &Record) -> usize {
+        let len = record.size();
+        assert!(offset + len <= self.area_size, "Record doesn't fit");
+
+        // Terminate the log behind the record first, so it can never be
+        // followed by a stale record once it's valid
+        self.terminate(generation, offset + len);
+
+        let (kind, mnode, arg, payload) = record.encode();
+        let mut header = RecordHeader {
+            checksum: 0,
+            generation,
+            kind,
+            len: (payload[0].len() + payload[1].len()) as u32,
+            mnode,
+            arg,
+        };
+        header.checksum = checksum(&header, payload);
+
+        unsafe {
+            // Safety: We hold the log lock (or write an inactive area) and the
+            // record fits in the area
+            let dst = self.area(generation).add(offset);
+            let data = dst.add(HEADER_SIZE);
+            ptr::copy_nonoverlapping(payload[0].as_ptr(), data, payload[0].len());
+            ptr::copy_nonoverlapping(
+                payload[1].as_ptr(),
+                data.add(payload[0].len()),
+                payload[1].len(),
+            );
+            ptr::write_volatile(dst as *mut RecordHeader, header);
+            persist(dst, len);
+        }
+
+        offset + len
+    }
+
+    /// Calls `apply` for every record the log held when it was mounted.
+    pub(crate) fn replay<F: FnMut(Record) -> Result<(), KError>>(&self, mut apply: F) {
+        let (generation, end) = self.mounted;
+        let mut offset = 0;
+        while offset < end {
+            match self.read_record(generation, offset) {
+                Some((record, len)) => {
+                    if let Err(e) = apply(record) {
+                        warn!("Failed to replay {:?}: {:?}", record, e);
+                    }
+                    offset += len;
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Returns true for the first caller, which from then on is responsible
+    /// for appending all updates to the store.
+    pub(crate) fn claim(&self) -> bool {
+        !self.writer.swap(true, Ordering::Relaxed)
+    }
+
+    /// Starts an update that will append up to `len` bytes of records.
+    ///
+    /// If the active area doesn't have enough room left, `snapshot` is asked
+    /// to write the current state of the file-system to a [`Checkpoint`]
+    /// which then replaces the log. Updates are serialized until the returned
+    /// [`Journal`] is dropped.
+    pub(crate) fn begin<F>(&self, len: usize, snapshot: F) -> Result<Journal<'_>, KError>
+    where
+        F: FnOnce(&mut Checkpoint) -> Result<(), KError>,
+    {
+        let mut log = self.log.lock();
+        if log.tail + len > self.area_size {
+            let mut checkpoint = Checkpoint {
+                store: self,
+                generation: log.generation + 1,
+                tail: 0,
+            };
+            self.terminate(checkpoint.generation, 0);
+            snapshot(&mut checkpoint)?;
+            if checkpoint.tail + len > self.area_size {
+                return Err(KError::FileSystemFull);
+            }
+
+            self.activate(checkpoint.generation);
+            log.generation = checkpoint.generation;
+            log.tail = checkpoint.tail;
+        }
+
+        Ok(Journal { store: self, log })
+    }
+}
+
+/// An update in progress (see [`PmemStore::begin`]).
+pub(crate) struct Journal<'a> {
+    store: &'a PmemStore,
+    log: MutexGuard<'a, LogState>,
+}
+
+impl<'a> Journal<'a> {
+    /// Appends `record` (which can't be longer than what was asked for in
+    /// [`PmemStore::begin`]) to the log.
+    pub(crate) fn commit(mut self, record: &Record) {
+        self.log.tail = self
+            .store
+            .write_record(self.log.generation, self.log.tail, record);
+    }
+}
+
+/// The state of the file-system that's written to the inactive area.
+pub(crate) struct Checkpoint<'a> {
+    store: &'a PmemStore,
+    generation: u64,
+    tail: usize,
+}
+
+impl<'a> Checkpoint<'a> {
+    /// Appends `record` to the checkpoint.
+    pub(crate) fn append(&mut self, record: &Record) -> Result<(), KError> {
+        if self.tail + record.size() > self.store.area_size {
+            return Err(KError::FileSystemFull);
+        }
+        self.tail = self.store.write_record(self.generation, self.tail, record);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// A (DRAM) region for a store of `size` bytes.
+    fn region(size: usize) -> Vec<u64> {
+        alloc::vec![0xdead_beef; size / 8]
+    }
+
+    #[test]
+    fn record_roundtrip() {
+        let records = [
+            Record::Create {
+                mnode: 2,
+                path: "/dir/file",
+                modes: FileModes::S_IRWXU,
+            },
+            Record::MkDir {
+                mnode: 3,
+                path: "/dir",
+            },
+            Record::Write {
+                mnode: 2,
+                offset: 77,
+                data: &[1, 2, 3],
+            },
+            Record::Resize { mnode: 2, len: 0 },
+            Record::Delete { path: "/dir/file" },
+            Record::Rename {
+                oldname: "/a",
+                newname: "/b/c",
+            },
+        ];
+
+        let mut mem = region(64 * 1024);
+        let store = unsafe { PmemStore::mount(mem.as_mut_ptr() as *mut u8, mem.len() * 8) };
+        let mut offset = 0;
+        for record in records.iter() {
+            offset = store.write_record(1, offset, record);
+        }
+
+        let mut offset = 0;
+        for record in records.iter() {
+            let (read, len) = store.read_record(1, offset).unwrap();
+            assert_eq!(read, *record);
+            assert_eq!(len, record.size());
+            offset += len;
+        }
+        assert!(store.read_record(1, offset).is_none());
+    }
+
+    #[test]
+    fn remount() {
+        let mut mem = region(64 * 1024);
+        let base = mem.as_mut_ptr() as *mut u8;
+        let store = unsafe { PmemStore::mount(base, mem.len() * 8) };
+        assert!(store.claim());
+        assert!(!store.claim());
+
+        let record = Record::MkDir {
+            mnode: 2,
+            path: "/dir",
+        };
+        store
+            .begin(record.size(), |_c| unreachable!())
+            .unwrap()
+            .commit(&record);
+
+        let store = unsafe { PmemStore::mount(base, mem.len() * 8) };
+        let mut replayed = Vec::new();
+        store.replay(|r| {
+            replayed.push(r.size());
+            assert_eq!(r, record);
+            Ok(())
+        });
+        assert_eq!(replayed.len(), 1);
+    }
+
+    #[test]
+    fn torn_record_ends_log() {
+        let mut mem = region(64 * 1024);
+        let base = mem.as_mut_ptr() as *mut u8;
+        let store = unsafe { PmemStore::mount(base, mem.len() * 8) };
+
+        let data = [0xaa; 100];
+        for offset in [0, 100] {
+            let record = Record::Write {
+                mnode: 2,
+                offset,
+                data: &data,
+            };
+            store
+                .begin(record.size(), |_c| unreachable!())
+                .unwrap()
+                .commit(&record);
+        }
+
+        // Corrupt the payload of the second record
+        let second = SUPERBLOCK_SIZE + 2 * HEADER_SIZE + 100 + 4;
+        unsafe { *base.add(second + 50) = 0 };
+
+        let store = unsafe { PmemStore::mount(base, mem.len() * 8) };
+        let mut replayed = 0;
+        store.replay(|_r| {
+            replayed += 1;
+            Ok(())
+        });
+        assert_eq!(replayed, 1);
+    }
+
+    #[test]
+    fn checkpoint_replaces_log() {
+        let mut mem = region(SUPERBLOCK_SIZE + 2 * 1024);
+        let base = mem.as_mut_ptr() as *mut u8;
+        let store = unsafe { PmemStore::mount(base, mem.len() * 8) };
+
+        let data = [0xbb; 200];
+        let record = Record::Write {
+            mnode: 2,
+            offset: 0,
+            data: &data,
+        };
+        let mut checkpoints = 0;
+        for _i in 0..10 {
+            store
+                .begin(record.size(), |checkpoint| {
+                    checkpoints += 1;
+                    checkpoint.append(&Record::MkDir {
+                        mnode: 2,
+                        path: "/snap",
+                    })
+                })
+                .unwrap()
+                .commit(&record);
+        }
+        assert!(checkpoints > 0);
+
+        // The log starts with the checkpoint now
+        let store = unsafe { PmemStore::mount(base, mem.len() * 8) };
+        let mut replayed = Vec::new();
+        store.replay(|r| {
+            replayed.push(r.size());
+            Ok(())
+        });
+        assert_eq!(
+            replayed[0],
+            Record::MkDir {
+                mnode: 2,
+                path: "/snap"
+            }
+            .size()
+        );
+
+        // Records that never fit are rejected
+        let big = [0; 4096];
+        let record = Record::Write {
+            mnode: 2,
+            offset: 0,
+            data: &big,
+        };
+        assert!(matches!(
+            store.begin(record.size(), |_c| Ok(())),
+            Err(KError::FileSystemFull)
+        ));
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright © 2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Executes the file operations a process queued in its [`IoRing`].
+//!
+//! A process registers a page with its ring once (`RingRegister`), after that
+//! the kernel accesses the ring through the kernel alias of the page (like
+//! the `VirtualCpu` area of an executor). Every `RingEnter` takes the queued
+//! submissions from the ring and posts their completions to it.
+//!
+//! The process can change the ring while we work on it: we read every
+//! submission exactly once and only trust the indices as far as they stay
+//! within the queues.
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt::{Debug, LowerHex};
+use core::sync::atomic::Ordering;
+
+use fallible_collections::FallibleVecGlobal;
+use kpi::io::SeekWhence;
+use kpi::ring::{Completion, IoRing, Submission, RING_ENTRIES};
+use kpi::{FileOperation, SystemCallError};
+use log::trace;
+
+use crate::arch::process::ArchProcess;
+use crate::error::{KError, KResult};
+use crate::memory::{paddr_to_kernel_vaddr, BASE_PAGE_SIZE};
+use crate::nrproc::NrProcess;
+use crate::process::{Pid, UVAddr, UserSlice};
+use crate::syscalls::FsDispatch;
+
+use super::fd::FileDescriptor;
+
+/// A file operation taken from the ring.
+#[derive(Debug)]
+pub(crate) enum RingOp {
+    /// Read into the buffer from the offset (-1 uses the offset of the fd).
+    Read(FileDescriptor, UserSlice, i64),
+    /// Write the buffer at the offset (-1 uses the offset of the fd).
+    Write(FileDescriptor, UserSlice, i64),
+    Truncate(FileDescriptor, u64),
+    Seek(FileDescriptor, i64, SeekWhence),
+    Close(FileDescriptor),
+}
+
+impl RingOp {
+    /// Checks the arguments of `submission`.
+    fn new(pid: Pid, submission: &Submission) -> KResult<RingOp> {
+        let op = FileOperation::new(submission.op)
+            .ok_or(KError::InvalidFileOperation { a: submission.op })?;
+        let fd = FileDescriptor::try_from(submission.fd)?;
+        let buffer = || {
+            UserSlice::new(
+                pid,
+                UVAddr::try_from(submission.buf)?,
+                submission.len as usize,
+            )
+        };
+
+        match op {
+            FileOperation::Read => Ok(RingOp::Read(fd, buffer()?, -1)),
+            FileOperation::ReadAt => Ok(RingOp::Read(fd, buffer()?, submission.offset)),
+            FileOperation::Write => Ok(RingOp::Write(fd, buffer()?, -1)),
+            FileOperation::WriteAt => Ok(RingOp::Write(fd, buffer()?, submission.offset)),
+            FileOperation::Truncate => Ok(RingOp::Truncate(fd, submission.len)),
+            FileOperation::Seek => Ok(RingOp::Seek(
+                fd,
+                submission.offset,
+                SeekWhence::new(submission.len).ok_or(KError::InvalidFlags)?,
+            )),
+            FileOperation::Close => Ok(RingOp::Close(fd)),
+            _ => Err(KError::NotSupported),
+        }
+    }
+
+    /// Executes the operation with the regular system call.
+    pub(crate) fn execute<W, F>(self, fs: &F) -> KResult<(W, W)>
+    where
+        W: Into<u64> + LowerHex + Debug + Copy + Clone,
+        F: FsDispatch<W> + ?Sized,
+    {
+        match self {
+            RingOp::Read(fd, buffer, -1) => fs.read(fd, buffer),
+            RingOp::Read(fd, buffer, offset) => fs.read_at(fd, buffer, offset),
+            RingOp::Write(fd, buffer, -1) => fs.write(fd, buffer),
+            RingOp::Write(fd, buffer, offset) => fs.write_at(fd, buffer, offset),
+            RingOp::Truncate(fd, len) => fs.truncate(fd, len),
+            RingOp::Seek(fd, offset, whence) => fs.seek(fd, offset, whence),
+            RingOp::Close(fd) => fs.close(fd),
+        }
+    }
+}
+
+/// Registers the page at `base` as the ring of process `pid`.
+pub(crate) fn register(pid: Pid, base: UVAddr) -> KResult<(u64, u64)> {
+    if base.as_usize() % BASE_PAGE_SIZE != 0 {
+        return Err(KError::InvalidUserBufferArgs);
+    }
+
+    // We access the page through its kernel alias, so it has to be backed
+    // (and not be copy-on-write) already
+    UserSlice::new(pid, base, core::mem::size_of::<IoRing>())?.populate(true)?;
+    NrProcess::<ArchProcess>::set_io_ring(pid, base.vaddr())?;
+    Ok((0, 0))
+}
+
+/// The ring registered by process `pid`, through the kernel alias of its
+/// page.
+///
+/// # Safety
+/// The pointer is only valid as long as the process doesn't unmap the page,
+/// so it must not outlive the system call.
+unsafe fn registered(pid: Pid) -> KResult<*mut IoRing> {
+    let base = NrProcess::<ArchProcess>::io_ring(pid)?.ok_or(KError::NoIoRing)?;
+    let (mut paddr, rights) = NrProcess::<ArchProcess>::resolve(pid, base)?;
+    if !rights.is_writable() {
+        // The page became copy-on-write (e.g., with a snapshot) since it was
+        // registered, the process gets its own copy first
+        let ring = UserSlice::new(pid, UVAddr::try_from(base)?, core::mem::size_of::<IoRing>())?;
+        ring.populate(true)?;
+        paddr = NrProcess::<ArchProcess>::resolve(pid, base)?.0;
+    }
+
+    Ok(paddr_to_kernel_vaddr(paddr).as_mut_ptr::<IoRing>())
+}
+
+/// Executes the queued submissions of the ring of process `pid` (as many as
+/// there is room for completions), returns how many completed.
+///
+/// The operations go to `fs` as one batch, so it can append them to its
+/// logs together.
+pub(crate) fn enter<F: FsDispatch<u64>>(fs: &F, pid: Pid) -> KResult<(u64, u64)> {
+    let ring_ptr = unsafe { registered(pid)? };
+    let ring = unsafe { &*ring_ptr };
+
+    let sq_head = ring.sq_head.load(Ordering::Relaxed);
+    let sq_tail = ring.sq_tail.load(Ordering::Acquire);
+    let cq_head = ring.cq_head.load(Ordering::Acquire);
+    let cq_tail = ring.cq_tail.load(Ordering::Relaxed);
+
+    let pending = sq_tail.wrapping_sub(sq_head).min(RING_ENTRIES as u64) as usize;
+    let queued = cq_tail.wrapping_sub(cq_head).min(RING_ENTRIES as u64) as usize;
+    let n = pending.min(RING_ENTRIES - queued);
+    trace!("ring_enter {} submissions ({} pending)", n, pending);
+
+    // Read every submission once, the process may change them any time
+    let mut submissions: Vec<Submission> = Vec::try_with_capacity(n)?;
+    for i in 0..n {
+        let slot = (sq_head.wrapping_add(i as u64) % RING_ENTRIES as u64) as usize;
+        submissions.push(unsafe { core::ptr::read_volatile(core::ptr::addr_of!(ring.sq[slot])) });
+    }
+
+    // Operations with invalid arguments fail right away, the rest is
+    // executed as a batch
+    let mut results: Vec<Option<KResult<(u64, u64)>>> = Vec::try_with_capacity(n)?;
+    let mut ops: Vec<RingOp> = Vec::try_with_capacity(n)?;
+    for submission in &submissions {
+        match RingOp::new(pid, submission) {
+            Ok(op) => {
+                ops.push(op);
+                results.push(None);
+            }
+            Err(e) => results.push(Some(Err(e))),
+        }
+    }
+    let mut batch_results = fs.execute_batch(ops)?.into_iter();
+    for result in results.iter_mut().filter(|result| result.is_none()) {
+        *result = batch_results.next();
+    }
+
+    // Post the completions in the free slots, then tell user-space about
+    // them
+    for (i, (submission, result)) in submissions.iter().zip(results.into_iter()).enumerate() {
+        let slot = (cq_tail.wrapping_add(i as u64) % RING_ENTRIES as u64) as usize;
+        let completion = match result.unwrap_or(Err(KError::NotSupported)) {
+            Ok((result, _)) => Completion {
+                user_data: submission.user_data,
+                result,
+                error: 0,
+            },
+            Err(e) => Completion {
+                user_data: submission.user_data,
+                result: 0,
+                error: SystemCallError::from(e) as u64,
+            },
+        };
+        unsafe {
+            // Safety: The slot is free (the process consumed it already)
+            // and we own the completion queue up to `cq_tail + n`
+            core::ptr::write_volatile(core::ptr::addr_of_mut!((*ring_ptr).cq[slot]), completion);
+        }
+    }
+    ring.cq_tail
+        .store(cq_tail.wrapping_add(n as u64), Ordering::Release);
+    ring.sq_head
+        .store(sq_head.wrapping_add(n as u64), Ordering::Release);
+
+    Ok((n as u64, 0))
+}
@@ -3,6 +3,9 @@
 
 //! Test the file-sytem implementation using unit-tests and proptest.
 
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::cell::RefCell;
 use core::cmp::{Eq, PartialEq};
@@ -24,11 +27,15 @@ use crate::*;
 enum ModelOperation {
     /// Stores a write to an mnode, at given offset, pattern, length.
     Write(MnodeNum, usize, char, usize),
-    /// Stores info about created files.
-    Created(String, FileModes, MnodeNum),
+    /// Stores info about created files and directories (by their normalized
+    /// path, see `ModelFS::normalize`).
+    Created(String, FileModes, MnodeNum, FileType),
 }
 
 /// The FS model that we strive to implement.
+///
+/// Unlike the implementation, the model doesn't build a tree: it only
+/// remembers the full path of every file and directory.
 struct ModelFS {
     /// A log that stores all operations on the model FS.
     oplog: RefCell<Vec<ModelOperation>>,
@@ -43,6 +50,7 @@ impl Default for ModelFS {
             "/".to_string(),
             FileModes::from_bits_truncate(0),
             1,
+            FileType::Directory,
         ));
         ModelFS {
             oplog,
@@ -52,12 +60,58 @@ impl Default for ModelFS {
 }
 
 impl ModelFS {
-    /// Find mnode of a path.
-    fn path_to_mnode(&self, path: &String) -> Option<MnodeNum> {
+    /// Normalizes a path to the form `/a/b` (or `/` for the root).
+    fn normalize(path: &str) -> String {
+        let mut normalized = String::new();
+        for name in path_components(path) {
+            normalized.push('/');
+            normalized.push_str(name);
+        }
+        if normalized.is_empty() {
+            normalized.push('/');
+        }
+        normalized
+    }
+
+    /// Returns the parent of a normalized path (`None` for the root).
+    fn parent(path: &str) -> Option<&str> {
+        match path.rfind('/') {
+            _ if path == "/" => None,
+            Some(0) => Some("/"),
+            Some(idx) => Some(&path[..idx]),
+            None => None,
+        }
+    }
+
+    /// Returns the last component of a normalized path.
+    fn name(path: &str) -> &str {
+        &path[path.rfind('/').map_or(0, |idx| idx + 1)..]
+    }
+
+    /// Appends `name` to the normalized path `dir`.
+    fn join(dir: &str, name: &str) -> String {
+        if dir == "/" {
+            format!("/{}", name)
+        } else {
+            format!("{}/{}", dir, name)
+        }
+    }
+
+    /// Checks if the normalized `path` is somewhere below the directory `dir`.
+    fn is_below(path: &str, dir: &str) -> bool {
+        if dir == "/" {
+            path != "/"
+        } else {
+            path.starts_with(dir) && path[dir.len()..].starts_with('/')
+        }
+    }
+
+    /// Find mnode of a (normalized) path.
+    fn path_to_mnode(&self, path: &str) -> Option<MnodeNum> {
         for x in self.oplog.borrow().iter().rev() {
             match x {
-                ModelOperation::Created(name, _mode, mnode) => {
-                    if &name == &path {
+                ModelOperation::Created(name, _mode, mnode, _ftype) => {
+                    if name == path {
                         return Some(*mnode);
                     }
                 }
@@ -68,12 +122,12 @@ impl ModelFS {
         None
     }
 
-    /// Find index of a path in the oplog.
-    fn path_to_idx(&self, path: &String) -> Option<usize> {
+    /// Find index of a (normalized) path in the oplog.
+    fn path_to_idx(&self, path: &str) -> Option<usize> {
         for (idx, x) in self.oplog.borrow().iter().enumerate().rev() {
             match x {
-                ModelOperation::Created(name, _mode, _mnode) => {
-                    if &name == &path {
+                ModelOperation::Created(name, _mode, _mnode, _ftype) => {
+                    if name == path {
                         return Some(idx);
                     }
                 }
@@ -84,25 +138,85 @@ impl ModelFS {
         None
     }
 
-    /// Check if a given path exists.
-    fn file_exists(&self, path: &String) -> bool {
-        self.path_to_mnode(path).is_some()
-    }
-
-    /// Check if a mnode exists.
-    fn mnode_exists(&self, look_for: MnodeNum) -> bool {
+    /// Find the type of a mnode (`None` if it doesn't exist).
+    fn mnode_type(&self, look_for: MnodeNum) -> Option<FileType> {
         for x in self.oplog.borrow().iter().rev() {
             match x {
-                ModelOperation::Created(_name, _mode, mnode) => {
+                ModelOperation::Created(_name, _mode, mnode, ftype) => {
                     if look_for == *mnode {
-                        return true;
+                        return Some(*ftype);
                     }
                 }
                 _ => {}
             }
         }
 
-        false
+        None
+    }
+
+    /// Check if a mnode exists.
+    fn mnode_exists(&self, look_for: MnodeNum) -> bool {
+        self.mnode_type(look_for).is_some()
+    }
+
+    /// Check if anything exists directly below the (normalized) path.
+    fn has_children(&self, path: &str) -> bool {
+        self.oplog.borrow().iter().any(|x| match x {
+            ModelOperation::Created(name, _mode, _mnode, _ftype) => {
+                ModelFS::parent(name) == Some(path)
+            }
+            _ => false,
+        })
+    }
+
+    /// Resolves a path component by component, like the implementation.
+    fn resolve(&self, path: &str) -> Result<MnodeNum, KError> {
+        let mut cur = String::from("/");
+        let mut mnode = self.path_to_mnode(&cur).unwrap();
+        for name in path_components(path) {
+            if self.mnode_type(mnode) != Some(FileType::Directory) {
+                return Err(KError::NotADirectory);
+            }
+            cur = ModelFS::join(&cur, name);
+            mnode = self.path_to_mnode(&cur).ok_or(KError::InvalidFile)?;
+        }
+        Ok(mnode)
+    }
+
+    /// Checks that the parent of the (normalized) path is a directory.
+    ///
+    /// Fails with `on_root` if path is the root.
+    fn resolve_parent(&self, path: &str, on_root: KError) -> Result<(), KError> {
+        let parent = ModelFS::parent(path).ok_or(on_root)?;
+        let mnode = self.resolve(parent)?;
+        if self.mnode_type(mnode) != Some(FileType::Directory) {
+            return Err(KError::NotADirectory);
+        }
+        Ok(())
+    }
+
+    /// Create a file or directory: put it in the oplog and increase the mnode
+    /// counter.
+    fn create_mnode(
+        &self,
+        path: String,
+        mode: FileModes,
+        ftype: FileType,
+    ) -> Result<MnodeNum, KError> {
+        let path = ModelFS::normalize(&path);
+        self.resolve_parent(&path, KError::AlreadyPresent)?;
+        if self.path_to_mnode(&path).is_some() {
+            Err(KError::AlreadyPresent)
+        } else {
+            *self.mnode_counter.borrow_mut() += 1;
+            self.oplog.borrow_mut().push(ModelOperation::Created(
+                path,
+                mode,
+                *self.mnode_counter.borrow(),
+                ftype,
+            ));
+            Ok(*self.mnode_counter.borrow())
+        }
     }
 
     /// Checks if there is overlap between two ranges
@@ -135,29 +249,23 @@ impl ModelFS {
 impl FileSystem for ModelFS {
     // Create just puts the file in the oplop and increases mnode counter.
     fn create(&self, path: String, mode: FileModes) -> Result<u64, KError> {
-        if self.file_exists(&path) {
-            Err(KError::AlreadyPresent)
-        } else {
-            *self.mnode_counter.borrow_mut() += 1;
-            self.oplog.borrow_mut().push(ModelOperation::Created(
-                path,
-                mode,
-                *self.mnode_counter.borrow(),
-            ));
-            Ok(*self.mnode_counter.borrow())
-        }
+        self.create_mnode(path, mode, FileType::File)
     }
 
     /// Write just logs the write to the oplog.
     ///
     /// Our model assumes that the buffer repeats the first byte for its entire length.
     fn write(&self, mnode_num: MnodeNum, buffer: &[u8], offset: usize) -> Result<usize, KError> {
+        if self.mnode_type(mnode_num) == Some(FileType::Directory) {
+            return Err(KError::PermissionError);
+        }
+
         if self.mnode_exists(mnode_num) {
             for x in self.oplog.borrow().iter().rev() {
                 trace!("seen {:?}", x);
                 match x {
                     // Check if the file is writable or not
-                    ModelOperation::Created(_path, mode, mnode) => {
+                    ModelOperation::Created(_path, mode, mnode, _ftype) => {
                         if mnode_num == *mnode && !FileModes::from(*mode).is_writable() {
                             return Err(KError::PermissionError);
                         }
@@ -193,6 +301,10 @@ impl FileSystem for ModelFS {
         offset: usize,
     ) -> Result<usize, KError> {
         let _len = buffer.len();
+        if self.mnode_type(mnode_num) == Some(FileType::Directory) {
+            return Err(KError::PermissionError);
+        }
+
         if self.mnode_exists(mnode_num) {
             // We store our 'retrieved' data in a buffer of Option<u8>
             // to make sure in case we have consecutive writes to the same region
@@ -237,7 +349,7 @@ impl FileSystem for ModelFS {
                         // else: The write is not relevant
                     }
 
-                    ModelOperation::Created(_path, mode, mnode) => {
+                    ModelOperation::Created(_path, mode, mnode, _ftype) => {
                         if mnode_num == *mnode && !FileModes::from(*mode).is_readable() {
                             return Err(KError::PermissionError);
                         }
@@ -272,20 +384,23 @@ impl FileSystem for ModelFS {
     }
 
     /// Lookup just returns the mnode.
-    fn lookup(&self, pathname: &str) -> Option<Arc<MnodeNum>> {
-        self.path_to_mnode(&String::from(pathname)).map(Arc::from)
+    fn lookup(&self, pathname: &str) -> Option<MnodeNum> {
+        self.resolve(pathname).ok()
     }
 
     /// Delete finds and removes a path from the oplog again.
     fn delete(&self, pathname: &str) -> Result<(), KError> {
-        if let Some(idx) = self.path_to_idx(&String::from(pathname)) {
-            self.oplog.borrow_mut().remove(idx);
-            // We leave corresponding ModelOperation::Write entries
-            // in the log for now...
-            Ok(())
-        } else {
-            Err(KError::InvalidFile)
+        let path = ModelFS::normalize(pathname);
+        self.resolve_parent(&path, KError::PermissionError)?;
+        let idx = self.path_to_idx(&path).ok_or(KError::InvalidFile)?;
+        if self.has_children(&path) {
+            return Err(KError::DirectoryNotEmpty);
         }
+
+        self.oplog.borrow_mut().remove(idx);
+        // We leave corresponding ModelOperation::Write entries
+        // in the log for now...
+        Ok(())
     }
 
     /// Returns a `dummy` file-info.
@@ -298,14 +413,89 @@ impl FileSystem for ModelFS {
         Ok(())
     }
 
-    /// Return a `dummy` response for rename operation
-    fn rename(&self, _oldname: &str, _newname: String) -> Result<(), KError> {
+    /// Return a `dummy` response, resizing is tested on `File` directly.
+    fn resize(&self, _mnode_num: MnodeNum, _len: usize) -> Result<(), KError> {
         Ok(())
     }
 
-    fn mkdir(&self, _pathname: String, _mode: FileModes) -> Result<(), KError> {
+    /// Rename rewrites the paths of the renamed mnode and everything below
+    /// it.
+    fn rename(&self, oldname: &str, newname: String) -> Result<(), KError> {
+        let old = ModelFS::normalize(oldname);
+        let new = ModelFS::normalize(&newname);
+        self.resolve_parent(&old, KError::PermissionError)?;
+        let old_mnode = self.path_to_mnode(&old).ok_or(KError::InvalidFile)?;
+        self.resolve_parent(&new, KError::PermissionError)?;
+        let replaced = self.path_to_mnode(&new);
+
+        if replaced == Some(old_mnode) {
+            return Ok(());
+        }
+
+        let old_type = self.mnode_type(old_mnode).unwrap();
+        if old_type == FileType::Directory && ModelFS::is_below(&new, &old) {
+            return Err(KError::PermissionError);
+        }
+
+        if let Some(replaced) = replaced {
+            match (old_type, self.mnode_type(replaced).unwrap()) {
+                (FileType::File, FileType::Directory) => return Err(KError::DirectoryError),
+                (FileType::Directory, FileType::File) => return Err(KError::NotADirectory),
+                _ => {}
+            }
+            if self.has_children(&new) {
+                return Err(KError::DirectoryNotEmpty);
+            }
+            let idx = self.path_to_idx(&new).unwrap();
+            self.oplog.borrow_mut().remove(idx);
+        }
+
+        for x in self.oplog.borrow_mut().iter_mut() {
+            if let ModelOperation::Created(path, _mode, _mnode, _ftype) = x {
+                if *path == old {
+                    *path = new.clone();
+                } else if ModelFS::is_below(path, &old) {
+                    *path = format!("{}{}", new, &path[old.len()..]);
+                }
+            }
+        }
+
         Ok(())
     }
+
+    fn mkdir(&self, pathname: String, mode: FileModes) -> Result<(), KError> {
+        self.create_mnode(pathname, mode, FileType::Directory)
+            .map(|_mnode| ())
+    }
+
+    /// Lists everything directly below `pathname`.
+    fn readdir(&self, pathname: &str) -> Result<Vec<DirEntry>, KError> {
+        let mnode = self.resolve(pathname)?;
+        if self.mnode_type(mnode) != Some(FileType::Directory) {
+            return Err(KError::NotADirectory);
+        }
+
+        let dir = ModelFS::normalize(pathname);
+        let mut entries: Vec<DirEntry> = self
+            .oplog
+            .borrow()
+            .iter()
+            .filter_map(|x| match x {
+                ModelOperation::Created(path, _mode, mnode, ftype)
+                    if ModelFS::parent(path) == Some(dir.as_str()) =>
+                {
+                    Some(DirEntry {
+                        name: ModelFS::name(path).to_owned(),
+                        mnode: *mnode,
+                        ftype: *ftype,
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
 }
 
 /// Two writes/reads at different offsets should return
@@ -317,19 +507,19 @@ fn model_read() {
     let mnode = mfs.lookup("/bla").unwrap();
 
     let wdata1 = &[1, 1];
-    assert!(mfs.write(*mnode, wdata1, 0).is_ok());
+    assert!(mfs.write(mnode, wdata1, 0).is_ok());
 
     let wdata = &[2, 2];
-    let r = mfs.write(*mnode, wdata, 4);
+    let r = mfs.write(mnode, wdata, 4);
     assert_eq!(r, Ok(2));
 
     let mut rdata = &mut [0, 0];
 
-    let r = mfs.read(*mnode, rdata, 0);
+    let r = mfs.read(mnode, rdata, 0);
     assert_eq!(rdata, &[1, 1]);
     assert_eq!(r, Ok(2));
 
-    let r = mfs.read(*mnode, rdata, 4);
+    let r = mfs.read(mnode, rdata, 4);
     assert_eq!(rdata, &[2, 2]);
     assert_eq!(r, Ok(2));
 }
@@ -345,13 +535,13 @@ fn model_overlapping_writes() {
     let mnode = mfs.lookup("/bla").unwrap();
 
     let data = &[1, 1, 1];
-    assert!(mfs.write(*mnode, data, 0).is_ok());
+    assert!(mfs.write(mnode, data, 0).is_ok());
 
     let wdata = &[2, 2, 2];
-    assert!(mfs.write(*mnode, wdata, 2).is_ok());
+    assert!(mfs.write(mnode, wdata, 2).is_ok());
 
     let rdata = &mut [0, 0, 0, 0, 0, 0];
-    let r = mfs.read(*mnode, rdata, 0);
+    let r = mfs.read(mnode, rdata, 0);
     assert_eq!(r, Ok(5));
     assert_eq!(rdata, &[1, 1, 2, 2, 2, 0]);
 }
@@ -367,6 +557,9 @@ enum TestAction {
     Create(Vec<String>, FileModes),
     Delete(Vec<String>),
     Lookup(Vec<String>),
+    MkDir(Vec<String>, FileModes),
+    Rename(Vec<String>, Vec<String>),
+    ReadDir(Vec<String>),
 }
 
 /// Generates one `TestAction` entry randomly.
@@ -384,6 +577,9 @@ fn action() -> impl Strategy<Value = TestAction> {
         (path(), mode_gen(0xfff)).prop_map(|(a, b)| TestAction::Create(a, b)),
         path().prop_map(TestAction::Delete),
         path().prop_map(TestAction::Lookup),
+        (path(), mode_gen(0xfff)).prop_map(|(a, b)| TestAction::MkDir(a, b)),
+        (path(), path()).prop_map(|(a, b)| TestAction::Rename(a, b)),
+        path().prop_map(TestAction::ReadDir),
     ]
 }
 
@@ -441,10 +637,10 @@ fn path_names() -> impl Strategy<Value = String> {
     ]
 }
 
-/// Creates a path of depth a given depth (4), represented as a
+/// Creates a path with a depth of up to 3, represented as a
 /// vector of Strings.
 fn path() -> impl Strategy<Value = Vec<String>> {
-    proptest::collection::vec(path_names(), 4)
+    proptest::collection::vec(path_names(), 1..4)
 }
 
 proptest! {
@@ -498,6 +694,28 @@ proptest! {
                     let rtotest = totest.lookup(path_str.as_str());
                     assert_eq!(rmodel, rtotest);
                 }
+                MkDir(path, mode) => {
+                    let path_str = path.join("/");
+
+                    let rmodel = model.mkdir(path_str.clone(), mode);
+                    let rtotest = totest.mkdir(path_str, mode);
+                    assert_eq!(rmodel, rtotest);
+                }
+                Rename(oldpath, newpath) => {
+                    let oldpath_str = oldpath.join("/");
+                    let newpath_str = newpath.join("/");
+
+                    let rmodel = model.rename(oldpath_str.as_str(), newpath_str.clone());
+                    let rtotest = totest.rename(oldpath_str.as_str(), newpath_str);
+                    assert_eq!(rmodel, rtotest);
+                }
+                ReadDir(path) => {
+                    let path_str = path.join("/");
+
+                    let rmodel = model.readdir(path_str.as_str());
+                    let rtotest = totest.readdir(path_str.as_str());
+                    assert_eq!(rmodel, rtotest);
+                }
             }
         }
     }
@@ -521,9 +739,9 @@ fn test_file_descriptor() {
 fn test_memfs_init() {
     let memfs: MlnrFS = Default::default();
     let root = String::from("/");
-    assert_eq!(memfs._root, (root.to_owned(), 1));
+    assert_eq!(memfs.root, ROOT_MNODE);
     assert_eq!(memfs.nextmemnode.load(Ordering::Relaxed), 2);
-    assert_eq!(memfs.files.read().get(&root), Some(&Arc::new(1)));
+    assert_eq!(memfs.lookup(&root), Some(ROOT_MNODE));
     assert_eq!(
         *memfs.mnodes.read().get(&1).unwrap().read(),
         MemNode::new(1, "/", FileModes::S_IRWXU.into(), FileType::Directory).unwrap()
@@ -540,10 +758,7 @@ fn test_file_create() {
         .unwrap();
     assert_eq!(mnode, 2);
     assert_eq!(memfs.nextmemnode.load(Ordering::Relaxed), 3);
-    assert_eq!(
-        memfs.files.read().get(&String::from("file.txt")),
-        Some(&Arc::new(2))
-    );
+    assert_eq!(memfs.lookup("file.txt"), Some(2));
 }
 
 /// Create a file with non-read permission and try to read it.
@@ -558,10 +773,7 @@ fn test_file_read_permission_error() {
         .unwrap();
     assert_eq!(mnode, 2);
     assert_eq!(memfs.nextmemnode.load(Ordering::Relaxed), 3);
-    assert_eq!(
-        memfs.files.read().get(&String::from("file.txt")),
-        Some(&Arc::new(2))
-    );
+    assert_eq!(memfs.lookup("file.txt"), Some(2));
     // On error read returns 0.
     assert_eq!(memfs.read(2, buffer, 0).is_err(), true);
 }
@@ -577,10 +789,7 @@ fn test_file_write_permission_error() {
         .unwrap();
     assert_eq!(mnode, 2);
     assert_eq!(memfs.nextmemnode.load(Ordering::Relaxed), 3);
-    assert_eq!(
-        memfs.files.read().get(&String::from("file.txt")),
-        Some(&Arc::new(2))
-    );
+    assert_eq!(memfs.lookup("file.txt"), Some(2));
     // On error read returns 0.
     assert_eq!(memfs.write(2, buffer, 0), Err(KError::PermissionError));
 }
@@ -596,10 +805,7 @@ fn test_file_write() {
         .unwrap();
     assert_eq!(mnode, 2);
     assert_eq!(memfs.nextmemnode.load(Ordering::Relaxed), 3);
-    assert_eq!(
-        memfs.files.read().get(&String::from("file.txt")),
-        Some(&Arc::new(2))
-    );
+    assert_eq!(memfs.lookup("file.txt"), Some(2));
     assert_eq!(memfs.write(2, buffer, 0).unwrap(), 10);
 }
 
@@ -617,10 +823,7 @@ fn test_file_read() {
         .unwrap();
     assert_eq!(mnode, 2);
     assert_eq!(memfs.nextmemnode.load(Ordering::Relaxed), 3);
-    assert_eq!(
-        memfs.files.read().get(&String::from("file.txt")),
-        Some(&Arc::new(2))
-    );
+    assert_eq!(memfs.lookup("file.txt"), Some(2));
     assert_eq!(memfs.write(2, wbuffer, 0).unwrap(), len);
     assert_eq!(memfs.read(2, rbuffer, 0).unwrap(), len);
     assert_eq!(rbuffer[0], 0xb);
@@ -637,12 +840,9 @@ fn test_file_lookup() {
         .unwrap();
     assert_eq!(mnode, 2);
     assert_eq!(memfs.nextmemnode.load(Ordering::Relaxed), 3);
-    assert_eq!(
-        memfs.files.read().get(&String::from("file.txt")),
-        Some(&Arc::new(2))
-    );
+    assert_eq!(memfs.lookup("file.txt"), Some(2));
     let mnode = memfs.lookup(filename);
-    assert_eq!(mnode, Some(Arc::new(2)));
+    assert_eq!(mnode, Some(2));
 }
 
 /// Lookup for a fake file.
@@ -655,10 +855,7 @@ fn test_file_fake_lookup() {
         .unwrap();
     assert_eq!(mnode, 2);
     assert_eq!(memfs.nextmemnode.load(Ordering::Relaxed), 3);
-    assert_eq!(
-        memfs.files.read().get(&String::from("file.txt")),
-        Some(&Arc::new(2))
-    );
+    assert_eq!(memfs.lookup("file.txt"), Some(2));
     let mnode = memfs.lookup("filename");
     assert_eq!(mnode, None);
 }
@@ -673,10 +870,7 @@ fn test_file_duplicate_create() {
         .unwrap();
     assert_eq!(mnode, 2);
     assert_eq!(memfs.nextmemnode.load(Ordering::Relaxed), 3);
-    assert_eq!(
-        memfs.files.read().get(&String::from("file.txt")),
-        Some(&Arc::new(2))
-    );
+    assert_eq!(memfs.lookup("file.txt"), Some(2));
     assert_eq!(
         memfs.create(filename.into(), FileModes::S_IRWXU.into()),
         Err(KError::AlreadyPresent)
@@ -693,10 +887,7 @@ fn test_file_info() {
         .unwrap();
     assert_eq!(mnode, 2);
     assert_eq!(memfs.nextmemnode.load(Ordering::Relaxed), 3);
-    assert_eq!(
-        memfs.files.read().get(&String::from("file.txt")),
-        Some(&Arc::new(2))
-    );
+    assert_eq!(memfs.lookup("file.txt"), Some(2));
     assert_eq!(memfs.file_info(2), FileInfo { ftype: 2, fsize: 0 });
 }
 
@@ -728,7 +919,7 @@ fn test_file_rename() {
         .unwrap();
     assert!(memfs.rename(filename, newname.into()).is_ok());
     let mnode = memfs.lookup(newname).unwrap();
-    assert_eq!(oldmnode, *mnode);
+    assert_eq!(oldmnode, mnode);
 }
 
 #[test]
@@ -746,7 +937,7 @@ fn test_file_rename_and_read() {
     let rbuffer: &mut [u8; 10] = &mut [0x0; 10];
     assert!(memfs.rename(filename, newname.into()).is_ok());
     let mnode = memfs.lookup(newname).unwrap();
-    assert_eq!(memfs.read(*mnode, rbuffer, 0), Ok(10));
+    assert_eq!(memfs.read(mnode, rbuffer, 0), Ok(10));
     assert_eq!(rbuffer[0], 0xb);
     assert_eq!(rbuffer[9], 0xb);
 }
@@ -761,13 +952,13 @@ fn test_file_rename_and_write() {
         .unwrap();
     assert!(memfs.rename(filename, newname.into()).is_ok());
     let mnode = memfs.lookup(newname).unwrap();
-    assert_eq!(oldmnode, *mnode);
+    assert_eq!(oldmnode, mnode);
 
-    let finfo = memfs.file_info(*mnode);
+    let finfo = memfs.file_info(mnode);
     assert_eq!(finfo.fsize, 0);
     let buffer: &mut [u8; 10] = &mut [0xb; 10];
-    assert_eq!(memfs.write(*mnode, buffer, 0), Ok(10));
-    let finfo = memfs.file_info(*mnode);
+    assert_eq!(memfs.write(mnode, buffer, 0), Ok(10));
+    let finfo = memfs.file_info(mnode);
     assert_eq!(finfo.fsize, 10);
 }
 
@@ -799,5 +990,261 @@ fn test_file_rename_to_existent_file() {
     // Old file is removed.
     assert_eq!(memfs.lookup(oldname), None);
     // New file points to old mnode.
-    assert_eq!(*memfs.lookup(newname).unwrap(), oldmnode);
+    assert_eq!(memfs.lookup(newname).unwrap(), oldmnode);
+}
+
+#[test]
+fn test_readdir() {
+    let memfs: MlnrFS = Default::default();
+    assert_eq!(memfs.readdir("/"), Ok(Vec::new()));
+
+    let a = memfs
+        .create("/a".into(), FileModes::S_IRWXU.into())
+        .unwrap();
+    assert!(memfs
+        .mkdir("/dir".into(), FileModes::S_IRWXU.into())
+        .is_ok());
+    let b = memfs
+        .create("/dir/b".into(), FileModes::S_IRWXU.into())
+        .unwrap();
+    let dir = memfs.lookup("/dir").unwrap();
+
+    assert_eq!(
+        memfs.readdir("/"),
+        Ok(vec![
+            DirEntry {
+                name: "a".to_owned(),
+                mnode: a,
+                ftype: FileType::File
+            },
+            DirEntry {
+                name: "dir".to_owned(),
+                mnode: dir,
+                ftype: FileType::Directory
+            },
+        ])
+    );
+    assert_eq!(
+        memfs.readdir("/dir/"),
+        Ok(vec![DirEntry {
+            name: "b".to_owned(),
+            mnode: b,
+            ftype: FileType::File
+        }])
+    );
+
+    assert_eq!(memfs.readdir("/a"), Err(KError::NotADirectory));
+    assert_eq!(memfs.readdir("/nonexistent"), Err(KError::InvalidFile));
+}
+
+#[test]
+fn test_create_checks_parent() {
+    let memfs: MlnrFS = Default::default();
+    let modes = FileModes::S_IRWXU.into();
+    assert_eq!(
+        memfs.create("/dir/file".into(), modes),
+        Err(KError::InvalidFile)
+    );
+    assert!(memfs.create("/file".into(), modes).is_ok());
+    assert_eq!(
+        memfs.create("/file/file".into(), modes),
+        Err(KError::NotADirectory)
+    );
+    assert_eq!(
+        memfs.mkdir("/file/dir".into(), modes),
+        Err(KError::NotADirectory)
+    );
+    assert_eq!(memfs.lookup("/file/file"), None);
+    assert_eq!(memfs.create("/".into(), modes), Err(KError::AlreadyPresent));
+}
+
+#[test]
+fn test_delete_directory() {
+    let memfs: MlnrFS = Default::default();
+    let modes = FileModes::S_IRWXU.into();
+    assert!(memfs.mkdir("/dir".into(), modes).is_ok());
+    assert!(memfs.create("/dir/file".into(), modes).is_ok());
+
+    assert_eq!(memfs.delete("/dir"), Err(KError::DirectoryNotEmpty));
+    assert_eq!(memfs.delete("/dir/file"), Ok(()));
+    assert_eq!(memfs.delete("/dir"), Ok(()));
+    assert_eq!(memfs.lookup("/dir"), None);
+    assert_eq!(memfs.delete("/"), Err(KError::PermissionError));
+}
+
+#[test]
+fn test_rename_directory() {
+    let memfs: MlnrFS = Default::default();
+    let modes = FileModes::S_IRWXU.into();
+    assert!(memfs.mkdir("/a".into(), modes).is_ok());
+    assert!(memfs.mkdir("/a/b".into(), modes).is_ok());
+    let file = memfs.create("/a/b/file".into(), modes).unwrap();
+
+    // Can't move a directory below itself
+    assert_eq!(
+        memfs.rename("/a", "/a/b/c".into()),
+        Err(KError::PermissionError)
+    );
+
+    // Moving a directory moves everything below it
+    assert!(memfs.mkdir("/x".into(), modes).is_ok());
+    assert_eq!(memfs.rename("/a/b", "/x/y".into()), Ok(()));
+    assert_eq!(memfs.lookup("/a/b/file"), None);
+    assert_eq!(memfs.lookup("/x/y/file"), Some(file));
+    assert_eq!(memfs.readdir("/a"), Ok(Vec::new()));
+
+    // A directory can only replace an empty directory
+    assert_eq!(
+        memfs.rename("/a", "/x".into()),
+        Err(KError::DirectoryNotEmpty)
+    );
+    assert_eq!(
+        memfs.rename("/x/y/file", "/a".into()),
+        Err(KError::DirectoryError)
+    );
+    assert_eq!(memfs.rename("/x", "/a".into()), Ok(()));
+    assert_eq!(memfs.lookup("/a/y/file"), Some(file));
+}
+
+#[test]
+fn test_map_file() {
+    let memfs: MlnrFS = Default::default();
+    let file = memfs
+        .create("/file".into(), FileModes::S_IRWXU.into())
+        .unwrap();
+    assert_eq!(memfs.write(file, &[0xa; 100], 0), Ok(100));
+    assert_eq!(
+        memfs.map(ROOT_MNODE, 0, crate::memory::BASE_PAGE_SIZE, false),
+        Err(KError::DirectoryError)
+    );
+    let frames = memfs
+        .map(file, 0, crate::memory::BASE_PAGE_SIZE, true)
+        .unwrap();
+    assert_eq!(frames.len(), 1);
+
+    // Write back doesn't grow the file
+    assert_eq!(memfs.write_back(file, &[0xb; 200], 50), Ok(50));
+    assert_eq!(memfs.file_info(file).fsize, 100);
+    let rbuffer: &mut [u8; 2] = &mut [0; 2];
+    assert_eq!(memfs.read(file, rbuffer, 49), Ok(2));
+    assert_eq!(rbuffer, &[0xa, 0xb]);
+
+    // A mapped file stays around until it's unmapped
+    assert_eq!(memfs.delete("/file"), Ok(()));
+    assert_eq!(memfs.lookup("/file"), None);
+    assert_eq!(memfs.read(file, rbuffer, 0), Ok(2));
+    memfs.unmap(file, 1);
+    assert_eq!(memfs.read(file, rbuffer, 0), Err(KError::InvalidFile));
+}
+
+/// Writes to a mapping only reach the other replicas with a write back.
+#[test]
+fn test_map_file_write_back() {
+    let local: MlnrFS = Default::default();
+    let remote: MlnrFS = Default::default();
+    let mut file = 0;
+    for replica in [&local, &remote] {
+        file = replica
+            .create("/file".into(), FileModes::S_IRWXU.into())
+            .unwrap();
+        assert_eq!(replica.write(file, &[0xa; 100], 0), Ok(100));
+        replica
+            .map(file, 0, crate::memory::BASE_PAGE_SIZE, true)
+            .unwrap();
+    }
+
+    // The process writes to the frames of the local replica
+    let frames = local
+        .map(file, 0, crate::memory::BASE_PAGE_SIZE, true)
+        .unwrap();
+    let page = unsafe {
+        core::slice::from_raw_parts_mut(frames[0].kernel_vaddr().as_mut_ptr::<u8>(), 100)
+    };
+    page[0] = 0xb;
+
+    let rbuffer: &mut [u8; 1] = &mut [0; 1];
+    assert_eq!(local.read(file, rbuffer, 0), Ok(1));
+    assert_eq!(rbuffer, &[0xb]);
+    assert_eq!(remote.read(file, rbuffer, 0), Ok(1));
+    assert_eq!(rbuffer, &[0xa]);
+
+    // What `sync_file` (or the exit of the process) does: every replica
+    // copies the pages (they are copied out of the mapping first)
+    let data = page.to_vec();
+    assert_eq!(local.write_back(file, &data, 0), Ok(100));
+    assert_eq!(remote.write_back(file, &data, 0), Ok(100));
+    assert_eq!(remote.read(file, rbuffer, 0), Ok(1));
+    assert_eq!(rbuffer, &[0xb]);
+}
+
+/// Mounts a persistent store in `mem` (which stands in for a PMem region).
+fn pmem_store(mem: &'static mut [u64]) -> &'static pmem::PmemStore {
+    let store = unsafe { pmem::PmemStore::mount(mem.as_mut_ptr() as *mut u8, mem.len() * 8) };
+    Box::leak(Box::new(store))
+}
+
+#[test]
+fn test_pmem_remount() {
+    let mem: &'static mut [u64] = vec![0; 64 * 1024].leak();
+    let base = mem.as_mut_ptr();
+    let len = mem.len();
+    let memfs = MlnrFS::new(Some(pmem_store(mem)));
+    let modes = FileModes::S_IRWXU.into();
+
+    assert!(memfs.mkdir("/dir".into(), modes).is_ok());
+    let file = memfs.create("/dir/file".into(), modes).unwrap();
+    assert_eq!(memfs.write(file, &[0xa; 100], 0), Ok(100));
+    assert_eq!(memfs.write(file, &[0xb; 100], 5000), Ok(100));
+    assert!(memfs.create("/dir/gone".into(), modes).is_ok());
+    assert_eq!(memfs.delete("/dir/gone"), Ok(()));
+    assert_eq!(memfs.rename("/dir/file", "/moved".into()), Ok(()));
+    let small = memfs.create("/small".into(), modes).unwrap();
+    assert_eq!(memfs.write(small, &[0xc; 100], 0), Ok(100));
+    assert_eq!(memfs.resize(small, 10), Ok(()));
+    // Failed operations aren't recorded
+    assert!(memfs.mkdir("/dir".into(), modes).is_err());
+
+    let mem = unsafe { core::slice::from_raw_parts_mut(base, len) };
+    let remounted = MlnrFS::new(Some(pmem_store(mem)));
+    assert_eq!(remounted.lookup("/dir/gone"), None);
+    assert_eq!(remounted.lookup("/dir/file"), None);
+    assert_eq!(remounted.lookup("/moved"), Some(file));
+    assert_eq!(remounted.lookup("/small"), Some(small));
+    assert_eq!(remounted.file_info(file).fsize, 5100);
+    assert_eq!(remounted.file_info(small).fsize, 10);
+    assert!(remounted.nextmemnode.load(Ordering::Relaxed) > small as usize);
+
+    let rbuffer: &mut [u8; 2] = &mut [0; 2];
+    assert_eq!(remounted.read(file, rbuffer, 99), Ok(2));
+    assert_eq!(rbuffer, &[0xa, 0x0]);
+    assert_eq!(remounted.read(file, rbuffer, 5098), Ok(2));
+    assert_eq!(rbuffer, &[0xb, 0xb]);
+}
+
+#[test]
+fn test_pmem_checkpoint() {
+    let mem: &'static mut [u64] = vec![0; 8 * 1024].leak();
+    let base = mem.as_mut_ptr();
+    let len = mem.len();
+    let memfs = MlnrFS::new(Some(pmem_store(mem)));
+    let modes = FileModes::S_IRWXU.into();
+
+    // Overwriting the same data over and over fills the log, but the state
+    // always fits in a checkpoint
+    assert!(memfs.mkdir("/dir".into(), modes).is_ok());
+    let file = memfs.create("/dir/file".into(), modes).unwrap();
+    let wbuffer = [0xd; 1000];
+    for i in 0..200 {
+        assert_eq!(memfs.write(file, &wbuffer, i % 10), Ok(1000));
+    }
+
+    let mem = unsafe { core::slice::from_raw_parts_mut(base, len) };
+    let remounted = MlnrFS::new(Some(pmem_store(mem)));
+    assert_eq!(remounted.lookup("/dir/file"), Some(file));
+    assert_eq!(remounted.file_info(file).fsize, 1009);
+
+    // Data that doesn't fit is rejected (and not applied)
+    let big = vec![0xe; 32 * 1024];
+    assert_eq!(remounted.write(file, &big, 0), Err(KError::FileSystemFull));
+    assert_eq!(remounted.file_info(file).fsize, 1009);
 }
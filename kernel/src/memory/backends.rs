@@ -11,7 +11,7 @@ use super::frame::Frame;
 /// Composite of trait that needs to be implemented by anything that wants to
 /// manage memory.
 pub(crate) trait MemManager:
-    PhysicalPageProvider + AllocatorStatistics + GrowBackend
+    PhysicalPageProvider + AllocatorStatistics + GrowBackend + ReapBackend
 {
 }
 
@@ -10,6 +10,7 @@
 #![allow(warnings)] // For now...
 
 use alloc::alloc::{alloc, dealloc};
+use alloc::boxed::Box;
 use alloc::sync::Arc;
 use core::alloc::Layout;
 use core::alloc::{AllocError, Allocator};
@@ -137,6 +138,38 @@ impl DeterministicAlloc {
         }
     }
 
+    /// Allocates `l` once on every node, e.g., for the initial state of the
+    /// replicas (which is created outside of the log, so it doesn't go
+    /// through the queues).
+    ///
+    /// Either all allocations succeed or none.
+    pub(crate) fn alloc_per_node(
+        &self,
+        l: Layout,
+    ) -> Result<ArrayVec<NonNull<u8>, MAX_NUMA_NODES>, AllocError> {
+        let pcm = per_core_mem();
+        let nid = *crate::environment::NODE_ID;
+
+        let mut allocs = ArrayVec::<NonNull<u8>, MAX_NUMA_NODES>::new();
+        for i in 0..self.qs.len() {
+            pcm.set_mem_affinity(i);
+            match NonNull::new(unsafe { alloc(l) }) {
+                Some(ptr) => allocs.push(ptr),
+                None => break,
+            }
+        }
+        pcm.set_mem_affinity(nid);
+
+        if allocs.len() == self.qs.len() {
+            Ok(allocs)
+        } else {
+            for ptr in allocs {
+                DeterministicAlloc::dealloc(ptr.as_ptr(), l);
+            }
+            Err(AllocError)
+        }
+    }
+
     pub(crate) fn dealloc(ptr: *mut u8, l: Layout) {
         // dealloc just goes to the underlying allocator
         unsafe { dealloc(ptr, l) }
@@ -150,6 +183,39 @@ impl DA {
     pub(crate) fn new() -> Result<Self, KError> {
         Ok(DA(Arc::try_new(DeterministicAlloc::new()?)?))
     }
+
+    /// Moves the value `init` returns for every node into memory of that
+    /// node (see [`DeterministicAlloc::alloc_per_node`]).
+    pub(crate) fn try_box_per_node<T, F>(
+        &self,
+        mut init: F,
+    ) -> Result<ArrayVec<Box<T, DA>, MAX_NUMA_NODES>, KError>
+    where
+        F: FnMut(atopology::NodeId) -> Result<T, KError>,
+    {
+        let layout = Layout::new::<T>();
+        let mut memory = self.0.alloc_per_node(layout)?.into_iter();
+
+        let mut boxes = ArrayVec::new();
+        for (node, ptr) in memory.by_ref().enumerate() {
+            match init(node) {
+                Ok(value) => unsafe {
+                    let ptr = ptr.as_ptr() as *mut T;
+                    ptr.write(value);
+                    boxes.push(Box::from_raw_in(ptr, self.clone()));
+                },
+                Err(e) => {
+                    DeterministicAlloc::dealloc(ptr.as_ptr(), layout);
+                    for ptr in memory {
+                        DeterministicAlloc::dealloc(ptr.as_ptr(), layout);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(boxes)
+    }
 }
 
 unsafe impl Allocator for DA {
@@ -176,6 +176,16 @@ impl Frame {
         self.fill(0);
     }
 
+    /// Copy the content of `src` into the frame.
+    pub unsafe fn copy_from(&mut self, src: &Frame) {
+        debug_assert!(src.size <= self.size);
+        core::ptr::copy_nonoverlapping(
+            src.kernel_vaddr().as_ptr::<u8>(),
+            self.kernel_vaddr().as_mut_ptr::<u8>(),
+            src.size,
+        );
+    }
+
     /// The kernel virtual address for this region.
     pub(crate) fn kernel_vaddr(&self) -> VAddr {
         paddr_to_kernel_vaddr(self.base)
@@ -5,6 +5,7 @@
 //! beginning.
 
 use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicUsize};
 
 use arrayvec::ArrayVec;
 use log::trace;
@@ -38,6 +39,18 @@ pub(crate) struct GlobalMemory {
     /// All node-caches in the system (one for every NUMA node).
     pub(crate) node_caches:
         ArrayVec<CachePadded<Mutex<&'static mut mcache::FrameCacheLarge>>, MAX_NUMA_NODES>,
+
+    /// Counts the reclamation requests for every NUMA node.
+    ///
+    /// Cores give back the memory they cache for a node once they see the
+    /// counter changed (see `reclaim.rs`).
+    pub(crate) reclaim_epochs: ArrayVec<AtomicUsize, MAX_NUMA_NODES>,
+
+    /// When (in ns) the last reclamation for every NUMA node was requested.
+    pub(crate) reclaim_requested: ArrayVec<AtomicU64, MAX_NUMA_NODES>,
+
+    /// How much memory (in bytes) every node-cache started out with.
+    pub(crate) node_sizes: ArrayVec<usize, MAX_NUMA_NODES>,
 }
 
 impl GlobalMemory {
@@ -126,6 +139,8 @@ impl GlobalMemory {
             );
 
             gm.node_caches.push(CachePadded::new(Mutex::new(ncache)));
+            gm.reclaim_epochs.push(AtomicUsize::new(0));
+            gm.reclaim_requested.push(AtomicU64::new(0));
         }
 
         // Populate the NCaches with all remaining memory
@@ -144,6 +159,7 @@ impl GlobalMemory {
                     ncache_locked.populate_2m_first(*frame);
                 }
             }
+            gm.node_sizes.push(ncache_locked.free());
         }
 
         Ok(gm)
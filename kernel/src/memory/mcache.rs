@@ -181,6 +181,22 @@ impl<const BP: usize, const LP: usize> MCache<BP, LP> {
         }
     }
 
+    /// Splits one of the cached large-pages into base-pages.
+    pub(crate) fn split_large_page(&mut self) -> Result<(), KError> {
+        const BASE_PAGES_PER_LARGE_PAGE: usize = LARGE_PAGE_SIZE / BASE_PAGE_SIZE;
+        if self.spare_base_page_capacity() < BASE_PAGES_PER_LARGE_PAGE {
+            return Err(KError::CacheFull);
+        }
+
+        let large_page = self.allocate_large_page()?;
+        for base_page in large_page.into_iter() {
+            self.base_page_addresses
+                .try_push(base_page.base)
+                .expect("We checked the capacity above");
+        }
+        Ok(())
+    }
+
     fn paddr_to_base_page(&self, pa: PAddr) -> Frame {
         Frame::new(pa, BASE_PAGE_SIZE, self.node)
     }
@@ -663,4 +679,25 @@ mod test {
             .allocate_base_page()
             .expect_err("Can't allocate more than we gave it");
     }
+
+    /// Large-pages can be split into base-pages if there is enough space.
+    #[test]
+    fn split_large_page() {
+        let mut tcache = FrameCacheSmall::new(0);
+        tcache
+            .release_large_page(Frame::new(PAddr::from(LARGE_PAGE_SIZE), LARGE_PAGE_SIZE, 0))
+            .expect("release");
+        assert_eq!(tcache.split_large_page(), Err(KError::CacheFull));
+        assert_eq!(tcache.free_large_pages(), 1);
+
+        let ncache = get_an_ncache::<1024, 4>();
+        assert_eq!(ncache.split_large_page(), Err(KError::CacheExhausted));
+        ncache
+            .release_large_page(Frame::new(PAddr::from(LARGE_PAGE_SIZE), LARGE_PAGE_SIZE, 0))
+            .expect("release");
+        ncache.split_large_page().expect("split");
+        assert_eq!(ncache.free_large_pages(), 0);
+        assert_eq!(ncache.free_base_pages(), LARGE_PAGE_SIZE / BASE_PAGE_SIZE);
+        assert_eq!(ncache.free(), LARGE_PAGE_SIZE);
+    }
 }
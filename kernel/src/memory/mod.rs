@@ -17,7 +17,7 @@ use log::{debug, error, trace, warn};
 use slabmalloc::{Allocator, ZoneAllocator};
 
 use crate::arch::kcb::try_per_core_mem;
-use backends::PhysicalPageProvider;
+use backends::{MemManager, PhysicalPageProvider};
 
 pub(crate) use frame::Frame;
 pub(crate) use kpi::MemType;
@@ -36,6 +36,8 @@ pub mod frame;
 pub mod global;
 pub mod mcache;
 pub mod per_core;
+pub mod reclaim;
+pub mod stats;
 pub mod utils;
 pub mod vspace;
 #[cfg(test)]
@@ -271,28 +273,157 @@ impl KernelAllocator {
                 (pcm.pgmanager.unwrap(), pcm.pmem_manager(), affinity)
             }
         };
-        let mut ncache = gmanager.node_caches[affinity].lock();
         // Make sure we don't overflow the FrameCacheSmall
-        let needed_base_pages =
-            core::cmp::min(mem_manager.spare_base_page_capacity(), needed_base_pages);
-        let needed_large_pages =
-            core::cmp::min(mem_manager.spare_large_page_capacity(), needed_large_pages);
-
-        for _i in 0..needed_base_pages {
-            let frame = ncache.allocate_base_page()?;
-            mem_manager
-                .grow_base_pages(&[frame])
-                .expect("We ensure to not overfill the FrameCacheSmall above.");
+        let want_base_pages = mem_manager.free_base_pages()
+            + core::cmp::min(mem_manager.spare_base_page_capacity(), needed_base_pages);
+        let want_large_pages = mem_manager.free_large_pages()
+            + core::cmp::min(mem_manager.spare_large_page_capacity(), needed_large_pages);
+
+        let refill = |mem_manager: &mut dyn MemManager,
+                      ncache: &mut mcache::FrameCacheLarge|
+         -> Result<(), KError> {
+            while mem_manager.free_base_pages() < want_base_pages {
+                let frame = match ncache.allocate_base_page() {
+                    // Break up a large-page before we give up
+                    Err(KError::CacheExhausted) => {
+                        ncache.split_large_page()?;
+                        ncache.allocate_base_page()?
+                    }
+                    r => r?,
+                };
+                mem_manager
+                    .grow_base_pages(&[frame])
+                    .expect("We ensure to not overfill the FrameCacheSmall above.");
+            }
+
+            while mem_manager.free_large_pages() < want_large_pages {
+                let frame = ncache.allocate_large_page()?;
+                mem_manager
+                    .grow_large_pages(&[frame])
+                    .expect("We ensure to not overfill the FrameCacheSmall above.");
+            }
+
+            Ok(())
+        };
+
+        let mut ncache = gmanager.node_caches[affinity].lock();
+        let mut r = refill(&mut *mem_manager, &mut **ncache);
+        if r == Err(KError::CacheExhausted) {
+            // Take back what this core caches for the node elsewhere before
+            // we give up
+            drop(ncache);
+            let reclaimed = reclaim::trim_local(gmanager, affinity, mem_type);
+            ncache = gmanager.node_caches[affinity].lock();
+            if reclaimed > 0 {
+                r = refill(&mut *mem_manager, &mut **ncache);
+            }
         }
 
-        for _i in 0..needed_large_pages {
-            let frame = ncache.allocate_large_page()?;
-            mem_manager
-                .grow_large_pages(&[frame])
-                .expect("We ensure to not overfill the FrameCacheSmall above.");
+        // Ask other cores for the memory they don't need before we run out
+        if reclaim::NCACHE_LOW.is_undercut_by(&**ncache) {
+            reclaim::request(gmanager, affinity);
         }
 
-        Ok(())
+        r
+    }
+
+    /// Give a frame back to the core-local tcache of type `mem_type`.
+    ///
+    /// If the tcache is full (or the frame is from another node), the frame
+    /// is returned to the NCache of the node the frame belongs to.
+    pub(crate) fn release_frame(frame: Frame, mem_type: MemType) -> Result<(), KError> {
+        let pcm = try_per_core_mem().ok_or(KError::KcbUnavailable)?;
+        let gmanager = match mem_type {
+            MemType::Mem => pcm.gmanager,
+            MemType::PMem => pcm.pgmanager,
+        };
+        let affinity = match mem_type {
+            MemType::Mem => pcm.physical_memory.borrow().affinity,
+            MemType::PMem => pcm.persistent_memory.borrow().affinity,
+        };
+        let mut pmanager = match mem_type {
+            MemType::Mem => pcm.try_mem_manager()?,
+            MemType::PMem => pcm.pmem_manager(),
+        };
+
+        let r = if frame.affinity != affinity {
+            Err(KError::CacheFull)
+        } else if frame.size == BASE_PAGE_SIZE {
+            pmanager.release_base_page(frame)
+        } else {
+            assert_eq!(frame.size, LARGE_PAGE_SIZE);
+            pmanager.release_large_page(frame)
+        };
+
+        match r {
+            Ok(()) => {
+                if let Some(gmanager) = gmanager {
+                    reclaim::maybe_trim(&mut *pmanager, gmanager, frame.affinity);
+                }
+                Ok(())
+            }
+            Err(KError::CacheFull) => {
+                let gmanager = gmanager.ok_or(KError::CacheFull)?;
+                let mut ncache = gmanager.node_caches[frame.affinity].lock();
+                if frame.size == BASE_PAGE_SIZE {
+                    ncache.release_base_page(frame)
+                } else {
+                    ncache.release_large_page(frame)
+                }
+            }
+            r => r,
+        }
+    }
+
+    /// Allocates a frame of `size` (a base or large page) from the NCache of
+    /// `node`, bypassing the core-local tcache.
+    pub(crate) fn allocate_frame_on(
+        node: atopology::NodeId,
+        size: usize,
+        mem_type: MemType,
+    ) -> Result<Frame, KError> {
+        let pcm = try_per_core_mem().ok_or(KError::KcbUnavailable)?;
+        let gmanager = match mem_type {
+            MemType::Mem => pcm.gmanager,
+            MemType::PMem => pcm.pgmanager,
+        }
+        .ok_or(KError::GlobalMemoryNotSet)?;
+        let node_cache = gmanager
+            .node_caches
+            .get(node)
+            .ok_or(KError::InvalidAffinityId)?;
+
+        let allocate = |ncache: &mut mcache::FrameCacheLarge| {
+            if size == BASE_PAGE_SIZE {
+                match ncache.allocate_base_page() {
+                    Err(KError::CacheExhausted) => ncache
+                        .split_large_page()
+                        .and_then(|_| ncache.allocate_base_page()),
+                    r => r,
+                }
+            } else {
+                assert_eq!(size, LARGE_PAGE_SIZE);
+                ncache.allocate_large_page()
+            }
+        };
+
+        let mut ncache = node_cache.lock();
+        let mut r = allocate(&mut **ncache);
+        if let Err(KError::CacheExhausted) = r {
+            // Take back what this core caches for the node before we give up
+            drop(ncache);
+            let reclaimed = reclaim::trim_local(gmanager, node, mem_type);
+            ncache = node_cache.lock();
+            if reclaimed > 0 {
+                r = allocate(&mut **ncache);
+            }
+        }
+
+        if reclaim::NCACHE_LOW.is_undercut_by(&**ncache) {
+            reclaim::request(gmanager, node);
+        }
+
+        r
     }
 
     /// Refill FrameCacheSmall only if the layout will exhaust the cache's current
@@ -360,6 +491,7 @@ impl KernelAllocator {
             let mut cas = pcm.try_allocator_state()?;
             if needs_a_base_page {
                 let frame = cas.pmanager.allocate_base_page()?;
+                cas.zone_size += frame.size;
                 unsafe {
                     let base_page_ptr: *mut slabmalloc::ObjectPage =
                         frame.uninitialized::<slabmalloc::ObjectPage>().as_mut_ptr();
@@ -370,6 +502,7 @@ impl KernelAllocator {
             } else {
                 // Needs a large page
                 let frame = cas.pmanager.allocate_large_page()?;
+                cas.zone_size += frame.size;
                 unsafe {
                     let large_page_ptr: *mut slabmalloc::LargeObjectPage = frame
                         .uninitialized::<slabmalloc::LargeObjectPage>()
@@ -484,7 +617,11 @@ unsafe impl GlobalAlloc for KernelAllocator {
                         );
 
                         match fmanager.release_base_page(frame) {
-                            Ok(_) => { /* Frame addition to tcache as successful.*/ }
+                            Ok(_) => {
+                                if let Some(gmanager) = pcm.gmanager {
+                                    reclaim::maybe_trim(&mut *fmanager, gmanager, node);
+                                }
+                            }
                             Err(_e) => match pcm.gmanager {
                                 // Try adding frame to ncache.
                                 Some(gmanager) => {
@@ -511,6 +648,9 @@ unsafe impl GlobalAlloc for KernelAllocator {
                         fmanager
                             .release_large_page(frame)
                             .expect("Can't deallocate frame");
+                        if let Some(gmanager) = pcm.gmanager {
+                            reclaim::maybe_trim(&mut *fmanager, gmanager, node);
+                        }
                     } else {
                         error!("Loosing large memory region. Oh well.")
                     }
@@ -28,6 +28,13 @@ pub(crate) struct PerCoreAllocatorState {
 
     /// A handle to the per-core ZoneAllocator.
     pub zone_allocator: ZoneAllocator<'static>,
+
+    /// The last reclamation request for `affinity` we answered.
+    pub reclaim_epoch: usize,
+
+    /// Memory (in bytes) we gave to `zone_allocator` (it never gives it
+    /// back).
+    pub zone_size: usize,
 }
 
 impl PerCoreAllocatorState {
@@ -37,6 +44,8 @@ impl PerCoreAllocatorState {
             affinity: node,
             pmanager: FrameCacheSmall::new(node),
             zone_allocator: ZoneAllocator::new(),
+            reclaim_epoch: 0,
+            zone_size: 0,
         }
     }
 }
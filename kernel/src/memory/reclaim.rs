@@ -0,0 +1,345 @@
+// Copyright © 2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Policy to move free frames from the per-core caches back to the node
+//! caches in [`GlobalMemory`].
+//!
+//! It works in three ways:
+//!
+//! - A core that releases frames into its cache trims the cache once it holds
+//!   more than [`TCACHE_HIGH`] frames (down to [`TCACHE_TARGET`]).
+//! - A core that finds its node cache below [`NCACHE_LOW`] (or empty) requests
+//!   a reclamation for the node (at most once every [`REQUEST_INTERVAL_NS`]).
+//!   Every core answers the request the next time it calls [`poll`] (e.g., on a
+//!   timer interrupt) by trimming its caches for that node down to
+//!   [`TCACHE_LOW`].
+//! - A core that can't allocate from the node cache trims its own caches for
+//!   the node right away with [`trim_local`] and tries again.
+
+use core::sync::atomic::Ordering;
+
+use log::debug;
+
+use crate::arch::kcb::try_per_core_mem;
+
+use super::backends::{AllocatorStatistics, GrowBackend, ReapBackend};
+use super::global::GlobalMemory;
+use super::per_core::PerCoreAllocatorState;
+use super::utils::DataSize;
+use super::{MemType, BASE_PAGE_SIZE, LARGE_PAGE_SIZE};
+
+/// A number of cached base and large pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Watermark {
+    pub base_pages: usize,
+    pub large_pages: usize,
+}
+
+impl Watermark {
+    /// Does `cache` hold more pages than the watermark (of either size)?
+    pub(crate) fn is_exceeded_by<C: AllocatorStatistics + ?Sized>(&self, cache: &C) -> bool {
+        cache.free_base_pages() > self.base_pages || cache.free_large_pages() > self.large_pages
+    }
+
+    /// Does `cache` hold less memory (in bytes) than the watermark?
+    ///
+    /// We compare the total because large pages get split for base page
+    /// allocations: A cache without base pages isn't low on memory.
+    pub(crate) fn is_undercut_by<C: AllocatorStatistics + ?Sized>(&self, cache: &C) -> bool {
+        cache.free_base_pages() * BASE_PAGE_SIZE + cache.free_large_pages() * LARGE_PAGE_SIZE
+            < self.bytes()
+    }
+
+    /// How much memory (in bytes) the watermark stands for.
+    pub(crate) const fn bytes(&self) -> usize {
+        self.base_pages * BASE_PAGE_SIZE + self.large_pages * LARGE_PAGE_SIZE
+    }
+}
+
+/// A per-core cache with more pages than this gets trimmed.
+pub(crate) const TCACHE_HIGH: Watermark = Watermark {
+    base_pages: 256,
+    large_pages: 16,
+};
+
+/// What a per-core cache is trimmed to after it exceeded [`TCACHE_HIGH`].
+pub(crate) const TCACHE_TARGET: Watermark = Watermark {
+    base_pages: 128,
+    large_pages: 8,
+};
+
+/// What a per-core cache is trimmed to if its node cache runs low.
+pub(crate) const TCACHE_LOW: Watermark = Watermark {
+    base_pages: 16,
+    large_pages: 1,
+};
+
+/// A node cache with less memory than this asks the cores for more.
+pub(crate) const NCACHE_LOW: Watermark = Watermark {
+    base_pages: 512,
+    large_pages: 8,
+};
+
+/// How long (in ns) we wait before we ask the cores again to give back
+/// memory for a node.
+///
+/// The cores answer on their timer interrupt, asking more often doesn't get
+/// the memory back any faster.
+pub(crate) const REQUEST_INTERVAL_NS: u64 = 10_000_000;
+
+/// How many frames we move at once.
+const BATCH_SIZE: usize = 32;
+
+/// Moves frames from `cache` to `ncache` until `cache` holds no more than
+/// `keep` pages (or `ncache` is full).
+///
+/// # Returns
+/// How many bytes were moved.
+pub(crate) fn trim<C, N>(cache: &mut C, ncache: &mut N, keep: Watermark) -> usize
+where
+    C: ReapBackend + AllocatorStatistics + ?Sized,
+    N: GrowBackend + ?Sized,
+{
+    let mut reclaimed = 0;
+    let mut free_list = [None; BATCH_SIZE];
+
+    loop {
+        let batch = cache
+            .free_base_pages()
+            .saturating_sub(keep.base_pages)
+            .min(ncache.spare_base_page_capacity())
+            .min(BATCH_SIZE);
+        if batch == 0 {
+            break;
+        }
+        cache.reap_base_pages(&mut free_list[..batch]);
+        for frame in free_list[..batch].iter_mut().filter_map(Option::take) {
+            ncache
+                .grow_base_pages(&[frame])
+                .expect("We checked the capacity above");
+            reclaimed += BASE_PAGE_SIZE;
+        }
+    }
+
+    loop {
+        let batch = cache
+            .free_large_pages()
+            .saturating_sub(keep.large_pages)
+            .min(ncache.spare_large_page_capacity())
+            .min(BATCH_SIZE);
+        if batch == 0 {
+            break;
+        }
+        cache.reap_large_pages(&mut free_list[..batch]);
+        for frame in free_list[..batch].iter_mut().filter_map(Option::take) {
+            ncache
+                .grow_large_pages(&[frame])
+                .expect("We checked the capacity above");
+            reclaimed += LARGE_PAGE_SIZE;
+        }
+    }
+
+    reclaimed
+}
+
+/// Trims the per-core `cache` for `node` if it exceeds [`TCACHE_HIGH`].
+pub(crate) fn maybe_trim<C>(cache: &mut C, gmanager: &GlobalMemory, node: atopology::NodeId)
+where
+    C: ReapBackend + AllocatorStatistics + ?Sized,
+{
+    if TCACHE_HIGH.is_exceeded_by(cache) {
+        let mut ncache = gmanager.node_caches[node].lock();
+        let reclaimed = trim(cache, &mut **ncache, TCACHE_TARGET);
+        debug!(
+            "Trimmed the cache for node {} by {}",
+            node,
+            DataSize::from_bytes(reclaimed)
+        );
+    }
+}
+
+/// Asks all cores to give back the memory they cache for `node` (and wakes
+/// the ones that sleep, so they answer).
+///
+/// Does nothing if we asked less than [`REQUEST_INTERVAL_NS`] ago.
+pub(crate) fn request(gmanager: &GlobalMemory, node: atopology::NodeId) {
+    let (epoch, requested) = match (
+        gmanager.reclaim_epochs.get(node),
+        gmanager.reclaim_requested.get(node),
+    ) {
+        (Some(epoch), Some(requested)) => (epoch, requested),
+        _ => return,
+    };
+
+    let now = rawtime::Instant::now().as_nanos() as u64;
+    let last = requested.load(Ordering::Relaxed);
+    if last != 0 && now.saturating_sub(last) < REQUEST_INTERVAL_NS {
+        return;
+    }
+    // Only one of the cores that race here sends the request
+    if requested
+        .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+        .is_ok()
+    {
+        epoch.fetch_add(1, Ordering::Relaxed);
+
+        let gtid = *crate::environment::CORE_ID;
+        for thread in atopology::MACHINE_TOPOLOGY.threads() {
+            if thread.id != gtid {
+                crate::arch::idle::wake(thread.id);
+            }
+        }
+    }
+}
+
+/// Gives the memory the current core caches for `node` back to the node
+/// right away (down to [`TCACHE_LOW`]), instead of waiting for the next
+/// [`poll`].
+///
+/// Caches that are in use right now are skipped. Must not be called with the
+/// lock of the node cache held.
+///
+/// # Returns
+/// How many bytes were moved.
+pub(crate) fn trim_local(
+    gmanager: &GlobalMemory,
+    node: atopology::NodeId,
+    mem_type: MemType,
+) -> usize {
+    let pcm = match try_per_core_mem() {
+        Some(pcm) if !pcm.use_emergency_allocator() => pcm,
+        _ => return 0,
+    };
+    let (tcache, arenas) = match mem_type {
+        MemType::Mem => (&pcm.physical_memory, &pcm.memory_arenas),
+        MemType::PMem => (&pcm.persistent_memory, &pcm.pmem_arenas),
+    };
+
+    let mut reclaimed = 0;
+    if let Ok(mut state) = tcache.try_borrow_mut() && state.affinity == node {
+        let mut ncache = gmanager.node_caches[node].lock();
+        reclaimed += trim(&mut state.pmanager, &mut **ncache, TCACHE_LOW);
+    }
+    if let Ok(mut arenas) = arenas.try_borrow_mut() {
+        for state in arenas.iter_mut().flatten().filter(|s| s.affinity == node) {
+            let mut ncache = gmanager.node_caches[node].lock();
+            reclaimed += trim(&mut state.pmanager, &mut **ncache, TCACHE_LOW);
+        }
+    }
+
+    debug!(
+        "Trimmed the local caches for node {} by {}",
+        node,
+        DataSize::from_bytes(reclaimed)
+    );
+    reclaimed
+}
+
+/// Answers the outstanding reclamation requests for the caches of the
+/// current core.
+pub(crate) fn poll() {
+    let pcm = match try_per_core_mem() {
+        Some(pcm) if !pcm.use_emergency_allocator() => pcm,
+        _ => return,
+    };
+
+    // Caches that are in use right now get trimmed on the next poll
+    if let Some(gmanager) = pcm.gmanager {
+        if let Ok(mut state) = pcm.physical_memory.try_borrow_mut() {
+            answer(gmanager, &mut state);
+        }
+        if let Ok(mut arenas) = pcm.memory_arenas.try_borrow_mut() {
+            for state in arenas.iter_mut().flatten() {
+                answer(gmanager, state);
+            }
+        }
+    }
+    if let Some(pgmanager) = pcm.pgmanager {
+        if let Ok(mut state) = pcm.persistent_memory.try_borrow_mut() {
+            answer(pgmanager, &mut state);
+        }
+        if let Ok(mut arenas) = pcm.pmem_arenas.try_borrow_mut() {
+            for state in arenas.iter_mut().flatten() {
+                answer(pgmanager, state);
+            }
+        }
+    }
+}
+
+/// Trims the cache of `state` if a reclamation was requested for its node
+/// since we last checked.
+fn answer(gmanager: &GlobalMemory, state: &mut PerCoreAllocatorState) {
+    let epoch = match gmanager.reclaim_epochs.get(state.affinity) {
+        Some(epoch) => epoch.load(Ordering::Relaxed),
+        None => return,
+    };
+
+    if epoch != state.reclaim_epoch {
+        state.reclaim_epoch = epoch;
+        let mut ncache = gmanager.node_caches[state.affinity].lock();
+        let reclaimed = trim(&mut state.pmanager, &mut **ncache, TCACHE_LOW);
+        debug!(
+            "Gave {} back to node {}",
+            DataSize::from_bytes(reclaimed),
+            state.affinity
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::backends::PhysicalPageProvider;
+    use crate::memory::mcache::FrameCacheSmall;
+    use crate::memory::{Frame, PAddr};
+
+    /// Trimming leaves the requested amount of pages in the cache.
+    #[test]
+    fn trim_to_watermark() {
+        let mut tcache = FrameCacheSmall::new(0);
+        let mut ncache = FrameCacheSmall::new(0);
+        for i in 0..64 {
+            tcache
+                .release_base_page(Frame::new(
+                    PAddr::from(i * BASE_PAGE_SIZE),
+                    BASE_PAGE_SIZE,
+                    0,
+                ))
+                .expect("release");
+        }
+        for i in 1..4 {
+            tcache
+                .release_large_page(Frame::new(
+                    PAddr::from(i * LARGE_PAGE_SIZE),
+                    LARGE_PAGE_SIZE,
+                    0,
+                ))
+                .expect("release");
+        }
+
+        let keep = Watermark {
+            base_pages: 10,
+            large_pages: 1,
+        };
+        assert!(keep.is_exceeded_by(&tcache));
+        let reclaimed = trim(&mut tcache, &mut ncache, keep);
+        assert_eq!(reclaimed, 54 * BASE_PAGE_SIZE + 2 * LARGE_PAGE_SIZE);
+        assert_eq!(tcache.free_base_pages(), 10);
+        assert_eq!(tcache.free_large_pages(), 1);
+        assert_eq!(ncache.free_base_pages(), 54);
+        assert_eq!(ncache.free_large_pages(), 2);
+        assert!(!keep.is_exceeded_by(&tcache));
+        assert!(!keep.is_undercut_by(&tcache));
+
+        // Large pages make up for missing base pages
+        let low = Watermark {
+            base_pages: 100,
+            large_pages: 0,
+        };
+        assert!(!low.is_undercut_by(&tcache));
+        assert!(low.is_undercut_by(&FrameCacheSmall::new(0)));
+
+        // Nothing more to do
+        assert_eq!(trim(&mut tcache, &mut ncache, keep), 0);
+    }
+}
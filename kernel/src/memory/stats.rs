@@ -0,0 +1,121 @@
+// Copyright © 2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Collects statistics about the memory of the kernel for user-space (see
+//! `SystemOperation::Stats`).
+//!
+//! The caches of a core can only be inspected by the core itself, so every
+//! core publishes its numbers periodically with [`publish`].
+
+use alloc::vec::Vec;
+
+use fallible_collections::{FallibleVec, FallibleVecGlobal};
+use kpi::system::{
+    CacheStats, CoreMemoryStats, MemoryStats, NodeMemoryStats, MEMORY_STATS_VERSION,
+};
+use spin::Mutex;
+
+use crate::arch::kcb::try_per_core_mem;
+use crate::arch::MAX_CORES;
+use crate::error::KError;
+
+use super::backends::AllocatorStatistics;
+use super::global::GlobalMemory;
+
+/// The statistics the cores published last (indexed by core id).
+static CORE_STATS: [Mutex<Option<CoreMemoryStats>>; MAX_CORES] = {
+    const UNPUBLISHED: Mutex<Option<CoreMemoryStats>> = Mutex::new(None);
+    [UNPUBLISHED; MAX_CORES]
+};
+
+fn cache_stats<C: AllocatorStatistics + ?Sized>(cache: &C) -> CacheStats {
+    CacheStats {
+        free_base_pages: cache.free_base_pages(),
+        free_large_pages: cache.free_large_pages(),
+        capacity: cache.capacity(),
+    }
+}
+
+/// Updates the statistics of the current core.
+pub(crate) fn publish() {
+    let pcm = match try_per_core_mem() {
+        Some(pcm) => pcm,
+        None => return,
+    };
+    let core = *crate::environment::CORE_ID;
+
+    // Caches that are in use right now get published the next time
+    let mut stats = CoreMemoryStats {
+        id: core,
+        ..Default::default()
+    };
+    match pcm.physical_memory.try_borrow() {
+        Ok(state) => {
+            stats.node_id = state.affinity;
+            stats.tcache = cache_stats(&state.pmanager);
+            stats.zone_size = state.zone_size;
+        }
+        Err(_) => return,
+    }
+    match pcm.persistent_memory.try_borrow() {
+        Ok(state) => stats.pmem_tcache = cache_stats(&state.pmanager),
+        Err(_) => return,
+    }
+
+    if let Some(mut slot) = CORE_STATS.get(core).and_then(|slot| slot.try_lock()) {
+        *slot = Some(stats);
+    }
+}
+
+/// Returns the memory of every NUMA node managed by `gmanager`.
+fn node_stats(gmanager: &GlobalMemory) -> Result<Vec<NodeMemoryStats>, KError> {
+    let mut nodes = Vec::try_with_capacity(gmanager.node_caches.len())?;
+    for (node, ncache) in gmanager.node_caches.iter().enumerate() {
+        let size = gmanager.node_sizes.get(node).copied().unwrap_or(0);
+        let ncache = ncache.lock();
+        let emergency = gmanager
+            .emem
+            .get(node)
+            .map(|emem| cache_stats(&*emem.lock()))
+            .unwrap_or_default();
+
+        nodes.try_push(NodeMemoryStats {
+            node_id: node,
+            size,
+            allocated: size.saturating_sub(ncache.free()),
+            ncache: cache_stats(&**ncache),
+            emergency,
+        })?;
+    }
+    Ok(nodes)
+}
+
+/// Gathers the memory statistics of the whole system.
+pub(crate) fn collect() -> Result<MemoryStats, KError> {
+    // Make sure the numbers of the calling core are recent
+    publish();
+
+    let pcm = try_per_core_mem().ok_or(KError::KcbUnavailable)?;
+    let nodes = match pcm.gmanager {
+        Some(gmanager) => node_stats(gmanager)?,
+        None => Vec::new(),
+    };
+    let pmem_nodes = match pcm.pgmanager {
+        Some(pgmanager) => node_stats(pgmanager)?,
+        None => Vec::new(),
+    };
+
+    let mut cores = Vec::new();
+    for slot in CORE_STATS.iter() {
+        if let Some(stats) = *slot.lock() {
+            cores.try_push(stats)?;
+        }
+    }
+
+    Ok(MemoryStats {
+        version: MEMORY_STATS_VERSION,
+        nodes,
+        pmem_nodes,
+        cores,
+    })
+}
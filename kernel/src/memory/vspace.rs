@@ -7,10 +7,13 @@ use core::cmp::PartialEq;
 use core::fmt;
 use core::ops::{BitOr, BitOrAssign};
 
+use alloc::vec::Vec;
+
 use crate::error::KError;
+use crate::fs::MnodeNum;
 use bit_field::BitField;
 
-use super::{Frame, PAddr, VAddr};
+use super::{Frame, MemType, PAddr, VAddr};
 
 /// A handle we use to flush specific TLB entries.
 #[derive(Debug, PartialEq, Clone)]
@@ -92,15 +95,34 @@ impl Iterator for CoreBitMapIter {
     }
 }
 
+/// What a mapping in a process' address space is used for.
+///
+/// This determines who owns the backing memory and how it is released once
+/// the process exits.
 #[cfg_attr(not(target_os = "none"), allow(dead_code))]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub(crate) enum MappingType {
-    _ElfText,
-    _ElfData,
-    _Executor,
-    Heap,
+    /// Read-only part of an ELF binary (allocated individually by every replica).
+    ElfText,
+    /// Writeable part of an ELF binary (shared by all replicas).
+    ElfData,
+    /// Stacks and vCPU area of the executors.
+    Executor,
+    /// Memory the process mapped explicitly from the given memory type.
+    Heap(MemType),
+    /// Device memory, not owned by the kernel.
+    Device,
+    /// Pages of a file starting at `offset` (owned by the file-system).
+    ///
+    /// Only a `writable` mapping gets written back to the file.
+    File {
+        mnode: MnodeNum,
+        offset: usize,
+        writable: bool,
+    },
 }
 
+#[derive(Clone, Copy)]
 pub(crate) struct MappingInfo {
     pub frame: Frame,
     pub rights: MapAction,
@@ -109,11 +131,11 @@ pub(crate) struct MappingInfo {
 
 impl MappingInfo {
     pub(crate) fn new(frame: Frame, rights: MapAction) -> Self {
-        MappingInfo {
-            frame,
-            rights,
-            typ: MappingType::Heap,
-        }
+        MappingInfo::with_type(frame, rights, MappingType::Heap(MemType::Mem))
+    }
+
+    pub(crate) fn with_type(frame: Frame, rights: MapAction, typ: MappingType) -> Self {
+        MappingInfo { frame, rights, typ }
     }
 
     /// Return range of the region if it would start at `base`
@@ -132,6 +154,26 @@ impl fmt::Debug for MappingInfo {
     }
 }
 
+/// A region of an address space that is only backed with memory once it is
+/// accessed.
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct Reservation {
+    /// Length of the region in bytes.
+    pub size: usize,
+    /// Rights of the pages once they are backed.
+    pub rights: MapAction,
+    /// What memory the pages are backed with.
+    pub mem_type: MemType,
+}
+
+impl Reservation {
+    /// Return range of the region if it would start at `base`
+    pub(crate) fn vrange(&self, base: VAddr) -> core::ops::Range<usize> {
+        base.as_usize()..base.as_usize() + self.size
+    }
+}
+
 /// Generic address space functionality.
 pub(crate) trait AddressSpace {
     /// Maps a list of `frames` at `base` in the address space
@@ -157,6 +199,19 @@ pub(crate) trait AddressSpace {
     /// something already mapped.
     fn map_frame(&mut self, base: VAddr, frame: Frame, action: MapAction) -> Result<(), KError>;
 
+    /// Same as `map_frame` but also records what the mapping is used for.
+    ///
+    /// Address spaces that don't keep track of mappings can ignore `typ`.
+    fn map_frame_as(
+        &mut self,
+        base: VAddr,
+        frame: Frame,
+        action: MapAction,
+        _typ: MappingType,
+    ) -> Result<(), KError> {
+        self.map_frame(base, frame, action)
+    }
+
     /// Estimates how many base-pages are needed (for page-tables)
     /// to map the given list of frames in the address space starting at `base`.
     ///
@@ -181,6 +236,76 @@ pub(crate) trait AddressSpace {
     /// invoked to flush the TLB.
     fn unmap(&mut self, vaddr: VAddr) -> Result<TlbFlushHandle, KError>;
 
+    /// Reserves the region starting at `base` without backing it with memory.
+    ///
+    /// The pages of the region are backed one at a time with `populate` once
+    /// they are accessed.
+    fn reserve(&mut self, _base: VAddr, _reservation: Reservation) -> Result<(), KError> {
+        Err(KError::NotSupported)
+    }
+
+    /// Returns the reserved region that contains `vaddr` (and where it starts).
+    fn reservation(&self, _vaddr: VAddr) -> Option<(VAddr, Reservation)> {
+        None
+    }
+
+    /// Backs the page containing `vaddr` in a reserved region with `frame`.
+    ///
+    /// Returns `AlreadyMapped` if the page is backed already.
+    fn populate(&mut self, _vaddr: VAddr, _frame: Frame) -> Result<(), KError> {
+        Err(KError::NotMapped)
+    }
+
+    /// Removes the reserved region that contains `vaddr`.
+    ///
+    /// # Returns
+    /// A `TlbFlushHandle` for the whole region along with the frames that were
+    /// backing it.
+    fn unreserve(&mut self, _vaddr: VAddr) -> Result<(TlbFlushHandle, Vec<Frame>), KError> {
+        Err(KError::NotMapped)
+    }
+
+    /// Returns the mapping that contains `vaddr` (and where it starts).
+    fn mapping(&self, _vaddr: VAddr) -> Option<(VAddr, MappingInfo)> {
+        None
+    }
+
+    /// Returns the first mapping that starts at or after `vaddr`.
+    fn next_mapping(&self, _vaddr: VAddr) -> Option<(VAddr, MappingInfo)> {
+        None
+    }
+
+    /// Maps the frames of `src..src+len` a second time at `dst`.
+    ///
+    /// Both mappings of a frame become copy-on-write, `src` has to consist of
+    /// whole, writable mappings.
+    ///
+    /// # Returns
+    /// A `TlbFlushHandle` for `src` (which lost write access) along with the
+    /// frames that got mapped at `dst`.
+    fn share_cow(
+        &mut self,
+        _src: VAddr,
+        _dst: VAddr,
+        _len: usize,
+    ) -> Result<(TlbFlushHandle, Vec<Frame>), KError> {
+        Err(KError::NotSupported)
+    }
+
+    /// Replaces the frame of the copy-on-write mapping that starts at `base`
+    /// with `frame` (which holds a copy of the content), the mapping becomes
+    /// writable.
+    ///
+    /// # Returns
+    /// A `TlbFlushHandle` for the mapping along with the replaced frame.
+    fn copy_on_write(
+        &mut self,
+        _base: VAddr,
+        _frame: Frame,
+    ) -> Result<(TlbFlushHandle, Frame), KError> {
+        Err(KError::NotSupported)
+    }
+
     // Returns an iterator of all currently mapped memory regions.
     //fn mappings()
 }
@@ -220,6 +345,11 @@ pub(crate) struct MapAction {
     /// This is possible with the `map_frame_id` API variants and we have to
     /// track it so we unly free the frame once all aliases are unmapped again.
     aliased: bool,
+    /// It's a mapping of a frame that is shared copy-on-write.
+    ///
+    /// The mapping is read-only until the first write, which replaces the
+    /// frame with a (writable) copy.
+    cow: bool,
 }
 
 impl MapAction {
@@ -232,6 +362,7 @@ impl MapAction {
             kernel: false,
             not_cached: false,
             aliased: false,
+            cow: false,
         }
     }
 
@@ -244,6 +375,7 @@ impl MapAction {
             kernel: false,
             not_cached: false,
             aliased: false,
+            cow: false,
         }
     }
 
@@ -256,6 +388,7 @@ impl MapAction {
             kernel: true,
             not_cached: false,
             aliased: false,
+            cow: false,
         }
     }
 
@@ -268,6 +401,7 @@ impl MapAction {
             kernel: false,
             not_cached: false,
             aliased: false,
+            cow: false,
         }
     }
 
@@ -280,6 +414,7 @@ impl MapAction {
             kernel: false,
             not_cached: false,
             aliased: false,
+            cow: false,
         }
     }
 
@@ -292,6 +427,7 @@ impl MapAction {
             kernel: false,
             not_cached: true,
             aliased: false,
+            cow: false,
         }
     }
 
@@ -305,6 +441,39 @@ impl MapAction {
             kernel: false,
             not_cached: false,
             aliased: true,
+            cow: false,
+        }
+    }
+
+    /// A mapping that is copy-on-write.
+    pub(crate) const fn cow() -> Self {
+        MapAction {
+            present: false,
+            write: false,
+            exec: false,
+            kernel: false,
+            not_cached: false,
+            aliased: false,
+            cow: true,
+        }
+    }
+
+    /// The same rights, but writes fault until the frame is copied.
+    pub(crate) fn copy_on_write(self) -> Self {
+        debug_assert!(self.write || self.cow, "Only writable mappings can be COW");
+        MapAction {
+            write: false,
+            cow: true,
+            ..self
+        }
+    }
+
+    /// The rights of a copy-on-write mapping once the frame is copied.
+    pub(crate) fn copied(self) -> Self {
+        MapAction {
+            write: self.write || self.cow,
+            cow: false,
+            ..self
         }
     }
 
@@ -334,6 +503,11 @@ impl MapAction {
         self.aliased
     }
 
+    /// Is this mapping copy-on-write?
+    pub(crate) fn is_cow(&self) -> bool {
+        self.cow
+    }
+
     /// Is this user-space memory?
     pub(crate) fn is_userspace(&self) -> bool {
         !self.kernel
@@ -354,6 +528,7 @@ impl fmt::Display for MapAction {
             kernel,
             not_cached,
             aliased,
+            cow,
         } = *self;
 
         let present = if present { 'p' } else { '-' };
@@ -362,11 +537,12 @@ impl fmt::Display for MapAction {
         let exec = if exec { "x" } else { "-" };
         let not_cached = if not_cached { "[nc]" } else { "-" };
         let aliased = if aliased { "[al]" } else { "-" };
+        let cow = if cow { "[cow]" } else { "-" };
 
         write!(
             f,
-            "{}{}{}{}{}{}",
-            kernel_user, present, read_write, exec, not_cached, aliased
+            "{}{}{}{}{}{}{}",
+            kernel_user, present, read_write, exec, not_cached, aliased, cow
         )
     }
 }
@@ -381,6 +557,7 @@ impl BitOr for MapAction {
             kernel: self.kernel || rhs.kernel,
             not_cached: self.not_cached || rhs.not_cached,
             aliased: self.aliased || rhs.aliased,
+            cow: self.cow || rhs.cow,
         }
     }
 }
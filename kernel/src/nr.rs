@@ -3,17 +3,20 @@
 
 use crate::prelude::*;
 use core::fmt::Debug;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use alloc::sync::Arc;
+use fallible_collections::{FallibleVec, FallibleVecGlobal};
 use hashbrown::HashMap;
+use kpi::process::ShmId;
 use log::{error, trace};
 use node_replication::{Dispatch, Replica, ReplicaToken};
 use spin::Once;
 
 use crate::arch::MAX_CORES;
 use crate::error::KError;
-use crate::memory::VAddr;
-use crate::process::{Pid, MAX_PROCESSES};
+use crate::memory::{Frame, VAddr};
+use crate::process::{max_processes, Pid};
 
 /// Kernel scheduler / process mgmt. replica
 #[thread_local]
@@ -21,7 +24,17 @@ pub(crate) static NR_REPLICA: Once<(Arc<Replica<'static, KernelNode>>, ReplicaTo
 
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub(crate) enum ReadOps {
-    CurrentProcess(atopology::GlobalThreadId),
+    /// The processes that share the given core.
+    CoreProcesses(atopology::GlobalThreadId),
+    /// How many processes share the given core.
+    CoreLoad(atopology::GlobalThreadId),
+    /// How many processes currently exist.
+    NumProcesses,
+    /// An exited child of the first process (any child if the second
+    /// argument is `None`)
+    ExitedChild(Pid, Option<Pid>),
+    /// The cores the process runs on.
+    ProcessCores(Pid),
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -29,23 +42,60 @@ pub(crate) enum Op {
     /// Allocate a new process (Pid)
     AllocatePid,
     /// Destroy a process
-    #[allow(unused)] // TODO
     FreePid(Pid),
-    /// Assign a core to a process
+    /// Make the first process the parent of the second process
+    SetParent(Pid, Pid),
+    /// The process exited with the given exit code
+    ProcessExited(Pid, u64),
+    /// Reap an exited child of the first process (any child if the second
+    /// argument is `None`)
+    Wait(Pid, Option<Pid>),
+    /// Assign a core to a process (the core may be shared with other
+    /// processes)
     SchedAllocateCore(
         Pid,
         Option<atopology::NodeId>,
         Option<atopology::GlobalThreadId>,
         VAddr,
     ),
+    /// Remove a core from a process
+    SchedReleaseCore(Pid, atopology::GlobalThreadId),
+    /// Remove all cores from a process
+    SchedReleaseCores(Pid),
+    /// Make the frame a shared memory object, held by the process
+    ShmCreate(Pid, Frame),
+    /// The process takes another reference to a shared memory object
+    ShmAttach(Pid, ShmId),
+    /// The process drops a reference to a shared memory object
+    ShmDetach(Pid, ShmId),
+    /// Drop all references the (exited) process holds on shared memory
+    ShmDetachProcess(Pid),
 }
 
 #[derive(Debug, Clone)]
 pub(crate) enum NodeResult {
     PidAllocated(Pid),
     PidReturned,
-    CoreInfo(CoreInfo),
+    CoreInfos(Vec<CoreInfo>),
+    CoreLoad(usize),
     CoreAllocated(atopology::GlobalThreadId),
+    CoreReleased,
+    CoresReleased(Vec<atopology::GlobalThreadId>),
+    NumProcesses(usize),
+    ParentSet,
+    /// The parent of the exited process (if it has one)
+    ProcessExited(Option<Pid>),
+    /// Pid and exit code of an exited child (if there is one)
+    ExitedChild(Option<(Pid, u64)>),
+    Cores(Vec<atopology::GlobalThreadId>),
+    /// Pid and exit code of a reaped child
+    Reaped(Pid, u64),
+    /// There are matching children but none of them has exited yet
+    NoExitedChild,
+    ShmCreated(ShmId),
+    ShmAttached(Frame),
+    /// Frames of shared memory objects that lost their last reference
+    ShmReleased(Vec<Frame>),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -54,16 +104,63 @@ pub(crate) struct CoreInfo {
     pub entry_point: VAddr,
 }
 
+/// What the kernel tracks about every allocated pid.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ProcessState {
+    /// The process that spawned us (it can wait for our exit).
+    pub parent: Option<Pid>,
+    /// The exit code, set once the process exited but hasn't been reaped by
+    /// its parent yet.
+    pub exit_code: Option<u64>,
+}
+
+/// A frame that is registered with more than one process.
+#[derive(Debug)]
+pub(crate) struct SharedFrame {
+    frame: Frame,
+    /// One entry for every time a process registered the frame.
+    holders: Vec<Pid>,
+}
+
+/// Wakes the main thread of every replica that holds up an NR log (the log
+/// calls this once it runs full).
+///
+/// Idle cores sleep until somebody needs them, so the replicas of their node
+/// only make progress if we ask.
+pub(crate) fn wake_lagging_replicas(replicas: &[AtomicBool]) {
+    for (replica, replica_signal) in replicas
+        .iter()
+        .enumerate()
+        .take(atopology::MACHINE_TOPOLOGY.num_nodes().max(1))
+    {
+        if replica_signal.load(Ordering::Relaxed) {
+            let main_thread = atopology::MACHINE_TOPOLOGY
+                .nodes()
+                .nth(replica)
+                .and_then(|node| node.threads().next())
+                .map_or(0, |thread| thread.id);
+            trace!("Replica {} needs to make progress", replica);
+            crate::arch::idle::wake(main_thread);
+            replica_signal.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
 pub(crate) struct KernelNode {
-    process_map: HashMap<Pid, ()>,
-    scheduler_map: HashMap<atopology::GlobalThreadId, CoreInfo>,
+    process_map: HashMap<Pid, ProcessState>,
+    /// The processes every core runs (round-robin).
+    scheduler_map: HashMap<atopology::GlobalThreadId, Vec<CoreInfo>>,
+    shm_map: HashMap<ShmId, SharedFrame>,
+    next_shm: ShmId,
 }
 
 impl Default for KernelNode {
     fn default() -> KernelNode {
         KernelNode {
-            process_map: HashMap::new(),   // with_capacity(MAX_PROCESSES),
+            process_map: HashMap::new(),   // with_capacity(max_processes()),
             scheduler_map: HashMap::new(), // with_capacity(MAX_CORES),
+            shm_map: HashMap::new(),
+            next_shm: 0,
         }
     }
 }
@@ -91,12 +188,284 @@ impl KernelNode {
                 let response = replica.execute_mut(op, *token);
 
                 match response {
-                    Ok(NodeResult::CoreAllocated(rgtid)) => Ok(rgtid),
+                    Ok(NodeResult::CoreAllocated(rgtid)) => {
+                        crate::scheduler::process_assigned(rgtid);
+                        Ok(rgtid)
+                    }
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Returns the processes that take turns on core `gtid`.
+    pub(crate) fn core_processes(gtid: atopology::GlobalThreadId) -> Result<Vec<CoreInfo>, KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute(ReadOps::CoreProcesses(gtid), *token);
+
+                match response {
+                    Ok(NodeResult::CoreInfos(infos)) => Ok(infos),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Returns how many processes share core `gtid`.
+    pub(crate) fn core_load(gtid: atopology::GlobalThreadId) -> Result<usize, KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute(ReadOps::CoreLoad(gtid), *token);
+
+                match response {
+                    Ok(NodeResult::CoreLoad(load)) => Ok(load),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Removes the process from core `gtid`.
+    pub(crate) fn release_core_from_process(
+        pid: Pid,
+        gtid: atopology::GlobalThreadId,
+    ) -> Result<(), KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut(Op::SchedReleaseCore(pid, gtid), *token);
+
+                match response {
+                    Ok(NodeResult::CoreReleased) => Ok(()),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Removes the process from all cores it is currently scheduled on.
+    ///
+    /// Returns the cores that ran the process.
+    pub(crate) fn release_cores(pid: Pid) -> Result<Vec<atopology::GlobalThreadId>, KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut(Op::SchedReleaseCores(pid), *token);
+
+                match response {
+                    Ok(NodeResult::CoresReleased(cores)) => Ok(cores),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Returns `pid` to the pool of unused process identifiers.
+    pub(crate) fn release_pid(pid: Pid) -> Result<(), KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut(Op::FreePid(pid), *token);
+
+                match response {
+                    Ok(NodeResult::PidReturned) => Ok(()),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Records `parent` as the parent of `child`.
+    pub(crate) fn set_parent(parent: Pid, child: Pid) -> Result<(), KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut(Op::SetParent(parent, child), *token);
+
+                match response {
+                    Ok(NodeResult::ParentSet) => Ok(()),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Records the exit of `pid`, returns the parent of `pid`.
+    ///
+    /// The pid stays allocated until the parent reaps it with `wait`, or is
+    /// released right away if the process has no parent.
+    pub(crate) fn process_exited(pid: Pid, code: u64) -> Result<Option<Pid>, KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut(Op::ProcessExited(pid, code), *token);
+
+                match response {
+                    Ok(NodeResult::ProcessExited(parent)) => Ok(parent),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Reaps an exited child of `parent` (any child if `child` is `None`).
+    ///
+    /// Returns the pid and exit code of the child or `None` if no matching
+    /// child has exited yet. Only modifies the log if there is a child to
+    /// reap, so it's fine to call this until a child exits.
+    pub(crate) fn wait(parent: Pid, child: Option<Pid>) -> Result<Option<(Pid, u64)>, KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let exited = match replica.execute(ReadOps::ExitedChild(parent, child), *token) {
+                    Ok(NodeResult::ExitedChild(exited)) => exited,
+                    Err(e) => return Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                };
+                let child = match exited {
+                    Some((pid, _code)) => pid,
+                    None => return Ok(None),
+                };
+
+                let response = replica.execute_mut(Op::Wait(parent, Some(child)), *token);
+
+                match response {
+                    Ok(NodeResult::Reaped(pid, code)) => Ok(Some((pid, code))),
+                    Ok(NodeResult::NoExitedChild) => Ok(None),
                     Err(e) => Err(e),
                     Ok(_) => unreachable!("Got unexpected response"),
                 }
             })
     }
+
+    /// The cores that run `pid`.
+    pub(crate) fn process_cores(pid: Pid) -> Result<Vec<atopology::GlobalThreadId>, KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute(ReadOps::ProcessCores(pid), *token);
+
+                match response {
+                    Ok(NodeResult::Cores(cores)) => Ok(cores),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Turns `frame` into a shared memory object, `pid` holds the first
+    /// reference to it.
+    pub(crate) fn shm_create(pid: Pid, frame: Frame) -> Result<ShmId, KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut(Op::ShmCreate(pid, frame), *token);
+
+                match response {
+                    Ok(NodeResult::ShmCreated(shm)) => Ok(shm),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Takes another reference to the shared memory object `shm` for `pid`.
+    ///
+    /// Returns the frame backing the object.
+    pub(crate) fn shm_attach(pid: Pid, shm: ShmId) -> Result<Frame, KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut(Op::ShmAttach(pid, shm), *token);
+
+                match response {
+                    Ok(NodeResult::ShmAttached(frame)) => Ok(frame),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Drops a reference of `pid` to the shared memory object `shm`.
+    ///
+    /// Returns the frame if this was the last reference, the caller has to
+    /// release it.
+    pub(crate) fn shm_detach(pid: Pid, shm: ShmId) -> Result<Option<Frame>, KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut(Op::ShmDetach(pid, shm), *token);
+
+                match response {
+                    Ok(NodeResult::ShmReleased(mut frames)) => Ok(frames.pop()),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    /// Drops all references `pid` holds on shared memory objects.
+    ///
+    /// Returns the frames that lost their last reference, the caller has to
+    /// release them.
+    pub(crate) fn shm_detach_process(pid: Pid) -> Result<Vec<Frame>, KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute_mut(Op::ShmDetachProcess(pid), *token);
+
+                match response {
+                    Ok(NodeResult::ShmReleased(frames)) => Ok(frames),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+
+    pub(crate) fn num_processes() -> Result<usize, KError> {
+        NR_REPLICA
+            .get()
+            .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
+                let response = replica.execute(ReadOps::NumProcesses, *token);
+
+                match response {
+                    Ok(NodeResult::NumProcesses(n)) => Ok(n),
+                    Err(e) => Err(e),
+                    Ok(_) => unreachable!("Got unexpected response"),
+                }
+            })
+    }
+}
+
+impl KernelNode {
+    /// An exited child of `parent` (any child if `child` is `None`).
+    ///
+    /// Fails if `parent` has no matching children at all.
+    fn exited_child(&self, parent: Pid, child: Option<Pid>) -> Result<Option<(Pid, u64)>, KError> {
+        let mut has_children = false;
+        let mut exited = None;
+        for (pid, state) in self.process_map.iter() {
+            if state.parent != Some(parent) || child.map_or(false, |c| c != *pid) {
+                continue;
+            }
+            has_children = true;
+            // Pick the lowest pid so all replicas reap the same child
+            if let Some(code) = state.exit_code {
+                if exited.map_or(true, |(epid, _)| *pid < epid) {
+                    exited = Some((*pid, code));
+                }
+            }
+        }
+
+        if exited.is_none() && !has_children {
+            Err(KError::NoChildProcess)
+        } else {
+            Ok(exited)
+        }
+    }
 }
 
 impl Dispatch for KernelNode {
@@ -106,12 +475,35 @@ impl Dispatch for KernelNode {
 
     fn dispatch<'rop>(&self, op: Self::ReadOperation<'_>) -> Self::Response {
         match op {
-            ReadOps::CurrentProcess(gtid) => {
-                let core_info = self
+            ReadOps::CoreProcesses(gtid) => {
+                let infos = self
                     .scheduler_map
                     .get(&gtid)
                     .ok_or(KError::NoExecutorForCore)?;
-                Ok(NodeResult::CoreInfo(*core_info))
+                let mut copy = Vec::try_with_capacity(infos.len())?;
+                copy.extend_from_slice(infos);
+                Ok(NodeResult::CoreInfos(copy))
+            }
+            ReadOps::CoreLoad(gtid) => Ok(NodeResult::CoreLoad(
+                self.scheduler_map.get(&gtid).map_or(0, |infos| infos.len()),
+            )),
+            ReadOps::NumProcesses => Ok(NodeResult::NumProcesses(
+                self.process_map
+                    .values()
+                    .filter(|state| state.exit_code.is_none())
+                    .count(),
+            )),
+            ReadOps::ExitedChild(parent, child) => {
+                Ok(NodeResult::ExitedChild(self.exited_child(parent, child)?))
+            }
+            ReadOps::ProcessCores(pid) => {
+                let mut cores = Vec::new();
+                for (gtid, infos) in self.scheduler_map.iter() {
+                    if infos.iter().any(|cinfo| cinfo.pid == pid) {
+                        cores.try_push(*gtid)?;
+                    }
+                }
+                Ok(NodeResult::Cores(cores))
             }
         }
     }
@@ -120,18 +512,18 @@ impl Dispatch for KernelNode {
         match op {
             Op::AllocatePid => {
                 // TODO(performance): O(n) scan probably not what we really
-                // want, fine for now, MAX_PROCESSES is tiny
-                for i in 0..MAX_PROCESSES {
+                // want, fine for now, max_processes() is small
+                for i in 0..max_processes() {
                     if !self.process_map.contains_key(&i) {
                         self.process_map.try_reserve(1)?;
-                        let r = self.process_map.insert(i, ());
+                        let r = self.process_map.insert(i, ProcessState::default());
                         assert!(r.is_none(), "!contains_key");
                         return Ok(NodeResult::PidAllocated(i));
                     }
                 }
                 Err(KError::OutOfPids)
             }
-            // TODO: better impl, what about scheduler_map?
+            // Cores are released first with `Op::SchedReleaseCores`
             Op::FreePid(pid) => match self.process_map.remove(&pid) {
                 Some(_) => Ok(NodeResult::PidReturned),
                 None => {
@@ -139,25 +531,139 @@ impl Dispatch for KernelNode {
                     Err(KError::NoProcessFoundForPid)
                 }
             },
+            Op::SetParent(parent, child) => {
+                if !self.process_map.contains_key(&parent) {
+                    return Err(KError::NoProcessFoundForPid);
+                }
+                let state = self
+                    .process_map
+                    .get_mut(&child)
+                    .ok_or(KError::NoProcessFoundForPid)?;
+                state.parent = Some(parent);
+                Ok(NodeResult::ParentSet)
+            }
+            Op::ProcessExited(pid, code) => {
+                let state = self
+                    .process_map
+                    .get_mut(&pid)
+                    .ok_or(KError::NoProcessFoundForPid)?;
+                // Keep the pid around until the parent collects the exit code
+                let parent = state.parent;
+                let parent_alive = parent.is_some();
+                state.exit_code = Some(code);
+                if !parent_alive {
+                    self.process_map.remove(&pid);
+                }
+
+                // Nobody is going to wait for our children anymore
+                self.process_map.retain(|_pid, state| {
+                    !(state.parent == Some(pid) && state.exit_code.is_some())
+                });
+                for state in self.process_map.values_mut() {
+                    if state.parent == Some(pid) {
+                        state.parent = None;
+                    }
+                }
+
+                Ok(NodeResult::ProcessExited(parent))
+            }
+            Op::Wait(parent, child) => match self.exited_child(parent, child)? {
+                Some((pid, code)) => {
+                    self.process_map.remove(&pid);
+                    Ok(NodeResult::Reaped(pid, code))
+                }
+                None => Ok(NodeResult::NoExitedChild),
+            },
             Op::SchedAllocateCore(pid, _affinity, Some(gtid), entry_point) => {
                 assert!(gtid < MAX_CORES, "Invalid gtid");
 
-                match self.scheduler_map.get(&gtid) {
-                    Some(_cinfo) => Err(KError::CoreAlreadyAllocated),
-                    None => {
-                        trace!("Op::SchedAllocateCore pid={}, gtid={}", pid, gtid);
+                self.scheduler_map.try_reserve(1)?;
+                let infos = self.scheduler_map.entry(gtid).or_default();
+                // A process runs at most one executor on every core
+                if infos.iter().any(|cinfo| cinfo.pid == pid) {
+                    return Err(KError::CoreAlreadyAllocated);
+                }
 
-                        self.scheduler_map.try_reserve(1)?;
-                        let r = self
-                            .scheduler_map
-                            .insert(gtid, CoreInfo { pid, entry_point });
-                        assert!(r.is_none(), "get() -> None");
+                trace!("Op::SchedAllocateCore pid={}, gtid={}", pid, gtid);
+                infos.try_reserve(1)?;
+                infos.push(CoreInfo { pid, entry_point });
+                Ok(NodeResult::CoreAllocated(gtid))
+            }
+            Op::SchedAllocateCore(_pid, _affinity, _gtid, _entry_point) => unimplemented!(),
+            Op::SchedReleaseCore(pid, gtid) => {
+                let infos = self
+                    .scheduler_map
+                    .get_mut(&gtid)
+                    .ok_or(KError::CoreNotAllocated)?;
+                let idx = infos
+                    .iter()
+                    .position(|cinfo| cinfo.pid == pid)
+                    .ok_or(KError::CoreNotAllocated)?;
 
-                        Ok(NodeResult::CoreAllocated(gtid))
+                trace!("Op::SchedReleaseCore pid={}, gtid={}", pid, gtid);
+                infos.remove(idx);
+                if infos.is_empty() {
+                    self.scheduler_map.remove(&gtid);
+                }
+                Ok(NodeResult::CoreReleased)
+            }
+            Op::SchedReleaseCores(pid) => {
+                let mut cores = Vec::try_with_capacity(self.scheduler_map.len())?;
+                for (gtid, infos) in self.scheduler_map.iter_mut() {
+                    if let Some(idx) = infos.iter().position(|cinfo| cinfo.pid == pid) {
+                        trace!("Op::SchedReleaseCores pid={}, gtid={}", pid, gtid);
+                        infos.remove(idx);
+                        cores.push(*gtid);
                     }
                 }
+                self.scheduler_map.retain(|_gtid, infos| !infos.is_empty());
+
+                Ok(NodeResult::CoresReleased(cores))
+            }
+            Op::ShmCreate(pid, frame) => {
+                let mut holders = Vec::try_with_capacity(1)?;
+                holders.push(pid);
+
+                let shm = self.next_shm;
+                self.shm_map.try_reserve(1)?;
+                self.shm_map.insert(shm, SharedFrame { frame, holders });
+                self.next_shm += 1;
+                Ok(NodeResult::ShmCreated(shm))
+            }
+            Op::ShmAttach(pid, shm) => {
+                let shared = self.shm_map.get_mut(&shm).ok_or(KError::InvalidShmId)?;
+                shared.holders.try_reserve(1)?;
+                shared.holders.push(pid);
+                Ok(NodeResult::ShmAttached(shared.frame))
+            }
+            Op::ShmDetach(pid, shm) => {
+                let mut released = Vec::try_with_capacity(1)?;
+                let shared = self.shm_map.get_mut(&shm).ok_or(KError::InvalidShmId)?;
+                let idx = shared
+                    .holders
+                    .iter()
+                    .position(|holder| *holder == pid)
+                    .ok_or(KError::InvalidShmId)?;
+                shared.holders.swap_remove(idx);
+
+                if shared.holders.is_empty() {
+                    let shared = self.shm_map.remove(&shm).expect("Found it above");
+                    released.push(shared.frame);
+                }
+                Ok(NodeResult::ShmReleased(released))
+            }
+            Op::ShmDetachProcess(pid) => {
+                let mut released = Vec::try_with_capacity(self.shm_map.len())?;
+                for shared in self.shm_map.values_mut() {
+                    shared.holders.retain(|holder| *holder != pid);
+                    if shared.holders.is_empty() {
+                        released.push(shared.frame);
+                    }
+                }
+                self.shm_map
+                    .retain(|_shm, shared| !shared.holders.is_empty());
+                Ok(NodeResult::ShmReleased(released))
             }
-            Op::SchedAllocateCore(_pid, _affinity, _gtid, _entry_point) => unimplemented!(),
         }
     }
 }
@@ -6,47 +6,145 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::alloc::Allocator;
 use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use fallible_collections::FallibleVecGlobal;
 
 use arrayvec::ArrayVec;
 use fallible_collections::vec::FallibleVec;
-use kpi::process::{FrameId, ProcessInfo};
-use kpi::MemType;
-use node_replication::{Dispatch, Replica, ReplicaToken};
+use kpi::process::{FrameId, MemUsage, ProcessInfo, ShmId};
+use kpi::{MemPolicy, MemType};
+use node_replication::{Dispatch, Log, Replica, ReplicaToken};
 use spin::Once;
 
-use crate::arch::process::PROCESS_TABLE;
+use crate::arch::kcb::per_core_mem;
+use crate::arch::process::{ArchProcess, PROCESS_TABLE};
 use crate::arch::{Module, MAX_NUMA_NODES};
 use crate::error::{KError, KResult};
 use crate::memory::detmem::DA;
-use crate::memory::vspace::{AddressSpace, MapAction, TlbFlushHandle};
-use crate::memory::{Frame, PAddr, VAddr};
+use crate::memory::vspace::{
+    AddressSpace, MapAction, MappingInfo, MappingType, Reservation, TlbFlushHandle,
+};
+use crate::memory::{Frame, PAddr, VAddr, BASE_PAGE_SIZE, LARGE_PAGE_SIZE};
 use crate::process::{
-    Eid, Executor, Pid, Process, SliceAccess, UserSlice, MAX_FRAMES_PER_PROCESS, MAX_PROCESSES,
+    Cmdline, Eid, Executor, Pid, Process, SliceAccess, UserSlice, MAX_FRAMES_PER_PROCESS,
 };
 
 /// The tokens per core to access the process replicas.
+///
+/// A core registers with the replicas of a process the first time it
+/// accesses the process.
 #[thread_local]
-pub(crate) static PROCESS_TOKEN: Once<ArrayVec<ReplicaToken, { MAX_PROCESSES }>> = Once::new();
+pub(crate) static PROCESS_TOKEN: Once<Vec<Once<ReplicaToken>>> = Once::new();
 
 /// Initializes `PROCESS_TOKEN`.
 ///
 /// Should be called on each core.
 pub(crate) fn register_thread_with_process_replicas() {
-    let node = *crate::environment::NODE_ID;
-    debug_assert!(PROCESS_TABLE.len() > node, "Invalid Node ID");
-
     PROCESS_TOKEN.call_once(|| {
-        let mut tokens = ArrayVec::new();
-        for pid in 0..MAX_PROCESSES {
-            debug_assert!(PROCESS_TABLE[node].len() > pid, "Invalid PID");
+        let max_processes = PROCESS_TABLE.capacity();
+        let mut tokens = Vec::try_with_capacity(max_processes)
+            .expect("Not enough memory to initialize process tokens");
+        tokens.resize_with(max_processes, Once::new);
+        tokens
+    });
+}
 
-            let token = PROCESS_TABLE[node][pid].register();
-            tokens.push(token.expect("Need to be able to register"));
+/// The replicas of a process (one per NUMA node).
+pub(crate) type ProcessReplicas<P> = ArrayVec<Arc<Replica<'static, NrProcess<P>>>, MAX_NUMA_NODES>;
+
+/// The process table, has a slot for every pid.
+///
+/// The number of slots is fixed at boot time (see `process::max_processes`),
+/// but the replicas (and their logs) for a pid are only created once the pid
+/// is handed out for the first time. After that they're reused by every
+/// process that gets the same pid.
+pub(crate) struct ProcessTable<P: Process> {
+    slots: Vec<Once<ProcessReplicas<P>>>,
+    /// All slots from this one on are unused (pids are handed out lowest
+    /// first, so the used ones are at the start of the table).
+    used: AtomicUsize,
+}
+
+impl<P: Process> ProcessTable<P> {
+    pub(crate) fn new(max_processes: usize) -> Result<Self, KError> {
+        let mut slots = Vec::try_with_capacity(max_processes)?;
+        slots.resize_with(max_processes, Once::new);
+        Ok(ProcessTable {
+            slots,
+            used: AtomicUsize::new(0),
+        })
+    }
+
+    /// The maximum number of processes.
+    pub(crate) fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// The pids that have replicas (and maybe some that are about to get
+    /// them).
+    pub(crate) fn used(&self) -> core::ops::Range<Pid> {
+        0..self.used.load(Ordering::Acquire)
+    }
+
+    /// Creates the replicas for `pid` unless they already exist.
+    pub(crate) fn create(&self, pid: Pid) -> Result<(), KError> {
+        let slot = self.slots.get(pid).ok_or(KError::TooManyProcesses)?;
+        slot.try_call_once(|| ProcessTable::create_replicas(pid))?;
+        self.used.fetch_max(pid + 1, Ordering::Release);
+        Ok(())
+    }
+
+    fn create_replicas(pid: Pid) -> Result<ProcessReplicas<P>, KError> {
+        // `Replica::with_data` takes the log (and hands out the replicas) in
+        // a regular `Arc`, so these stay on the global allocator
+        let mut log = Log::<<NrProcess<P> as Dispatch>::WriteOperation>::new(LARGE_PAGE_SIZE);
+        log.update_closure(|replicas, _idx| crate::nr::wake_lagging_replicas(replicas));
+        let log = Arc::try_new(log)?;
+        let da = DA::new()?;
+
+        // The state of every replica comes from the DA, on the node where
+        // the replica is used (the DA has a queue for every node, so we get
+        // at least one replica)
+        let pcm = per_core_mem();
+        let processes = da.try_box_per_node(|node| {
+            pcm.set_mem_affinity(node)?;
+            let process = P::new(pid, da.clone());
+            pcm.set_mem_affinity(*crate::environment::NODE_ID)?;
+            process
+        })?;
+
+        let mut replicas = ArrayVec::new();
+        for process in processes {
+            let nrp = NrProcess::new(process);
+            debug_assert!(!replicas.is_full(), "One process per node");
+            replicas.push(Replica::<NrProcess<P>>::with_data(&log, nrp));
         }
 
-        tokens
-    });
+        Ok(replicas)
+    }
+
+    /// Returns the replica of process `pid` on the current NUMA node and the
+    /// token of the current core to access it.
+    pub(crate) fn replica(
+        &self,
+        pid: Pid,
+    ) -> Result<(&Arc<Replica<'static, NrProcess<P>>>, ReplicaToken), KError> {
+        let node = *crate::environment::NODE_ID;
+        let replica = self
+            .slots
+            .get(pid)
+            .and_then(|slot| slot.get())
+            .and_then(|replicas| replicas.get(node))
+            .ok_or(KError::NoProcessFoundForPid)?;
+
+        let token = PROCESS_TOKEN
+            .get()
+            .and_then(|tokens| tokens.get(pid))
+            .ok_or(KError::ReplicaNotSet)?
+            .try_call_once(|| replica.register().ok_or(KError::ReplicaNotSet))?;
+
+        Ok((replica, *token))
+    }
 }
 
 /// A function we can "apply" on mutable slices of user-space memory.
@@ -63,7 +161,21 @@ type SliceExecFn<'buf> = Box<dyn Fn(&'buf [u8]) -> KResult<()>>;
 /// Immutable operations on the NrProcess.
 pub(crate) enum ProcessOp<'buf> {
     ProcessInfo,
+    Cmdline,
     MemResolve(VAddr),
+    /// Find the reserved region containing the address.
+    MemReservation(VAddr),
+    /// Find the mapping containing the address.
+    MemMapping(VAddr),
+    /// All writable, shared file mappings.
+    MemSharedFileMappings,
+    /// Find the first page in the region that is reserved but not backed
+    /// yet (or copy-on-write, if the region is going to be written).
+    MemUnbacked(VAddr, usize, bool),
+    /// The NUMA policy for anonymous memory.
+    MemPolicy,
+    /// The ring registered for file operations.
+    IoRing,
     ReadSlice(UserSlice),
     ReadString(UserSlice),
     WriteSlice(&'buf mut UserSlice, &'buf [u8]),
@@ -77,19 +189,56 @@ pub(crate) enum ProcessOp<'buf> {
 pub(crate) enum ProcessOpMut {
     Load(Pid, &'static Module, Vec<Frame>),
 
+    /// Set the arguments the process was spawned with.
+    SetCmdline(Cmdline),
+
+    /// Set how much memory of the given type the process may use.
+    SetMemLimit(MemType, u64),
+    /// Account memory the process is about to map (fails if it would exceed
+    /// the limit).
+    MemCharge(MemType, usize),
+    /// Take the limit for a new child (of at most the given size) from what
+    /// the process has left (returns the limit).
+    GrantMemLimit(MemType, Option<u64>),
+    /// A child that got a limit with `GrantMemLimit` exited.
+    ReturnMemLimit(MemType, u64),
+    /// Set the NUMA policy for anonymous memory.
+    SetMemPolicy(MemPolicy),
+    /// Set the (user-space) address of the ring for file operations.
+    SetIoRing(VAddr),
+
     /// Assign a core to a process.
     AssignExecutor(atopology::NodeId, atopology::GlobalThreadId),
+    /// The process no longer runs on the core.
+    ReleaseExecutor(atopology::GlobalThreadId),
 
     /// Assign a physical frame to a process (returns a FrameId).
     AllocateFrameToProcess(Frame),
+    /// Assign the frame of a shared memory object to a process (returns a FrameId).
+    AllocateSharedFrameToProcess(Frame, ShmId),
     /// Remove a physical frame previosuly allocated to the process (returns a Frame).
     ReleaseFrameFromProcess(FrameId),
     DispatcherAllocation(Frame),
 
-    MemMapFrame(VAddr, Frame, MapAction),
+    MemMapFrame(VAddr, Frame, MapAction, MappingType),
     MemMapDevice(Frame, MapAction),
     MemMapFrameId(VAddr, FrameId, MapAction),
     MemUnmap(VAddr),
+    /// Reserve a region that is backed with memory once it is accessed.
+    MemReserve(VAddr, Reservation),
+    /// Back the page of a reserved region with the frame.
+    MemPopulate(VAddr, Frame),
+    /// Change the rights of all mappings in the region.
+    MemProtect(VAddr, usize, MapAction),
+    /// Map the frames of a region a second time (copy-on-write) at another
+    /// address.
+    MemShareCow(VAddr, VAddr, usize),
+    /// Replace the frame of a copy-on-write mapping (if it still maps the
+    /// given address) with its copy.
+    MemCopyOnWrite(VAddr, PAddr, Frame),
+
+    /// Release all resources of the process (returns the shared frames).
+    Exit,
 }
 
 /// Possible return values from the NrProcess.
@@ -97,37 +246,46 @@ pub(crate) enum ProcessOpMut {
 pub(crate) enum ProcessResult<E: Executor> {
     Ok,
     SysRetOk((u64, u64)),
-    ProcessInfo(ProcessInfo),
+    ProcessInfo(ProcessInfo<'static>),
+    Cmdline(Option<Cmdline>),
     Executor(Box<E>),
     ExecutorsCreated(usize),
     MappedFrameId(PAddr, usize),
-    Unmapped(TlbFlushHandle),
+    /// The removed mapping and the frames it owned (if any).
+    Unmapped(TlbFlushHandle, Vec<(Frame, MemType)>),
+    Reservation(VAddr, Reservation),
+    Mappings(Vec<(VAddr, MappingInfo)>),
+    /// The first page to back and whether it has to be copied.
+    Unbacked(Option<(VAddr, bool)>),
+    MemLimit(u64),
+    Mapping(VAddr, MappingInfo),
+    MemPolicy(MemPolicy),
+    IoRing(Option<VAddr>),
+    /// The adjusted region (the TLB entries need to be flushed).
+    Protected(TlbFlushHandle),
+    /// The replaced mapping and the frame it used (if no other mapping refers
+    /// to it anymore).
+    Copied(TlbFlushHandle, Option<Frame>),
     Resolved(PAddr, MapAction),
     FrameId(usize),
-    Frame(Frame),
+    /// A released frame and the shared memory object it belongs to (if any).
+    Frame(Frame, Option<ShmId>),
     ReadSlice(Arc<[u8]>),
     ReadString(String),
+    Exited(Vec<(Frame, MemType)>),
 }
 
 /// Advances the replica of all the processes on the current NUMA node.
 pub(crate) fn advance_all() {
-    let node = *crate::environment::NODE_ID;
-
-    for pid in 0..MAX_PROCESSES {
-        PROCESS_TABLE[node][pid].sync(PROCESS_TOKEN.get().unwrap()[pid]);
+    for pid in PROCESS_TABLE.used() {
+        NrProcess::<ArchProcess>::synchronize(pid);
     }
 }
 
 pub(crate) trait ProcessManager {
     type Process: Process + Sync;
 
-    #[allow(clippy::type_complexity)] // fix this once `associated_type_defaults` works
-    fn process_table(
-        &self,
-    ) -> &'static ArrayVec<
-        ArrayVec<Arc<Replica<'static, NrProcess<Self::Process>>>, MAX_PROCESSES>,
-        MAX_NUMA_NODES,
-    >;
+    fn process_table(&self) -> &'static ProcessTable<Self::Process>;
 }
 
 /// A node-replicated process.
@@ -135,32 +293,66 @@ pub(crate) struct NrProcess<P: Process, M: Allocator + Clone = alloc::alloc::Glo
     /// A list of all cores where the current process is running.
     active_cores: Vec<(atopology::GlobalThreadId, Eid), M>,
     /// The process struct itself.
-    process: Box<P>,
+    process: Box<P, DA>,
+    /// DRAM used by the process.
+    mem_usage: MemUsage,
+    /// PMem used by the process.
+    pmem_usage: MemUsage,
+    /// Where anonymous memory of the process is allocated from.
+    mem_policy: MemPolicy,
+    /// The ring for file operations (a page in user-space).
+    io_ring: Option<VAddr>,
 }
 
 impl<P: Process> NrProcess<P> {
-    pub(crate) fn new(process: Box<P>, _da: DA) -> NrProcess<P> {
+    pub(crate) fn new(process: Box<P, DA>) -> NrProcess<P> {
         NrProcess {
             active_cores: Vec::new(),
             process,
+            mem_usage: Default::default(),
+            pmem_usage: Default::default(),
+            mem_policy: Default::default(),
+            io_ring: None,
         }
     }
 }
 
+impl<P: Process, M: Allocator + Clone> NrProcess<P, M> {
+    fn usage_mut(&mut self, mem_type: MemType) -> &mut MemUsage {
+        match mem_type {
+            MemType::Mem => &mut self.mem_usage,
+            MemType::PMem => &mut self.pmem_usage,
+        }
+    }
+
+    /// Accounts `mapped` and `allocated` bytes of `mem_type` to the process.
+    fn add_usage(&mut self, mem_type: MemType, mapped: usize, allocated: usize) -> KResult<()> {
+        let usage = self.usage_mut(mem_type);
+        if (mapped + allocated) as u64 > usage.available() {
+            return Err(KError::MemoryLimitExceeded);
+        }
+        usage.mapped += mapped as u64;
+        usage.allocated += allocated as u64;
+        Ok(())
+    }
+
+    /// Gives back memory accounted with `add_usage`.
+    fn remove_usage(&mut self, mem_type: MemType, mapped: usize, allocated: usize) {
+        let usage = self.usage_mut(mem_type);
+        usage.mapped = usage.mapped.saturating_sub(mapped as u64);
+        usage.allocated = usage.allocated.saturating_sub(allocated as u64);
+    }
+}
+
 impl<P: Process> NrProcess<P> {
     pub(crate) fn load(
         pid: Pid,
         module: &'static Module,
         writeable_sections: Vec<Frame>,
     ) -> Result<(), KError> {
-        debug_assert!(pid < MAX_PROCESSES, "Invalid PID");
-
-        let node = *crate::environment::NODE_ID;
-
-        let response = PROCESS_TABLE[node][pid].execute_mut(
-            ProcessOpMut::Load(pid, module, writeable_sections),
-            PROCESS_TOKEN.get().unwrap()[pid],
-        );
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response =
+            replica.execute_mut(ProcessOpMut::Load(pid, module, writeable_sections), token);
         match response {
             Ok(ProcessResult::Ok) => Ok(()),
             Err(e) => Err(e),
@@ -168,29 +360,23 @@ impl<P: Process> NrProcess<P> {
         }
     }
 
-    pub(crate) fn resolve(pid: Pid, base: VAddr) -> Result<(u64, u64), KError> {
-        debug_assert!(pid < MAX_PROCESSES, "Invalid PID");
+    pub(crate) fn resolve(pid: Pid, base: VAddr) -> Result<(PAddr, MapAction), KError> {
         debug_assert!(base.as_u64() < kpi::KERNEL_BASE, "Invalid base");
 
-        let node = *crate::environment::NODE_ID;
-
-        let response = PROCESS_TABLE[node][pid].execute(
-            ProcessOp::MemResolve(base),
-            PROCESS_TOKEN.get().unwrap()[pid],
-        );
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute(ProcessOp::MemResolve(base), token);
         match response {
-            Ok(ProcessResult::Resolved(paddr, _rights)) => Ok((paddr.as_u64(), 0x0)),
+            Ok(ProcessResult::Resolved(paddr, rights)) => Ok((paddr, rights)),
             Err(e) => Err(e),
             _ => unreachable!("Got unexpected response"),
         }
     }
 
+    /// Advances the replica of `pid` (if the process exists).
     pub(crate) fn synchronize(pid: Pid) {
-        debug_assert!(pid < MAX_PROCESSES, "Invalid PID");
-
-        let node = *crate::environment::NODE_ID;
-
-        PROCESS_TABLE[node][pid].sync(PROCESS_TOKEN.get().unwrap()[pid]);
+        if let Ok((replica, token)) = PROCESS_TABLE.replica(pid) {
+            replica.sync(token);
+        }
     }
 
     pub(crate) fn map_device_frame(
@@ -198,32 +384,153 @@ impl<P: Process> NrProcess<P> {
         frame: Frame,
         action: MapAction,
     ) -> Result<(u64, u64), KError> {
-        debug_assert!(pid < MAX_PROCESSES, "Invalid PID");
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::MemMapDevice(frame, action), token);
+        match response {
+            Ok(ProcessResult::Ok) => Ok((frame.base.as_u64(), frame.size() as u64)),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
 
-        let node = *crate::environment::NODE_ID;
+    /// Removes the mapping (or reserved region) that contains `base`.
+    ///
+    /// Returns the frames that backed a reserved region (and their memory
+    /// type), the caller has to release them once the TLB entries are flushed.
+    pub(crate) fn unmap(
+        pid: Pid,
+        base: VAddr,
+    ) -> Result<(TlbFlushHandle, Vec<(Frame, MemType)>), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::MemUnmap(base), token);
+        match response {
+            Ok(ProcessResult::Unmapped(handle, frames)) => Ok((handle, frames)),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
 
-        let response = PROCESS_TABLE[node][pid].execute_mut(
-            ProcessOpMut::MemMapDevice(frame, action),
-            PROCESS_TOKEN.get().unwrap()[pid],
-        );
+    pub(crate) fn reserve(pid: Pid, base: VAddr, reservation: Reservation) -> Result<(), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::MemReserve(base, reservation), token);
         match response {
-            Ok(ProcessResult::Ok) => Ok((frame.base.as_u64(), frame.size() as u64)),
+            Ok(ProcessResult::Ok) => Ok(()),
             Err(e) => Err(e),
             _ => unreachable!("Got unexpected response"),
         }
     }
 
-    pub(crate) fn unmap(pid: Pid, base: VAddr) -> Result<TlbFlushHandle, KError> {
-        debug_assert!(pid < MAX_PROCESSES, "Invalid PID");
+    /// Returns the reserved region that contains `vaddr`.
+    pub(crate) fn reservation(pid: Pid, vaddr: VAddr) -> Result<(VAddr, Reservation), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute(ProcessOp::MemReservation(vaddr), token);
+        match response {
+            Ok(ProcessResult::Reservation(base, reservation)) => Ok((base, reservation)),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
 
-        let node = *crate::environment::NODE_ID;
+    /// Returns the first page in `base..base+len` that is reserved but not
+    /// backed yet, or copy-on-write if `writeable` is set (the flag tells
+    /// which of the two it is).
+    pub(crate) fn unbacked(
+        pid: Pid,
+        base: VAddr,
+        len: usize,
+        writeable: bool,
+    ) -> Result<Option<(VAddr, bool)>, KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute(ProcessOp::MemUnbacked(base, len, writeable), token);
+        match response {
+            Ok(ProcessResult::Unbacked(page)) => Ok(page),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
 
-        let response = PROCESS_TABLE[node][pid].execute_mut(
-            ProcessOpMut::MemUnmap(base),
-            PROCESS_TOKEN.get().unwrap()[pid],
-        );
+    /// Changes the rights of the mapped region `base..base+len` to `rights`.
+    ///
+    /// Fails without changing anything if part of the region is not mapped.
+    pub(crate) fn protect(
+        pid: Pid,
+        base: VAddr,
+        len: usize,
+        rights: MapAction,
+    ) -> Result<TlbFlushHandle, KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::MemProtect(base, len, rights), token);
         match response {
-            Ok(ProcessResult::Unmapped(handle)) => Ok(handle),
+            Ok(ProcessResult::Protected(handle)) => Ok(handle),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    /// Returns the mapping that contains `vaddr`.
+    pub(crate) fn mapping(pid: Pid, vaddr: VAddr) -> Result<(VAddr, MappingInfo), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute(ProcessOp::MemMapping(vaddr), token);
+        match response {
+            Ok(ProcessResult::Mapping(base, mapping)) => Ok((base, mapping)),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    /// Returns the writable, shared file mappings of the process.
+    pub(crate) fn shared_file_mappings(pid: Pid) -> Result<Vec<(VAddr, MappingInfo)>, KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute(ProcessOp::MemSharedFileMappings, token);
+        match response {
+            Ok(ProcessResult::Mappings(mappings)) => Ok(mappings),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    /// Maps the memory of `src..src+len` at `dst` as well, both copies are
+    /// copy-on-write.
+    pub(crate) fn share_cow(
+        pid: Pid,
+        src: VAddr,
+        dst: VAddr,
+        len: usize,
+    ) -> Result<TlbFlushHandle, KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::MemShareCow(src, dst, len), token);
+        match response {
+            Ok(ProcessResult::Protected(handle)) => Ok(handle),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    /// Replaces the frame at `old` of the copy-on-write mapping starting at
+    /// `base` with `frame`.
+    ///
+    /// Returns `AlreadyMapped` if the mapping was copied already.
+    pub(crate) fn copy_on_write(
+        pid: Pid,
+        base: VAddr,
+        old: PAddr,
+        frame: Frame,
+    ) -> Result<(TlbFlushHandle, Option<Frame>), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::MemCopyOnWrite(base, old, frame), token);
+        match response {
+            Ok(ProcessResult::Copied(handle, released)) => Ok((handle, released)),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    /// Backs the page containing `vaddr` in a reserved region with `frame`.
+    pub(crate) fn populate(pid: Pid, vaddr: VAddr, frame: Frame) -> Result<(), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::MemPopulate(vaddr, frame), token);
+        match response {
+            Ok(ProcessResult::Ok) => Ok(()),
             Err(e) => Err(e),
             _ => unreachable!("Got unexpected response"),
         }
@@ -235,15 +542,11 @@ impl<P: Process> NrProcess<P> {
         base: VAddr,
         action: MapAction,
     ) -> Result<(PAddr, usize), KError> {
-        debug_assert!(pid < MAX_PROCESSES, "Invalid PID");
-
-        let node = *crate::environment::NODE_ID;
         //action.multiple_mappings(true);
 
-        let response = PROCESS_TABLE[node][pid].execute_mut(
-            ProcessOpMut::MemMapFrameId(base, frame_id, action),
-            PROCESS_TOKEN.get().unwrap()[pid],
-        );
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response =
+            replica.execute_mut(ProcessOpMut::MemMapFrameId(base, frame_id, action), token);
         match response {
             Ok(ProcessResult::MappedFrameId(paddr, size)) => Ok((paddr, size)),
             Err(e) => Err(e),
@@ -251,21 +554,39 @@ impl<P: Process> NrProcess<P> {
         }
     }
 
+    /// Maps `frame` at `base`.
+    ///
+    /// Unlike `map_frames` this returns an error if `base` is mapped already.
+    pub(crate) fn map_frame(
+        pid: Pid,
+        base: VAddr,
+        frame: Frame,
+        action: MapAction,
+        typ: MappingType,
+    ) -> Result<(), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response =
+            replica.execute_mut(ProcessOpMut::MemMapFrame(base, frame, action, typ), token);
+        match response {
+            Ok(ProcessResult::Ok) => Ok(()),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
     pub(crate) fn map_frames(
         pid: Pid,
         base: VAddr,
         frames: Vec<Frame>,
         action: MapAction,
+        typ: MappingType,
     ) -> Result<(u64, u64), KError> {
-        debug_assert!(pid < MAX_PROCESSES, "Invalid PID");
-
-        let node = *crate::environment::NODE_ID;
-
         let mut virtual_offset = 0;
         for frame in frames {
-            let response = PROCESS_TABLE[node][pid].execute_mut(
-                ProcessOpMut::MemMapFrame(base + virtual_offset, frame, action),
-                PROCESS_TOKEN.get().unwrap()[pid],
+            let (replica, token) = PROCESS_TABLE.replica(pid)?;
+            let response = replica.execute_mut(
+                ProcessOpMut::MemMapFrame(base + virtual_offset, frame, action, typ),
+                token,
             );
             match response {
                 Ok(ProcessResult::Ok) => {}
@@ -284,13 +605,23 @@ impl<P: Process> NrProcess<P> {
         Ok((base.as_u64(), virtual_offset as u64))
     }
 
-    pub(crate) fn pinfo(pid: Pid) -> Result<ProcessInfo, KError> {
-        debug_assert!(pid < MAX_PROCESSES, "Invalid PID");
-
-        let node = *crate::environment::NODE_ID;
+    /// Tears down the process.
+    ///
+    /// Returns the frames that were shared among all replicas of the process
+    /// so the caller can release them.
+    pub(crate) fn exit(pid: Pid) -> Result<Vec<(Frame, MemType)>, KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::Exit, token);
+        match response {
+            Ok(ProcessResult::Exited(frames)) => Ok(frames),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
 
-        let response = PROCESS_TABLE[node][pid]
-            .execute(ProcessOp::ProcessInfo, PROCESS_TOKEN.get().unwrap()[pid]);
+    pub(crate) fn pinfo(pid: Pid) -> Result<ProcessInfo<'static>, KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute(ProcessOp::ProcessInfo, token);
         match response {
             Ok(ProcessResult::ProcessInfo(pinfo)) => Ok(pinfo),
             Err(e) => Err(e),
@@ -298,6 +629,128 @@ impl<P: Process> NrProcess<P> {
         }
     }
 
+    /// Sets how many bytes of `mem_type` the process may use.
+    pub(crate) fn set_mem_limit(pid: Pid, mem_type: MemType, limit: u64) -> Result<(), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::SetMemLimit(mem_type, limit), token);
+        match response {
+            Ok(ProcessResult::Ok) => Ok(()),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    /// Takes the `mem_type` limit for a new child of `pid` from the memory
+    /// `pid` has left. The child gets at most `requested` bytes (everything
+    /// that's left if `None`).
+    ///
+    /// Processes without a limit hand out what was requested (no limit if
+    /// `None`) and aren't charged for it.
+    pub(crate) fn grant_mem_limit(
+        pid: Pid,
+        mem_type: MemType,
+        requested: Option<u64>,
+    ) -> Result<u64, KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::GrantMemLimit(mem_type, requested), token);
+        match response {
+            Ok(ProcessResult::MemLimit(limit)) => Ok(limit),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    /// Gives the `limit` of an exited child (see `grant_mem_limit`) back to
+    /// `pid`.
+    pub(crate) fn return_mem_limit(pid: Pid, mem_type: MemType, limit: u64) -> Result<(), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::ReturnMemLimit(mem_type, limit), token);
+        match response {
+            Ok(ProcessResult::Ok) => Ok(()),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    /// Accounts `size` bytes of `mem_type` the process is about to map.
+    ///
+    /// Returns `MemoryLimitExceeded` if the process can't use that much
+    /// memory.
+    pub(crate) fn charge(pid: Pid, mem_type: MemType, size: usize) -> Result<(), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::MemCharge(mem_type, size), token);
+        match response {
+            Ok(ProcessResult::Ok) => Ok(()),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    /// Returns the NUMA policy for anonymous memory of the process.
+    pub(crate) fn mem_policy(pid: Pid) -> Result<MemPolicy, KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute(ProcessOp::MemPolicy, token);
+        match response {
+            Ok(ProcessResult::MemPolicy(policy)) => Ok(policy),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    /// Sets the NUMA policy for anonymous memory of the process.
+    pub(crate) fn set_mem_policy(pid: Pid, policy: MemPolicy) -> Result<(), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::SetMemPolicy(policy), token);
+        match response {
+            Ok(ProcessResult::Ok) => Ok(()),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    /// The address of the ring the process registered for file operations.
+    pub(crate) fn io_ring(pid: Pid) -> Result<Option<VAddr>, KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute(ProcessOp::IoRing, token);
+        match response {
+            Ok(ProcessResult::IoRing(ring)) => Ok(ring),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    /// Registers the ring for file operations of the process (replaces the
+    /// previous one).
+    pub(crate) fn set_io_ring(pid: Pid, ring: VAddr) -> Result<(), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::SetIoRing(ring), token);
+        match response {
+            Ok(ProcessResult::Ok) => Ok(()),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    pub(crate) fn cmdline(pid: Pid) -> Result<Option<Cmdline>, KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute(ProcessOp::Cmdline, token);
+        match response {
+            Ok(ProcessResult::Cmdline(cmdline)) => Ok(cmdline),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
+    pub(crate) fn set_cmdline(pid: Pid, cmdline: Cmdline) -> Result<(), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::SetCmdline(cmdline), token);
+        match response {
+            Ok(ProcessResult::Ok) => Ok(()),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
+
     fn try_assign_executor<A>(pm: &A, pid: Pid) -> Result<Box<P::E>, KError>
     where
         A: ProcessManager<Process = P>,
@@ -306,10 +759,8 @@ impl<P: Process> NrProcess<P> {
         let gtid = *crate::environment::CORE_ID;
         let node = *crate::environment::NODE_ID;
 
-        let response = pm.process_table()[node][pid].execute_mut(
-            ProcessOpMut::AssignExecutor(gtid, node),
-            PROCESS_TOKEN.get().unwrap()[pid],
-        );
+        let (replica, token) = pm.process_table().replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::AssignExecutor(gtid, node), token);
         match response {
             Ok(ProcessResult::Executor(executor)) => Ok(executor),
             Err(e) => Err(e),
@@ -322,11 +773,10 @@ impl<P: Process> NrProcess<P> {
         A: ProcessManager<Process = P>,
         P: Process + core::marker::Sync + 'static,
     {
-        debug_assert!(pid < MAX_PROCESSES, "Invalid PID");
-
         let response = NrProcess::try_assign_executor(pm, pid);
-        // If we didn't have dispatcher memory allocated, allocate and try again
-        if let Err(KError::NoExecutorAllocated) = response {
+        // If we didn't have (enough) dispatcher memory allocated, allocate and
+        // try again (executors of released cores aren't reused)
+        if let Err(KError::NoExecutorAllocated | KError::ExecutorCacheExhausted) = response {
             let node = *crate::environment::NODE_ID;
             super::process::allocate_dispatchers::<P>(pid, node)?;
             NrProcess::try_assign_executor(pm, pid)
@@ -336,13 +786,24 @@ impl<P: Process> NrProcess<P> {
     }
 
     pub(crate) fn allocate_frame_to_process(pid: Pid, frame: Frame) -> Result<FrameId, KError> {
-        debug_assert!(pid < MAX_PROCESSES, "Invalid PID");
-
-        let node = *crate::environment::NODE_ID;
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::AllocateFrameToProcess(frame), token);
+        match response {
+            Ok(ProcessResult::FrameId(fid)) => Ok(fid),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
 
-        let response = PROCESS_TABLE[node][pid].execute_mut(
-            ProcessOpMut::AllocateFrameToProcess(frame),
-            PROCESS_TOKEN.get().unwrap()[pid],
+    pub(crate) fn allocate_shared_frame_to_process(
+        pid: Pid,
+        frame: Frame,
+        shm: ShmId,
+    ) -> Result<FrameId, KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(
+            ProcessOpMut::AllocateSharedFrameToProcess(frame, shm),
+            token,
         );
         match response {
             Ok(ProcessResult::FrameId(fid)) => Ok(fid),
@@ -351,32 +812,43 @@ impl<P: Process> NrProcess<P> {
         }
     }
 
-    pub(crate) fn release_frame_from_process(pid: Pid, fid: FrameId) -> Result<Frame, KError> {
-        debug_assert!(pid < MAX_PROCESSES, "Invalid PID");
+    /// Removes the frame `fid` from the process.
+    ///
+    /// Returns the frame and, if the frame belongs to a shared memory object,
+    /// the object the process still holds a reference to.
+    pub(crate) fn release_frame_from_process(
+        pid: Pid,
+        fid: FrameId,
+    ) -> Result<(Frame, Option<ShmId>), KError> {
         debug_assert!(fid < MAX_FRAMES_PER_PROCESS, "Invalid FID");
 
-        let node = *crate::environment::NODE_ID;
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::ReleaseFrameFromProcess(fid), token);
+        match response {
+            Ok(ProcessResult::Frame(f, shm)) => Ok((f, shm)),
+            Err(e) => Err(e),
+            _ => unreachable!("Got unexpected response"),
+        }
+    }
 
-        let response = PROCESS_TABLE[node][pid].execute_mut(
-            ProcessOpMut::ReleaseFrameFromProcess(fid),
-            PROCESS_TOKEN.get().unwrap()[pid],
-        );
+    /// Stops tracking core `gtid` as one the process runs on (it no longer
+    /// needs TLB shootdowns for the process).
+    pub(crate) fn release_executor(
+        pid: Pid,
+        gtid: atopology::GlobalThreadId,
+    ) -> Result<(), KError> {
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::ReleaseExecutor(gtid), token);
         match response {
-            Ok(ProcessResult::Frame(f)) => Ok(f),
+            Ok(ProcessResult::Ok) => Ok(()),
             Err(e) => Err(e),
             _ => unreachable!("Got unexpected response"),
         }
     }
 
     pub(crate) fn allocate_dispatchers(pid: Pid, frame: Frame) -> Result<usize, KError> {
-        debug_assert!(pid < MAX_PROCESSES, "Invalid PID");
-
-        let node = *crate::environment::NODE_ID;
-
-        let response = PROCESS_TABLE[node][pid].execute_mut(
-            ProcessOpMut::DispatcherAllocation(frame),
-            PROCESS_TOKEN.get().unwrap()[pid],
-        );
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute_mut(ProcessOpMut::DispatcherAllocation(frame), token);
 
         match response {
             Ok(ProcessResult::ExecutorsCreated(how_many)) => Ok(how_many),
@@ -386,12 +858,9 @@ impl<P: Process> NrProcess<P> {
     }
 
     pub(crate) fn userslice_to_arc_slice(from: UserSlice) -> Result<Arc<[u8]>, KError> {
-        let node = *crate::environment::NODE_ID;
-
-        let response = PROCESS_TABLE[node][from.pid].execute(
-            ProcessOp::ReadSlice(from),
-            PROCESS_TOKEN.get().unwrap()[from.pid],
-        );
+        from.populate(false)?;
+        let (replica, token) = PROCESS_TABLE.replica(from.pid)?;
+        let response = replica.execute(ProcessOp::ReadSlice(from), token);
         match response {
             Ok(ProcessResult::ReadSlice(v)) => Ok(v),
             Err(e) => Err(e),
@@ -400,12 +869,9 @@ impl<P: Process> NrProcess<P> {
     }
 
     pub(crate) fn read_string_from_userspace(from: UserSlice) -> Result<String, KError> {
-        let node = *crate::environment::NODE_ID;
-
-        let response = PROCESS_TABLE[node][from.pid].execute(
-            ProcessOp::ReadString(from),
-            PROCESS_TOKEN.get().unwrap()[from.pid],
-        );
+        from.populate(false)?;
+        let (replica, token) = PROCESS_TABLE.replica(from.pid)?;
+        let response = replica.execute(ProcessOp::ReadString(from), token);
         match response {
             Ok(ProcessResult::ReadString(s)) => Ok(s),
             Err(e) => Err(e),
@@ -414,13 +880,11 @@ impl<P: Process> NrProcess<P> {
     }
 
     pub(crate) fn write_to_userspace(to: &mut UserSlice, kbuf: &[u8]) -> Result<(), KError> {
-        let node = *crate::environment::NODE_ID;
         let pid = to.pid;
+        to.populate(true)?;
 
-        let response = PROCESS_TABLE[node][pid].execute(
-            ProcessOp::WriteSlice(to, kbuf),
-            PROCESS_TOKEN.get().unwrap()[pid],
-        );
+        let (replica, token) = PROCESS_TABLE.replica(pid)?;
+        let response = replica.execute(ProcessOp::WriteSlice(to, kbuf), token);
         match response {
             Ok(ProcessResult::Ok) => Ok(()),
             Err(e) => Err(e),
@@ -433,12 +897,9 @@ impl<P: Process> NrProcess<P> {
         on: UserSlice,
         f: Box<dyn Fn(&mut [u8]) -> KResult<(u64, u64)>>,
     ) -> Result<(u64, u64), KError> {
-        let node = *crate::environment::NODE_ID;
-
-        let response = PROCESS_TABLE[node][on.pid].execute(
-            ProcessOp::ExecSliceMut(on, f),
-            PROCESS_TOKEN.get().unwrap()[on.pid],
-        );
+        on.populate(true)?;
+        let (replica, token) = PROCESS_TABLE.replica(on.pid)?;
+        let response = replica.execute(ProcessOp::ExecSliceMut(on, f), token);
         match response {
             Ok(ProcessResult::SysRetOk((a, b))) => Ok((a, b)),
             Err(e) => Err(e),
@@ -450,12 +911,9 @@ impl<P: Process> NrProcess<P> {
         on: &'a UserSlice,
         f: Box<dyn Fn(&'a [u8]) -> KResult<()>>,
     ) -> Result<(), KError> {
-        let node = *crate::environment::NODE_ID;
-
-        let response = PROCESS_TABLE[node][on.pid].execute(
-            ProcessOp::ExecSlice(on, f),
-            PROCESS_TOKEN.get().unwrap()[on.pid],
-        );
+        on.populate(false)?;
+        let (replica, token) = PROCESS_TABLE.replica(on.pid)?;
+        let response = replica.execute(ProcessOp::ExecSlice(on, f), token);
         match response {
             Ok(ProcessResult::Ok) => Ok(()),
             Err(e) => Err(e),
@@ -476,11 +934,60 @@ where
 
     fn dispatch<'buf>(&self, op: Self::ReadOperation<'_>) -> Self::Response {
         match op {
-            ProcessOp::ProcessInfo => Ok(ProcessResult::ProcessInfo(*self.process.pinfo())),
+            ProcessOp::ProcessInfo => {
+                let mut pinfo = *self.process.pinfo();
+                pinfo.mem_usage = self.mem_usage;
+                pinfo.pmem_usage = self.pmem_usage;
+                Ok(ProcessResult::ProcessInfo(pinfo))
+            }
+            ProcessOp::Cmdline => Ok(ProcessResult::Cmdline(self.process.cmdline().copied())),
             ProcessOp::MemResolve(base) => {
                 let (paddr, rights) = self.process.vspace().resolve(base)?;
                 Ok(ProcessResult::Resolved(paddr, rights))
             }
+            ProcessOp::MemMapping(vaddr) => {
+                let (base, mapping) = self
+                    .process
+                    .vspace()
+                    .mapping(vaddr)
+                    .ok_or(KError::NotMapped)?;
+                Ok(ProcessResult::Mapping(base, mapping))
+            }
+            ProcessOp::MemSharedFileMappings => {
+                let vspace = self.process.vspace();
+                let mut mappings = Vec::new();
+                let mut next = vspace.next_mapping(VAddr::zero());
+                while let Some((base, mapping)) = next {
+                    if let MappingType::File { writable: true, .. } = mapping.typ {
+                        mappings.try_push((base, mapping))?;
+                    }
+                    next = vspace.next_mapping(base + mapping.frame.size);
+                }
+                Ok(ProcessResult::Mappings(mappings))
+            }
+            ProcessOp::MemPolicy => Ok(ProcessResult::MemPolicy(self.mem_policy)),
+            ProcessOp::IoRing => Ok(ProcessResult::IoRing(self.io_ring)),
+            ProcessOp::MemReservation(vaddr) => {
+                let (base, reservation) = self
+                    .process
+                    .vspace()
+                    .reservation(vaddr)
+                    .ok_or(KError::NotMapped)?;
+                Ok(ProcessResult::Reservation(base, reservation))
+            }
+            ProcessOp::MemUnbacked(base, len, writeable) => {
+                let vspace = self.process.vspace();
+                let start = base.as_usize() & !(BASE_PAGE_SIZE - 1);
+                let unbacked = (start..base.as_usize() + len)
+                    .step_by(BASE_PAGE_SIZE)
+                    .map(VAddr::from)
+                    .find_map(|page| match vspace.resolve(page) {
+                        Ok((_paddr, rights)) if writeable && rights.is_cow() => Some((page, true)),
+                        Ok(_) => None,
+                        Err(_) => vspace.reservation(page).map(|_| (page, false)),
+                    });
+                Ok(ProcessResult::Unbacked(unbacked))
+            }
             ProcessOp::ReadSlice(uslice) => {
                 // We're going to copy what we read into this thing
                 // TODO(panic+oom): need `try_new_uninit_slice` https://github.com/rust-lang/rust/issues/63291
@@ -534,6 +1041,54 @@ where
         match op {
             ProcessOpMut::Load(pid, module, writeable_sections) => {
                 self.process.load(pid, module, writeable_sections)?;
+                // A previous process with the same pid might have used memory
+                self.mem_usage = Default::default();
+                self.pmem_usage = Default::default();
+                self.mem_policy = Default::default();
+                self.io_ring = None;
+                Ok(ProcessResult::Ok)
+            }
+
+            ProcessOpMut::SetCmdline(cmdline) => {
+                self.process.set_cmdline(cmdline);
+                Ok(ProcessResult::Ok)
+            }
+
+            ProcessOpMut::SetMemLimit(mem_type, limit) => {
+                self.usage_mut(mem_type).limit = limit;
+                Ok(ProcessResult::Ok)
+            }
+
+            ProcessOpMut::MemCharge(mem_type, size) => {
+                self.add_usage(mem_type, size, 0)?;
+                Ok(ProcessResult::Ok)
+            }
+
+            ProcessOpMut::GrantMemLimit(mem_type, requested) => {
+                let usage = self.usage_mut(mem_type);
+                if usage.limit == u64::MAX {
+                    return Ok(ProcessResult::MemLimit(requested.unwrap_or(u64::MAX)));
+                }
+                let limit = requested.map_or(usage.available(), |requested| {
+                    requested.min(usage.available())
+                });
+                usage.children += limit;
+                Ok(ProcessResult::MemLimit(limit))
+            }
+
+            ProcessOpMut::ReturnMemLimit(mem_type, limit) => {
+                let usage = self.usage_mut(mem_type);
+                usage.children = usage.children.saturating_sub(limit);
+                Ok(ProcessResult::Ok)
+            }
+
+            ProcessOpMut::SetMemPolicy(policy) => {
+                self.mem_policy = policy;
+                Ok(ProcessResult::Ok)
+            }
+
+            ProcessOpMut::SetIoRing(ring) => {
+                self.io_ring = Some(ring);
                 Ok(ProcessResult::Ok)
             }
 
@@ -542,16 +1097,20 @@ where
                 Ok(ProcessResult::ExecutorsCreated(how_many))
             }
 
-            ProcessOpMut::MemMapFrame(base, frame, action) => {
+            ProcessOpMut::MemMapFrame(base, frame, action, typ) => {
                 crate::memory::KernelAllocator::try_refill_tcache(7, 0, MemType::Mem)?;
-                self.process.vspace_mut().map_frame(base, frame, action)?;
+                self.process
+                    .vspace_mut()
+                    .map_frame_as(base, frame, action, typ)?;
                 Ok(ProcessResult::Ok)
             }
 
             // Can be MapFrame with base supplied ...
             ProcessOpMut::MemMapDevice(frame, action) => {
                 let base = VAddr::from(frame.base.as_u64());
-                self.process.vspace_mut().map_frame(base, frame, action)?;
+                self.process
+                    .vspace_mut()
+                    .map_frame_as(base, frame, action, MappingType::Device)?;
                 Ok(ProcessResult::Ok)
             }
 
@@ -564,12 +1123,51 @@ where
             }
 
             ProcessOpMut::MemUnmap(vaddr) => {
-                let mut shootdown_handle = self.process.vspace_mut().unmap(vaddr)?;
+                // The memory type the process was charged for (if any)
+                let mem_type = match self.process.vspace().reservation(vaddr) {
+                    Some((_base, reservation)) => Some(reservation.mem_type),
+                    None => match self.process.vspace().mapping(vaddr) {
+                        Some((_base, mapping)) => match mapping.typ {
+                            MappingType::Heap(mem_type) => Some(mem_type),
+                            _ => None,
+                        },
+                        None => None,
+                    },
+                };
+
+                let (mut shootdown_handle, mut frames, reserved) =
+                    match self.process.vspace_mut().unreserve(vaddr) {
+                        Err(KError::NotMapped) => {
+                            (self.process.vspace_mut().unmap(vaddr)?, Vec::new(), false)
+                        }
+                        r => {
+                            let (handle, frames) = r?;
+                            (handle, frames, true)
+                        }
+                    };
+                // The backed pages of a reserved region
+                let mut unmapped: usize = frames.iter().map(|frame| frame.size).sum();
                 if shootdown_handle.flags.is_aliasable() {
+                    // Accounted as allocated frame
                     self.process
                         .remove_frame_mapping(shootdown_handle.paddr, shootdown_handle.vaddr)
                         .expect("is_aliasable implies this op can't fail");
+                } else if shootdown_handle.flags.is_cow() {
+                    if let Some(frame) = self.process.remove_cow_mapping(shootdown_handle.paddr)? {
+                        unmapped += frame.size;
+                        frames.try_push(frame)?;
+                    }
+                } else if !reserved {
+                    unmapped += shootdown_handle.size;
                 }
+                if let Some(mem_type) = mem_type {
+                    self.remove_usage(mem_type, unmapped, 0);
+                }
+                // Owned frames come from reserved regions or heap mappings,
+                // which both know their memory type
+                let mem_type = mem_type.unwrap_or(MemType::Mem);
+                let mut owned = Vec::try_with_capacity(frames.len())?;
+                owned.extend(frames.into_iter().map(|frame| (frame, mem_type)));
 
                 // Figure out which cores are running our current process
                 // (this is where we send IPIs later)
@@ -577,7 +1175,138 @@ where
                     shootdown_handle.add_core(*gtid);
                 }
 
-                Ok(ProcessResult::Unmapped(shootdown_handle))
+                Ok(ProcessResult::Unmapped(shootdown_handle, owned))
+            }
+
+            ProcessOpMut::MemReserve(base, reservation) => {
+                self.process.vspace_mut().reserve(base, reservation)?;
+                Ok(ProcessResult::Ok)
+            }
+
+            ProcessOpMut::MemProtect(base, len, rights) => {
+                let end = base + len;
+
+                // Check first so we don't leave the region half-adjusted
+                for vaddr in (base.as_usize()..end.as_usize()).step_by(BASE_PAGE_SIZE) {
+                    let (_paddr, old_rights) = self.process.vspace().resolve(VAddr::from(vaddr))?;
+                    // A shared frame can't lose its copy-on-write semantics
+                    if old_rights.is_cow() && !rights.is_writable() {
+                        return Err(KError::NotSupported);
+                    }
+                    // Writes to a read-only file mapping would never reach the file
+                    if let Some((_base, mapping)) =
+                        self.process.vspace().mapping(VAddr::from(vaddr))
+                    {
+                        if matches!(
+                            mapping.typ,
+                            MappingType::File {
+                                writable: false,
+                                ..
+                            }
+                        ) && rights.is_writable()
+                        {
+                            return Err(KError::PermissionError);
+                        }
+                    }
+                }
+
+                let (mut start, mut stop) = (base, end);
+                let mut vaddr = base;
+                while vaddr < end {
+                    // Unmapping relies on the aliased flag, it has to stay
+                    let (_paddr, old_rights) = self.process.vspace().resolve(vaddr)?;
+                    let new_rights = if old_rights.is_aliasable() {
+                        rights | MapAction::aliased()
+                    } else if old_rights.is_cow() {
+                        rights.copy_on_write()
+                    } else {
+                        rights
+                    };
+
+                    let (region, size) = self
+                        .process
+                        .vspace_mut()
+                        .adjust(vaddr.align_down_to_base_page(), new_rights)?;
+                    start = core::cmp::min(start, region);
+                    stop = core::cmp::max(stop, region + size);
+                    vaddr = region + size;
+                }
+
+                let mut shootdown_handle =
+                    TlbFlushHandle::new(start, PAddr::zero(), (stop - start).as_usize(), rights);
+                for (gtid, _eid) in self.active_cores.iter() {
+                    shootdown_handle.add_core(*gtid);
+                }
+
+                Ok(ProcessResult::Protected(shootdown_handle))
+            }
+
+            ProcessOpMut::MemShareCow(src, dst, len) => {
+                crate::memory::KernelAllocator::try_refill_tcache(14, 0, MemType::Mem)?;
+                let (mut shootdown_handle, frames) =
+                    self.process.vspace_mut().share_cow(src, dst, len)?;
+                for frame in frames {
+                    self.process.add_cow_mapping(frame)?;
+                }
+
+                for (gtid, _eid) in self.active_cores.iter() {
+                    shootdown_handle.add_core(*gtid);
+                }
+
+                Ok(ProcessResult::Protected(shootdown_handle))
+            }
+
+            ProcessOpMut::MemCopyOnWrite(base, old, frame) => {
+                // Another core might have copied the frame in the meantime
+                let mem_type = match self.process.vspace().mapping(base) {
+                    Some((mbase, mapping))
+                        if mbase == base
+                            && mapping.frame.base == old
+                            && mapping.rights.is_cow() =>
+                    {
+                        match mapping.typ {
+                            MappingType::Heap(mem_type) => mem_type,
+                            _ => return Err(KError::NotSupported),
+                        }
+                    }
+                    _ => return Err(KError::AlreadyMapped { base }),
+                };
+
+                crate::memory::KernelAllocator::try_refill_tcache(7, 0, MemType::Mem)?;
+                self.add_usage(mem_type, frame.size, 0)?;
+                let (mut shootdown_handle, replaced) =
+                    match self.process.vspace_mut().copy_on_write(base, frame) {
+                        Ok(r) => r,
+                        Err(e) => {
+                            self.remove_usage(mem_type, frame.size, 0);
+                            return Err(e);
+                        }
+                    };
+                let released = self.process.remove_cow_mapping(replaced.base)?;
+                if let Some(released) = released {
+                    self.remove_usage(mem_type, released.size, 0);
+                }
+
+                for (gtid, _eid) in self.active_cores.iter() {
+                    shootdown_handle.add_core(*gtid);
+                }
+
+                Ok(ProcessResult::Copied(shootdown_handle, released))
+            }
+
+            ProcessOpMut::MemPopulate(vaddr, frame) => {
+                crate::memory::KernelAllocator::try_refill_tcache(7, 0, MemType::Mem)?;
+                let (_base, reservation) = self
+                    .process
+                    .vspace()
+                    .reservation(vaddr)
+                    .ok_or(KError::NotMapped)?;
+                self.add_usage(reservation.mem_type, frame.size, 0)?;
+                if let Err(e) = self.process.vspace_mut().populate(vaddr, frame) {
+                    self.remove_usage(reservation.mem_type, frame.size, 0);
+                    return Err(e);
+                }
+                Ok(ProcessResult::Ok)
             }
 
             ProcessOpMut::AssignExecutor(gtid, region) => {
@@ -587,14 +1316,47 @@ where
                 Ok(ProcessResult::Executor(executor))
             }
 
+            ProcessOpMut::ReleaseExecutor(gtid) => {
+                self.active_cores.retain(|(core, _eid)| *core != gtid);
+                Ok(ProcessResult::Ok)
+            }
+
             ProcessOpMut::AllocateFrameToProcess(frame) => {
-                let fid = self.process.add_frame(frame)?;
+                self.add_usage(MemType::Mem, 0, frame.size)?;
+                let fid = match self.process.add_frame(frame) {
+                    Ok(fid) => fid,
+                    Err(e) => {
+                        self.remove_usage(MemType::Mem, 0, frame.size);
+                        return Err(e);
+                    }
+                };
+                Ok(ProcessResult::FrameId(fid))
+            }
+
+            ProcessOpMut::AllocateSharedFrameToProcess(frame, shm) => {
+                self.add_usage(MemType::Mem, 0, frame.size)?;
+                let fid = match self.process.add_shared_frame(frame, shm) {
+                    Ok(fid) => fid,
+                    Err(e) => {
+                        self.remove_usage(MemType::Mem, 0, frame.size);
+                        return Err(e);
+                    }
+                };
                 Ok(ProcessResult::FrameId(fid))
             }
 
             ProcessOpMut::ReleaseFrameFromProcess(fid) => {
-                let frame = self.process.deallocate_frame(fid)?;
-                Ok(ProcessResult::Frame(frame))
+                let (frame, shm) = self.process.deallocate_frame(fid)?;
+                self.remove_usage(MemType::Mem, 0, frame.size);
+                Ok(ProcessResult::Frame(frame, shm))
+            }
+
+            ProcessOpMut::Exit => {
+                let frames = self.process.teardown()?;
+                self.active_cores.clear();
+                self.mem_usage = Default::default();
+                self.pmem_usage = Default::default();
+                Ok(ProcessResult::Exited(frames))
             }
         }
     }
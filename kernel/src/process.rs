@@ -11,11 +11,12 @@ use core::convert::{TryFrom, TryInto};
 use core::fmt::Debug;
 use core::mem::MaybeUninit;
 
-use arrayvec::ArrayVec;
+use arrayvec::{ArrayString, ArrayVec};
+use fallible_collections::btree::BTreeMap;
 use fallible_collections::vec::FallibleVecGlobal;
 use fallible_collections::vec::TryCollect;
 use fallible_collections::TryReserveError;
-use kpi::process::{FrameId, ELF_OFFSET};
+use kpi::process::{FrameId, ShmId, ELF_OFFSET, MAX_CMDLINE_LEN};
 use kpi::MemType;
 use log::{debug, info, trace};
 
@@ -27,6 +28,7 @@ use crate::cmdline::CommandLineArguments;
 use crate::error::{KError, KResult};
 use crate::fs::{cnrfs, fd::FileDescriptorEntry};
 use crate::memory::backends::PhysicalPageProvider;
+use crate::memory::detmem::DA;
 use crate::memory::vspace::AddressSpace;
 use crate::memory::{Frame, KernelAllocator, PAddr, VAddr, KERNEL_BASE};
 use crate::prelude::overlaps;
@@ -38,8 +40,16 @@ pub(crate) type Pid = usize;
 /// Executor ID.
 pub(crate) type Eid = usize;
 
-/// How many (concurrent) processes the systems supports.
-pub(crate) const MAX_PROCESSES: usize = 12;
+/// How many (concurrent) processes the systems supports by default (can be
+/// changed with the `maxprocs` command line argument).
+pub(crate) const DEFAULT_MAX_PROCESSES: usize = 12;
+
+/// How many (concurrent) processes the system supports.
+pub(crate) fn max_processes() -> usize {
+    crate::CMDLINE
+        .get()
+        .map_or(DEFAULT_MAX_PROCESSES, |c| c.max_processes)
+}
 
 /// How many registered "named" frames a process can have.
 pub(crate) const MAX_FRAMES_PER_PROCESS: usize = MAX_CORES;
@@ -47,11 +57,18 @@ pub(crate) const MAX_FRAMES_PER_PROCESS: usize = MAX_CORES;
 /// How many writable sections a process can have (part of the ELF file).
 pub(crate) const MAX_WRITEABLE_SECTIONS_PER_PROCESS: usize = 4;
 
+/// Arguments a process was spawned with.
+pub(crate) type Cmdline = ArrayString<MAX_CMDLINE_LEN>;
+
 /// Abstract definition of a process.
 pub(crate) trait Process: FrameManagement {
     type E: Executor + Copy + Sync + Send + Debug + PartialEq;
     type A: AddressSpace;
 
+    fn new(pid: Pid, da: DA) -> Result<Self, KError>
+    where
+        Self: core::marker::Sized;
+
     fn pid(&self) -> Pid;
 
     fn load(
@@ -82,28 +99,59 @@ pub(crate) trait Process: FrameManagement {
 
     fn get_fd(&self, index: usize) -> &FileDescriptorEntry;
 
-    fn pinfo(&self) -> &kpi::process::ProcessInfo;
+    fn pinfo(&self) -> &kpi::process::ProcessInfo<'static>;
+
+    /// Arguments the process was spawned with (`None` uses the arguments
+    /// from the kernel command line).
+    fn cmdline(&self) -> Option<&Cmdline>;
+
+    fn set_cmdline(&mut self, cmdline: Cmdline);
+
+    /// Releases all resources held by the process and resets it, so the
+    /// process slot can be reused by a new process.
+    ///
+    /// Memory that is private to the replica (e.g., the read-only ELF
+    /// sections) is freed directly. Memory shared by all replicas is returned
+    /// to the caller which has to release it exactly once. If it fails, the
+    /// process is left untouched.
+    fn teardown(&mut self) -> Result<Vec<(Frame, MemType)>, KError>;
 }
 
 pub(crate) trait FrameManagement {
     fn add_frame(&mut self, frame: Frame) -> Result<FrameId, KError>;
+    fn add_shared_frame(&mut self, frame: Frame, shm: ShmId) -> Result<FrameId, KError>;
     fn get_frame(&mut self, frame_id: FrameId) -> Result<(Frame, usize), KError>;
     fn add_frame_mapping(&mut self, frame_id: FrameId, vaddr: VAddr) -> Result<(), KError>;
     fn remove_frame_mapping(&mut self, paddr: PAddr, _vaddr: VAddr) -> Result<(), KError>;
-    fn deallocate_frame(&mut self, fid: FrameId) -> Result<Frame, KError>;
+    fn deallocate_frame(&mut self, fid: FrameId) -> Result<(Frame, Option<ShmId>), KError>;
+    fn add_cow_mapping(&mut self, frame: Frame) -> Result<(), KError>;
+    fn remove_cow_mapping(&mut self, paddr: PAddr) -> Result<Option<Frame>, KError>;
 }
 
 /// Implementation for managing a process' frames.
 pub(crate) struct ProcessFrames {
     /// Physical frame objects registered to the process.
     frames: ArrayVec<(Option<Frame>, usize), MAX_FRAMES_PER_PROCESS>,
+    /// The shared memory object a registered frame belongs to (if any).
+    ///
+    /// Shared frames are not owned by the process, the references of the
+    /// process to them are tracked in [`nr::KernelNode`].
+    shm: ArrayVec<Option<ShmId>, MAX_FRAMES_PER_PROCESS>,
+    /// Frames that are shared copy-on-write and how many mappings refer to
+    /// them.
+    cow: BTreeMap<PAddr, (Frame, usize)>,
 }
 
 impl Default for ProcessFrames {
     fn default() -> Self {
         let frames: ArrayVec<(Option<Frame>, usize), MAX_FRAMES_PER_PROCESS> =
             ArrayVec::from([(None, 0); MAX_FRAMES_PER_PROCESS]);
-        Self { frames }
+        let shm = ArrayVec::from([None; MAX_FRAMES_PER_PROCESS]);
+        Self {
+            frames,
+            shm,
+            cow: BTreeMap::new(),
+        }
     }
 }
 
@@ -111,12 +159,19 @@ impl FrameManagement for ProcessFrames {
     fn add_frame(&mut self, frame: Frame) -> Result<FrameId, KError> {
         if let Some(fid) = self.frames.iter().position(|entry| entry.0.is_none()) {
             self.frames[fid] = (Some(frame), 0);
+            self.shm[fid] = None;
             Ok(fid)
         } else {
             Err(KError::TooManyRegisteredFrames)
         }
     }
 
+    fn add_shared_frame(&mut self, frame: Frame, shm: ShmId) -> Result<FrameId, KError> {
+        let fid = self.add_frame(frame)?;
+        self.shm[fid] = Some(shm);
+        Ok(fid)
+    }
+
     fn get_frame(&mut self, frame_id: FrameId) -> Result<(Frame, usize), KError> {
         let (frame, metadata) = self
             .frames
@@ -151,28 +206,78 @@ impl FrameManagement for ProcessFrames {
         // then.
         static_assertions::const_assert!(MAX_FRAMES_PER_PROCESS < 1024);
 
+        // A shared frame can be registered more than once, any registration
+        // that is still mapped will do.
+        let mut found = false;
         for (frame, ref mut refcnt) in self.frames.iter_mut() {
             if let Some(frame) = frame {
-                if frame.base == paddr && *refcnt > 0 {
-                    *refcnt -= 1;
-                    return Ok(());
-                } else {
-                    panic!("Can't call remove_frame_mapping on 0 refcnt frame");
+                if frame.base == paddr {
+                    found = true;
+                    if *refcnt > 0 {
+                        *refcnt -= 1;
+                        return Ok(());
+                    }
                 }
             }
         }
+
+        if found {
+            panic!("Can't call remove_frame_mapping on 0 refcnt frame");
+        }
         // Frame not found
         Err(KError::InvalidFrameId)
     }
 
-    fn deallocate_frame(&mut self, fid: FrameId) -> Result<Frame, KError> {
+    fn deallocate_frame(&mut self, fid: FrameId) -> Result<(Frame, Option<ShmId>), KError> {
         let (frame, refcnt) = self.frames.get_mut(fid).ok_or(KError::InvalidFrameId)?;
         if *refcnt == 0 {
-            frame.take().ok_or(KError::InvalidFrameId)
+            let frame = frame.take().ok_or(KError::InvalidFrameId)?;
+            Ok((frame, self.shm[fid].take()))
         } else {
             Err(KError::FrameStillMapped)
         }
     }
+
+    fn add_cow_mapping(&mut self, frame: Frame) -> Result<(), KError> {
+        if let Some((_frame, refcnt)) = self.cow.get_mut(&frame.base) {
+            *refcnt += 1;
+        } else {
+            // The frame was mapped once before it got shared
+            self.cow.try_insert(frame.base, (frame, 2))?;
+        }
+        Ok(())
+    }
+
+    fn remove_cow_mapping(&mut self, paddr: PAddr) -> Result<Option<Frame>, KError> {
+        let (frame, refcnt) = self.cow.get_mut(&paddr).ok_or(KError::InvalidFrame)?;
+        *refcnt -= 1;
+        if *refcnt == 0 {
+            let frame = *frame;
+            self.cow.remove(&paddr);
+            Ok(Some(frame))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl ProcessFrames {
+    /// Removes all frames registered to the process (regardless of whether
+    /// they are still mapped) and returns the ones owned by the process.
+    ///
+    /// Shared frames are dropped, they are released with
+    /// `nr::KernelNode::shm_detach_process`.
+    pub(crate) fn release_all(&mut self) -> ArrayVec<Frame, MAX_FRAMES_PER_PROCESS> {
+        self.frames
+            .iter_mut()
+            .zip(self.shm.iter_mut())
+            .filter_map(|((frame, refcnt), shm)| {
+                *refcnt = 0;
+                let frame = frame.take();
+                shm.take().map_or(frame, |_shm| None)
+            })
+            .collect()
+    }
 }
 
 /// ResumeHandle is the HW specific logic that switches the CPU
@@ -387,6 +492,18 @@ impl elfloader::ElfLoader for DataSecAllocator {
     }
 }
 
+/// Looks up a module loaded by the bootloader by its name.
+///
+/// Returns the name with a static lifetime, as required by `make_process`.
+pub(crate) fn find_binary(binary: &str) -> Option<&'static str> {
+    crate::KERNEL_ARGS.get().and_then(|args| {
+        args.modules
+            .iter()
+            .find(|module| module.name() == binary)
+            .map(|module| module.name())
+    })
+}
+
 /// Create a new process
 ///
 /// Parse & relocate ELF
@@ -445,6 +562,10 @@ pub(crate) fn make_process<P: Process>(binary: &'static str) -> Result<Pid, KErr
         .map_or(Err(KError::ReplicaNotSet), |(replica, token)| {
             let response = replica.execute_mut(nr::Op::AllocatePid, *token)?;
             if let nr::NodeResult::PidAllocated(pid) = response {
+                if let Err(e) = crate::arch::process::PROCESS_TABLE.create(pid) {
+                    nr::KernelNode::release_pid(pid)?;
+                    return Err(e);
+                }
                 cnrfs::MlnrKernelNode::add_process(pid)
                     .expect("TODO(error-handling): revert state");
                 crate::nrproc::NrProcess::<P>::load(pid, mod_file, data_frames)
@@ -700,7 +821,7 @@ impl UserSlice {
     ///
     /// Returns an error if slice addresses potential kernel memory or null.
     pub(crate) fn new(pid: Pid, base: UVAddr, len: usize) -> KResult<Self> {
-        debug_assert!(pid < MAX_PROCESSES, "Invalid PID");
+        debug_assert!(pid < max_processes(), "Invalid PID");
         if len > i32::MAX as usize {
             // Don't allow buffers > 2GB, this is pretty arbitrary (and probably
             // still too big) but at least sets some "bound" on syscall duration
@@ -727,6 +848,31 @@ impl UserSlice {
         Self::new(pid, base, len)
     }
 
+    /// Backs the pages of the slice that are reserved but weren't touched by
+    /// the process yet, so the kernel can access them. If `writeable` is set,
+    /// copy-on-write pages of the slice are copied too.
+    ///
+    /// The process itself gets them with a page-fault, which we can't take
+    /// in the kernel. Has to be called outside of process replica operations.
+    pub(crate) fn populate(&self, writeable: bool) -> KResult<()> {
+        let end = self.base.as_usize() + self.len;
+        let mut from = self.base.vaddr();
+        while let Some((page, cow)) = nrproc::NrProcess::<ArchProcess>::unbacked(
+            self.pid,
+            from,
+            end - from.as_usize(),
+            writeable,
+        )? {
+            if cow {
+                crate::arch::process::copy_on_write(self.pid, page)?;
+            } else {
+                crate::arch::process::populate_reserved(self.pid, page)?;
+            }
+            from = page + BASE_PAGE_SIZE;
+        }
+        Ok(())
+    }
+
     /// Checks if the user-slice is accessible given the mappings installed in
     /// the page-tables of the user-space process.
     ///
@@ -763,7 +909,9 @@ impl UserSlice {
             let addr_to_check: UVAddr = tocheck.try_into()?;
             // Check that this memory is mapped and readable by user-space:
             let (_paddr, rights) = process.vspace().resolve(addr_to_check.vaddr())?;
-            if writeable && !rights.is_writable() {
+            if !rights.is_userspace() {
+                return Err(KError::UserPtMissingReadAccess);
+            } else if writeable && !rights.is_writable() {
                 return Err(KError::UserPtMissingWriteAccess);
             } else if !writeable && !rights.is_readable() {
                 return Err(KError::UserPtMissingReadAccess);
@@ -796,7 +944,9 @@ impl UserSlice {
     where
         F: FnOnce(&'b mut [u8]) -> KResult<R>,
     {
-        self.is_accessible(process, false)?;
+        // Copy-on-write pages aren't writable, they have to be copied with
+        // `populate` first
+        self.is_accessible(process, true)?;
 
         let user_slice = unsafe {
             // Safety: `from_raw_parts_mut`
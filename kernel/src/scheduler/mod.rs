@@ -2,23 +2,56 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 //! Scheduling logic
+//!
+//! Every core has a run-queue with an executor for each process that was
+//! assigned to the core. The executors take turns (round-robin), they get
+//! preempted by the timer once their time slice is used up.
+//!
+//! Cores without executors sleep (see `arch::idle`) and don't take timer
+//! interrupts. They get woken up when a process is assigned to them or
+//! (with an IPI) when a replica log needs them to make progress.
+//!
+//! A core only looks up the processes assigned to it (see
+//! [`admit_processes`]) after [`process_assigned`] told it about a new one.
 
 use core::intrinsics::unlikely;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use log::warn;
 
 use crate::arch::process::ArchProcessManagement;
-use crate::arch::timer;
+use crate::arch::{timer, MAX_CORES};
 use crate::error::KError;
 use crate::nr;
-use crate::nr::NR_REPLICA;
 use crate::nrproc::NrProcess;
 use crate::process::{Executor, ResumeHandle};
 
+pub(crate) mod runqueue;
+
+/// How long an executor runs before the next executor on the same core gets
+/// a turn (in rdtsc ticks).
+pub(crate) const TIME_SLICE: u64 = 20_000_000;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Set for every core that got a process assigned which it didn't admit yet.
+static ADMIT_PENDING: [AtomicBool; MAX_CORES] = [NOT_PENDING; MAX_CORES];
+
+/// Tells core `gtid` that a process was assigned to it (with
+/// `KernelNode::allocate_core_to_process`).
+///
+/// The core admits the process once it wakes up or on its next timer
+/// interrupt.
+pub(crate) fn process_assigned(gtid: atopology::GlobalThreadId) {
+    ADMIT_PENDING[gtid].store(true, Ordering::SeqCst);
+    crate::arch::idle::wake(gtid);
+}
+
 /// Runs the process allocated to the given core.
 pub(crate) fn schedule() -> ! {
-    let apm = ArchProcessManagement;
-
     // Are we the master/first thread in that replica?
-    // Then we should set timer to periodically advance the state
+    // Then we should periodically advance the state
     #[cfg(target_os = "none")]
     let is_replica_main_thread = {
         let thread = atopology::MACHINE_TOPOLOGY.current_thread();
@@ -35,65 +68,31 @@ pub(crate) fn schedule() -> ! {
     let is_replica_main_thread = false;
 
     // No process assigned to core? Figure out if there is one now:
-    if unlikely(!crate::arch::process::has_executor()) {
-        if let Some((replica, token)) = NR_REPLICA.get() {
-            loop {
-                let response = replica.execute(
-                    nr::ReadOps::CurrentProcess(*crate::environment::CORE_ID),
-                    *token,
-                );
-
-                match response {
-                    Ok(nr::NodeResult::CoreInfo(ci)) => {
-                        let executor =
-                            NrProcess::allocate_executor(&apm, ci.pid).expect("This should work");
-                        unsafe {
-                            (*executor.vcpu_kernel()).resume_with_upcall = ci.entry_point;
-                        }
-
-                        // info!("Start execution of {} on gtid {}", executor.eid, gtid);
-                        let no = crate::arch::process::swap_current_executor(executor);
-                        assert!(no.is_none(), "Handle the case where we replace a process.");
-                        if is_replica_main_thread {
-                            // Make sure we periodically try and advance the replica on main-thread
-                            // even if we're running something (e.g., if everything polls in
-                            // user-space we can livelock)
-                            timer::set(timer::DEFAULT_TIMER_DEADLINE);
-                        }
-                        break;
-                    }
-                    Err(KError::NoExecutorForCore) => {
-                        if is_replica_main_thread {
-                            // There is no process but we're the "main" thread,
-                            // aggressively try and advance the replica
-                            let start = rawtime::Instant::now();
-                            crate::nrproc::advance_all();
-                            crate::arch::advance_fs_replica();
-
-                            if start.elapsed().as_millis() < 1 {
-                                // Wait for a bit in case we don't end up doing
-                                // any work, otherwise this causes too much
-                                // contention and tput drops around ~300k
-                                for _i in 0..25_000 {
-                                    core::hint::spin_loop();
-                                }
-                            }
-                            continue;
-                        } else {
-                            // There is no process, set a timer and go to sleep
-                            timer::set(timer::DEFAULT_TIMER_DEADLINE);
-                        }
-                        crate::arch::halt();
-                    }
-                    other => {
-                        unreachable!(
-                            "Unexpected return from ReadOps::CurrentExecutor {:?}.",
-                            other
-                        );
-                    }
-                };
-            }
+    if unlikely(!crate::arch::process::has_executor()) && nr::NR_REPLICA.get().is_some() {
+        // Anybody who assigns a process to us from now on wakes us up
+        crate::arch::idle::enter();
+        admit_processes();
+        if let Some(resumer) = crate::arch::process::dispatch_next() {
+            crate::arch::idle::exit();
+            arm_timer();
+            unsafe { resumer.resume() }
+        }
+
+        // There is no process, answer what the timer interrupt would have
+        // answered (the logs and `reclaim::request` wake us for anything
+        // that comes later)
+        crate::memory::reclaim::poll();
+        crate::memory::stats::publish();
+        if is_replica_main_thread {
+            // We're the "main" thread, bring the replicas up to date before
+            // we sleep
+            crate::nrproc::advance_all();
+            crate::arch::advance_fs_replica();
         }
+
+        // Sleep until somebody needs us
+        timer::disarm();
+        crate::arch::idle::sleep();
     }
     debug_assert!(
         crate::arch::process::has_executor(),
@@ -101,6 +100,7 @@ pub(crate) fn schedule() -> ! {
     );
 
     // If we come here, we have a new process, dispatch it:
+    arm_timer();
     unsafe {
         let pe = crate::arch::process::CURRENT_EXECUTOR.borrow();
         let rh = pe.as_ref().expect("Can't borrow current executor").start();
@@ -110,3 +110,56 @@ pub(crate) fn schedule() -> ! {
         rh.resume()
     }
 }
+
+/// Adds an executor to the run-queue of the core for every process that got
+/// assigned to the core since we last checked.
+///
+/// Does nothing unless [`process_assigned`] was called for the core, this
+/// runs on every timer interrupt.
+pub(crate) fn admit_processes() {
+    let gtid = *crate::environment::CORE_ID;
+    if !ADMIT_PENDING[gtid].swap(false, Ordering::SeqCst) {
+        return;
+    }
+
+    let processes = match nr::KernelNode::core_processes(gtid) {
+        Ok(processes) => processes,
+        Err(KError::NoExecutorForCore) => return,
+        Err(e) => {
+            warn!("Can't look up the processes of core {}: {:?}", gtid, e);
+            ADMIT_PENDING[gtid].store(true, Ordering::SeqCst);
+            return;
+        }
+    };
+
+    let apm = ArchProcessManagement;
+    for ci in processes {
+        if crate::arch::process::runs_process(ci.pid) {
+            continue;
+        }
+
+        // We'll try again on the next tick if this fails
+        let admitted = NrProcess::allocate_executor(&apm, ci.pid).and_then(|executor| {
+            unsafe {
+                (*executor.vcpu_kernel()).resume_with_upcall = ci.entry_point;
+            }
+            crate::arch::process::queue_executor(executor)
+        });
+        if let Err(e) = admitted {
+            warn!("Can't run process {} on core {}: {:?}", ci.pid, gtid, e);
+            ADMIT_PENDING[gtid].store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Makes sure the core gets interrupted again: at the end of the time slice
+/// if other executors wait for the core, otherwise eventually (to admit
+/// processes that get assigned to the core and to periodically advance the
+/// replicas, even if everything polls in user-space).
+pub(crate) fn arm_timer() {
+    if crate::arch::process::has_waiting_executors() {
+        timer::set(TIME_SLICE);
+    } else {
+        timer::set(timer::DEFAULT_TIMER_DEADLINE);
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright © 2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! The executors that share a core.
+
+use alloc::collections::VecDeque;
+
+use crate::error::KError;
+use crate::process::Pid;
+
+/// An executor waiting for its turn on a core.
+pub(crate) struct Runnable<E> {
+    /// The process the executor belongs to.
+    pub pid: Pid,
+    /// The executor.
+    pub executor: E,
+    /// The executor ran before (and got preempted or waits in a system
+    /// call), so it has to be resumed from its save area rather than started.
+    pub started: bool,
+}
+
+/// A per-core queue of executors that take turns (round-robin) on the core.
+///
+/// The executor that currently runs on the core is not part of the queue.
+pub(crate) struct RunQueue<E> {
+    queue: VecDeque<Runnable<E>>,
+}
+
+impl<E> RunQueue<E> {
+    pub(crate) const fn new() -> Self {
+        RunQueue {
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Adds an executor of `pid` at the end of the queue.
+    pub(crate) fn push(&mut self, pid: Pid, executor: E, started: bool) -> Result<(), KError> {
+        self.queue.try_reserve(1)?;
+        self.queue.push_back(Runnable {
+            pid,
+            executor,
+            started,
+        });
+        Ok(())
+    }
+
+    /// Makes sure the next `push` doesn't have to allocate.
+    pub(crate) fn reserve(&mut self) -> Result<(), KError> {
+        self.queue.try_reserve(1)?;
+        Ok(())
+    }
+
+    /// Removes the executor whose turn it is next.
+    pub(crate) fn pop(&mut self) -> Option<Runnable<E>> {
+        self.queue.pop_front()
+    }
+
+    /// Puts `current` at the end of the queue and returns the executor whose
+    /// turn it is instead (or `current` if it's the only one).
+    ///
+    /// Never allocates, so it's fine to call this from an interrupt handler.
+    pub(crate) fn rotate(&mut self, current: Runnable<E>) -> Runnable<E> {
+        match self.queue.pop_front() {
+            Some(next) => {
+                // Can't fail, we just made room for it
+                self.queue.push_back(current);
+                next
+            }
+            None => current,
+        }
+    }
+
+    /// Drops all executors of `pid`.
+    pub(crate) fn remove(&mut self, pid: Pid) {
+        self.queue.retain(|runnable| runnable.pid != pid);
+    }
+
+    /// Returns true if an executor of `pid` waits in the queue.
+    pub(crate) fn contains(&self, pid: Pid) -> bool {
+        self.queue.iter().any(|runnable| runnable.pid == pid)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_robin() {
+        let mut rq: RunQueue<usize> = RunQueue::new();
+        assert!(rq.pop().is_none());
+        for pid in 1..=3 {
+            assert!(rq.push(pid, pid * 10, false).is_ok());
+        }
+        assert_eq!(rq.len(), 3);
+
+        let mut current = rq.pop().unwrap();
+        assert_eq!(current.pid, 1);
+        let mut order = Vec::new();
+        for _tick in 0..6 {
+            current.started = true;
+            current = rq.rotate(current);
+            order.push((current.pid, current.started));
+        }
+        assert_eq!(
+            order,
+            [
+                (2, false),
+                (3, false),
+                (1, true),
+                (2, true),
+                (3, true),
+                (1, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn rotate_alone() {
+        let mut rq: RunQueue<usize> = RunQueue::new();
+        let current = Runnable {
+            pid: 1,
+            executor: 10,
+            started: true,
+        };
+        let current = rq.rotate(current);
+        assert_eq!(current.pid, 1);
+        assert!(rq.is_empty());
+    }
+
+    #[test]
+    fn remove_process() {
+        let mut rq: RunQueue<usize> = RunQueue::new();
+        assert!(rq.push(1, 10, false).is_ok());
+        assert!(rq.push(2, 20, false).is_ok());
+        assert!(rq.push(1, 11, true).is_ok());
+        assert!(rq.contains(1));
+
+        rq.remove(1);
+        assert!(!rq.contains(1));
+        assert_eq!(rq.len(), 1);
+        assert_eq!(rq.pop().map(|r| r.executor), Some(20));
+    }
+}
@@ -4,17 +4,25 @@
 //! Generic system call abstraction
 
 use alloc::string::String;
+use alloc::vec::Vec;
 use core::fmt::{Debug, LowerHex};
 
-use kpi::io::{FileFlags, FileModes};
-use kpi::{FileOperation, ProcessOperation, SystemCall, SystemOperation, VSpaceOperation};
+use fallible_collections::FallibleVecGlobal;
+
+use kpi::io::{FileFlags, FileModes, SeekWhence};
+use kpi::{
+    FileOperation, MapFileFlags, MemPolicy, MemProtection, ProcessOperation, SystemCall,
+    SystemOperation, VSpaceOperation,
+};
 use log::{error, trace};
 
 use crate::arch::process::current_pid;
 use crate::error::{KError, KResult};
 use crate::fs::cnrfs;
 use crate::fs::fd::FileDescriptor;
-use crate::process::UserSlice;
+use crate::fs::ring::{self, RingOp};
+use crate::memory::BASE_PAGE_SIZE;
+use crate::process::{SliceAccess, UVAddr, UserSlice};
 
 /// FileOperation: Arch specific implementations
 pub(crate) trait FsDispatch<W: Into<u64> + LowerHex + Debug + Copy + Clone> {
@@ -28,6 +36,26 @@ pub(crate) trait FsDispatch<W: Into<u64> + LowerHex + Debug + Copy + Clone> {
     fn delete(&self, path: UserSlice) -> KResult<(W, W)>;
     fn file_rename(&self, oldpath: UserSlice, newpath: UserSlice) -> KResult<(W, W)>;
     fn mkdir(&self, path: UserSlice, modes: FileModes) -> KResult<(W, W)>;
+    fn readdir(&self, path: UserSlice, buffer: UserSlice) -> KResult<(W, W)>;
+    fn truncate(&self, fd: FileDescriptor, len: u64) -> KResult<(W, W)>;
+    fn seek(&self, fd: FileDescriptor, offset: i64, whence: SeekWhence) -> KResult<(W, W)>;
+    fn fstat(&self, fd: FileDescriptor) -> KResult<(W, W)>;
+    fn pipe(&self) -> KResult<(W, W)>;
+    fn ring_register(&self, ring: UVAddr) -> KResult<(W, W)>;
+    fn ring_enter(&self) -> KResult<(W, W)>;
+
+    /// Executes `ops` one after the other, returns the result of every
+    /// operation.
+    ///
+    /// Implementations can batch them, by default it's a system call per
+    /// operation.
+    fn execute_batch(&self, ops: Vec<RingOp>) -> KResult<Vec<KResult<(W, W)>>> {
+        let mut results = Vec::try_with_capacity(ops.len())?;
+        for op in ops {
+            results.push(op.execute(self));
+        }
+        Ok(results)
+    }
 }
 
 /// Parsed and validated arguments of the file system calls.
@@ -42,6 +70,13 @@ enum FileOperationArgs {
     Delete(UserSlice),
     FileRename(UserSlice, UserSlice),
     MkDir(UserSlice, FileModes),
+    ReadDir(UserSlice, UserSlice),
+    Truncate(FileDescriptor, u64),
+    Seek(FileDescriptor, i64, SeekWhence),
+    FStat(FileDescriptor),
+    Pipe,
+    RingRegister(UVAddr),
+    RingEnter,
 }
 
 impl FileOperationArgs {
@@ -99,6 +134,20 @@ impl FileOperationArgs {
                 UserSlice::for_current_proc(arg2.into(), arg3.into())?,
                 arg4.into().into(),
             )),
+            FileOperation::ReadDir => Ok(Self::ReadDir(
+                UserSlice::for_current_proc(arg2.into(), arg3.into())?,
+                UserSlice::for_current_proc(arg4.into(), arg5.into())?,
+            )),
+            FileOperation::Truncate => Ok(Self::Truncate(arg2.into().try_into()?, arg3.into())),
+            FileOperation::Seek => Ok(Self::Seek(
+                arg2.into().try_into()?,
+                arg3.into() as i64,
+                SeekWhence::new(arg4.into()).ok_or(KError::InvalidFlags)?,
+            )),
+            FileOperation::FStat => Ok(Self::FStat(arg2.into().try_into()?)),
+            FileOperation::Pipe => Ok(Self::Pipe),
+            FileOperation::RingRegister => Ok(Self::RingRegister(UVAddr::try_from(arg2.into())?)),
+            FileOperation::RingEnter => Ok(Self::RingEnter),
         }
     }
 }
@@ -110,9 +159,15 @@ pub(crate) trait ProcessDispatch<W: Into<u64> + LowerHex + Debug + Copy + Clone>
     fn allocate_vector(&self, vector: W, core: W) -> KResult<(W, W)>;
     fn get_process_info(&self, vaddr_buf: W, vaddr_buf_len: W) -> KResult<(W, W)>;
     fn request_core(&self, core_id: W, entry_point: W) -> KResult<(W, W)>;
+    fn release_core(&self, core_id: W) -> KResult<(W, W)>;
     fn allocate_physical(&self, page_size: W, affinity: W) -> KResult<(W, W)>;
     fn release_physical(&self, page_id: W) -> KResult<(W, W)>;
+    fn create_shm(&self, page_size: W) -> KResult<(W, W)>;
+    fn attach_shm(&self, shm_id: W) -> KResult<(W, W)>;
     fn exit(&self, code: W) -> KResult<(W, W)>;
+    fn spawn(&self, args: UserSlice) -> KResult<(W, W)>;
+    fn wait(&self, pid: W) -> KResult<(W, W)>;
+    fn yield_core(&self) -> KResult<(W, W)>;
 }
 
 /// Parsed and validated arguments of the process system calls.
@@ -123,8 +178,14 @@ enum ProcessOperationArgs<W> {
     AllocateVector(W, W),
     GetProcessInfo(W, W),
     RequestCore(W, W),
+    ReleaseCore(W),
     AllocatePhysical(W, W),
     ReleasePhysical(W),
+    CreateShm(W),
+    AttachShm(W),
+    Spawn(UserSlice),
+    Wait(W),
+    Yield,
 }
 
 impl<W: Into<u64> + LowerHex + Debug + Copy + Clone> ProcessOperationArgs<W> {
@@ -144,8 +205,17 @@ impl<W: Into<u64> + LowerHex + Debug + Copy + Clone> ProcessOperationArgs<W> {
             ProcessOperation::Exit => Ok(Self::Exit(arg2)),
             ProcessOperation::GetProcessInfo => Ok(Self::GetProcessInfo(arg2, arg3)),
             ProcessOperation::RequestCore => Ok(Self::RequestCore(arg2, arg3)),
+            ProcessOperation::ReleaseCore => Ok(Self::ReleaseCore(arg2)),
             ProcessOperation::AllocatePhysical => Ok(Self::AllocatePhysical(arg2, arg3)),
             ProcessOperation::ReleasePhysical => Ok(Self::ReleasePhysical(arg2)),
+            ProcessOperation::CreateShm => Ok(Self::CreateShm(arg2)),
+            ProcessOperation::AttachShm => Ok(Self::AttachShm(arg2)),
+            ProcessOperation::Spawn => Ok(Self::Spawn(UserSlice::for_current_proc(
+                arg2.into(),
+                arg3.into(),
+            )?)),
+            ProcessOperation::Wait => Ok(Self::Wait(arg2)),
+            ProcessOperation::Yield => Ok(Self::Yield),
             ProcessOperation::SubscribeEvent => {
                 error!("SubscribeEvent is not implemented");
                 Err(KError::InvalidProcessOperation { a: arg1.into() })
@@ -157,12 +227,27 @@ impl<W: Into<u64> + LowerHex + Debug + Copy + Clone> ProcessOperationArgs<W> {
 /// VSpaceOperation: Arch specific implementations
 pub(crate) trait VSpaceDispatch<W: Into<u64> + LowerHex + Debug + Copy + Clone> {
     fn map_mem(&self, base: W, size: W) -> KResult<(W, W)>;
+    fn map_mem_policy(&self, base: W, size: W, policy: MemPolicy) -> KResult<(W, W)>;
     fn map_pmem(&self, base: W, size: W) -> KResult<(W, W)>;
     fn map_device(&self, base: W, size: W) -> KResult<(W, W)>;
     fn map_frame_id(&self, base: W, frame_id: W) -> KResult<(W, W)>;
     fn unmap_mem(&self, base: W) -> KResult<(W, W)>;
     fn unmap_pmem(&self, base: W) -> KResult<(W, W)>;
+    fn reserve_mem(&self, base: W, size: W) -> KResult<(W, W)>;
+    fn protect(&self, base: W, size: W, rights: MemProtection) -> KResult<(W, W)>;
+    fn snapshot(&self, src: W, dst: W, size: W) -> KResult<(W, W)>;
+    fn set_mem_policy(&self, policy: MemPolicy) -> KResult<(W, W)>;
     fn identify(&self, addr: W) -> KResult<(W, W)>;
+    fn map_file(
+        &self,
+        base: W,
+        size: W,
+        fd: FileDescriptor,
+        offset: u64,
+        flags: MapFileFlags,
+    ) -> KResult<(W, W)>;
+    fn unmap_file(&self, base: W, size: W) -> KResult<(W, W)>;
+    fn sync_file(&self, base: W, size: W) -> KResult<(W, W)>;
 }
 
 /// Parsed and validated arguments of the vspace system calls.
@@ -174,13 +259,21 @@ enum VSpaceOperationArgs<W> {
     Identify(W),
     MapPMem(W, W),
     UnmapPMem(W),
+    ReserveMem(W, W),
+    Protect(W, W, MemProtection),
+    Snapshot(W, W, W),
+    MapMemPolicy(W, W, MemPolicy),
+    SetMemPolicy(MemPolicy),
+    MapFile(W, W, FileDescriptor, u64, MapFileFlags),
+    UnmapFile(W, W),
+    SyncFile(W, W),
 }
 
 impl<W: Into<u64> + LowerHex + Debug + Copy + Clone> VSpaceOperationArgs<W> {
     /// Validate/check the arguments for the VSpaceOperation calls.
     ///
     /// Returns an error if the arguments are invalid.
-    fn validate(arg1: W, arg2: W, arg3: W) -> Result<Self, KError> {
+    fn validate(arg1: W, arg2: W, arg3: W, arg4: W, arg5: W) -> Result<Self, KError> {
         let op = VSpaceOperation::new(arg1.into())
             .ok_or(KError::InvalidVSpaceOperation { a: arg1.into() })?;
 
@@ -192,6 +285,34 @@ impl<W: Into<u64> + LowerHex + Debug + Copy + Clone> VSpaceOperationArgs<W> {
             VSpaceOperation::UnmapMem => Ok(Self::UnmapMem(arg2)),
             VSpaceOperation::UnmapPMem => Ok(Self::UnmapPMem(arg2)),
             VSpaceOperation::Identify => Ok(Self::Identify(arg2)),
+            VSpaceOperation::ReserveMem => Ok(Self::ReserveMem(arg2, arg3)),
+            VSpaceOperation::Protect => Ok(Self::Protect(
+                arg2,
+                arg3,
+                MemProtection::from_bits(arg4.into()).ok_or(KError::InvalidFlags)?,
+            )),
+            VSpaceOperation::Snapshot => Ok(Self::Snapshot(arg2, arg3, arg4)),
+            VSpaceOperation::MapMemPolicy => Ok(Self::MapMemPolicy(
+                arg2,
+                arg3,
+                MemPolicy::new(arg4.into()).ok_or(KError::InvalidFlags)?,
+            )),
+            VSpaceOperation::SetMemPolicy => Ok(Self::SetMemPolicy(
+                MemPolicy::new(arg2.into()).ok_or(KError::InvalidFlags)?,
+            )),
+            VSpaceOperation::MapFile => {
+                // The offset is page aligned, the flags are in the lower bits
+                let flag_bits = arg5.into() % BASE_PAGE_SIZE as u64;
+                Ok(Self::MapFile(
+                    arg2,
+                    arg3,
+                    arg4.into().try_into()?,
+                    arg5.into() - flag_bits,
+                    MapFileFlags::from_bits(flag_bits).ok_or(KError::InvalidFlags)?,
+                ))
+            }
+            VSpaceOperation::UnmapFile => Ok(Self::UnmapFile(arg2, arg3)),
+            VSpaceOperation::SyncFile => Ok(Self::SyncFile(arg2, arg3)),
         }
     }
 }
@@ -199,14 +320,14 @@ impl<W: Into<u64> + LowerHex + Debug + Copy + Clone> VSpaceOperationArgs<W> {
 /// SystemOperation: Arch specific implementations
 pub(crate) trait SystemDispatch<W: Into<u64> + LowerHex + Debug + Copy + Clone> {
     fn get_hardware_threads(&self, vbuf_base: W, vbuf_len: W) -> KResult<(W, W)>;
-    fn get_stats(&self) -> KResult<(W, W)>;
+    fn get_stats(&self, vbuf_base: W, vbuf_len: W) -> KResult<(W, W)>;
     fn get_core_id(&self) -> KResult<(W, W)>;
 }
 
 /// Parsed and validated arguments of the system query system calls.
 enum SystemOperationArgs<W> {
     GetHardwareThreads(W, W),
-    Stats,
+    Stats(W, W),
     GetCoreID,
 }
 
@@ -220,7 +341,7 @@ impl<W: Into<u64> + LowerHex + Debug + Copy + Clone> SystemOperationArgs<W> {
 
         match op {
             SystemOperation::GetHardwareThreads => Ok(Self::GetHardwareThreads(arg2, arg3)),
-            SystemOperation::Stats => Ok(Self::Stats),
+            SystemOperation::Stats => Ok(Self::Stats(arg2, arg3)),
             SystemOperation::GetCoreID => Ok(Self::GetCoreID),
         }
     }
@@ -247,7 +368,7 @@ pub(crate) trait SystemCallDispatch<W: Into<u64> + LowerHex + Debug + Copy + Clo
         {
             SystemCall::System => self.system(arg1, arg2, arg3),
             SystemCall::Process => self.process(arg1, arg2, arg3),
-            SystemCall::VSpace => self.vspace(arg1, arg2, arg3),
+            SystemCall::VSpace => self.vspace(arg1, arg2, arg3, arg4, arg5),
             SystemCall::FileIO => self.fileio(arg1, arg2, arg3, arg4, arg5),
             SystemCall::Test => self.test(arg1, arg2, arg3, arg4, arg5),
         }
@@ -259,7 +380,7 @@ pub(crate) trait SystemCallDispatch<W: Into<u64> + LowerHex + Debug + Copy + Clo
             GetHardwareThreads(vbuf_base, vbuf_len) => {
                 self.get_hardware_threads(vbuf_base, vbuf_len)
             }
-            Stats => self.get_stats(),
+            Stats(vbuf_base, vbuf_len) => self.get_stats(vbuf_base, vbuf_len),
             GetCoreID => self.get_core_id(),
         }
     }
@@ -276,17 +397,30 @@ pub(crate) trait SystemCallDispatch<W: Into<u64> + LowerHex + Debug + Copy + Clo
                 self.get_process_info(vaddr_buf, vaddr_len)
             }
             Poa::RequestCore(core_id, entry_point) => self.request_core(core_id, entry_point),
+            Poa::ReleaseCore(core_id) => self.release_core(core_id),
             Poa::AllocatePhysical(page_size, affinity) => {
                 self.allocate_physical(page_size, affinity)
             }
             Poa::ReleasePhysical(frame_id) => self.release_physical(frame_id),
+            Poa::CreateShm(page_size) => self.create_shm(page_size),
+            Poa::AttachShm(shm_id) => self.attach_shm(shm_id),
+            Poa::Spawn(args) => self.spawn(args),
+            Poa::Wait(pid) => self.wait(pid),
+            Poa::Yield => self.yield_core(),
         }
     }
 
-    fn vspace(&self, arg1: W, arg2: W, arg3: W) -> KResult<(W, W)> {
+    fn vspace(&self, arg1: W, arg2: W, arg3: W, arg4: W, arg5: W) -> KResult<(W, W)> {
         use VSpaceOperationArgs::*;
-        trace!("vspace({:#x}, {:#x}, {:#x})", arg1, arg2, arg3);
-        match VSpaceOperationArgs::validate(arg1, arg2, arg3)? {
+        trace!(
+            "vspace({:#x}, {:#x}, {:#x}, {:#x}, {:#x})",
+            arg1,
+            arg2,
+            arg3,
+            arg4,
+            arg5
+        );
+        match VSpaceOperationArgs::validate(arg1, arg2, arg3, arg4, arg5)? {
             MapMem(base, size) => self.map_mem(base, size),
             MapPMem(base, size) => self.map_pmem(base, size),
             MapDevice(base, size) => self.map_device(base, size),
@@ -294,6 +428,14 @@ pub(crate) trait SystemCallDispatch<W: Into<u64> + LowerHex + Debug + Copy + Clo
             UnmapMem(base) => self.unmap_mem(base),
             UnmapPMem(base) => self.unmap_pmem(base),
             Identify(base) => self.identify(base),
+            ReserveMem(base, size) => self.reserve_mem(base, size),
+            Protect(base, size, rights) => self.protect(base, size, rights),
+            Snapshot(src, dst, size) => self.snapshot(src, dst, size),
+            MapMemPolicy(base, size, policy) => self.map_mem_policy(base, size, policy),
+            SetMemPolicy(policy) => self.set_mem_policy(policy),
+            MapFile(base, size, fd, offset, flags) => self.map_file(base, size, fd, offset, flags),
+            UnmapFile(base, size) => self.unmap_file(base, size),
+            SyncFile(base, size) => self.sync_file(base, size),
         }
     }
 
@@ -310,6 +452,13 @@ pub(crate) trait SystemCallDispatch<W: Into<u64> + LowerHex + Debug + Copy + Clo
             Delete(name) => self.delete(name),
             FileRename(oldname, newname) => self.file_rename(oldname, newname),
             MkDir(pathname, modes) => self.mkdir(pathname, modes),
+            ReadDir(pathname, buffer) => self.readdir(pathname, buffer),
+            Truncate(fd, len) => self.truncate(fd, len),
+            Seek(fd, offset, whence) => self.seek(fd, offset, whence),
+            FStat(fd) => self.fstat(fd),
+            Pipe => self.pipe(),
+            RingRegister(ring) => self.ring_register(ring),
+            RingEnter => self.ring_enter(),
         }
     }
 }
@@ -413,4 +562,56 @@ impl<T: CnrFsDispatch> FsDispatch<u64> for T {
         let pathstring: String = path.try_into()?;
         cnrfs::MlnrKernelNode::mkdir(pid, pathstring, modes)
     }
+
+    fn readdir(&self, path: UserSlice, mut buffer: UserSlice) -> KResult<(u64, u64)> {
+        let pid = path.pid;
+        let pathstring: String = path.try_into()?;
+        let entries = cnrfs::MlnrKernelNode::readdir(pid, pathstring)?;
+
+        // Entries are returned serialized; if they don't fit we still return
+        // the required length so the caller can retry with a bigger buffer
+        let serialized = serde_cbor::to_vec(&entries).map_err(|_e| KError::OutOfMemory)?;
+        if serialized.len() <= buffer.len() {
+            buffer.write_subslice(&serialized, 0)?;
+        }
+
+        Ok((serialized.len() as u64, entries.len() as u64))
+    }
+
+    fn truncate(&self, fd: FileDescriptor, len: u64) -> KResult<(u64, u64)> {
+        let pid = current_pid()?;
+        cnrfs::MlnrKernelNode::file_truncate(pid, fd, len)
+    }
+
+    fn seek(&self, fd: FileDescriptor, offset: i64, whence: SeekWhence) -> KResult<(u64, u64)> {
+        let pid = current_pid()?;
+        cnrfs::MlnrKernelNode::file_seek(pid, fd, offset, whence)
+    }
+
+    fn fstat(&self, fd: FileDescriptor) -> KResult<(u64, u64)> {
+        let pid = current_pid()?;
+        cnrfs::MlnrKernelNode::file_stat(pid, fd)
+    }
+
+    fn ring_register(&self, ring: UVAddr) -> KResult<(u64, u64)> {
+        let pid = current_pid()?;
+        ring::register(pid, ring)
+    }
+
+    fn ring_enter(&self) -> KResult<(u64, u64)> {
+        let pid = current_pid()?;
+        ring::enter(self, pid)
+    }
+
+    /// Appends the operations on files to the logs together (one operation
+    /// per log for every run of reads or modifications).
+    fn execute_batch(&self, ops: Vec<RingOp>) -> KResult<Vec<KResult<(u64, u64)>>> {
+        let pid = current_pid()?;
+        cnrfs::MlnrKernelNode::file_batch(pid, ops)
+    }
+
+    fn pipe(&self) -> KResult<(u64, u64)> {
+        let pid = current_pid()?;
+        cnrfs::MlnrKernelNode::pipe(pid)
+    }
 }
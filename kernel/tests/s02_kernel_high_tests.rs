@@ -309,6 +309,41 @@ fn s02_test_fs_prop() {
     check_for_successful_exit(&cmdline, qemu_run(), output);
 }
 
+/// Tests that the file-system contents survive a reboot when it's stored in
+/// persistent memory.
+#[test]
+fn s02_test_pmem_fs() {
+    let pmem_path = "pmem-fs-test";
+    let _ignore = std::fs::remove_dir_all(pmem_path);
+
+    let build = BuildArgs::default()
+        .module("init")
+        .user_feature("test-pmem-fs")
+        .release()
+        .build();
+    let cmdline = RunnerArgs::new_with_build("userspace", &build)
+        .nodes(1)
+        .pmem(512)
+        .pmem_path(pmem_path)
+        .cmd("fs=pmem")
+        .timeout(20_000);
+
+    for boot in 1..=3 {
+        let mut output = String::new();
+        let mut qemu_run = || -> Result<WaitStatus> {
+            let mut p = spawn_nrk(&cmdline)?;
+
+            p.exp_string(&format!("pmem_fs_test OK (boot {})", boot))?;
+            output = p.exp_eof()?;
+            p.process.exit()
+        };
+
+        check_for_successful_exit(&cmdline, qemu_run(), output);
+    }
+
+    let _ignore = std::fs::remove_dir_all(pmem_path);
+}
+
 /// Checks vspace debug functionality.
 #[test]
 fn s02_vspace_debug() {
@@ -25,6 +25,8 @@ pub struct RunnerArgs<'a> {
     memory: usize,
     /// Total persistent memory of the system (in MiB).
     pmem: usize,
+    /// Directory of the files that back the persistent memory.
+    pmem_path: String,
     /// Kernel command line argument.
     cmd: Option<&'a str>,
     /// If true don't run, just compile.
@@ -70,6 +72,7 @@ impl<'a> RunnerArgs<'a> {
             cores: 1,
             memory: 1024,
             pmem: 0,
+            pmem_path: String::new(),
             cmd: None,
             norun: false,
             nobuild: false,
@@ -104,6 +107,7 @@ impl<'a> RunnerArgs<'a> {
             cores: 1,
             memory: 1024,
             pmem: 0,
+            pmem_path: String::new(),
             cmd: None,
             norun: false,
             nobuild: false,
@@ -181,6 +185,13 @@ impl<'a> RunnerArgs<'a> {
         self
     }
 
+    /// Back the persistent memory with files in `path`, so its contents
+    /// survive from one run to the next.
+    pub fn pmem_path(mut self, path: &str) -> RunnerArgs<'a> {
+        self.pmem_path = String::from(path);
+        self
+    }
+
     /// Command line passed to the kernel.
     pub fn cmd(mut self, cmd: &'a str) -> RunnerArgs<'a> {
         self.cmd = Some(cmd);
@@ -316,6 +327,11 @@ impl<'a> RunnerArgs<'a> {
                     cmd.push(format!("{}", self.pmem));
                 }
 
+                if !self.pmem_path.is_empty() {
+                    cmd.push(String::from("--qemu-pmem-path"));
+                    cmd.push(format!("{}", self.pmem_path));
+                }
+
                 if self.shmem_size > 0 {
                     cmd.push(String::from("--qemu-ivshmem"));
                     cmd.push(format!("{}", self.shmem_size));
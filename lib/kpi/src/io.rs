@@ -1,8 +1,11 @@
 // Copyright © 2021 VMware, Inc. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
+use alloc::string::String;
+
 use abomonation::Abomonation;
 use bitflags::*;
+use serde::{Deserialize, Serialize};
 
 /// Struct used in `file_getinfo` systemcall.
 #[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
@@ -13,7 +16,7 @@ pub struct FileInfo {
 unsafe_abomonate!(FileInfo);
 
 /// Each file-node can be of two types: directory or a file.
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
 #[repr(u64)]
 pub enum FileType {
     /// The mnode is of directory type
@@ -31,6 +34,49 @@ impl From<FileType> for u64 {
     }
 }
 
+/// Reference point for the new offset in the `lseek` systemcall.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+#[repr(u64)]
+pub enum SeekWhence {
+    /// The offset is set to the given value
+    Set = 0,
+    /// The offset is set to the current offset plus the given value
+    Current = 1,
+    /// The offset is set to the size of the file plus the given value
+    End = 2,
+}
+
+impl SeekWhence {
+    /// Construct a SeekWhence enum based on a 64-bit value.
+    pub fn new(whence: u64) -> Option<Self> {
+        match whence {
+            0 => Some(Self::Set),
+            1 => Some(Self::Current),
+            2 => Some(Self::End),
+            _ => None,
+        }
+    }
+}
+
+impl From<SeekWhence> for u64 {
+    fn from(whence: SeekWhence) -> Self {
+        whence as u64
+    }
+}
+
+/// An entry of a directory, returned by the `readdir` systemcall.
+///
+/// Serialized with serde since the names don't fit into registers.
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct DirEntry {
+    /// Name of the entry (relative to the directory).
+    pub name: String,
+    /// The mnode number of the entry.
+    pub mnode: u64,
+    /// File or directory.
+    pub ftype: FileType,
+}
+
 bitflags! {
     /// File flags to open the file
     pub struct FileFlags: u64 {
@@ -13,6 +13,7 @@ extern crate abomonation;
 
 pub mod io;
 pub mod process;
+pub mod ring;
 pub mod system;
 pub mod upcall;
 pub mod x86_64;
@@ -56,6 +57,20 @@ pub enum SystemCallError {
     PermissionError = 9,
     /// Bad offset
     OffsetError = 10,
+    /// The operation would have to block (e.g., reading from an empty pipe).
+    WouldBlock = 11,
+    /// Writing to a pipe without readers.
+    BrokenPipe = 12,
+    /// The process has no (matching) child process to wait for.
+    NoChildProcess = 13,
+    /// There is no binary with the requested name.
+    UnknownBinary = 14,
+    /// The arguments are malformed (e.g., a command line that is too long).
+    InvalidArguments = 15,
+    /// A component of the path is not a directory.
+    NotADirectory = 16,
+    /// The directory isn't empty.
+    DirectoryNotEmpty = 17,
     /// Placeholder for an invalid, unknown error code.
     Unknown,
 }
@@ -74,6 +89,13 @@ impl From<u64> for SystemCallError {
             8 => SystemCallError::BadFlags,
             9 => SystemCallError::PermissionError,
             10 => SystemCallError::OffsetError,
+            11 => SystemCallError::WouldBlock,
+            12 => SystemCallError::BrokenPipe,
+            13 => SystemCallError::NoChildProcess,
+            14 => SystemCallError::UnknownBinary,
+            15 => SystemCallError::InvalidArguments,
+            16 => SystemCallError::NotADirectory,
+            17 => SystemCallError::DirectoryNotEmpty,
             _ => SystemCallError::Unknown,
         }
     }
@@ -102,6 +124,18 @@ pub enum ProcessOperation {
     AllocatePhysical = 8,
     /// Release a physical memory page from the process.
     ReleasePhysical = 9,
+    /// Start a new process from a binary loaded by the bootloader.
+    Spawn = 10,
+    /// Wait for a child process to exit.
+    Wait = 11,
+    /// Allocate a physical memory page as a shared memory object.
+    CreateShm = 12,
+    /// Register an existing shared memory object with the process.
+    AttachShm = 13,
+    /// Give a core back to the kernel.
+    ReleaseCore = 14,
+    /// Let other processes that share the core run first.
+    Yield = 15,
 }
 
 impl ProcessOperation {
@@ -117,6 +151,12 @@ impl ProcessOperation {
             7 => Some(Self::RequestCore),
             8 => Some(Self::AllocatePhysical),
             9 => Some(Self::ReleasePhysical),
+            10 => Some(Self::Spawn),
+            11 => Some(Self::Wait),
+            12 => Some(Self::CreateShm),
+            13 => Some(Self::AttachShm),
+            14 => Some(Self::ReleaseCore),
+            15 => Some(Self::Yield),
             _ => None,
         }
     }
@@ -150,6 +190,22 @@ pub enum VSpaceOperation {
     MapPMem = 6,
     /// Unmap a PMem mapped region
     UnmapPMem = 7,
+    /// Reserve a region that is backed with memory on first access
+    ReserveMem = 8,
+    /// Change the access rights of a mapped region
+    Protect = 9,
+    /// Map the memory of a region a second time (copy-on-write)
+    Snapshot = 10,
+    /// Map some anonymous memory with a given NUMA policy
+    MapMemPolicy = 11,
+    /// Set the NUMA policy for anonymous memory of the process
+    SetMemPolicy = 12,
+    /// Map a range of an open file
+    MapFile = 13,
+    /// Unmap a mapped range of a file
+    UnmapFile = 14,
+    /// Write the changes of a shared file mapping back to the file
+    SyncFile = 15,
 }
 
 impl VSpaceOperation {
@@ -163,11 +219,87 @@ impl VSpaceOperation {
             5 => Some(Self::Identify),
             6 => Some(Self::MapPMem),
             7 => Some(Self::UnmapPMem),
+            8 => Some(Self::ReserveMem),
+            9 => Some(Self::Protect),
+            10 => Some(Self::Snapshot),
+            11 => Some(Self::MapMemPolicy),
+            12 => Some(Self::SetMemPolicy),
+            13 => Some(Self::MapFile),
+            14 => Some(Self::UnmapFile),
+            15 => Some(Self::SyncFile),
             _ => None,
         }
     }
 }
 
+/// NUMA nodes anonymous memory is allocated from (see
+/// `VSpaceOperation::MapMemPolicy` and `VSpaceOperation::SetMemPolicy`).
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum MemPolicy {
+    /// From the node of the core that maps the memory.
+    Local,
+    /// Only from the given node.
+    Bind(u64),
+    /// Page by page from all nodes in turn.
+    Interleave,
+    /// From the given node, or from the local node if it runs out of memory.
+    Preferred(u64),
+}
+
+impl MemPolicy {
+    /// Construct a MemPolicy from its 64-bit encoding (the policy in the
+    /// lowest byte, the node above it).
+    pub fn new(policy: u64) -> Option<Self> {
+        let node = policy >> 8;
+        match policy & 0xff {
+            0 if node == 0 => Some(Self::Local),
+            1 => Some(Self::Bind(node)),
+            2 if node == 0 => Some(Self::Interleave),
+            3 => Some(Self::Preferred(node)),
+            _ => None,
+        }
+    }
+}
+
+impl Default for MemPolicy {
+    fn default() -> Self {
+        MemPolicy::Local
+    }
+}
+
+impl From<MemPolicy> for u64 {
+    fn from(policy: MemPolicy) -> u64 {
+        match policy {
+            MemPolicy::Local => 0,
+            MemPolicy::Bind(node) => 1 | node << 8,
+            MemPolicy::Interleave => 2,
+            MemPolicy::Preferred(node) => 3 | node << 8,
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// Access rights for `VSpaceOperation::Protect` (no rights at all make
+    /// a guard region).
+    pub struct MemProtection: u64 {
+        const NONE = 0x0;
+        const READ = 0x1;
+        const WRITE = 0x2;
+        const EXEC = 0x4;
+    }
+}
+
+bitflags::bitflags! {
+    /// How `VSpaceOperation::MapFile` maps a file.
+    pub struct MapFileFlags: u64 {
+        /// Map the memory of the file itself, writes go to the file (without
+        /// it the mapping is a private copy of the file content).
+        const SHARED = 0x1;
+        /// The mapping is writable.
+        const WRITE = 0x2;
+    }
+}
+
 /// Flags for the fs related system call
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 #[repr(u64)]
@@ -192,6 +324,20 @@ pub enum FileOperation {
     FileRename = 9,
     /// Create a directory.
     MkDir = 10,
+    /// List the entries of a directory.
+    ReadDir = 11,
+    /// Shrink or grow a file to a given length.
+    Truncate = 12,
+    /// Move the offset of a file descriptor.
+    Seek = 13,
+    /// Get the information related to the file given by a file descriptor.
+    FStat = 14,
+    /// Create a pipe, returns a file descriptor for each end.
+    Pipe = 15,
+    /// Execute the file operations queued in the registered `IoRing`.
+    RingEnter = 16,
+    /// Register the `IoRing` of the process.
+    RingRegister = 17,
 }
 
 impl FileOperation {
@@ -208,6 +354,13 @@ impl FileOperation {
             8 => Some(Self::Delete),
             9 => Some(Self::FileRename),
             10 => Some(Self::MkDir),
+            11 => Some(Self::ReadDir),
+            12 => Some(Self::Truncate),
+            13 => Some(Self::Seek),
+            14 => Some(Self::FStat),
+            15 => Some(Self::Pipe),
+            16 => Some(Self::RingEnter),
+            17 => Some(Self::RingRegister),
             _ => None,
         }
     }
@@ -263,3 +416,19 @@ impl SystemCall {
         }
     }
 }
+
+#[cfg(test)]
+#[test]
+fn mem_policy_roundtrip() {
+    for policy in [
+        MemPolicy::Local,
+        MemPolicy::Bind(3),
+        MemPolicy::Interleave,
+        MemPolicy::Preferred(0),
+        MemPolicy::Preferred(7),
+    ] {
+        assert_eq!(MemPolicy::new(u64::from(policy)), Some(policy));
+    }
+    assert_eq!(MemPolicy::new(4), None);
+    assert_eq!(MemPolicy::new(2 | 1 << 8), None);
+}
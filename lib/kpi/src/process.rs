@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 use core::convert::TryInto;
+use core::iter::FromIterator;
 
 use serde::{Deserialize, Serialize};
 use x86::bits64::paging::PML4_SLOT_SIZE;
@@ -24,6 +25,12 @@ pub const HEAP_PER_CORE_REGION: usize = 0x2_0000_0000;
 /// End of Heap memory.
 pub const HEAP_END: usize = HEAP_START + ((MAX_CORES + 1) * HEAP_PER_CORE_REGION);
 
+/// Maximum length (in bytes) of the argument string of a spawned process.
+pub const MAX_CMDLINE_LEN: usize = 256;
+
+/// Pid argument for `ProcessOperation::Wait` to wait for any child.
+pub const WAIT_ANY: u64 = u64::MAX;
+
 // Make sure that all our process regions are in the first PML4 slot. This isn't
 // really necessary for anything except benchmarking: it helps for scalability
 // benchmarks if we know that all other slots are "empty" and we don't
@@ -31,9 +38,14 @@ pub const HEAP_END: usize = HEAP_START + ((MAX_CORES + 1) * HEAP_PER_CORE_REGION
 static_assertions::const_assert!(HEAP_END <= 2 * PML4_SLOT_SIZE);
 static_assertions::const_assert!(EXECUTOR_OFFSET <= PML4_SLOT_SIZE);
 static_assertions::const_assert!(ELF_OFFSET <= PML4_SLOT_SIZE);
+// A `CoreMask` has one bit for every core.
+static_assertions::const_assert!(MAX_CORES <= 128);
 
 pub type FrameId = usize;
 
+/// Identifies a shared memory object other processes can attach to.
+pub type ShmId = u64;
+
 #[derive(Debug)]
 pub struct CoreToken(usize);
 
@@ -44,10 +56,122 @@ impl CoreToken {
     }
 }
 
+/// A set of cores (hardware thread IDs) a process is allowed to run on.
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct CoreMask(u128);
+
+impl CoreMask {
+    /// The mask that allows all cores.
+    pub const fn all() -> Self {
+        CoreMask(u128::MAX)
+    }
+
+    /// Adds `core` to the mask.
+    pub fn set(&mut self, core: usize) {
+        assert!(core < MAX_CORES, "Invalid core");
+        self.0 |= 1u128 << core;
+    }
+
+    /// Removes `core` from the mask.
+    pub fn clear(&mut self, core: usize) {
+        assert!(core < MAX_CORES, "Invalid core");
+        self.0 &= !(1u128 << core);
+    }
+
+    /// Does the mask contain `core`?
+    pub fn contains(&self, core: usize) -> bool {
+        core < MAX_CORES && self.0 & (1u128 << core) != 0
+    }
+
+    /// Returns true if no core is set.
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Iterates over all cores in the mask.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..MAX_CORES).filter(move |core| self.contains(*core))
+    }
+}
+
+impl FromIterator<usize> for CoreMask {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut mask = CoreMask::default();
+        for core in iter {
+            mask.set(core);
+        }
+        mask
+    }
+}
+
+/// Arguments of the spawn system call (see `ProcessOperation::Spawn`).
+///
+/// Serialized with serde since the strings don't fit into registers.
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct SpawnArgs<'a> {
+    /// Name of the binary (a module loaded by the bootloader).
+    pub binary: &'a str,
+    /// Arguments, the new process finds them in `ProcessInfo::cmdline`.
+    pub cmdline: &'a str,
+    /// Cores the new process may start on (an empty mask means any core).
+    pub affinity: CoreMask,
+    /// Copy the file descriptors of the parent (e.g., the ends of a pipe).
+    pub inherit_fds: bool,
+    /// How many bytes of DRAM the new process may use.
+    ///
+    /// If the parent has a limit, the limit of the new process is taken from
+    /// what the parent has left (`None` takes all of it) and given back once
+    /// the new process exits.
+    pub mem_limit: Option<u64>,
+    /// How many bytes of PMem the new process may use (see `mem_limit`).
+    pub pmem_limit: Option<u64>,
+}
+
+/// The memory of one `MemType` a process uses (see `ProcessInfo`).
+///
+/// This only counts memory the process asked for explicitly (mapped memory,
+/// reserved memory once it is accessed, copies of copy-on-write pages and
+/// frames from `AllocatePhysical`), not its binary or its executors.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MemUsage {
+    /// Bytes of memory mapped into the address space.
+    pub mapped: u64,
+    /// Bytes of frames allocated to the process (mapping them again doesn't
+    /// count towards `mapped`).
+    pub allocated: u64,
+    /// Bytes handed to the (running) children of the process as their limit.
+    pub children: u64,
+    /// How many bytes `mapped`, `allocated` and `children` may add up to.
+    pub limit: u64,
+}
+
+impl MemUsage {
+    /// How many bytes the process uses in total.
+    pub fn used(&self) -> u64 {
+        self.mapped + self.allocated + self.children
+    }
+
+    /// How many more bytes the process may use.
+    pub fn available(&self) -> u64 {
+        self.limit.saturating_sub(self.used())
+    }
+}
+
+impl Default for MemUsage {
+    fn default() -> Self {
+        MemUsage {
+            mapped: 0,
+            allocated: 0,
+            children: 0,
+            limit: u64::MAX,
+        }
+    }
+}
+
 // TODO: still use serde instead of abomonation because abomonation doesn't
-// know how to handle 'static string.
+// know how to handle borrowed strings.
 #[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, Eq, PartialEq)]
-pub struct ProcessInfo {
+pub struct ProcessInfo<'a> {
     pub has_tls: bool,
     /// Start of initial TLS data section in the address space.
     pub tls_data: u64,
@@ -58,10 +182,14 @@ pub struct ProcessInfo {
     /// Required alignment
     pub alignment: u64,
     /// Command line arguments
-    pub cmdline: &'static str,
+    pub cmdline: &'a str,
     /// App specific command line argument, for example: benchmarks, reads,
     /// value_size for leveldb (passed to the rump init function).
-    pub app_cmdline: &'static str,
+    pub app_cmdline: &'a str,
+    /// DRAM used by the process (`MemType::Mem`).
+    pub mem_usage: MemUsage,
+    /// PMem used by the process (`MemType::PMem`).
+    pub pmem_usage: MemUsage,
 }
 
 #[cfg(test)]
@@ -78,10 +206,46 @@ fn serialize() {
         alignment: 3,
         cmdline: "test",
         app_cmdline: "app_cmdline",
+        mem_usage: MemUsage {
+            mapped: 4096,
+            allocated: 2 * 1024 * 1024,
+            children: 16 * 1024 * 1024,
+            limit: 64 * 1024 * 1024,
+        },
+        pmem_usage: Default::default(),
     };
 
     let serialized: &'static [u8] = Vec::leak(serde_cbor::to_vec(&point).unwrap());
     let deserialized: ProcessInfo = serde_cbor::from_slice(&serialized).unwrap();
     log::info!("serialized.len = {}", serialized.len());
     log::info!("deserialized = {:?}", deserialized);
+    assert_eq!(point, deserialized);
+    assert_eq!(deserialized.mem_usage.available(), 46 * 1024 * 1024 - 4096);
+}
+
+#[cfg(test)]
+#[test]
+fn spawn_args_roundtrip() {
+    let mut affinity = CoreMask::default();
+    affinity.set(1);
+    affinity.set(MAX_CORES - 1);
+    let args = SpawnArgs {
+        binary: "init",
+        cmdline: "testcmd=fs",
+        affinity,
+        inherit_fds: true,
+        mem_limit: Some(1 << 30),
+        pmem_limit: None,
+    };
+
+    let serialized = serde_cbor::to_vec(&args).unwrap();
+    let deserialized: SpawnArgs = serde_cbor::from_slice(&serialized).unwrap();
+    assert_eq!(args, deserialized);
+    assert_eq!(
+        deserialized
+            .affinity
+            .iter()
+            .collect::<alloc::vec::Vec<usize>>(),
+        [1, MAX_CORES - 1]
+    );
 }
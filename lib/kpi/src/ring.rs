@@ -0,0 +1,224 @@
+// Copyright © 2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! A ring of file operations shared between a process and the kernel.
+//!
+//! The process registers its [`IoRing`] with the kernel once
+//! (`FileOperation::RingRegister`). It puts [`Submission`]s in the submission
+//! queue of the ring and enters the kernel once for all of them
+//! (`FileOperation::RingEnter`). The kernel executes them in order and puts a
+//! [`Completion`] for each of them in the completion queue.
+//!
+//! Both queues are single-producer, single-consumer: the process produces
+//! submissions and consumes completions, the kernel does the opposite. The
+//! indices grow forever, an entry lives at `index % RING_ENTRIES`.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{FileOperation, SystemCallError};
+
+/// How many entries fit in each of the queues.
+pub const RING_ENTRIES: usize = 32;
+
+/// A file operation to execute.
+///
+/// Uses the arguments of the system call with the same operation, except
+/// for `Seek` (the whence is in `len`) and `Truncate` (the new length is in
+/// `len`).
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Submission {
+    /// The `FileOperation` to execute.
+    pub op: u64,
+    /// The file descriptor to operate on.
+    pub fd: u64,
+    /// Address of the buffer to read to or write from.
+    pub buf: u64,
+    /// Length of the buffer.
+    pub len: u64,
+    /// Offset in the file (-1 uses and updates the offset of the fd).
+    pub offset: i64,
+    /// Handed back in the completion of the operation.
+    pub user_data: u64,
+}
+
+impl Submission {
+    /// Reads from the offset of `fd` into `buffer`.
+    pub fn read(fd: u64, buffer: &mut [u8], user_data: u64) -> Self {
+        Submission::read_at(fd, buffer, -1, user_data)
+    }
+
+    /// Reads from `offset` in `fd` into `buffer`.
+    pub fn read_at(fd: u64, buffer: &mut [u8], offset: i64, user_data: u64) -> Self {
+        Submission {
+            op: FileOperation::ReadAt as u64,
+            fd,
+            buf: buffer.as_mut_ptr() as u64,
+            len: buffer.len() as u64,
+            offset,
+            user_data,
+        }
+    }
+
+    /// Writes `buffer` at the offset of `fd`.
+    pub fn write(fd: u64, buffer: &[u8], user_data: u64) -> Self {
+        Submission::write_at(fd, buffer, -1, user_data)
+    }
+
+    /// Writes `buffer` at `offset` in `fd`.
+    pub fn write_at(fd: u64, buffer: &[u8], offset: i64, user_data: u64) -> Self {
+        Submission {
+            op: FileOperation::WriteAt as u64,
+            fd,
+            buf: buffer.as_ptr() as u64,
+            len: buffer.len() as u64,
+            offset,
+            user_data,
+        }
+    }
+
+    /// Closes `fd`.
+    pub fn close(fd: u64, user_data: u64) -> Self {
+        Submission {
+            op: FileOperation::Close as u64,
+            fd,
+            user_data,
+            ..Default::default()
+        }
+    }
+}
+
+/// The outcome of a [`Submission`].
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Completion {
+    /// The `user_data` of the submission.
+    pub user_data: u64,
+    /// What the system call would have returned (e.g., the bytes written).
+    pub result: u64,
+    /// A `SystemCallError` (0 if the operation succeeded).
+    pub error: u64,
+}
+
+impl Completion {
+    /// The result of the operation.
+    pub fn result(&self) -> Result<u64, SystemCallError> {
+        if self.error == 0 {
+            Ok(self.result)
+        } else {
+            Err(SystemCallError::from(self.error))
+        }
+    }
+}
+
+/// The submission and completion queues, they take up one page.
+#[repr(C, align(4096))]
+#[derive(Debug)]
+pub struct IoRing {
+    /// Next submission the kernel takes (written by the kernel).
+    pub sq_head: AtomicU64,
+    /// Next free submission slot (written by the process).
+    pub sq_tail: AtomicU64,
+    /// Next completion the process takes (written by the process).
+    pub cq_head: AtomicU64,
+    /// Next free completion slot (written by the kernel).
+    pub cq_tail: AtomicU64,
+    /// The submission queue.
+    pub sq: [Submission; RING_ENTRIES],
+    /// The completion queue.
+    pub cq: [Completion; RING_ENTRIES],
+}
+
+static_assertions::const_assert_eq!(core::mem::size_of::<IoRing>(), 4096);
+
+impl Default for IoRing {
+    fn default() -> Self {
+        IoRing {
+            sq_head: AtomicU64::new(0),
+            sq_tail: AtomicU64::new(0),
+            cq_head: AtomicU64::new(0),
+            cq_tail: AtomicU64::new(0),
+            sq: [Default::default(); RING_ENTRIES],
+            cq: [Default::default(); RING_ENTRIES],
+        }
+    }
+}
+
+impl IoRing {
+    /// Queues `submission` (it runs on the next `RingEnter`).
+    ///
+    /// Hands `submission` back if the submission queue is full.
+    pub fn submit(&mut self, submission: Submission) -> Result<(), Submission> {
+        let tail = self.sq_tail.load(Ordering::Relaxed);
+        let head = self.sq_head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) >= RING_ENTRIES as u64 {
+            return Err(submission);
+        }
+
+        self.sq[tail as usize % RING_ENTRIES] = submission;
+        self.sq_tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Takes the oldest completion from the completion queue.
+    pub fn complete(&mut self) -> Option<Completion> {
+        let head = self.cq_head.load(Ordering::Relaxed);
+        let tail = self.cq_tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+
+        let completion = self.cq[head as usize % RING_ENTRIES];
+        self.cq_head.store(head.wrapping_add(1), Ordering::Release);
+        Some(completion)
+    }
+
+    /// How many submissions the kernel didn't take yet.
+    pub fn pending(&self) -> usize {
+        let tail = self.sq_tail.load(Ordering::Relaxed);
+        let head = self.sq_head.load(Ordering::Acquire);
+        tail.wrapping_sub(head) as usize
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn ring_wraps_around() {
+    let mut ring: IoRing = Default::default();
+
+    for round in 0..3u64 {
+        for i in 0..RING_ENTRIES as u64 {
+            assert!(ring.submit(Submission::close(i, round)).is_ok());
+        }
+        assert!(
+            ring.submit(Submission::close(0, 0)).is_err(),
+            "Queue is full"
+        );
+        assert_eq!(ring.pending(), RING_ENTRIES);
+
+        // Play kernel: take all submissions and complete them
+        let head = ring.sq_head.load(Ordering::Relaxed);
+        let tail = ring.cq_tail.load(Ordering::Relaxed);
+        for i in 0..RING_ENTRIES as u64 {
+            let submission = ring.sq[(head + i) as usize % RING_ENTRIES];
+            assert_eq!(submission.fd, i);
+            ring.cq[(tail + i) as usize % RING_ENTRIES] = Completion {
+                user_data: submission.user_data,
+                result: submission.fd,
+                error: 0,
+            };
+        }
+        ring.sq_head
+            .store(head + RING_ENTRIES as u64, Ordering::Release);
+        ring.cq_tail
+            .store(tail + RING_ENTRIES as u64, Ordering::Release);
+
+        for i in 0..RING_ENTRIES as u64 {
+            let completion = ring.complete().expect("Missing completion");
+            assert_eq!(completion.user_data, round);
+            assert_eq!(completion.result(), Ok(i));
+        }
+        assert_eq!(ring.complete(), None);
+        assert_eq!(ring.pending(), 0);
+    }
+}
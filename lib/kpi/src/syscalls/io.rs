@@ -3,13 +3,17 @@
 
 //! Abstraction for system calls to access the global file-system and control interrupts.
 
+use alloc::vec::Vec;
 use core::convert::TryInto;
 
 use crate::io::*;
+use crate::ring::IoRing;
 use crate::*;
 
 use crate::syscall;
 
+use super::Process;
+
 /// System calls related to interrupt routing.
 pub struct Irq;
 
@@ -78,7 +82,19 @@ impl Fs {
         }
     }
 
+    /// Read from `fd`, waits until data is available if `fd` is an empty pipe.
     pub fn read(fd: u64, buffer: &mut [u8]) -> Result<u64, SystemCallError> {
+        Fs::blocking(|| Fs::try_read(fd, buffer))
+    }
+
+    /// Write to `fd`, waits until there is space if `fd` is a full pipe.
+    pub fn write(fd: u64, buffer: &[u8]) -> Result<u64, SystemCallError> {
+        Fs::blocking(|| Fs::try_write(fd, buffer))
+    }
+
+    /// Read from `fd`, returns `SystemCallError::WouldBlock` if `fd` is an
+    /// empty pipe.
+    pub fn try_read(fd: u64, buffer: &mut [u8]) -> Result<u64, SystemCallError> {
         Fs::fileio(
             FileOperation::Read,
             fd,
@@ -87,7 +103,9 @@ impl Fs {
         )
     }
 
-    pub fn write(fd: u64, buffer: &[u8]) -> Result<u64, SystemCallError> {
+    /// Write to `fd`, returns `SystemCallError::WouldBlock` if `fd` is a full
+    /// pipe.
+    pub fn try_write(fd: u64, buffer: &[u8]) -> Result<u64, SystemCallError> {
         Fs::fileio(
             FileOperation::Write,
             fd,
@@ -96,6 +114,74 @@ impl Fs {
         )
     }
 
+    /// Retries `op` as long as it would block, the other end gets to run
+    /// in between (if it shares the core with us).
+    fn blocking<F: FnMut() -> Result<u64, SystemCallError>>(
+        mut op: F,
+    ) -> Result<u64, SystemCallError> {
+        loop {
+            match op() {
+                Err(SystemCallError::WouldBlock) => Process::yield_core()?,
+                r => return r,
+            }
+        }
+    }
+
+    /// Create a pipe.
+    ///
+    /// Returns the file descriptors of the read and the write end. Reading
+    /// returns 0 bytes once the pipe is empty and all write ends are closed,
+    /// writing fails with `SystemCallError::BrokenPipe` once all read ends
+    /// are closed.
+    pub fn pipe() -> Result<(u64, u64), SystemCallError> {
+        let (r, read_fd, write_fd) =
+            unsafe { syscall!(SystemCall::FileIO as u64, FileOperation::Pipe, 3) };
+
+        if r == 0 {
+            Ok((read_fd, write_fd))
+        } else {
+            Err(SystemCallError::from(r))
+        }
+    }
+
+    /// Register `ring` for the file operations of the process (replaces the
+    /// previous ring).
+    ///
+    /// The kernel accesses the ring on every `ring_enter` from now on, so it
+    /// has to stay where it is for as long as the process uses it.
+    pub fn ring_register(ring: &mut IoRing) -> Result<(), SystemCallError> {
+        let r = unsafe {
+            syscall!(
+                SystemCall::FileIO as u64,
+                FileOperation::RingRegister,
+                ring as *mut IoRing as u64,
+                1
+            )
+        };
+
+        if r == 0 {
+            Ok(())
+        } else {
+            Err(SystemCallError::from(r))
+        }
+    }
+
+    /// Execute the file operations queued in the registered ring (with one
+    /// system call).
+    ///
+    /// Operations run in the order they were submitted. Returns how many
+    /// completed, the rest stays queued if the completion queue fills up.
+    pub fn ring_enter() -> Result<u64, SystemCallError> {
+        let (r, completed) =
+            unsafe { syscall!(SystemCall::FileIO as u64, FileOperation::RingEnter, 2) };
+
+        if r == 0 {
+            Ok(completed)
+        } else {
+            Err(SystemCallError::from(r))
+        }
+    }
+
     /// Read or write an opened file. `fd` is the file descriptor for the opened file.
     fn fileio(op: FileOperation, fd: u64, buffer: u64, len: u64) -> Result<u64, SystemCallError> {
         if len == 0 {
@@ -248,4 +334,92 @@ impl Fs {
             Err(SystemCallError::from(r))
         }
     }
+
+    /// List the entries of the directory given by `path`.
+    pub fn readdir<T: AsRef<str>>(path: T) -> Result<Vec<DirEntry>, SystemCallError> {
+        let mut buf = alloc::vec![0; 1024];
+        loop {
+            let (r, len) = unsafe {
+                syscall!(
+                    SystemCall::FileIO as u64,
+                    FileOperation::ReadDir as u64,
+                    path.as_ref().as_ptr(),
+                    path.as_ref().len(),
+                    buf.as_mut_ptr() as u64,
+                    buf.len() as u64,
+                    2
+                )
+            };
+
+            if r != 0 {
+                return Err(SystemCallError::from(r));
+            }
+
+            let len = len as usize;
+            if len > buf.len() {
+                // Directory didn't fit, retry with the size the kernel asked for
+                buf.resize(len, 0);
+                continue;
+            }
+
+            return serde_cbor::from_slice(&buf[..len])
+                .map_err(|_e| SystemCallError::InternalError);
+        }
+    }
+
+    /// Set the length of the file referred to by `fd` to `len` bytes.
+    ///
+    /// If the file shrinks the data past `len` is discarded, if it grows the
+    /// new part of the file reads as zeros.
+    pub fn truncate(fd: u64, len: u64) -> Result<(), SystemCallError> {
+        let r = unsafe {
+            syscall!(
+                SystemCall::FileIO as u64,
+                FileOperation::Truncate,
+                fd,
+                len,
+                1
+            )
+        };
+
+        if r == 0 {
+            Ok(())
+        } else {
+            Err(SystemCallError::from(r))
+        }
+    }
+
+    /// Move the offset of the file descriptor `fd` relative to `whence`.
+    ///
+    /// Returns the new offset from the start of the file.
+    pub fn lseek(fd: u64, offset: i64, whence: SeekWhence) -> Result<u64, SystemCallError> {
+        let (r, new_offset) = unsafe {
+            syscall!(
+                SystemCall::FileIO as u64,
+                FileOperation::Seek,
+                fd,
+                offset as u64,
+                u64::from(whence),
+                2
+            )
+        };
+
+        if r == 0 {
+            Ok(new_offset)
+        } else {
+            Err(SystemCallError::from(r))
+        }
+    }
+
+    /// Retrieve information about the file referred to by `fd`.
+    pub fn fstat(fd: u64) -> Result<FileInfo, SystemCallError> {
+        let (r, ftype, fsize) =
+            unsafe { syscall!(SystemCall::FileIO as u64, FileOperation::FStat, fd, 3) };
+
+        if r == 0 {
+            Ok(FileInfo { ftype, fsize })
+        } else {
+            Err(SystemCallError::from(r))
+        }
+    }
 }
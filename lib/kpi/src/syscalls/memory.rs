@@ -5,7 +5,7 @@
 
 use core::convert::TryInto;
 
-use crate::process::FrameId;
+use crate::process::{FrameId, ShmId};
 use crate::*;
 
 use crate::syscall;
@@ -24,6 +24,168 @@ impl VSpace {
         VSpace::vspace(VSpaceOperation::MapMem, base, bound)
     }
 
+    /// Back a region of memory with DRAM from the NUMA nodes given by
+    /// `policy` (instead of the policy of the process).
+    ///
+    /// # Safety
+    /// Manipulates address space of process.
+    pub unsafe fn map_with_policy(
+        base: u64,
+        bound: u64,
+        policy: MemPolicy,
+    ) -> Result<(VAddr, PAddr), SystemCallError> {
+        let (err, paddr, _size) = syscall!(
+            SystemCall::VSpace as u64,
+            VSpaceOperation::MapMemPolicy as u64,
+            base,
+            bound,
+            u64::from(policy),
+            3
+        );
+
+        if err == 0 {
+            Ok((VAddr::from(base), PAddr::from(paddr)))
+        } else {
+            Err(SystemCallError::from(err))
+        }
+    }
+
+    /// Set the NUMA nodes `map` and `map_pmem` allocate memory from (the
+    /// local node by default).
+    pub fn set_policy(policy: MemPolicy) -> Result<(), SystemCallError> {
+        let (err, _, _) = unsafe {
+            syscall!(
+                SystemCall::VSpace as u64,
+                VSpaceOperation::SetMemPolicy as u64,
+                u64::from(policy),
+                3
+            )
+        };
+
+        if err == 0 {
+            Ok(())
+        } else {
+            Err(SystemCallError::from(err))
+        }
+    }
+
+    /// Reserve a region of memory that is backed with DRAM page by page on
+    /// first access.
+    ///
+    /// The region is released again with `unmap`. System calls don't fault in
+    /// pages, so buffers passed to the kernel need to be touched first.
+    ///
+    /// # Safety
+    /// Manipulates address space of process.
+    pub unsafe fn reserve(base: u64, bound: u64) -> Result<VAddr, SystemCallError> {
+        VSpace::vspace(VSpaceOperation::ReserveMem, base, bound).map(|(vaddr, _paddr)| vaddr)
+    }
+
+    /// Change the access rights of the mapped region `base..base+bound`.
+    ///
+    /// Pages backing the region are changed as a whole (a large page
+    /// partially covered by the region gets the new rights too).
+    ///
+    /// # Safety
+    /// Manipulates address space of process.
+    pub unsafe fn protect(
+        base: u64,
+        bound: u64,
+        rights: MemProtection,
+    ) -> Result<(), SystemCallError> {
+        let (err, _, _) = syscall!(
+            SystemCall::VSpace as u64,
+            VSpaceOperation::Protect as u64,
+            base,
+            bound,
+            rights.bits(),
+            3
+        );
+
+        if err == 0 {
+            Ok(())
+        } else {
+            Err(SystemCallError::from(err))
+        }
+    }
+
+    /// Map the memory of `src..src+bound` a second time at `dst`.
+    ///
+    /// Both regions refer to the same memory until one of them is written
+    /// to, which gives the written page its own copy. `src` has to consist of
+    /// whole, writable mappings of `map` (or of earlier snapshots).
+    ///
+    /// # Safety
+    /// Manipulates address space of process.
+    pub unsafe fn snapshot(src: u64, dst: u64, bound: u64) -> Result<(), SystemCallError> {
+        let (err, _, _) = syscall!(
+            SystemCall::VSpace as u64,
+            VSpaceOperation::Snapshot as u64,
+            src,
+            dst,
+            bound,
+            3
+        );
+
+        if err == 0 {
+            Ok(())
+        } else {
+            Err(SystemCallError::from(err))
+        }
+    }
+
+    /// Map `bound` bytes of the file `fd` starting at `offset` at `base`.
+    ///
+    /// A shared mapping refers to the memory of the file, writes to it are
+    /// visible in the file on the same NUMA node right away and everywhere
+    /// else only after [`VSpace::sync_file`], [`VSpace::unmap_file`] or once
+    /// the process exits (like `msync`). Two processes on different NUMA
+    /// nodes that map the same file don't see each others writes until
+    /// then. A private
+    /// mapping starts out as a copy of the file content. `base`, `bound` and
+    /// `offset` have to be page aligned.
+    ///
+    /// # Safety
+    /// Manipulates address space of process.
+    pub unsafe fn map_file(
+        fd: u64,
+        base: u64,
+        bound: u64,
+        offset: u64,
+        flags: MapFileFlags,
+    ) -> Result<(), SystemCallError> {
+        // `offset` is page aligned, so the flags go into the lower bits
+        let err = syscall!(
+            SystemCall::VSpace as u64,
+            VSpaceOperation::MapFile as u64,
+            base,
+            bound,
+            fd,
+            offset | flags.bits(),
+            1
+        );
+
+        if err == 0 {
+            Ok(())
+        } else {
+            Err(SystemCallError::from(err))
+        }
+    }
+
+    /// Unmap a region mapped with [`VSpace::map_file`] (shared mappings
+    /// write their changes back to the file first).
+    ///
+    /// # Safety
+    /// Manipulates address space of process.
+    pub unsafe fn unmap_file(base: u64, bound: u64) -> Result<(), SystemCallError> {
+        VSpace::vspace(VSpaceOperation::UnmapFile, base, bound).map(|_r| ())
+    }
+
+    /// Write the changes in a shared file mapping back to the file.
+    pub fn sync_file(base: u64, bound: u64) -> Result<(), SystemCallError> {
+        unsafe { VSpace::vspace(VSpaceOperation::SyncFile, base, bound).map(|_r| ()) }
+    }
+
     /// Unmap region of virtual memory.
     ///
     /// # Safety
@@ -148,6 +310,54 @@ impl PhysicalMemory {
         }
     }
 
+    /// Allocate a page that other processes can attach to with the returned
+    /// `ShmId`.
+    ///
+    /// The page is registered with the calling process just like a frame from
+    /// `allocate_base_page` (it can be mapped with `VSpace::map_frame` and is
+    /// given back with `release_frame`). The page is freed once every process
+    /// released it.
+    pub fn create_shared(is_base: bool) -> Result<(ShmId, FrameId), SystemCallError> {
+        let page_size = if is_base {
+            x86::current::paging::BASE_PAGE_SIZE
+        } else {
+            x86::current::paging::LARGE_PAGE_SIZE
+        };
+        unsafe {
+            let (err, shm_id, frame_id) = syscall!(
+                SystemCall::Process as u64,
+                ProcessOperation::CreateShm as u64,
+                page_size,
+                3
+            );
+
+            if err == 0 {
+                Ok((shm_id, frame_id.try_into().unwrap()))
+            } else {
+                Err(SystemCallError::from(err))
+            }
+        }
+    }
+
+    /// Register the page of the shared memory object `id` with the process.
+    pub fn attach_shared(id: ShmId) -> Result<(FrameId, PAddr), SystemCallError> {
+        unsafe {
+            let (err, frame_id, paddr) = syscall!(
+                SystemCall::Process as u64,
+                ProcessOperation::AttachShm as u64,
+                id,
+                3
+            );
+
+            if err == 0 {
+                debug_assert!(paddr > 0, "Valid PAddr");
+                Ok((frame_id.try_into().unwrap(), PAddr::from(paddr)))
+            } else {
+                Err(SystemCallError::from(err))
+            }
+        }
+    }
+
     pub fn release_frame(id: FrameId) -> Result<(), SystemCallError> {
         PhysicalMemory::release_page(id)
     }
@@ -5,7 +5,7 @@
 
 use crate::*;
 
-use crate::process::{CoreToken, ProcessInfo};
+use crate::process::{CoreMask, CoreToken, ProcessInfo, SpawnArgs, WAIT_ANY};
 use crate::syscall;
 use crate::x86_64::VirtualCpu;
 
@@ -34,6 +34,28 @@ impl Process {
         }
     }
 
+    /// Give `core_id` (previously acquired with `request_core`) back to the
+    /// kernel.
+    ///
+    /// The executor of the process on that core stops (if this is the core we
+    /// run on, the call doesn't return).
+    pub fn release_core(core_id: usize) -> Result<(), SystemCallError> {
+        let r = unsafe {
+            syscall!(
+                SystemCall::Process as u64,
+                ProcessOperation::ReleaseCore as u64,
+                core_id as u64,
+                1
+            )
+        };
+
+        if r == 0 {
+            Ok(())
+        } else {
+            Err(SystemCallError::from(r))
+        }
+    }
+
     /// Print `buffer` on the console.
     pub fn print(buffer: &str) -> Result<(), SystemCallError> {
         let r = unsafe {
@@ -76,9 +98,97 @@ impl Process {
         }
     }
 
+    /// Start `binary` (a module loaded by the bootloader) as a new process.
+    ///
+    /// The new process can read `cmdline` (at most `MAX_CMDLINE_LEN` bytes)
+    /// from its `ProcessInfo` and starts
+    /// on one of the cores in `affinity` (any core if `affinity` is empty).
+    /// With `inherit_fds` the new process starts with a copy of our file
+    /// descriptors.
+    /// Returns the pid of the new process, or
+    /// `SystemCallError::UnknownBinary` if there is no module named `binary`.
+    pub fn spawn(
+        binary: &str,
+        cmdline: &str,
+        affinity: CoreMask,
+        inherit_fds: bool,
+    ) -> Result<u64, SystemCallError> {
+        Process::spawn_with(&SpawnArgs {
+            binary,
+            cmdline,
+            affinity,
+            inherit_fds,
+            mem_limit: None,
+            pmem_limit: None,
+        })
+    }
+
+    /// Start a new process as described by `args` (see `spawn`).
+    ///
+    /// Returns the pid of the new process.
+    pub fn spawn_with(args: &SpawnArgs) -> Result<u64, SystemCallError> {
+        let buf = serde_cbor::to_vec(args).map_err(|_e| SystemCallError::InternalError)?;
+
+        let (r, pid) = unsafe {
+            syscall!(
+                SystemCall::Process as u64,
+                ProcessOperation::Spawn as u64,
+                buf.as_ptr() as u64,
+                buf.len() as u64,
+                2
+            )
+        };
+
+        if r == 0 {
+            Ok(pid)
+        } else {
+            Err(SystemCallError::from(r))
+        }
+    }
+
+    /// Blocks until the child process `pid` (any child if `pid` is `None`)
+    /// exited.
+    ///
+    /// Returns the pid and the exit code of the child, or
+    /// `SystemCallError::NoChildProcess` if there is no such child (left).
+    pub fn wait(pid: Option<u64>) -> Result<(u64, u64), SystemCallError> {
+        let (r, pid, code) = unsafe {
+            syscall!(
+                SystemCall::Process as u64,
+                ProcessOperation::Wait as u64,
+                pid.unwrap_or(WAIT_ANY),
+                3
+            )
+        };
+
+        if r == 0 {
+            Ok((pid, code))
+        } else {
+            Err(SystemCallError::from(r))
+        }
+    }
+
+    /// Let the other processes that share the core run before we continue
+    /// (returns right away if there are none).
+    pub fn yield_core() -> Result<(), SystemCallError> {
+        let r = unsafe {
+            syscall!(
+                SystemCall::Process as u64,
+                ProcessOperation::Yield as u64,
+                1
+            )
+        };
+
+        if r == 0 {
+            Ok(())
+        } else {
+            Err(SystemCallError::from(r))
+        }
+    }
+
     /// Query process specific information.
-    pub fn process_info() -> Result<ProcessInfo, SystemCallError> {
-        let mut buf = alloc::vec![0; 256];
+    pub fn process_info() -> Result<ProcessInfo<'static>, SystemCallError> {
+        let mut buf = alloc::vec![0; 512];
         let (r, len) = unsafe {
             syscall!(
                 SystemCall::Process as u64,
@@ -94,7 +204,7 @@ impl Process {
             debug_assert!(len <= buf.len());
             buf.resize(len, 0);
             let static_buf = alloc::vec::Vec::leak(buf);
-            let deserialized: ProcessInfo = serde_cbor::from_slice(static_buf).unwrap();
+            let deserialized: ProcessInfo<'static> = serde_cbor::from_slice(static_buf).unwrap();
             Ok(deserialized)
         } else {
             Err(SystemCallError::from(r))
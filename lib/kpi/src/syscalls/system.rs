@@ -10,7 +10,7 @@ use abomonation::decode;
 
 use crate::{syscall, *};
 
-use crate::system::{CoreId, CpuThread};
+use crate::system::{CoreId, CpuThread, MemoryStats, MEMORY_STATS_VERSION};
 
 pub struct System;
 
@@ -49,14 +49,37 @@ impl System {
         }
     }
 
-    /// Prints some stats for the core.
-    pub fn stats() -> Result<(), SystemCallError> {
-        let r = unsafe { syscall!(SystemCall::System as u64, SystemOperation::Stats as u64, 1) };
+    /// Query the memory usage of the kernel (also prints some stats for the
+    /// core).
+    pub fn stats() -> Result<MemoryStats, SystemCallError> {
+        let mut buf = alloc::vec![0; 4096];
+        loop {
+            let (r, len) = unsafe {
+                syscall!(
+                    SystemCall::System as u64,
+                    SystemOperation::Stats as u64,
+                    buf.as_mut_ptr() as u64,
+                    buf.len() as u64,
+                    2
+                )
+            };
+            if r != 0 {
+                return Err(SystemCallError::from(r));
+            }
 
-        if r == 0 {
-            Ok(())
-        } else {
-            Err(SystemCallError::from(r))
+            let len = len as usize;
+            if len > buf.len() {
+                // Didn't fit, try again with a big enough buffer
+                buf.resize(len, 0);
+                continue;
+            }
+
+            let stats: MemoryStats =
+                serde_cbor::from_slice(&buf[..len]).map_err(|_e| SystemCallError::InternalError)?;
+            if stats.version != MEMORY_STATS_VERSION {
+                return Err(SystemCallError::NotSupported);
+            }
+            return Ok(stats);
         }
     }
 
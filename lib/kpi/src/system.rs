@@ -2,9 +2,12 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 //! Data structures to exchange system-wide information between kernel and user-space.
+use alloc::vec::Vec;
+
 use abomonation::{unsafe_abomonate, Abomonation};
 use core2::io::Result as IOResult;
 use core2::io::Write;
+use serde::{Deserialize, Serialize};
 
 /// A system global ID for a CPU hardware thread.
 pub type GlobalThreadId = usize;
@@ -35,3 +38,93 @@ pub struct CpuThread {
     pub thread_id: ThreadId,
 }
 unsafe_abomonate!(CpuThread: id, node_id, package_id, core_id, thread_id);
+
+/// Version of the [`MemoryStats`] layout, changes whenever the layout does.
+pub const MEMORY_STATS_VERSION: u64 = 1;
+
+/// Memory usage of the kernel (see `SystemOperation::Stats`).
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Eq, PartialEq)]
+pub struct MemoryStats {
+    /// The layout of the statistics (`MEMORY_STATS_VERSION`).
+    pub version: u64,
+    /// DRAM of every NUMA node.
+    pub nodes: Vec<NodeMemoryStats>,
+    /// PMem of every NUMA node (empty if there is no PMem).
+    pub pmem_nodes: Vec<NodeMemoryStats>,
+    /// Memory cached by the cores (a core updates its entry periodically,
+    /// cores that never did aren't listed).
+    pub cores: Vec<CoreMemoryStats>,
+}
+
+/// Free pages in one of the page caches of the kernel.
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct CacheStats {
+    /// Free base pages in the cache.
+    pub free_base_pages: usize,
+    /// Free large pages in the cache.
+    pub free_large_pages: usize,
+    /// How much memory (in bytes) the cache can hold.
+    pub capacity: usize,
+}
+
+/// Memory of a NUMA node.
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct NodeMemoryStats {
+    /// ID of the NUMA node.
+    pub node_id: NodeId,
+    /// Memory (in bytes) of the node.
+    pub size: usize,
+    /// Memory (in bytes) handed out by the node cache (this includes the
+    /// memory cached by the cores).
+    pub allocated: usize,
+    /// The cache that holds the free memory of the node.
+    pub ncache: CacheStats,
+    /// The memory set aside for when the kernel runs out of memory.
+    pub emergency: CacheStats,
+}
+
+/// Memory cached by a core.
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct CoreMemoryStats {
+    /// ID of the hardware thread.
+    pub id: GlobalThreadId,
+    /// The NUMA node the core allocates memory from.
+    pub node_id: NodeId,
+    /// Free DRAM pages of the core.
+    pub tcache: CacheStats,
+    /// Free PMem pages of the core.
+    pub pmem_tcache: CacheStats,
+    /// Memory (in bytes) the core gave to its allocator for small kernel
+    /// objects.
+    pub zone_size: usize,
+}
+
+#[cfg(test)]
+#[test]
+fn memory_stats_roundtrip() {
+    let stats = MemoryStats {
+        version: MEMORY_STATS_VERSION,
+        nodes: alloc::vec![NodeMemoryStats {
+            node_id: 0,
+            size: 1 << 30,
+            allocated: 1 << 21,
+            ncache: CacheStats {
+                free_base_pages: 12,
+                free_large_pages: 510,
+                capacity: 1 << 31,
+            },
+            emergency: Default::default(),
+        }],
+        pmem_nodes: Vec::new(),
+        cores: alloc::vec![CoreMemoryStats {
+            id: 3,
+            node_id: 0,
+            zone_size: 4096,
+            ..Default::default()
+        }],
+    };
+
+    let serialized = serde_cbor::to_vec(&stats).unwrap();
+    let deserialized: MemoryStats = serde_cbor::from_slice(&serialized).unwrap();
+    assert_eq!(stats, deserialized);
+}
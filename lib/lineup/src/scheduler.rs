@@ -5,15 +5,17 @@
 //!
 //! Has the following properties:
 //! * Cooperative scheduling (threads can yield voluntarily)
-//! * Round robin scheduling (per-core)
+//! * Priority scheduling, round robin among threads of the same priority (per-core)
 //! * Per core run and wait lists
-//! * Thread affinity can be defined upon thread creation (currently no migration)
+//! * Thread affinity is defined upon thread creation and can be changed later
+//!   (this pins the thread to the new core)
+//! * Optional work stealing of unpinned threads by idle cores
 //! * Waitlist is sorted according to thread wake-up times.
 
 use alloc::collections::VecDeque;
 use alloc::vec::Vec;
 use core::ptr;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use arr_macro::arr;
 use fringe::generator::Generator;
@@ -21,11 +23,20 @@ use log::{error, trace};
 use rawtime::Instant;
 
 use crate::stack::LineupStack;
-use crate::threads::{Runnable, Thread, ThreadId, YieldRequest, YieldResume};
+use crate::threads::{Priority, Runnable, SchedInfo, Thread, ThreadId, YieldRequest, YieldResume};
 use crate::tls2::{self, SchedulerControlBlock, ThreadControlBlock};
 use crate::upcalls::Upcalls;
 use crate::{CoreId, IrqVector};
 
+/// An entry in the `runnable` list of a core.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Ready {
+    tid: ThreadId,
+    priority: Priority,
+    /// Other cores may not steal the thread.
+    pinned: bool,
+}
+
 /// Scheduler per-core state.
 ///
 /// # Lock order
@@ -36,7 +47,8 @@ struct SchedulerCoreState {
     /// Per-core list of runnable threads.
     ///
     /// Protected by a mutex since anyone could put threads here.
-    runnable: spin::Mutex<VecDeque<ThreadId>>,
+    /// Sorted by priority (highest first).
+    runnable: spin::Mutex<VecDeque<Ready>>,
 
     /// Per-core list of `waiting` threads.
     ///
@@ -71,6 +83,8 @@ pub struct SmpScheduler<'a> {
     tid_counter: AtomicUsize,
     /// Maps interrupt vectors to ThreadId
     irqvec_to_tid: spin::Mutex<hashbrown::HashMap<IrqVector, ThreadId>>,
+    /// Cores without runnable threads take threads from other cores.
+    work_stealing: AtomicBool,
 }
 
 unsafe impl Send for SmpScheduler<'static> {}
@@ -95,9 +109,16 @@ impl<'a> SmpScheduler<'a> {
             tid_counter: AtomicUsize::new(0),
             per_core: arr![SchedulerCoreState::new(); 96], // MAX_THREADS
             irqvec_to_tid: spin::Mutex::new(hashbrown::HashMap::with_capacity(8)),
+            work_stealing: AtomicBool::new(false),
         }
     }
 
+    /// Lets cores that run out of runnable threads take (unpinned) threads
+    /// from other cores.
+    pub fn set_work_stealing(&self, enabled: bool) {
+        self.work_stealing.store(enabled, Ordering::Relaxed);
+    }
+
     /// Returns true as long as we have 'active', unfinished thread.
     ///
     /// A thread that is currently blocked/waiting still counts as active.
@@ -185,9 +206,32 @@ impl<'a> SmpScheduler<'a> {
     }
 
     /// Marks a thread as sunnable by inserting it into
-    /// `runnable`.
+    /// `runnable` (behind all threads with the same or a higher priority).
     fn mark_runnable(&self, tid: ThreadId, affinity: CoreId) {
-        self.per_core[affinity].runnable.lock().push_back(tid);
+        let ready = self.threads.lock().get(&tid).map_or(
+            Ready {
+                tid,
+                priority: Default::default(),
+                pinned: true,
+            },
+            |t| Ready {
+                tid,
+                priority: t.priority,
+                pinned: t.pinned,
+            },
+        );
+
+        let mut runnable = self.per_core[affinity].runnable.lock();
+        match runnable.back() {
+            Some(last) if last.priority < ready.priority => {
+                let pos = runnable
+                    .iter()
+                    .position(|r| r.priority < ready.priority)
+                    .unwrap_or(runnable.len());
+                runnable.insert(pos, ready);
+            }
+            _ => runnable.push_back(ready),
+        }
     }
 
     /// Make a thread no longer runnable.
@@ -197,7 +241,75 @@ impl<'a> SmpScheduler<'a> {
     /// call it if tid is different from current thread.
     fn mark_unrunnable(&self, tid: ThreadId, affinity: CoreId) {
         let mut runnable = self.per_core[affinity].runnable.lock();
-        runnable.retain(|&ltid| ltid != tid);
+        runnable.retain(|r| r.tid != tid);
+    }
+
+    /// Moves thread `tid` from the run or waitlist of core `from` to the
+    /// one of core `to`.
+    ///
+    /// TODO(race): Like for `YieldRequest::Runnable`, core `from` may wake
+    /// the thread up in-between.
+    fn migrate(&self, tid: ThreadId, from: CoreId, to: CoreId) {
+        let until = {
+            let mut waiting = self.per_core[from].waiting.lock();
+            waiting
+                .iter()
+                .position(|&(_instant, wtid)| wtid == tid)
+                .map(|pos| waiting.remove(pos).0)
+        };
+        if let Some(until) = until {
+            self.waitlist_insert(tid, to, until);
+        }
+
+        let was_runnable = {
+            let mut runnable = self.per_core[from].runnable.lock();
+            let len = runnable.len();
+            runnable.retain(|r| r.tid != tid);
+            runnable.len() != len
+        };
+        if was_runnable {
+            self.mark_runnable(tid, to);
+        }
+    }
+
+    /// Takes an unpinned thread from another core's `runnable` list and
+    /// moves it to `core_id`.
+    ///
+    /// Skips cores whose lists are locked (they're busy with them) and
+    /// takes the thread with the lowest priority.
+    fn steal(&self, core_id: CoreId) -> Option<ThreadId> {
+        let victims = (core_id + 1..self.per_core.len()).chain(0..core_id);
+        for victim in victims {
+            let stolen = match self.per_core[victim].runnable.try_lock() {
+                Some(mut runnable) if !runnable.is_empty() => {
+                    // A thread that is still running (has no generator)
+                    // has to be dispatched by its own core
+                    let generators = self.generators.lock();
+                    runnable
+                        .iter()
+                        .rposition(|r| !r.pinned && generators.contains_key(&r.tid))
+                        .and_then(|pos| runnable.remove(pos))
+                }
+                _ => None,
+            };
+
+            if let Some(ready) = stolen {
+                trace!("Core {} steals {} from core {}", core_id, ready.tid, victim);
+                let mut thread_map = self.threads.lock();
+                let thread = thread_map
+                    .get_mut(&ready.tid)
+                    .expect("Can't find thread state?");
+                thread.affinity = core_id;
+                if !thread.state.is_null() {
+                    unsafe {
+                        (*thread.state).current_core = core_id;
+                    }
+                }
+                return Some(ready.tid);
+            }
+        }
+
+        None
     }
 
     /// Remove a thread from the waitlist.
@@ -227,10 +339,16 @@ impl<'a> SmpScheduler<'a> {
 
     /// Handles a yield request of the thread given by `tid`.
     ///
-    /// Updates run and waitlists accordingly.
-    fn handle_yield_request(&self, tid: ThreadId, result: Option<YieldRequest>) -> YieldResume {
+    /// Updates run and waitlists accordingly. Returns how to resume the
+    /// thread and whether it should be put back in `runnable` after it got
+    /// descheduled.
+    fn handle_yield_request(
+        &self,
+        tid: ThreadId,
+        result: Option<YieldRequest>,
+    ) -> (YieldResume, bool) {
         let affinity = self.threads.lock().get(&tid).unwrap().affinity;
-        match result {
+        let resume = match result {
             None => {
                 trace!("Thread {} has terminated.", tid);
                 self.mark_unrunnable(tid, affinity);
@@ -240,8 +358,14 @@ impl<'a> SmpScheduler<'a> {
                     .remove(&tid)
                     .expect("Can't remove thread?");
 
-                // Wake up all the waiters
+                // Wake up all the waiters (on the core they're on now, they
+                // might have been moved while they waited)
                 for (sleeping_tid, sleeping_affinity) in thread.joinlist {
+                    let sleeping_affinity = self
+                        .threads
+                        .lock()
+                        .get(&sleeping_tid)
+                        .map_or(sleeping_affinity, |t| t.affinity);
                     log::debug!(
                         "{} will return from join on core {}",
                         sleeping_tid,
@@ -256,9 +380,8 @@ impl<'a> SmpScheduler<'a> {
                     "Thread {} has voluntarily yielded its time (YieldRequest::None).",
                     tid
                 );
-                // Put us back at end of the queue:
-                self.mark_runnable(tid, affinity);
-                YieldResume::Interrupted
+                // Put us back at end of the queue (once we're descheduled)
+                return (YieldResume::Interrupted, true);
             }
             Some(YieldRequest::Runnable(rtid)) => {
                 trace!("YieldRequest::Runnable {:?} {}", rtid, affinity);
@@ -382,7 +505,72 @@ impl<'a> SmpScheduler<'a> {
                     .expect("Can't spawn the thread");
                 YieldResume::Spawned(tid)
             }
-        }
+            Some(YieldRequest::SetAffinity(rtid, core)) => {
+                trace!("YieldRequest::SetAffinity {:?} {:?}", rtid, core);
+                if core.map_or(false, |core| core >= self.per_core.len()) {
+                    error!("Can't move {} to non-existing core {:?}", rtid, core);
+                    return (YieldResume::Completed, false);
+                }
+
+                let rtid_affinity = {
+                    let mut thread_map = self.threads.lock();
+                    match thread_map.get_mut(&rtid) {
+                        Some(thread) => {
+                            let old_affinity = thread.affinity;
+                            thread.pinned = core.is_some();
+                            thread.affinity = core.unwrap_or(old_affinity);
+                            if !thread.state.is_null() {
+                                unsafe {
+                                    (*thread.state).current_core = thread.affinity;
+                                }
+                            }
+                            Some(old_affinity)
+                        }
+                        None => None,
+                    }
+                };
+
+                match (rtid_affinity, core) {
+                    (Some(from), Some(to)) if from != to => {
+                        if rtid == tid {
+                            // Continue on the new core:
+                            return (YieldResume::Interrupted, true);
+                        }
+                        self.migrate(rtid, from, to);
+                        YieldResume::Completed
+                    }
+                    _ => YieldResume::Completed,
+                }
+            }
+            Some(YieldRequest::SetPriority(rtid, priority)) => {
+                trace!("YieldRequest::SetPriority {:?} {:?}", rtid, priority);
+                let rtid_affinity = {
+                    let mut thread_map = self.threads.lock();
+                    thread_map.get_mut(&rtid).map(|thread| {
+                        thread.priority = priority;
+                        thread.affinity
+                    })
+                };
+
+                // Sort it in again at its new position (if it's runnable)
+                if let Some(rtid_affinity) = rtid_affinity {
+                    if rtid != tid {
+                        self.migrate(rtid, rtid_affinity, rtid_affinity);
+                    }
+                }
+                YieldResume::Completed
+            }
+            Some(YieldRequest::GetSchedInfo(rtid)) => {
+                let info = self.threads.lock().get(&rtid).map(|thread| SchedInfo {
+                    core: thread.affinity,
+                    pinned: thread.pinned,
+                    priority: thread.priority,
+                });
+                YieldResume::SchedInfo(info)
+            }
+        };
+
+        (resume, false)
     }
 
     /// Finds threads with expired timeouts and re-inserts them from `waiting` into `runnable`
@@ -440,7 +628,15 @@ impl<'a> SmpScheduler<'a> {
             self.check_wakeups(core_id);
 
             // The next thread ID we want to run
-            let next_tid = self.per_core[core_id].runnable.lock().pop_front();
+            let mut next_tid = self.per_core[core_id]
+                .runnable
+                .lock()
+                .pop_front()
+                .map(|r| r.tid);
+            if next_tid.is_none() && self.work_stealing.load(Ordering::Relaxed) {
+                next_tid = self.steal(core_id);
+            }
+
             match next_tid {
                 Some(tid) => {
                     let mut generator = self
@@ -473,23 +669,32 @@ impl<'a> SmpScheduler<'a> {
                         trace!("{:?} generator.resume = {:?}", tid, resume_action);
                         let yielded_with = generator.resume(resume_action);
                         trace!("yielded_with = {:?}", yielded_with);
-                        resume_action = self.handle_yield_request(tid, yielded_with);
+                        let (action, requeue) = self.handle_yield_request(tid, yielded_with);
+                        resume_action = action;
                         trace!("{:?} resume_action = {:?}", tid, resume_action);
                         if resume_action == YieldResume::Interrupted {
                             // If we're not done we need to put the generator back:
                             self.generators.lock().insert(tid, generator);
 
                             // And preserve the TLS value in the Thread struct:
-                            let mut thread_map = self.threads.lock();
-                            let thread =
-                                thread_map.get_mut(&tid).expect("Can't find thread state?");
-                            // Also preserve the TLS
-                            if thread.state.is_null() {
-                                unsafe {
-                                    thread.state = tls2::arch::get_tcb();
+                            let affinity = {
+                                let mut thread_map = self.threads.lock();
+                                let thread =
+                                    thread_map.get_mut(&tid).expect("Can't find thread state?");
+                                // Also preserve the TLS
+                                if thread.state.is_null() {
+                                    unsafe {
+                                        thread.state = tls2::arch::get_tcb();
+                                    }
                                 }
+                                assert!(!thread.state.is_null());
+                                thread.affinity
+                            };
+
+                            // Only now another core can dispatch it
+                            if requeue {
+                                self.mark_runnable(tid, affinity);
                             }
-                            assert!(!thread.state.is_null());
                             break;
                         }
                         if resume_action == YieldResume::DoNotResume {
@@ -848,6 +1053,134 @@ mod tests {
         debug_assert!(waitlist[2].1 == ThreadId(1));
     }
 
+    /// Test that threads with a higher priority are dispatched first and
+    /// threads with the same priority in FIFO order.
+    #[test]
+    fn runnable_is_sorted_by_priority() {
+        let s: Arc<SmpScheduler> = Default::default();
+        let order: Arc<ArrayQueue<ThreadId>> = Arc::new(ArrayQueue::new(4));
+
+        let mut tids = Vec::new();
+        for _i in 0..4 {
+            let order = order.clone();
+            let tid = s
+                .spawn(
+                    DEFAULT_STACK_SIZE_BYTES,
+                    move |_| {
+                        let _r = order.push(Environment::tid());
+                    },
+                    ptr::null_mut(),
+                    0,
+                    None,
+                )
+                .unwrap();
+            tids.push(tid);
+        }
+
+        // Raise the priority of the last two threads
+        for (tid, priority) in [(tids[2], Priority(1)), (tids[3], Priority(5))] {
+            s.threads.lock().get_mut(&tid).unwrap().priority = priority;
+            s.migrate(tid, 0, 0);
+        }
+
+        let scb: SchedulerControlBlock = SchedulerControlBlock::new(0);
+        s.run(&scb);
+
+        assert_eq!(order.pop(), Some(tids[3]));
+        assert_eq!(order.pop(), Some(tids[2]));
+        assert_eq!(order.pop(), Some(tids[0]));
+        assert_eq!(order.pop(), Some(tids[1]));
+    }
+
+    /// Test that a thread can move itself to another core and stays there.
+    #[test]
+    fn set_affinity_migrates() {
+        let _r = env_logger::try_init();
+        let s: Arc<SmpScheduler> = Default::default();
+        let cores: Arc<ArrayQueue<(CoreId, Option<SchedInfo>)>> = Arc::new(ArrayQueue::new(2));
+        let cores1 = cores.clone();
+
+        s.spawn(
+            DEFAULT_STACK_SIZE_BYTES,
+            move |_| {
+                let tid = Environment::tid();
+                let _r = cores1.push((
+                    Environment::thread().current_core,
+                    Environment::thread().sched_info(tid),
+                ));
+                Environment::thread().set_affinity(tid, Some(1));
+                let _r = cores1.push((
+                    Environment::thread().current_core,
+                    Environment::thread().sched_info(tid),
+                ));
+            },
+            ptr::null_mut(),
+            0,
+            None,
+        );
+
+        // Core 0 runs the thread until it moves away
+        let scb0: SchedulerControlBlock = SchedulerControlBlock::new(0);
+        s.run(&scb0);
+        assert_eq!(cores.len(), 1);
+        assert!(s.has_active_threads());
+
+        let scb1: SchedulerControlBlock = SchedulerControlBlock::new(1);
+        s.run(&scb1);
+        assert!(!s.has_active_threads());
+
+        let unpinned = SchedInfo {
+            core: 0,
+            pinned: false,
+            priority: Priority(0),
+        };
+        let pinned = SchedInfo {
+            core: 1,
+            pinned: true,
+            priority: Priority(0),
+        };
+        assert_eq!(cores.pop(), Some((0, Some(unpinned))));
+        assert_eq!(cores.pop(), Some((1, Some(pinned))));
+    }
+
+    /// Test that idle cores only take threads from other cores if work
+    /// stealing is enabled, and never pinned ones.
+    #[test]
+    fn idle_cores_steal_work() {
+        let s: Arc<SmpScheduler> = Default::default();
+        let cores: Arc<ArrayQueue<CoreId>> = Arc::new(ArrayQueue::new(2));
+
+        for _i in 0..2 {
+            let cores = cores.clone();
+            s.spawn(
+                DEFAULT_STACK_SIZE_BYTES,
+                move |_| {
+                    let _r = cores.push(Environment::thread().current_core);
+                },
+                ptr::null_mut(),
+                0,
+                None,
+            );
+        }
+        // Pin the first thread to core 0
+        s.threads.lock().get_mut(&ThreadId(0)).unwrap().pinned = true;
+        s.migrate(ThreadId(0), 0, 0);
+
+        let scb1: SchedulerControlBlock = SchedulerControlBlock::new(1);
+        s.run(&scb1);
+        assert!(cores.is_empty(), "Stole work without work stealing?");
+
+        s.set_work_stealing(true);
+        s.run(&scb1);
+        assert_eq!(cores.pop(), Some(1));
+        assert!(cores.is_empty(), "Stole a pinned thread?");
+
+        let scb0: SchedulerControlBlock = SchedulerControlBlock::new(0);
+        s.run(&scb0);
+        assert_eq!(cores.pop(), Some(0));
+        assert!(!s.has_active_threads());
+    }
+
     /// Test that sleeping events wake up in the correct order
     /// and sleep as long as we expect them to.
     #[test]
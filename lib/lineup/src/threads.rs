@@ -26,6 +26,24 @@ impl fmt::Display for ThreadId {
     }
 }
 
+/// The scheduling priority of a thread.
+///
+/// Runnable threads with a higher priority always run before the ones with a
+/// lower priority, threads with the same priority take turns.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Priority(pub u8);
+
+/// Where and how a thread gets scheduled.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SchedInfo {
+    /// The core the thread currently runs on.
+    pub core: CoreId,
+    /// The thread stays on `core` (it's not taken by work stealing).
+    pub pinned: bool,
+    /// The priority of the thread.
+    pub priority: Priority,
+}
+
 pub(crate) struct Thread {
     /// Thread ID
     pub(crate) id: ThreadId,
@@ -33,6 +51,12 @@ pub(crate) struct Thread {
     /// Current core affinity of the thread.
     pub(crate) affinity: CoreId,
 
+    /// The thread stays on `affinity` even if other cores could steal it.
+    pub(crate) pinned: bool,
+
+    /// Scheduling priority of the thread.
+    pub(crate) priority: Priority,
+
     /// Storage area for resume result (is thread was put in waiting list).
     pub(crate) return_with: Option<YieldResume>,
 
@@ -96,6 +120,9 @@ impl Thread {
         let thread = Thread {
             id: tid,
             affinity,
+            // Interrupts are dispatched to the core that gets them
+            pinned: _interrupt_vector.is_some(),
+            priority: Default::default(),
             return_with: None,
             _interrupt_vector,
             joinlist: Vec::with_capacity(crate::scheduler::SmpScheduler::MAX_THREADS),
@@ -140,6 +167,13 @@ pub(crate) enum YieldRequest {
     RunnableList(Vec<ThreadId>),
     /// Wait until the thread with given ID is finished.
     JoinOn(ThreadId),
+    /// Move the thread to the given core and keep it there (or allow it to
+    /// be moved by work stealing again if `None`).
+    SetAffinity(ThreadId, Option<CoreId>),
+    /// Change the priority of the thread.
+    SetPriority(ThreadId, Priority),
+    /// Tell where and how the thread is scheduled.
+    GetSchedInfo(ThreadId),
     /// Spawn a new thread that runs the provided function and argument.
     Spawn(
         Option<unsafe extern "C" fn(arg1: *mut u8) -> *mut u8>,
@@ -168,6 +202,8 @@ pub(crate) enum YieldResume {
     Interrupted,
     /// A child thread was spawned with the given ThreadId.
     Spawned(ThreadId),
+    /// Scheduling information about a thread (if it exists).
+    SchedInfo(Option<SchedInfo>),
     /// Thread has completed (and has been removed from the scheduler state)
     DoNotResume,
 }
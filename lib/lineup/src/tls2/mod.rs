@@ -27,7 +27,7 @@ use crossbeam_queue::ArrayQueue;
 use rawtime::{Duration, Instant};
 
 use crate::stack::LineupStack;
-use crate::threads::{ThreadId, YieldRequest, YieldResume};
+use crate::threads::{Priority, SchedInfo, ThreadId, YieldRequest, YieldResume};
 use crate::upcalls::Upcalls;
 use crate::{CoreId, IrqVector};
 
@@ -234,6 +234,29 @@ impl<'a> ThreadControlBlock<'a> {
         self.yielder().suspend(request);
     }
 
+    /// Moves thread `tid` to `core_id` and keeps it there, or lets it be
+    /// stolen by other cores again (`None`).
+    pub fn set_affinity(&self, tid: ThreadId, core_id: Option<CoreId>) {
+        let request = YieldRequest::SetAffinity(tid, core_id);
+        self.yielder().suspend(request);
+    }
+
+    /// Changes the priority of thread `tid` (if it's runnable it gets sorted
+    /// into the run-queue of its core at the new position).
+    pub fn set_priority(&self, tid: ThreadId, priority: Priority) {
+        let request = YieldRequest::SetPriority(tid, priority);
+        self.yielder().suspend(request);
+    }
+
+    /// Where and how thread `tid` is scheduled (`None` if it doesn't exist).
+    pub fn sched_info(&self, tid: ThreadId) -> Option<SchedInfo> {
+        let request = YieldRequest::GetSchedInfo(tid);
+        match self.yielder().suspend(request) {
+            YieldResume::SchedInfo(info) => info,
+            _ => None,
+        }
+    }
+
     pub(crate) fn suspend(&self, request: YieldRequest) {
         self.yielder().suspend(request);
     }
@@ -33,7 +33,11 @@ pub enum RPCError {
     PermissionError,
     AlreadyPresent,
     DirectoryError,
+    NotADirectory,
+    DirectoryNotEmpty,
     OpenFileLimit,
+    WouldBlock,
+    BrokenPipe,
     FileDescForPidAlreadyAdded,
     NoFileDescForPid,
 
@@ -3,23 +3,105 @@
 
 //! Implements the interactions with the scheduler.
 //!
-//! Just stubs that don't to anything for now.
+//! Affinity and priorities of LWPs map onto the lineup threads that run them,
+//! the rest are just stubs that don't to anything for now.
 
-use crate::rumprt::{c_int, c_size_t, c_void, errno};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use lineup::threads::{Priority, ThreadId};
+use lineup::tls2::Environment;
+
+use crate::rumprt::prt::get_rumprun_lwp_thread;
+use crate::rumprt::{c_int, c_size_t, c_void, errno, lwpid_t, pid_t};
+
+/// The scheduling policy we report (we only have one).
+const SCHED_OTHER: c_int = 0;
+
+/// The `struct sched_param` C representation for rust code.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct sched_param {
+    pub sched_priority: c_int,
+}
+
+/// Finds the thread that runs LWP `lid` (0 is the calling LWP).
+///
+/// We only have one process so `pid` is ignored.
+fn lwp_thread(_pid: pid_t, lid: lwpid_t) -> Option<ThreadId> {
+    if lid == 0 {
+        Some(Environment::tid())
+    } else {
+        get_rumprun_lwp_thread(lid)
+    }
+}
+
+/// Sets errno to `code` and returns the error value of a system call.
+unsafe fn fail(code: c_int) -> c_int {
+    crate::rumprt::errno::rumpuser_seterrno(code);
+    -1
+}
 
 #[no_mangle]
 pub unsafe extern "C" fn _sys_sched_yield() {
     unreachable!("_sys_sched_yield");
 }
 
+/// Returns the cores the LWP may run on as `cpuset`: the core it is pinned
+/// to, or all cores of the machine if it can move.
 #[no_mangle]
-pub unsafe extern "C" fn _sched_getaffinity() {
-    unreachable!("_sched_getaffinity");
+pub unsafe extern "C" fn _sched_getaffinity(
+    pid: pid_t,
+    lid: lwpid_t,
+    size: c_size_t,
+    cpuset: *mut u32,
+) -> c_int {
+    let info = match lwp_thread(pid, lid).and_then(|tid| Environment::thread().sched_info(tid)) {
+        Some(info) => info,
+        None => return fail(errno::ESRCH),
+    };
+    let cores: Vec<usize> = if info.pinned {
+        vec![info.core]
+    } else {
+        match crate::syscalls::System::threads() {
+            Ok(threads) => threads.iter().map(|thread| thread.id).collect(),
+            Err(_) => return fail(errno::EINVAL),
+        }
+    };
+    let words = size / core::mem::size_of::<u32>();
+    if cpuset.is_null() || cores.iter().any(|core| core / 32 >= words) {
+        return fail(errno::EINVAL);
+    }
+
+    let bits = core::slice::from_raw_parts_mut(cpuset, words);
+    bits.fill(0);
+    for core in cores {
+        bits[core / 32] |= 1 << (core % 32);
+    }
+
+    0
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn _sched_getparam() {
-    unreachable!("_sched_getparam");
+pub unsafe extern "C" fn _sched_getparam(
+    pid: pid_t,
+    lid: lwpid_t,
+    policy: *mut c_int,
+    params: *mut sched_param,
+) -> c_int {
+    let info = match lwp_thread(pid, lid).and_then(|tid| Environment::thread().sched_info(tid)) {
+        Some(info) => info,
+        None => return fail(errno::ESRCH),
+    };
+
+    if !policy.is_null() {
+        *policy = SCHED_OTHER;
+    }
+    if !params.is_null() {
+        (*params).sched_priority = info.priority.0 as c_int;
+    }
+
+    0
 }
 
 #[no_mangle]
@@ -27,14 +109,66 @@ pub unsafe extern "C" fn _sched_protect() -> c_int {
     return 0;
 }
 
+/// Pins the LWP to a core in `cpuset`.
+///
+/// A thread can only run on one core: it stays on its current core if that
+/// one is in the set, otherwise it moves to the first core of the set. An
+/// empty set lets it move again.
 #[no_mangle]
-pub unsafe extern "C" fn _sched_setaffinity() {
-    unreachable!("_sched_setaffinity");
+pub unsafe extern "C" fn _sched_setaffinity(
+    pid: pid_t,
+    lid: lwpid_t,
+    size: c_size_t,
+    cpuset: *const u32,
+) -> c_int {
+    let tid = match lwp_thread(pid, lid) {
+        Some(tid) => tid,
+        None => return fail(errno::ESRCH),
+    };
+    let info = match Environment::thread().sched_info(tid) {
+        Some(info) => info,
+        None => return fail(errno::ESRCH),
+    };
+    if cpuset.is_null() {
+        return fail(errno::EINVAL);
+    }
+
+    let bits = core::slice::from_raw_parts(cpuset, size / core::mem::size_of::<u32>());
+    let in_set = |core: usize| core / 32 < bits.len() && bits[core / 32] & (1 << (core % 32)) != 0;
+    let core = if in_set(info.core) {
+        Some(info.core)
+    } else {
+        (0..bits.len() * 32).find(|&core| in_set(core))
+    };
+
+    // The set may contain more cores than the machine has
+    let ncores = crate::syscalls::System::threads().map_or(0, |threads| threads.len());
+    if core.map_or(false, |core| core >= ncores) {
+        return fail(errno::EINVAL);
+    }
+
+    Environment::thread().set_affinity(tid, core);
+    0
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn _sched_setparam() {
-    unreachable!("_sched_setparam");
+pub unsafe extern "C" fn _sched_setparam(
+    pid: pid_t,
+    lid: lwpid_t,
+    _policy: c_int,
+    params: *const sched_param,
+) -> c_int {
+    let tid = match lwp_thread(pid, lid) {
+        Some(tid) => tid,
+        None => return fail(errno::ESRCH),
+    };
+    if params.is_null() {
+        return fail(errno::EINVAL);
+    }
+
+    let priority = (*params).sched_priority.clamp(0, u8::MAX as c_int) as u8;
+    Environment::thread().set_priority(tid, Priority(priority));
+    0
 }
 
 #[no_mangle]
@@ -42,7 +176,6 @@ pub unsafe extern "C" fn sched_yield() {
     // error stmt here because untested and sched_yield doesn't seeem to happen
     // except in failure case for our current applications...
     log::error!("sched_yield called");
-    use lineup::tls2::Environment;
     Environment::thread().relinquish()
 }
 
@@ -126,6 +126,14 @@ fn get_rumprun_lwp_context(lwpid: lwpid_t) -> *const rumprun_lwp {
         .as_ptr()
 }
 
+/// Finds the lineup thread that runs LWP `lwpid` (if the LWP exists).
+pub(crate) fn get_rumprun_lwp_thread(lwpid: lwpid_t) -> Option<ThreadId> {
+    LWP_HT
+        .lock()
+        .get(&lwpid)
+        .map(|lwp| unsafe { (*lwp.0.as_ptr()).rl_thread })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn rumprun_makelwp(
     start: LwpMain,
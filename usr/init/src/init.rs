@@ -15,7 +15,7 @@
 extern crate alloc;
 
 use core::ptr;
-use core::slice::from_raw_parts_mut;
+use core::slice::{from_raw_parts, from_raw_parts_mut};
 use core::sync::atomic::{AtomicBool, Ordering};
 
 #[cfg(feature = "rumprt")]
@@ -55,6 +55,69 @@ fn map_test() {
             *i = 0xb;
         }
         assert_eq!(slice[99], 0xb);
+
+        // Make the first page read-only and restore it again
+        vibrio::syscalls::VSpace::protect(base, 0x1000, vibrio::MemProtection::READ)
+            .expect("Protect syscall failed");
+        assert_eq!(slice[0], 0xb);
+        vibrio::syscalls::VSpace::protect(
+            base,
+            0x1000,
+            vibrio::MemProtection::READ | vibrio::MemProtection::WRITE,
+        )
+        .expect("Protect syscall failed");
+        slice[0] = 0xa;
+        assert_eq!(slice[0], 0xa);
+
+        // A snapshot keeps the old content once the original is written to
+        let copy_base: u64 = 0x0530_0000_0000;
+        vibrio::syscalls::VSpace::snapshot(base, copy_base, size).expect("Snapshot syscall failed");
+        let copy: &mut [u8] = from_raw_parts_mut(copy_base as *mut u8, size as usize);
+        assert_eq!(copy[0], 0xa);
+        slice[0] = 0xe;
+        copy[1] = 0xf;
+        assert_eq!(copy[0], 0xa);
+        assert_eq!(slice[0], 0xe);
+        assert_eq!(slice[1], 0xb);
+        vibrio::syscalls::VSpace::unmap(copy_base, size).expect("Unmap syscall failed");
+    }
+
+    // Memory spread across all NUMA nodes
+    let base: u64 = 0x0540_0000_0000;
+    let size: u64 = 0x1000 * 16;
+    unsafe {
+        vibrio::syscalls::VSpace::map_with_policy(base, size, vibrio::MemPolicy::Interleave)
+            .expect("Map syscall failed");
+        let slice: &mut [u8] = from_raw_parts_mut(base as *mut u8, size as usize);
+        assert_eq!(slice[0x1234], 0x0);
+        slice[size as usize - 1] = 0xd;
+        assert_eq!(slice[size as usize - 1], 0xd);
+        vibrio::syscalls::VSpace::unmap(base, size).expect("Unmap syscall failed");
+
+        // Nodes that don't exist are rejected
+        vibrio::syscalls::VSpace::set_policy(vibrio::MemPolicy::Bind(u32::MAX as u64))
+            .expect_err("Policy for invalid node");
+        vibrio::syscalls::VSpace::set_policy(vibrio::MemPolicy::Preferred(0))
+            .expect("Can't set policy");
+        vibrio::syscalls::VSpace::map(base, size).expect("Map syscall failed");
+        vibrio::syscalls::VSpace::unmap(base, size).expect("Unmap syscall failed");
+        vibrio::syscalls::VSpace::set_policy(vibrio::MemPolicy::Local).expect("Can't set policy");
+    }
+
+    // A reserved region only gets backed by memory where we touch it
+    let base: u64 = 0x0520_0000_0000;
+    let size: u64 = 1024 * 1024 * 1024;
+    unsafe {
+        vibrio::syscalls::VSpace::reserve(base, size).expect("Reserve syscall failed");
+
+        let slice: &mut [u8] = from_raw_parts_mut(base as *mut u8, size as usize);
+        assert_eq!(slice[0x1234], 0x0);
+        slice[0x1234] = 0xc;
+        slice[size as usize - 1] = 0xd;
+        assert_eq!(slice[0x1234], 0xc);
+        assert_eq!(slice[size as usize - 1], 0xd);
+
+        vibrio::syscalls::VSpace::unmap(base, size).expect("Unmap syscall failed");
     }
 
     info!("map_test OK");
@@ -70,6 +133,14 @@ fn alloc_test() {
 
     assert_eq!(v[255], 255);
     assert_eq!(v.len(), 256);
+
+    let stats = vibrio::syscalls::System::stats().expect("Can't get memory stats");
+    assert!(!stats.nodes.is_empty());
+    for node in stats.nodes.iter() {
+        assert!(node.allocated <= node.size);
+    }
+    let core_id = vibrio::syscalls::System::core_id().expect("Can't get core id");
+    assert!(stats.cores.iter().any(|core| core.id == core_id));
     info!("alloc_test OK");
 }
 
@@ -122,9 +193,17 @@ fn alloc_physical_test() {
     }
 
     // Allocate a large page of physical memory
+    let usage = vibrio::syscalls::Process::process_info()
+        .expect("Can't read process info")
+        .mem_usage;
     let (frame_id2, paddr2) = vibrio::syscalls::PhysicalMemory::allocate_large_page()
         .expect("Failed to get physical memory large page");
     info!("large frame id={:?}, paddr={:?}", frame_id2, paddr2);
+    let allocated = vibrio::syscalls::Process::process_info()
+        .expect("Can't read process info")
+        .mem_usage
+        .allocated;
+    assert_eq!(allocated, usage.allocated + LARGE_PAGE_SIZE as u64);
 
     // Test allocation by checking to see if we can map it okay
     unsafe {
@@ -141,6 +220,42 @@ fn alloc_physical_test() {
     // Release large page
     vibrio::syscalls::PhysicalMemory::release_frame(frame_id2)
         .expect("Failed to release physical memory large page");
+    let released = vibrio::syscalls::Process::process_info()
+        .expect("Can't read process info")
+        .mem_usage;
+    assert_eq!(released, usage);
+
+    // A shared page registered twice refers to the same memory
+    let (shm_id, shm_frame_id) = vibrio::syscalls::PhysicalMemory::create_shared(true)
+        .expect("Failed to create shared memory object");
+    let (shm_frame_id2, shm_paddr) = vibrio::syscalls::PhysicalMemory::attach_shared(shm_id)
+        .expect("Failed to attach shared memory object");
+    info!("shared frame id={:?}, paddr={:?}", shm_id, shm_paddr);
+    unsafe {
+        vibrio::syscalls::VSpace::map_frame(shm_frame_id, base).expect("Failed to map shm");
+        vibrio::syscalls::VSpace::map_frame(shm_frame_id2, base + 0x1000)
+            .expect("Failed to map shm again");
+        let slice: &mut [u8] = from_raw_parts_mut(base as *mut u8, BASE_PAGE_SIZE);
+        let alias: &[u8] = from_raw_parts((base + 0x1000) as *const u8, BASE_PAGE_SIZE);
+        assert_eq!(alias[99], 0x0, "Shared memory is zeroed");
+        slice[99] = 0xd;
+        assert_eq!(alias[99], 0xd);
+
+        vibrio::syscalls::VSpace::unmap(base, BASE_PAGE_SIZE as u64).expect("Unmap syscall failed");
+        vibrio::syscalls::VSpace::unmap(base + 0x1000, BASE_PAGE_SIZE as u64)
+            .expect("Unmap syscall failed");
+    }
+    vibrio::syscalls::PhysicalMemory::release_frame(shm_frame_id)
+        .expect("Failed to release shared memory");
+    // The object stays around as long as somebody holds it
+    let (shm_frame_id3, _paddr) = vibrio::syscalls::PhysicalMemory::attach_shared(shm_id)
+        .expect("Failed to attach shared memory object");
+    vibrio::syscalls::PhysicalMemory::release_frame(shm_frame_id2)
+        .expect("Failed to release shared memory");
+    vibrio::syscalls::PhysicalMemory::release_frame(shm_frame_id3)
+        .expect("Failed to release shared memory");
+    vibrio::syscalls::PhysicalMemory::attach_shared(shm_id)
+        .expect_err("Shared memory object should be gone");
 
     info!("phys_alloc_test OK");
 }
@@ -667,6 +782,12 @@ fn fs_test() {
         assert_eq!(fileinfo.fsize, 256);
         assert_eq!(fileinfo.ftype, FileType::File.into());
 
+        // List the directory
+        let entries = vibrio::syscalls::Fs::readdir("mydir").expect("ReadDir syscall failed");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "file.txt");
+        assert_eq!(entries[0].ftype, FileType::File);
+
         // Reset the slice content. And read the file content from the file and
         // check if it's same as the date which was written to the file.
         for i in slice.iter_mut() {
@@ -682,9 +803,82 @@ fn fs_test() {
         let _r = vibrio::syscalls::Fs::write_at(fd, &slice[0..256], 4096 * 255)
             .expect("FileWriteAt syscall failed");
 
+        // Shrink the file and grow it again, the grown part should be zeroed.
+        vibrio::syscalls::Fs::truncate(fd, 128).expect("Truncate syscall failed");
+        vibrio::syscalls::Fs::truncate(fd, 256).expect("Truncate syscall failed");
+        let fileinfo =
+            vibrio::syscalls::Fs::getinfo("mydir/file.txt").expect("GetInfo syscall failed");
+        assert_eq!(fileinfo.fsize, 256);
+        let ret = vibrio::syscalls::Fs::read_at(fd, &mut slice[0..256], 0)
+            .expect("FileReadAt syscall failed");
+        assert_eq!(ret, 256);
+        assert_eq!(slice[127], 0xb);
+        assert_eq!(slice[128], 0);
+
+        // Reading into a snapshot copies the page first, the original stays
+        // untouched.
+        slice[0] = 0xa;
+        let copy_base: u64 = 0x0550_0000_0000;
+        vibrio::syscalls::VSpace::snapshot(base, copy_base, size).expect("Snapshot syscall failed");
+        let copy: &mut [u8] = from_raw_parts_mut(copy_base as *mut u8, size as usize);
+        let ret = vibrio::syscalls::Fs::read_at(fd, &mut copy[0..256], 0)
+            .expect("FileReadAt syscall failed");
+        assert_eq!(ret, 256);
+        assert_eq!(copy[0], 0xb);
+        assert_eq!(slice[0], 0xa);
+        vibrio::syscalls::VSpace::unmap(copy_base, size).expect("Unmap syscall failed");
+
+        // Move the fd offset around and query the file by descriptor.
+        let fileinfo = vibrio::syscalls::Fs::fstat(fd).expect("FStat syscall failed");
+        assert_eq!(fileinfo.fsize, 256);
+        assert_eq!(fileinfo.ftype, FileType::File.into());
+        let offset =
+            vibrio::syscalls::Fs::lseek(fd, 100, SeekWhence::Set).expect("Seek syscall failed");
+        assert_eq!(offset, 100);
+        let offset =
+            vibrio::syscalls::Fs::lseek(fd, 27, SeekWhence::Current).expect("Seek syscall failed");
+        assert_eq!(offset, 127);
+        let ret =
+            vibrio::syscalls::Fs::read(fd, &mut slice[0..2]).expect("FileRead syscall failed");
+        assert_eq!(ret, 2);
+        assert_eq!(slice[0..2], [0xb, 0]);
+        let offset =
+            vibrio::syscalls::Fs::lseek(fd, -6, SeekWhence::End).expect("Seek syscall failed");
+        assert_eq!(offset, 250);
+        vibrio::syscalls::Fs::lseek(fd, -1, SeekWhence::Set).expect_err("Seek did not fail");
+
+        // Changes to a shared mapping of the file end up in the file.
+        let map_base: u64 = 0x2ef_ff000;
+        let map_flags = vibrio::MapFileFlags::SHARED | vibrio::MapFileFlags::WRITE;
+        vibrio::syscalls::VSpace::map_file(fd, map_base, 0x1000, 0, map_flags)
+            .expect("MapFile syscall failed");
+        let mapped: &mut [u8] = from_raw_parts_mut(map_base as *mut u8, 0x1000);
+        assert_eq!(mapped[127], 0xb);
+        assert_eq!(mapped[128], 0);
+        mapped[0] = 0xc;
+        vibrio::syscalls::VSpace::sync_file(map_base, 0x1000).expect("SyncFile syscall failed");
+        vibrio::syscalls::Fs::read_at(fd, &mut slice[0..1], 0).expect("FileReadAt syscall failed");
+        assert_eq!(slice[0], 0xc);
+        vibrio::syscalls::VSpace::unmap_file(map_base, 0x1000).expect("UnmapFile syscall failed");
+
+        // A private mapping is a copy of the file.
+        vibrio::syscalls::VSpace::map_file(fd, map_base, 0x1000, 0, vibrio::MapFileFlags::WRITE)
+            .expect("MapFile syscall failed");
+        assert_eq!(mapped[0], 0xc);
+        mapped[0] = 0xd;
+        vibrio::syscalls::Fs::read_at(fd, &mut slice[0..1], 0).expect("FileReadAt syscall failed");
+        assert_eq!(slice[0], 0xc);
+        vibrio::syscalls::VSpace::unmap(map_base, 0x1000).expect("Unmap syscall failed");
+
         // Close the file.
         vibrio::syscalls::Fs::close(fd).expect("FileClose syscall failed");
 
+        // A directory with entries can't be deleted.
+        assert_eq!(
+            vibrio::syscalls::Fs::delete("mydir"),
+            Err(vibrio::SystemCallError::DirectoryNotEmpty)
+        );
+
         // Rename the file
         let ret = vibrio::syscalls::Fs::rename("mydir/file.txt", "filenew.txt")
             .expect("FileRename syscall failed");
@@ -695,6 +889,70 @@ fn fs_test() {
         let ret =
             vibrio::syscalls::Fs::delete("mydir").expect("FileDelete syscall on directory failed");
 
+        // Send data through a pipe.
+        let (read_fd, write_fd) = vibrio::syscalls::Fs::pipe().expect("Pipe syscall failed");
+        let mut buf = [0u8; 8];
+        let _r = vibrio::syscalls::Fs::try_read(read_fd, &mut buf)
+            .expect_err("Read on empty pipe did not fail");
+        let ret = vibrio::syscalls::Fs::write(write_fd, b"pipe").expect("Pipe write failed");
+        assert_eq!(ret, 4);
+        let ret = vibrio::syscalls::Fs::read(read_fd, &mut buf).expect("Pipe read failed");
+        assert_eq!(&buf[..ret as usize], b"pipe");
+        vibrio::syscalls::Fs::close(write_fd).expect("FileClose syscall failed");
+        let ret = vibrio::syscalls::Fs::read(read_fd, &mut buf).expect("Pipe read failed");
+        assert_eq!(ret, 0);
+        vibrio::syscalls::Fs::close(read_fd).expect("FileClose syscall failed");
+
+        // Queue operations in a ring and execute them with one syscall.
+        use vibrio::ring::{IoRing, Submission};
+        vibrio::syscalls::Fs::ring_enter().expect_err("RingEnter without a ring did not fail");
+        let ring: &mut IoRing = alloc::boxed::Box::leak(Default::default());
+        vibrio::syscalls::Fs::ring_register(ring).expect("RingRegister syscall failed");
+
+        let (read_fd, write_fd) = vibrio::syscalls::Fs::pipe().expect("Pipe syscall failed");
+        let mut buf = [0u8; 8];
+        ring.submit(Submission::write(write_fd, b"ri", 0)).unwrap();
+        ring.submit(Submission::write(write_fd, b"ng", 1)).unwrap();
+        ring.submit(Submission::read(read_fd, &mut buf, 2)).unwrap();
+        ring.submit(Submission::close(write_fd, 3)).unwrap();
+        ring.submit(Submission::close(read_fd, 4)).unwrap();
+        let ret = vibrio::syscalls::Fs::ring_enter().expect("RingEnter syscall failed");
+        assert_eq!(ret, 5);
+        assert_eq!(ring.pending(), 0);
+        for (user_data, result) in [2, 2, 4, 0, 0].iter().enumerate() {
+            let completion = ring.complete().expect("Missing completion");
+            assert_eq!(completion.user_data, user_data as u64);
+            assert_eq!(completion.result(), Ok(*result));
+        }
+        assert_eq!(&buf[..4], b"ring");
+
+        // Mixed operations on a file go to the logs in batches.
+        let fd = vibrio::syscalls::Fs::open(
+            "ring.txt",
+            FileFlags::O_RDWR | FileFlags::O_CREAT,
+            FileModes::S_IRWXU,
+        )
+        .expect("FileOpen syscall failed");
+        let mut buf = [0u8; 8];
+        ring.submit(Submission::write_at(fd, b"ring", 0, 0))
+            .unwrap();
+        ring.submit(Submission::read_at(fd, &mut buf[..4], 0, 1))
+            .unwrap();
+        ring.submit(Submission::write_at(fd, b"RING", 2, 2))
+            .unwrap();
+        ring.submit(Submission::read_at(fd, &mut buf[4..], 0, 3))
+            .unwrap();
+        let ret = vibrio::syscalls::Fs::ring_enter().expect("RingEnter syscall failed");
+        assert_eq!(ret, 4);
+        for user_data in 0..4 {
+            let completion = ring.complete().expect("Missing completion");
+            assert_eq!(completion.user_data, user_data);
+            assert_eq!(completion.result(), Ok(4));
+        }
+        assert_eq!(&buf, b"ringriRI");
+        vibrio::syscalls::Fs::close(fd).expect("FileClose syscall failed");
+        vibrio::syscalls::Fs::delete("ring.txt").expect("FileDelete syscall failed");
+
         // Test fs with invalid userspace pointers
         test_fs_invalid_addresses();
     }
@@ -795,6 +1053,48 @@ pub fn install_vcpu_area() {
         VAddr::from(vibrio::upcalls::upcall_while_enabled as *const fn() as u64);
 }
 
+/// Checks that files survive a reboot (needs a kernel started with
+/// `fs=pmem`).
+///
+/// The first boot creates a file, every boot after checks what the earlier
+/// ones wrote to it and appends another byte.
+fn pmem_fs_test() {
+    use vibrio::io::*;
+    use vibrio::syscalls::Fs;
+
+    let path = "pmemfs/boots";
+    let boots = match Fs::getinfo(path) {
+        Ok(fileinfo) => fileinfo.fsize as usize,
+        Err(_) => {
+            Fs::mkdir_simple("pmemfs", FileModes::S_IRWXU).expect("MkDir syscall failed");
+            0
+        }
+    };
+
+    let fd = Fs::open(
+        path,
+        FileFlags::O_RDWR | FileFlags::O_CREAT,
+        FileModes::S_IRWXU,
+    )
+    .expect("FileOpen syscall failed");
+
+    let mut contents = [0u8; 64];
+    assert!(boots < contents.len(), "Too many boots");
+    if boots > 0 {
+        let ret = Fs::read_at(fd, &mut contents[..boots], 0).expect("FileRead syscall failed");
+        assert_eq!(ret as usize, boots);
+        for (i, b) in contents[..boots].iter().enumerate() {
+            assert_eq!(*b as usize, i + 1);
+        }
+    }
+
+    let ret = Fs::write_at(fd, &[boots as u8 + 1], boots as i64).expect("FileWrite syscall failed");
+    assert_eq!(ret, 1);
+    Fs::close(fd).expect("FileClose syscall failed");
+
+    info!("pmem_fs_test OK (boot {})", boots + 1);
+}
+
 pub fn upcall_test() {
     sys_println!("causing a debug exception");
     unsafe { x86::int!(3) };
@@ -887,6 +1187,9 @@ pub extern "C" fn _start() -> ! {
     #[cfg(feature = "test-fs-prop")]
     fs_prop_test();
 
+    #[cfg(feature = "test-pmem-fs")]
+    pmem_fs_test();
+
     vibrio::vconsole::init();
 
     #[cfg(feature = "test-scheduler-smp")]